@@ -0,0 +1,143 @@
+//! The `bf!` macro, which parses and optimizes a Brainfuck program at compile time.
+//!
+//! ```text
+//! use bf_macros::bf;
+//!
+//! let prog: Vec<bf::Instruction> = bf!("++++++++[>+++++++++<-]>.");
+//! let output = bf::events::run(&prog, &bf::config::Config::default(), b"")?;
+//! ```
+//!
+//! The program is parsed and optimized with the default configuration, and the macro
+//! expands to the optimized instructions, so running it does not parse anything.
+//! Invalid programs fail to compile, with the error at the string literal.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+use bf::{Instruction, config::Config, lexer::Lexer, opt::PassManager, parser::Parser};
+
+/// Parse and optimize the program in the string literal, and expand to its instructions
+/// as a `Vec<bf::Instruction>`.
+#[proc_macro]
+pub fn bf(input: TokenStream) -> TokenStream {
+    let mut tokens = input.into_iter();
+    let (literal, span) = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => {
+            let span = literal.span();
+            (literal.to_string(), span)
+        },
+        (Some(token), _) => return compile_error("expected a single string literal", token.span()),
+        (None, _) => return compile_error("expected a single string literal", Span::call_site()),
+    };
+    let Some(src) = unquote(&literal) else {
+        return compile_error("expected a string literal", span);
+    };
+
+    let config = Config::default();
+    let mut prog = match Parser::new(Lexer::with_config(&src, &config)).parse() {
+        Ok(prog) => prog,
+        Err(e) => return compile_error(&e.to_string(), span),
+    };
+    PassManager::from_config(&config)
+        .expect("the default passes exist")
+        .run(&mut prog);
+
+    vec_expr(&prog).parse().expect("the instructions are valid Rust")
+}
+
+/// The value of the string literal `literal`, as written in the source, or `None` if it
+/// is not a string literal.
+fn unquote(literal: &str) -> Option<String> {
+    // Raw strings, such as r#"..."#, have no escapes
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return raw[hashes..].strip_suffix(&"#".repeat(hashes))?
+            .strip_prefix('"')?
+            .strip_suffix('"')
+            .map(str::to_string);
+    }
+
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            c @ ('\\' | '\'' | '"') => out.push(c),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            },
+            'u' => {
+                let hex: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            },
+            // A line continuation skips the line break and the whitespace after it
+            '\n' => while chars.next_if(|c| c.is_whitespace()).is_some() {},
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Rust code creating the instructions of `prog` as a vector.
+fn vec_expr(prog: &[Instruction]) -> String {
+    let items: Vec<String> = prog.iter().map(instr_expr).collect();
+    format!("::std::vec![{}]", items.join(", "))
+}
+
+/// Rust code creating the instruction `instr`.
+fn instr_expr(instr: &Instruction) -> String {
+    use Instruction::*;
+    let variant = match instr {
+        IncPtr(x) => format!("IncPtr({})", x),
+        DecPtr(x) => format!("DecPtr({})", x),
+        IncVal(x) => format!("IncVal({})", x),
+        DecVal(x) => format!("DecVal({})", x),
+        ClearVal => "ClearVal".to_string(),
+        SetVal(x) => format!("SetVal({})", x),
+        Write => "Write".to_string(),
+        WriteConst(x) => format!("WriteConst({})", x),
+        Read => "Read".to_string(),
+        SwitchTape(x) => format!("SwitchTape({})", x),
+        Push => "Push".to_string(),
+        Pop => "Pop".to_string(),
+        Halt => "Halt".to_string(),
+        Random => "Random".to_string(),
+        MulLoop(targets, step) => {
+            let targets: Vec<String> = targets.iter()
+                .map(|(offset, factor)| format!("({}, {})", offset, factor))
+                .collect();
+            format!("MulLoop(::std::vec![{}], {})", targets.join(", "), step)
+        },
+        Loop(body, shift) => {
+            let shift = match shift {
+                Some(shift) => format!("::core::option::Option::Some({})", shift),
+                None => "::core::option::Option::None".to_string(),
+            };
+            format!("Loop({}, {})", vec_expr(body), shift)
+        },
+    };
+    format!("::bf::Instruction::{}", variant)
+}
+
+/// A `compile_error!` with `message` at `span`.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenTree::Literal(message).into());
+    args.set_span(span);
+    [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ].into_iter().collect()
+}
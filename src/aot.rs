@@ -0,0 +1,105 @@
+//! Running programs ahead-of-time compiled to a shared library, loaded into this process.
+
+use std::{env, ffi::{CStr, CString, c_char, c_int, c_void}, fs, hash::{DefaultHasher, Hash, Hasher}, path::PathBuf, process};
+
+use bf::{Instruction, config::Config, backend::{self, c}};
+
+use crate::{build, cli::Options};
+
+/// Compile the program to a shared library, or reuse it from the cache, and run it.
+///
+/// Returns the error message of the program if it fails.
+pub fn run(prog: &[Instruction], config: &Config, opts: &Options) -> Result<Result<(), String>, String> {
+    backend::supported(prog, config)?;
+    let src = c::generate_shared(prog, config);
+    let lib = compile(&src, opts)?;
+
+    let path = CString::new(lib.to_string_lossy().into_owned()).map_err(|e| e.to_string())?;
+    let entry = CString::new(c::ENTRY_POINT).unwrap();
+    // SAFETY: the library was generated by us, and exports the entry point with this signature
+    unsafe {
+        let handle = sys::dlopen(path.as_ptr(), sys::RTLD_NOW);
+        if handle.is_null() {
+            return Err(format!("could not load `{}`: {}", lib.display(), sys::error()));
+        }
+        let sym = sys::dlsym(handle, entry.as_ptr());
+        if sym.is_null() {
+            return Err(format!("could not find `{}` in `{}`: {}", c::ENTRY_POINT, lib.display(), sys::error()));
+        }
+
+        let bf_run: extern "C" fn() -> *const c_char = std::mem::transmute(sym);
+        let error = bf_run();
+        let result = if error.is_null() {
+            Ok(())
+        } else {
+            Err(CStr::from_ptr(error).to_string_lossy().into_owned())
+        };
+        sys::dlclose(handle);
+        Ok(result)
+    }
+}
+
+/// Compile the C source to a shared library in the cache directory, unless it is already there.
+fn compile(src: &str, opts: &Options) -> Result<PathBuf, String> {
+    // The artifact depends on the source, the compiler and its flags
+    let mut hasher = DefaultHasher::new();
+    src.hash(&mut hasher);
+    env::var("CC").ok().hash(&mut hasher);
+    opts.cc_flags.hash(&mut hasher);
+
+    let dir = cache_dir();
+    let lib = dir.join(format!("{:016x}.so", hasher.finish()));
+    if lib.is_file() {
+        return Ok(lib);
+    }
+
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("could not create `{}`: {}", dir.display(), e))?;
+    let c = dir.join(format!("{}.c", process::id()));
+    fs::write(&c, src)
+        .map_err(|e| format!("could not write `{}`: {}", c.display(), e))?;
+
+    // Build under a temporary name, so concurrent runs never load a partial library
+    let tmp = dir.join(format!("{}.so", process::id()));
+    let mut cc = build::compiler(opts);
+    cc.args(["-shared", "-fPIC", "-o"]).arg(&tmp).arg(&c);
+    let result = build::run(cc)
+        .and_then(|()| fs::rename(&tmp, &lib).map_err(|e| format!("could not write `{}`: {}", lib.display(), e)));
+    let _ = fs::remove_file(&c);
+    result.map(|()| lib)
+}
+
+/// Directory of cached shared libraries.
+fn cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    base.join("bf").join("aot")
+}
+
+mod sys {
+    use super::*;
+
+    pub const RTLD_NOW: c_int = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    unsafe extern "C" {
+        pub fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        pub fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        pub fn dlclose(handle: *mut c_void) -> c_int;
+        fn dlerror() -> *const c_char;
+    }
+
+    /// The last error of the dynamic linker.
+    pub fn error() -> String {
+        // SAFETY: dlerror returns null or a valid C string
+        unsafe {
+            let msg = dlerror();
+            if msg.is_null() {
+                "unknown error".to_string()
+            } else {
+                CStr::from_ptr(msg).to_string_lossy().into_owned()
+            }
+        }
+    }
+}
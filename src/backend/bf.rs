@@ -0,0 +1,339 @@
+//! Brainfuck generation, for optimizing programs to run with other interpreters.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::{Instruction, opt, config::{CellWidth, Config, Dialect}};
+
+/// Maximum length of a line of output.
+const LINE_WIDTH: usize = 80;
+
+/// Generate Brainfuck equivalent to `prog` under the given settings,
+/// or `None` if the program contains instructions that cannot be written as Brainfuck,
+/// i.e. [`Instruction::WriteConst`], which does not say which cell holds the constant.
+pub fn generate(prog: &[Instruction], config: &Config) -> Option<String> {
+    let mut code = String::new();
+    block(prog, config, &mut code)?;
+
+    let mut out = String::with_capacity(code.len() + code.len() / LINE_WIDTH + 1);
+    for line in code.as_bytes().chunks(LINE_WIDTH) {
+        // The code is plain ASCII
+        out += std::str::from_utf8(line).unwrap();
+        out.push('\n');
+    }
+    Some(out)
+}
+
+fn block(prog: &[Instruction], config: &Config, out: &mut String) -> Option<()> {
+    // Boolfuck flips bits with `+` only, and writes them with `;`
+    let boolfuck = config.dialect == Dialect::Boolfuck;
+    let clear = if boolfuck { "[+]" } else { "[-]" };
+    for instr in prog {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => *out += &">".repeat(*x),
+            DecPtr(x) => *out += &"<".repeat(*x),
+            IncVal(x) => *out += &"+".repeat(*x as usize),
+            DecVal(x) if boolfuck => *out += &"+".repeat(*x as usize),
+            DecVal(x) => *out += &"-".repeat(*x as usize),
+            ClearVal => *out += clear,
+            SetVal(x) => {
+                *out += clear;
+                add(*x, config, out);
+            },
+            Write if boolfuck => out.push(';'),
+            Write => out.push('.'),
+            Read => out.push(','),
+            Halt => out.push('@'),
+            Random => out.push('?'),
+            Push => out.push('^'),
+            Pop => out.push('_'),
+            SwitchTape(x) if *x >= 0 => *out += &"}".repeat(*x as usize),
+            SwitchTape(x) => *out += &"{".repeat(x.unsigned_abs()),
+            WriteConst(_) => return None,
+            MulLoop(targets, step) => {
+                // Write the loop that the closed form replaced
+                out.push('[');
+                add(step.wrapping_neg(), config, out);
+                let mut offset = 0;
+                for (target, factor) in targets {
+                    move_by(target - offset, out);
+                    add(*factor, config, out);
+                    offset = *target;
+                }
+                move_by(-offset, out);
+                out.push(']');
+            },
+            Loop(body, _) => {
+                out.push('[');
+                block(body, config, out)?;
+                out.push(']');
+            },
+        }
+    }
+    Some(())
+}
+
+/// Add a constant to the current cell, by incrementing or decrementing whichever is shorter.
+/// With 1-bit cells, this only increments.
+fn add(x: u32, config: &Config, out: &mut String) {
+    let x = x & config.cell_width.mask();
+    let down = x.wrapping_neg() & config.cell_width.mask();
+    if x <= down {
+        *out += &"+".repeat(x as usize);
+    } else {
+        *out += &"-".repeat(down as usize);
+    }
+}
+
+/// Generate Brainfuck equivalent to `prog` that is as short as it can be made, on a single
+/// line, or `None` as with [`generate`].
+///
+/// This keeps track of the cells whose value is known. Clears and loops of cells that are
+/// known to be zero are left out, and constants are added with a multiplication loop over
+/// a neighboring cell known to be zero whenever that is shorter, such as
+/// `>++++++++[<+++++++++>-]<` for 72. Multiply loops visit their targets in the order
+/// that moves the least, and consecutive moves are merged. Values are only known with wrapping cells of at least a byte,
+/// outside of Boolfuck; otherwise this is [`generate`] on a single line.
+pub fn golf(prog: &[Instruction], config: &Config) -> Option<String> {
+    let values = match config.cell_width {
+        _ if config.dialect == Dialect::Boolfuck => None,
+        CellWidth::U8 | CellWidth::U16 | CellWidth::U32 => Some(config.cell_width.mask() as u64 + 1),
+        CellWidth::Bit | CellWidth::Big => None,
+    };
+    let mut out = String::new();
+    match values {
+        Some(values) => {
+            let mut golfer = Golfer { values, tape_size: config.tape_size as isize, out };
+            golfer.block(prog, &mut Known { cells: BTreeMap::new(), zero: true, pos: 0 })?;
+            out = merge_moves(&golfer.out);
+        },
+        None => block(prog, config, &mut out)?,
+    }
+    out.push('\n');
+    Some(out)
+}
+
+/// What is known about the cells, relative to the data pointer at an earlier point.
+#[derive(Clone)]
+struct Known {
+    /// Values of cells by offset, or `None` for cells whose value is not known.
+    cells: BTreeMap<isize, Option<u32>>,
+    /// Whether the other cells are zero, as the program has not changed them yet.
+    /// Offsets are then positions on the tape.
+    zero: bool,
+    /// Offset of the data pointer.
+    pos: isize,
+}
+
+impl Known {
+    /// Nothing is known about any cell.
+    fn unknown() -> Self {
+        Self { cells: BTreeMap::new(), zero: false, pos: 0 }
+    }
+
+    fn get(&self, offset: isize) -> Option<u32> {
+        match self.cells.get(&offset) {
+            Some(value) => *value,
+            None => self.zero.then_some(0),
+        }
+    }
+
+    fn set(&mut self, value: Option<u32>) {
+        self.cells.insert(self.pos, value);
+    }
+
+    /// Forget the values of the cells that `body`, run from the data pointer, may change.
+    fn forget(&mut self, body: &[Instruction]) {
+        let mut touched = BTreeSet::new();
+        opt::touched(body, self.pos, &mut touched);
+        for offset in touched {
+            self.cells.insert(offset, None);
+        }
+    }
+}
+
+struct Golfer {
+    /// Number of values of a cell.
+    values: u64,
+    tape_size: isize,
+    out: String,
+}
+
+impl Golfer {
+    fn block(&mut self, prog: &[Instruction], known: &mut Known) -> Option<()> {
+        for instr in prog {
+            use Instruction::*;
+            let current = known.get(known.pos);
+            match instr {
+                IncPtr(x) => {
+                    self.out += &">".repeat(*x);
+                    known.pos += *x as isize;
+                },
+                DecPtr(x) => {
+                    self.out += &"<".repeat(*x);
+                    known.pos -= *x as isize;
+                },
+                IncVal(x) => self.change(*x as u32, current.map(|v| v.wrapping_add(*x as u32)), known),
+                DecVal(x) => self.change((*x as u32).wrapping_neg(), current.map(|v| v.wrapping_sub(*x as u32)), known),
+                ClearVal | SetVal(_) => {
+                    let x = if let SetVal(x) = instr { *x & (self.values - 1) as u32 } else { 0 };
+                    if current == Some(x) {
+                        continue;
+                    }
+                    let temp = self.temp(known);
+                    let cleared = "[-]".to_string() + &self.add(x, temp);
+                    let code = match current {
+                        Some(value) if self.add(x.wrapping_sub(value), temp).len() < cleared.len() => self.add(x.wrapping_sub(value), temp),
+                        _ => cleared,
+                    };
+                    self.out += &code;
+                    known.set(Some(x));
+                },
+                Write => self.out.push('.'),
+                Read => {
+                    self.out.push(',');
+                    known.set(None);
+                },
+                Halt => self.out.push('@'),
+                Random => {
+                    self.out.push('?');
+                    known.set(None);
+                },
+                Push => self.out.push('^'),
+                Pop => {
+                    self.out.push('_');
+                    known.set(None);
+                },
+                SwitchTape(x) => {
+                    let c = if *x >= 0 { "}" } else { "{" };
+                    self.out += &c.repeat(x.unsigned_abs());
+                    *known = Known::unknown();
+                },
+                WriteConst(_) => return None,
+                MulLoop(..) | Loop(..) if current == Some(0) => {},
+                MulLoop(targets, step) => {
+                    // Visit the targets from left to right or from right to left, whichever moves less
+                    let mut targets = targets.clone();
+                    targets.sort_unstable();
+                    let (first, last) = (targets[0].0, targets[targets.len() - 1].0);
+                    if first.abs() > last.abs() {
+                        targets.reverse();
+                    }
+                    self.out.push('[');
+                    self.out += &self.add(step.wrapping_neg(), None);
+                    let mut offset = 0;
+                    for &(target, factor) in &targets {
+                        move_by(target - offset, &mut self.out);
+                        self.out += &self.add(factor, None);
+                        known.cells.insert(known.pos + target, None);
+                        offset = target;
+                    }
+                    move_by(-offset, &mut self.out);
+                    self.out.push(']');
+                    known.set(Some(0));
+                },
+                Loop(body, shift) => {
+                    let balanced = *shift == Some(0);
+                    let mut inner = if balanced { known.clone() } else { Known::unknown() };
+                    if balanced {
+                        inner.forget(body);
+                    }
+                    inner.set(None);
+                    self.out.push('[');
+                    self.block(body, &mut inner)?;
+                    self.out.push(']');
+                    if balanced {
+                        known.forget(body);
+                    } else {
+                        *known = Known::unknown();
+                    }
+                    known.set(Some(0));
+                },
+            }
+        }
+        Some(())
+    }
+
+    /// Add `x` to the current cell, whose value becomes `value`.
+    fn change(&mut self, x: u32, value: Option<u32>, known: &mut Known) {
+        let temp = self.temp(known);
+        self.out += &self.add(x, temp);
+        known.set(value.map(|v| v & (self.values - 1) as u32));
+    }
+
+    /// The direction of a neighbor of the data pointer known to be zero, if any.
+    fn temp(&self, known: &Known) -> Option<isize> {
+        [1, -1].into_iter().find(|&dir| {
+            let offset = known.pos + dir;
+            // Cells that were never visited may lie outside of the tape
+            let on_tape = known.cells.contains_key(&offset) || (0..self.tape_size).contains(&offset);
+            on_tape && known.get(offset) == Some(0)
+        })
+    }
+
+    /// The shortest code that adds `x` to the current cell, with a loop over the zero
+    /// cell in direction `temp` if there is one and that is shorter.
+    fn add(&self, x: u32, temp: Option<isize>) -> String {
+        let x = x as u64 & (self.values - 1);
+        let mut best = self.add_direct(x);
+        let Some(dir) = temp else {
+            return best;
+        };
+        let (there, back) = if dir > 0 { ('>', '<') } else { ('<', '>') };
+        // The loop itself and the moves around it take 7 commands
+        for times in 2..=MAX_FACTOR {
+            for factor in 1..=MAX_FACTOR {
+                if times + factor + 7 >= best.len() as u64 {
+                    break;
+                }
+                for up in [true, false] {
+                    let product = if up { times * factor } else { self.values - times * factor % self.values };
+                    let rest = self.add_direct(x.wrapping_sub(product) & (self.values - 1));
+                    if times + factor + 7 + (rest.len() as u64) < best.len() as u64 {
+                        let step = if up { "+" } else { "-" };
+                        best = format!("{}{}[{}{}{}-]{}{}", there, "+".repeat(times as usize), back,
+                            step.repeat(factor as usize), there, back, rest);
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Add `x` by incrementing or decrementing, whichever is shorter.
+    fn add_direct(&self, x: u64) -> String {
+        let down = (self.values - x) & (self.values - 1);
+        if x <= down { "+".repeat(x as usize) } else { "-".repeat(down as usize) }
+    }
+}
+
+/// Replace every run of `>` and `<` by its net move, such as the `<<>` of leaving a cell
+/// to build a constant next to the one before it.
+fn merge_moves(code: &str) -> String {
+    let mut out = String::with_capacity(code.len());
+    let mut offset = 0;
+    for c in code.chars() {
+        match c {
+            '>' => offset += 1,
+            '<' => offset -= 1,
+            c => {
+                move_by(offset, &mut out);
+                offset = 0;
+                out.push(c);
+            },
+        }
+    }
+    move_by(offset, &mut out);
+    out
+}
+
+/// Largest number of times a loop runs, and largest factor, that [`Golfer::add`] tries.
+const MAX_FACTOR: u64 = 64;
+
+fn move_by(offset: isize, out: &mut String) {
+    if offset > 0 {
+        *out += &">".repeat(offset as usize);
+    } else {
+        *out += &"<".repeat(-offset as usize);
+    }
+}
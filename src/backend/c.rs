@@ -0,0 +1,187 @@
+//! C source generation, for building native executables with the system C compiler.
+
+use std::fmt::Write as _;
+
+use crate::{Instruction, opt, config::{Config, CellWidth, EofMode, EmptyLoopMode}};
+
+use super::target::Target;
+
+/// Generate a standalone C program equivalent to `prog` under the given settings, for the host.
+///
+/// The program checks that the data pointer stays on the tape, like the interpreter,
+/// but does not enforce a step limit.
+pub fn generate(prog: &[Instruction], config: &Config) -> String {
+    generate_for(prog, config, &Target::host())
+}
+
+/// Name of the function run by bare-metal platforms, and exported by [`generate_shared`].
+pub const ENTRY_POINT: &str = "bf_run";
+
+/// Generate C for the given platform.
+///
+/// For a hosted platform this is a standalone program. For a bare-metal platform it is
+/// a function `void bf_run(void)` using the functions `int bf_getchar(void)`, which returns
+/// the next input byte or `-1` at the end of input, `void bf_putchar(int)`, and
+/// `_Noreturn void bf_fail(const char *)`, which the platform has to provide.
+pub fn generate_for(prog: &[Instruction], config: &Config, target: &Target) -> String {
+    let mut out = String::new();
+    if target.hosted {
+        out += "#include <stdint.h>\n#include <stdio.h>\n#include <stdlib.h>\n\n";
+        out += HOSTED_IO;
+        out += "static void fail(const char *msg) {\n";
+        out += "    fflush(stdout);\n";
+        out += "    fprintf(stderr, \"error: %s\\n\", msg);\n";
+        out += "    exit(1);\n";
+        out += "}\n\n";
+        out += &tape(config);
+        out += "int main(void) {\n";
+    } else {
+        out += "#include <stddef.h>\n#include <stdint.h>\n\n";
+        out += "/* Provided by the platform */\n";
+        out += "int bf_getchar(void);\n";
+        out += "void bf_putchar(int c);\n";
+        out += "_Noreturn void bf_fail(const char *msg);\n\n";
+        out += "#define get bf_getchar\n#define put bf_putchar\n#define fail bf_fail\n\n";
+        out += &tape(config);
+        let _ = writeln!(out, "void {}(void) {{", ENTRY_POINT);
+    }
+    out += "    size_t p = 0;\n";
+    out += "    int c;\n";
+    out += "    (void)c;\n";
+    block(prog, config, 1, &mut out);
+    end(prog, &mut out);
+    if target.hosted {
+        out += "    fflush(stdout);\n";
+        out += "    return 0;\n";
+    }
+    out += "}\n";
+    out
+}
+
+/// Generate C for a shared library exporting `const char *bf_run(void)`,
+/// which runs the program on a fresh tape and returns `NULL`, or an error message if it fails.
+pub fn generate_shared(prog: &[Instruction], config: &Config) -> String {
+    let mut out = String::new();
+    out += "#include <setjmp.h>\n#include <stdint.h>\n#include <stdio.h>\n#include <string.h>\n\n";
+    out += HOSTED_IO;
+    out += "static jmp_buf on_error;\n";
+    out += "static const char *error;\n\n";
+    out += "static void fail(const char *msg) {\n";
+    out += "    fflush(stdout);\n";
+    out += "    error = msg;\n";
+    out += "    longjmp(on_error, 1);\n";
+    out += "}\n\n";
+    out += &tape(config);
+    let _ = writeln!(out, "const char *{}(void) {{", ENTRY_POINT);
+    out += "    size_t p = 0;\n";
+    out += "    int c;\n";
+    out += "    (void)c;\n";
+    out += "    memset(tape, 0, sizeof tape);\n";
+    out += "    if (setjmp(on_error)) return error;\n";
+    block(prog, config, 1, &mut out);
+    end(prog, &mut out);
+    out += "    fflush(stdout);\n";
+    out += "    return NULL;\n";
+    out += "}\n";
+    out
+}
+
+/// Input and output through the C standard library.
+const HOSTED_IO: &str = "\
+static void put(int c) {
+    putchar(c);
+}
+
+static int get(void) {
+    /* Make sure prompts are visible before blocking on input */
+    fflush(stdout);
+    return getchar();
+}
+
+";
+
+/// The cell type and the tape.
+fn tape(config: &Config) -> String {
+    let cell = match config.cell_width {
+        CellWidth::U8 => "uint8_t",
+        CellWidth::U16 => "uint16_t",
+        CellWidth::U32 => "uint32_t",
+        CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "#define TAPE_SIZE {}\n", config.tape_size);
+    let _ = writeln!(out, "typedef {} cell;\n", cell);
+    out += "static cell tape[TAPE_SIZE];\n\n";
+    out
+}
+
+/// The label that `@` jumps to, at the end of the program, if it has any.
+fn end(prog: &[Instruction], out: &mut String) {
+    if super::halts(prog) {
+        *out += "halt:;\n";
+    }
+}
+
+fn block(prog: &[Instruction], config: &Config, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for instr in prog {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => {
+                let _ = writeln!(out, "{}if (TAPE_SIZE - p <= {}) fail(\"data pointer moved outside of the tape\");", indent, x);
+                let _ = writeln!(out, "{}p += {};", indent, x);
+            },
+            DecPtr(x) => {
+                let _ = writeln!(out, "{}if (p < {}) fail(\"data pointer moved outside of the tape\");", indent, x);
+                let _ = writeln!(out, "{}p -= {};", indent, x);
+            },
+            IncVal(x) => { let _ = writeln!(out, "{}tape[p] += {};", indent, x); },
+            DecVal(x) => { let _ = writeln!(out, "{}tape[p] -= {};", indent, x); },
+            ClearVal => { let _ = writeln!(out, "{}tape[p] = 0;", indent); },
+            SetVal(x) => { let _ = writeln!(out, "{}tape[p] = {}u;", indent, x & config.cell_width.mask()); },
+            Write => { let _ = writeln!(out, "{}put(tape[p] & 255);", indent); },
+            WriteConst(x) => { let _ = writeln!(out, "{}put({});", indent, x); },
+            Read => {
+                let eof = match config.eof {
+                    EofMode::Unchanged => String::new(),
+                    EofMode::Zero => " else tape[p] = 0;".to_string(),
+                    EofMode::Max => " else tape[p] = (cell)-1;".to_string(),
+                    EofMode::Error => " else fail(\"unexpected end of file\");".to_string(),
+                };
+                let _ = writeln!(out, "{}c = get();", indent);
+                let _ = writeln!(out, "{}if (c >= 0) tape[p] = (cell)c;{}", indent, eof);
+            },
+            MulLoop(targets, step) => {
+                let _ = writeln!(out, "{}if (tape[p]) {{", indent);
+                let _ = writeln!(out, "{}    uint32_t n = (uint32_t)tape[p] * {}u;", indent, opt::inverse(*step));
+                for (offset, factor) in targets {
+                    let (check, cell) = if *offset >= 0 {
+                        (format!("TAPE_SIZE - p <= {}", offset), format!("tape[p + {}]", offset))
+                    } else {
+                        (format!("p < {}", -offset), format!("tape[p - {}]", -offset))
+                    };
+                    let _ = writeln!(out, "{}    if ({}) fail(\"data pointer moved outside of the tape\");", indent, check);
+                    let _ = writeln!(out, "{}    {} += (cell)(n * {}u);", indent, cell, factor);
+                }
+                let _ = writeln!(out, "{}    tape[p] = 0;", indent);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+            Halt => { let _ = writeln!(out, "{}goto halt;", indent); },
+            SwitchTape(_) | Random | Push | Pop => unreachable!("rejected by `backend::supported`"),
+            Loop(body, _) if body.is_empty() => match config.empty_loop {
+                // Compilers may assume that a loop without side effects terminates,
+                // unless its condition is a constant
+                EmptyLoopMode::Hang => { let _ = writeln!(out, "{}if (tape[p]) for (;;) {{}}", indent); },
+                EmptyLoopMode::Error => {
+                    let _ = writeln!(out, "{}if (tape[p]) fail(\"entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)\");", indent);
+                },
+            },
+            Loop(body, _) => {
+                let _ = writeln!(out, "{}while (tape[p]) {{", indent);
+                block(body, config, depth + 1, out);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! JavaScript generation, for running programs with Node.js.
+
+use std::fmt::Write as _;
+
+use crate::{Instruction, opt, config::{Config, CellWidth, EofMode, EmptyLoopMode}};
+
+/// Runtime support: buffered output, unbuffered input and error reporting.
+const RUNTIME: &str = r#"const fs = require("fs");
+
+class BfError extends Error {}
+
+const output = Buffer.alloc(8192);
+let outputLen = 0;
+
+function flush() {
+    let written = 0;
+    while (written < outputLen) {
+        written += fs.writeSync(1, output, written, outputLen - written);
+    }
+    outputLen = 0;
+}
+
+function put(byte) {
+    if (outputLen === output.length) flush();
+    output[outputLen++] = byte;
+}
+
+const input = Buffer.alloc(1);
+
+function get() {
+    // Make sure prompts are visible before blocking on input
+    flush();
+    for (;;) {
+        try {
+            return fs.readSync(0, input, 0, 1, null) === 1 ? input[0] : -1;
+        } catch (e) {
+            if (e.code === "EAGAIN") continue;
+            if (e.code === "EOF") return -1;
+            throw e;
+        }
+    }
+}
+
+function fail(msg) {
+    throw new BfError(msg);
+}
+"#;
+
+/// Generate a Node.js script equivalent to `prog` under the given settings.
+///
+/// Like the interpreter, the script checks that the data pointer stays on the tape,
+/// but it does not enforce a step limit.
+pub fn generate(prog: &[Instruction], config: &Config) -> String {
+    let array = match config.cell_width {
+        CellWidth::U8 => "Uint8Array",
+        CellWidth::U16 => "Uint16Array",
+        CellWidth::U32 => "Uint32Array",
+        CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+    };
+
+    let mut out = String::new();
+    out += "#!/usr/bin/env node\n\"use strict\";\n\n";
+    out += RUNTIME;
+    let _ = writeln!(out, "\nconst TAPE_SIZE = {};", config.tape_size);
+    let _ = writeln!(out, "const t = new {}(TAPE_SIZE);", array);
+    out += "let p = 0;\n\n";
+    // `@` breaks out of the labelled statement
+    out += "run: try {\n";
+    out += "    let c, n;\n";
+    block(prog, config, 1, &mut out);
+    out += "} catch (e) {\n";
+    out += "    if (!(e instanceof BfError)) throw e;\n";
+    out += "    flush();\n";
+    out += "    process.stderr.write(\"error: \" + e.message + \"\\n\");\n";
+    out += "    process.exitCode = 1;\n";
+    out += "}\n";
+    out += "flush();\n";
+    out
+}
+
+fn block(prog: &[Instruction], config: &Config, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mask = config.cell_width.mask();
+    for instr in prog {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => {
+                let _ = writeln!(out, "{}p += {};", indent, x);
+                let _ = writeln!(out, "{}if (p >= TAPE_SIZE) fail(\"data pointer moved outside of the tape\");", indent);
+            },
+            DecPtr(x) => {
+                let _ = writeln!(out, "{}p -= {};", indent, x);
+                let _ = writeln!(out, "{}if (p < 0) fail(\"data pointer moved outside of the tape\");", indent);
+            },
+            // Typed arrays wrap values on assignment
+            IncVal(x) => { let _ = writeln!(out, "{}t[p] += {};", indent, x); },
+            DecVal(x) => { let _ = writeln!(out, "{}t[p] -= {};", indent, x); },
+            ClearVal => { let _ = writeln!(out, "{}t[p] = 0;", indent); },
+            SetVal(x) => { let _ = writeln!(out, "{}t[p] = {};", indent, x & mask); },
+            Write => { let _ = writeln!(out, "{}put(t[p] & 255);", indent); },
+            WriteConst(x) => { let _ = writeln!(out, "{}put({});", indent, x); },
+            Read => {
+                let eof = match config.eof {
+                    EofMode::Unchanged => String::new(),
+                    EofMode::Zero => " else t[p] = 0;".to_string(),
+                    EofMode::Max => format!(" else t[p] = {};", mask),
+                    EofMode::Error => " else fail(\"unexpected end of file\");".to_string(),
+                };
+                let _ = writeln!(out, "{}c = get();", indent);
+                let _ = writeln!(out, "{}if (c >= 0) t[p] = c;{}", indent, eof);
+            },
+            MulLoop(targets, step) => {
+                let _ = writeln!(out, "{}if (t[p] !== 0) {{", indent);
+                let _ = writeln!(out, "{}    n = (Math.imul(t[p], {}) & {}) >>> 0;", indent, opt::inverse(*step) as i32, mask as i32);
+                for (offset, factor) in targets {
+                    let cell = if *offset >= 0 { format!("p + {}", offset) } else { format!("p - {}", -offset) };
+                    let _ = writeln!(out, "{}    if ({} < 0 || {} >= TAPE_SIZE) fail(\"data pointer moved outside of the tape\");", indent, cell, cell);
+                    let _ = writeln!(out, "{}    t[{}] += Math.imul(n, {});", indent, cell, *factor as i32);
+                }
+                let _ = writeln!(out, "{}    t[p] = 0;", indent);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+            Halt => { let _ = writeln!(out, "{}break run;", indent); },
+            SwitchTape(_) | Random | Push | Pop => unreachable!("rejected by `backend::supported`"),
+            Loop(body, _) if body.is_empty() && config.empty_loop == EmptyLoopMode::Error => {
+                let _ = writeln!(out, "{}if (t[p] !== 0) fail(\"entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)\");", indent);
+            },
+            Loop(body, _) => {
+                let _ = writeln!(out, "{}while (t[p] !== 0) {{", indent);
+                block(body, config, depth + 1, out);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+        }
+    }
+}
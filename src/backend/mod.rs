@@ -0,0 +1,135 @@
+//! Translation of optimized programs to other languages.
+
+use crate::{Instruction, config::{Config, CellWidth, IoMode}};
+
+pub mod bf;
+pub mod c;
+pub mod js;
+pub mod pseudo;
+pub mod python;
+pub mod rust;
+pub mod target;
+pub mod wasm;
+
+/// Check that the program can be translated to C, JavaScript, Python, Rust or WebAssembly.
+/// Programs that switch tapes, use `?` or the stack, big or 1-bit cells, or numeric, bit or
+/// Unicode I/O can only be interpreted, or written as Brainfuck.
+pub fn supported(prog: &[Instruction], config: &Config) -> Result<(), String> {
+    if config.cell_width == CellWidth::Big {
+        return Err("programs with big cells can only be interpreted".to_string());
+    }
+    if config.cell_width == CellWidth::Bit {
+        return Err("programs with 1-bit cells can only be interpreted".to_string());
+    }
+    if config.io == IoMode::Numeric {
+        return Err("numeric I/O is only supported by the interpreter".to_string());
+    }
+    if config.io == IoMode::Bits {
+        return Err("bit I/O is only supported by the interpreter".to_string());
+    }
+    if config.io == IoMode::Unicode {
+        return Err("Unicode I/O is only supported by the interpreter".to_string());
+    }
+    if contains(prog, &|instr| matches!(instr, Instruction::Random)) {
+        return Err("programs using `?` can only be interpreted".to_string());
+    }
+    if contains(prog, &|instr| matches!(instr, Instruction::Push | Instruction::Pop)) {
+        return Err("programs using the stack can only be interpreted".to_string());
+    }
+    switches_tapes(prog).map_or(Ok(()), |()| Err("programs with several tapes can only be interpreted".to_string()))
+}
+
+/// Whether the program contains `@`, which ends it early.
+pub(crate) fn halts(prog: &[Instruction]) -> bool {
+    prog.iter().any(|instr| match instr {
+        Instruction::Halt => true,
+        Instruction::Loop(body, _) => halts(body),
+        _ => false,
+    })
+}
+
+/// Whether the program contains an instruction for which `f` holds, in loops as well.
+fn contains(prog: &[Instruction], f: &impl Fn(&Instruction) -> bool) -> bool {
+    prog.iter().any(|instr| match instr {
+        Instruction::Loop(body, _) => contains(body, f),
+        instr => f(instr),
+    })
+}
+
+fn switches_tapes(prog: &[Instruction]) -> Option<()> {
+    prog.iter().find_map(|instr| match instr {
+        Instruction::SwitchTape(_) => Some(()),
+        Instruction::Loop(body, _) => switches_tapes(body),
+        _ => None,
+    })
+}
+
+/// A language that programs can be translated to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    C,
+    /// JavaScript for Node.js.
+    JavaScript,
+    Python,
+    Rust,
+    /// A WebAssembly module for WASI runtimes.
+    Wasm,
+    /// The optimized intermediate representation as JSON, see [`json`](crate::json).
+    /// Requires the `json` feature.
+    Json,
+    /// The optimized intermediate representation as readable pseudo-C, see [`pseudo`].
+    Pseudo,
+}
+
+impl Language {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "c" => Ok(Language::C),
+            "js" => Ok(Language::JavaScript),
+            "py" | "python" => Ok(Language::Python),
+            "rs" | "rust" => Ok(Language::Rust),
+            "wasm" | "wasi" => Ok(Language::Wasm),
+            "json" if cfg!(feature = "json") => Ok(Language::Json),
+            "json" => Err("emitting JSON requires building with the `json` feature".to_string()),
+            "pseudo" => Ok(Language::Pseudo),
+            _ => Err(format!("invalid output language `{}`, expected native, c, js, py, rs, wasm, json or pseudo", s)),
+        }
+    }
+
+    /// Extension of files in this language.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Language::C => "c",
+            Language::JavaScript => "js",
+            Language::Python => "py",
+            Language::Rust => "rs",
+            Language::Wasm => "wasm",
+            Language::Json => "json",
+            Language::Pseudo => "txt",
+        }
+    }
+
+    /// Translate the program to a standalone file in this language.
+    pub fn generate(self, prog: &[Instruction], config: &Config) -> Result<Vec<u8>, String> {
+        // The intermediate representation describes every program
+        if self == Language::Json {
+            #[cfg(feature = "json")]
+            return Ok(crate::json::to_json(prog).into_bytes());
+            #[cfg(not(feature = "json"))]
+            unreachable!("rejected by `Language::parse`");
+        }
+        if self == Language::Pseudo {
+            return Ok(pseudo::generate(prog, config).into_bytes());
+        }
+        supported(prog, config)?;
+        Ok(match self {
+            Language::C => c::generate(prog, config).into_bytes(),
+            Language::JavaScript => js::generate(prog, config).into_bytes(),
+            Language::Python => python::generate(prog, config).into_bytes(),
+            Language::Rust => rust::generate(prog, config).into_bytes(),
+            Language::Wasm => wasm::generate(prog, config)
+                .ok_or("the tape does not fit in the memory of a WebAssembly module")?,
+            Language::Json | Language::Pseudo => unreachable!(),
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! A listing of optimized programs as pseudo-C, for reading rather than compiling.
+
+use std::fmt::Write as _;
+
+use crate::{Instruction, opt, config::Config};
+
+/// Write the program as pseudo-C, with `p` pointing to the current cell:
+///
+/// ```text
+/// p += 2;
+/// *p -= 3;
+/// while (*p) {
+///     p[1] += *p * 2;
+///     *p = 0;
+/// }
+/// ```
+///
+/// Unlike the other backends this describes every program, and leaves out the checks that
+/// the data pointer stays on the tape. Constants that wrap around are written as negative
+/// numbers, and loops that move the data pointer are annotated with how far.
+pub fn generate(prog: &[Instruction], config: &Config) -> String {
+    let mut out = String::new();
+    block(prog, config, 0, &mut out);
+    out
+}
+
+fn block(prog: &[Instruction], config: &Config, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for instr in prog {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => { let _ = writeln!(out, "{}p += {};", indent, x); },
+            DecPtr(x) => { let _ = writeln!(out, "{}p -= {};", indent, x); },
+            IncVal(x) => { let _ = writeln!(out, "{}*p += {};", indent, x); },
+            DecVal(x) => { let _ = writeln!(out, "{}*p -= {};", indent, x); },
+            ClearVal => { let _ = writeln!(out, "{}*p = 0;", indent); },
+            SetVal(x) => { let _ = writeln!(out, "{}*p = {};", indent, signed(*x, config)); },
+            Write => { let _ = writeln!(out, "{}putchar(*p);", indent); },
+            WriteConst(x) => { let _ = writeln!(out, "{}putchar({});", indent, char_literal(*x)); },
+            Read => { let _ = writeln!(out, "{}*p = getchar();", indent); },
+            Halt => { let _ = writeln!(out, "{}exit(0);", indent); },
+            Random => { let _ = writeln!(out, "{}*p = random_byte();", indent); },
+            Push => { let _ = writeln!(out, "{}push(*p);", indent); },
+            Pop => { let _ = writeln!(out, "{}*p = pop();", indent); },
+            SwitchTape(x) if *x >= 0 => { let _ = writeln!(out, "{}tape += {};", indent, x); },
+            SwitchTape(x) => { let _ = writeln!(out, "{}tape -= {};", indent, x.unsigned_abs()); },
+            MulLoop(targets, step) => {
+                // The loop runs `*p / step` times, computed with the inverse as cells wrap around
+                let times = match step {
+                    1 => "*p".to_string(),
+                    _ => {
+                        let _ = writeln!(out, "{}n = *p * {}; // *p / {}", indent, opt::inverse(*step), step);
+                        "n".to_string()
+                    },
+                };
+                for (offset, factor) in targets {
+                    let (op, factor) = match signed(*factor, config) {
+                        factor if factor < 0 => ("-=", -factor),
+                        factor => ("+=", factor),
+                    };
+                    match factor {
+                        1 => { let _ = writeln!(out, "{}p[{}] {} {};", indent, offset, op, times); },
+                        _ => { let _ = writeln!(out, "{}p[{}] {} {} * {};", indent, offset, op, times, factor); },
+                    }
+                }
+                let _ = writeln!(out, "{}*p = 0;", indent);
+            },
+            Loop(body, shift) => {
+                let note = match shift {
+                    Some(0) => String::new(),
+                    Some(shift) => format!(" // p {} {} per iteration", if *shift > 0 { "+=" } else { "-=" }, shift.unsigned_abs()),
+                    None => " // moves p by a varying amount".to_string(),
+                };
+                let _ = writeln!(out, "{}while (*p) {{{}", indent, note);
+                block(body, config, depth + 1, out);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+        }
+    }
+}
+
+/// The cell value `x` as a negative number if that is shorter, such as `-1` for 255 in 8-bit cells.
+fn signed(x: u32, config: &Config) -> i64 {
+    let values = config.cell_width.mask() as i64 + 1;
+    let x = x as i64 & (values - 1);
+    if x > values / 2 { x - values } else { x }
+}
+
+/// The byte `x` as a C character literal, or as a number if it has no short literal.
+fn char_literal(x: u8) -> String {
+    match x {
+        b'\n' => "'\\n'".to_string(),
+        b'\t' => "'\\t'".to_string(),
+        b'\r' => "'\\r'".to_string(),
+        b'\0' => "'\\0'".to_string(),
+        b'\'' | b'\\' => format!("'\\{}'", x as char),
+        b' '..=b'~' => format!("'{}'", x as char),
+        x => x.to_string(),
+    }
+}
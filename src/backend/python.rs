@@ -0,0 +1,167 @@
+//! Python generation, showing what the optimized program does in readable form.
+
+use std::fmt::Write as _;
+
+use crate::{Instruction, opt, config::{Config, EofMode, EmptyLoopMode}};
+
+/// Runtime support: unbuffered input, error reporting and `@`.
+const RUNTIME: &str = r#"import sys
+
+out = sys.stdout.buffer
+inp = sys.stdin.buffer
+
+
+class BfError(Exception):
+    pass
+
+
+class Halt(Exception):
+    pass
+
+
+def get():
+    # Make sure prompts are visible before blocking on input
+    out.flush()
+    b = inp.read1(1)
+    return b[0] if b else -1
+"#;
+
+/// Python allows at most 20 nested blocks per function, so deeper loops become functions.
+const MAX_NESTING: usize = 16;
+
+/// Generate a Python 3 script equivalent to `prog` under the given settings.
+///
+/// Like the interpreter, the script checks that the data pointer stays on the tape,
+/// but it does not enforce a step limit.
+pub fn generate(prog: &[Instruction], config: &Config) -> String {
+    let mut generator = Generator { config, functions: String::new(), count: 0 };
+    let mut body = String::new();
+    generator.block(prog, 1, &mut body);
+
+    let mut out = String::new();
+    out += "#!/usr/bin/env python3\n";
+    out += RUNTIME;
+    let _ = writeln!(out, "\nTAPE_SIZE = {}", config.tape_size);
+    let _ = writeln!(out, "MASK = {}", config.cell_width.mask());
+    out += "t = [0] * TAPE_SIZE\n";
+    out += &generator.functions;
+    out += "\n\ndef run():\n";
+    out += "    p = 0\n";
+    out += &body;
+    out += "\n\ntry:\n";
+    out += "    run()\n";
+    out += "except Halt:\n";
+    out += "    pass\n";
+    out += "except BfError as e:\n";
+    out += "    out.flush()\n";
+    out += "    sys.stderr.write(\"error: %s\\n\" % e)\n";
+    out += "    sys.exit(1)\n";
+    out += "out.flush()\n";
+    out
+}
+
+struct Generator<'a> {
+    config: &'a Config,
+    /// Definitions of the functions for deeply nested loops.
+    functions: String,
+    count: usize,
+}
+
+impl Generator<'_> {
+    fn block(&mut self, prog: &[Instruction], depth: usize, out: &mut String) {
+        let indent = "    ".repeat(depth);
+        let oob = format!("{}    raise BfError(\"data pointer moved outside of the tape\")", indent);
+        let mut i = 0;
+        while i < prog.len() {
+            use Instruction::*;
+            match &prog[i] {
+                IncPtr(x) => {
+                    let _ = writeln!(out, "{}p += {}\n{}if p >= TAPE_SIZE:\n{}", indent, x, indent, oob);
+                },
+                DecPtr(x) => {
+                    let _ = writeln!(out, "{}p -= {}\n{}if p < 0:\n{}", indent, x, indent, oob);
+                },
+                IncVal(x) => { let _ = writeln!(out, "{}t[p] = (t[p] + {}) & MASK", indent, x); },
+                DecVal(x) => { let _ = writeln!(out, "{}t[p] = (t[p] - {}) & MASK", indent, x); },
+                ClearVal => { let _ = writeln!(out, "{}t[p] = 0", indent); },
+                SetVal(x) => { let _ = writeln!(out, "{}t[p] = {}", indent, x & self.config.cell_width.mask()); },
+                Write => { let _ = writeln!(out, "{}out.write(bytes((t[p] & 255,)))", indent); },
+                WriteConst(_) => {
+                    // Write runs of constant output at once
+                    let mut bytes = Vec::new();
+                    while let Some(WriteConst(x)) = prog.get(i) {
+                        bytes.push(*x);
+                        i += 1;
+                    }
+                    let _ = writeln!(out, "{}out.write({})", indent, bytes_literal(&bytes));
+                    continue;
+                },
+                Read => {
+                    let _ = writeln!(out, "{}c = get()", indent);
+                    let _ = writeln!(out, "{}if c >= 0:\n{}    t[p] = c", indent, indent);
+                    match self.config.eof {
+                        EofMode::Unchanged => {},
+                        EofMode::Zero => { let _ = writeln!(out, "{}else:\n{}    t[p] = 0", indent, indent); },
+                        EofMode::Max => { let _ = writeln!(out, "{}else:\n{}    t[p] = MASK", indent, indent); },
+                        EofMode::Error => {
+                            let _ = writeln!(out, "{}else:\n{}    raise BfError(\"unexpected end of file\")", indent, indent);
+                        },
+                    }
+                },
+                MulLoop(targets, step) => {
+                    let _ = writeln!(out, "{}# Closed form of a counting loop", indent);
+                    let _ = writeln!(out, "{}if t[p]:", indent);
+                    let _ = writeln!(out, "{}    n = t[p] * {} & MASK", indent, opt::inverse(*step));
+                    for (offset, factor) in targets {
+                        let cell = if *offset >= 0 { format!("p + {}", offset) } else { format!("p - {}", -offset) };
+                        let _ = writeln!(out, "{}    if not 0 <= {} < TAPE_SIZE:\n{}    {}", indent, cell, indent, oob);
+                        let _ = writeln!(out, "{}    t[{}] = (t[{}] + n * {}) & MASK", indent, cell, cell, factor);
+                    }
+                    let _ = writeln!(out, "{}    t[p] = 0", indent);
+                },
+                Halt => { let _ = writeln!(out, "{}raise Halt()", indent); },
+                SwitchTape(_) | Random | Push | Pop => unreachable!("rejected by `backend::supported`"),
+                Loop(body, _) if body.is_empty() => match self.config.empty_loop {
+                    EmptyLoopMode::Hang => { let _ = writeln!(out, "{}while t[p]:\n{}    pass", indent, indent); },
+                    EmptyLoopMode::Error => {
+                        let _ = writeln!(out, "{}if t[p]:\n{}    raise BfError(\"entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)\")", indent, indent);
+                    },
+                },
+                Loop(body, _) if depth >= MAX_NESTING => {
+                    self.count += 1;
+                    let name = format!("loop{}", self.count);
+                    let mut function = format!("\n\ndef {}(p):\n    while t[p]:\n", name);
+                    self.block(body, 2, &mut function);
+                    function += "    return p\n";
+                    self.functions += &function;
+                    let _ = writeln!(out, "{}p = {}(p)", indent, name);
+                },
+                Loop(body, _) => {
+                    let _ = writeln!(out, "{}while t[p]:", indent);
+                    self.block(body, depth + 1, out);
+                },
+            }
+            i += 1;
+        }
+
+        if prog.is_empty() {
+            let _ = writeln!(out, "{}pass", indent);
+        }
+    }
+}
+
+/// A Python bytes literal.
+fn bytes_literal(bytes: &[u8]) -> String {
+    let mut s = String::from("b\"");
+    for &b in bytes {
+        match b {
+            b'"' => s += "\\\"",
+            b'\\' => s += "\\\\",
+            b'\n' => s += "\\n",
+            0x20..=0x7E => s.push(b as char),
+            _ => { let _ = write!(s, "\\x{:02x}", b); },
+        }
+    }
+    s.push('"');
+    s
+}
@@ -0,0 +1,133 @@
+//! Rust generation, for embedding programs in applications with [`embed`](crate::embed).
+
+use std::fmt::Write as _;
+
+use crate::{Instruction, opt, config::{Config, CellWidth, EofMode, EmptyLoopMode}};
+
+/// Generate a standalone Rust program equivalent to `prog` under the given settings,
+/// reading from stdin and writing to stdout.
+pub fn generate(prog: &[Instruction], config: &Config) -> String {
+    let mut out = generate_fn(prog, config, "run");
+    out += "\nfn main() {\n";
+    out += "    if let Err(e) = run(&mut std::io::stdin().lock(), &mut std::io::stdout().lock()) {\n";
+    out += "        eprintln!(\"error: {}\", e);\n";
+    out += "        std::process::exit(1);\n";
+    out += "    }\n";
+    out += "}\n";
+    out
+}
+
+/// Generate a Rust function `pub fn name(input: &mut impl Read, output: &mut impl Write)
+/// -> std::io::Result<()>` equivalent to `prog` under the given settings.
+///
+/// Like the interpreter, the function fails when the data pointer leaves the tape, but it
+/// does not enforce a step limit. Output is flushed before every read and at the end.
+pub fn generate_fn(prog: &[Instruction], config: &Config, name: &str) -> String {
+    let cell = match config.cell_width {
+        CellWidth::U8 => "u8",
+        CellWidth::U16 => "u16",
+        CellWidth::U32 => "u32",
+        CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+    };
+
+    let mut out = String::new();
+    out += "#[allow(unused, clippy::all)]\n";
+    let _ = writeln!(out, "pub fn {}(input: &mut impl std::io::Read, output: &mut impl std::io::Write) -> std::io::Result<()> {{", name);
+    out += "    fn get(input: &mut impl std::io::Read) -> std::io::Result<Option<u8>> {\n";
+    out += "        let mut byte = [0];\n";
+    out += "        loop {\n";
+    out += "            match input.read(&mut byte) {\n";
+    out += "                Ok(0) => return Ok(None),\n";
+    out += "                Ok(_) => return Ok(Some(byte[0])),\n";
+    out += "                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},\n";
+    out += "                Err(e) => return Err(e),\n";
+    out += "            }\n";
+    out += "        }\n";
+    out += "    }\n";
+    out += "    fn fail(msg: &str) -> std::io::Error {\n";
+    out += "        std::io::Error::other(msg.to_string())\n";
+    out += "    }\n\n";
+    let _ = writeln!(out, "    let mut tape = vec![0{}; {}];", cell, config.tape_size);
+    out += "    let mut p: usize = 0;\n";
+    block(prog, config, 1, &mut out);
+    out += "    output.flush()\n";
+    out += "}\n";
+    out
+}
+
+fn block(prog: &[Instruction], config: &Config, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mask = config.cell_width.mask();
+    let oob = "return Err(fail(\"data pointer moved outside of the tape\"));";
+    let mut i = 0;
+    while i < prog.len() {
+        use Instruction::*;
+        match &prog[i] {
+            IncPtr(x) => {
+                let _ = writeln!(out, "{}if tape.len() - p <= {} {{ {} }}", indent, x, oob);
+                let _ = writeln!(out, "{}p += {};", indent, x);
+            },
+            DecPtr(x) => {
+                let _ = writeln!(out, "{}if p < {} {{ {} }}", indent, x, oob);
+                let _ = writeln!(out, "{}p -= {};", indent, x);
+            },
+            IncVal(x) => { let _ = writeln!(out, "{}tape[p] = tape[p].wrapping_add({});", indent, *x as u32 & mask); },
+            DecVal(x) => { let _ = writeln!(out, "{}tape[p] = tape[p].wrapping_sub({});", indent, *x as u32 & mask); },
+            ClearVal => { let _ = writeln!(out, "{}tape[p] = 0;", indent); },
+            SetVal(x) => { let _ = writeln!(out, "{}tape[p] = {};", indent, x & mask); },
+            Write => { let _ = writeln!(out, "{}output.write_all(&[tape[p] as u8])?;", indent); },
+            WriteConst(_) => {
+                // Write consecutive constants at once
+                let mut bytes = Vec::new();
+                while let Some(WriteConst(x)) = prog.get(i) {
+                    bytes.push(*x);
+                    i += 1;
+                }
+                let _ = writeln!(out, "{}output.write_all(b\"{}\")?;", indent, bytes.escape_ascii());
+                continue;
+            },
+            Read => {
+                let eof = match config.eof {
+                    EofMode::Unchanged => "{}".to_string(),
+                    EofMode::Zero => "tape[p] = 0,".to_string(),
+                    EofMode::Max => format!("tape[p] = {},", mask),
+                    EofMode::Error => "return Err(fail(\"unexpected end of file\")),".to_string(),
+                };
+                let _ = writeln!(out, "{}output.flush()?;", indent);
+                let _ = writeln!(out, "{}match get(input)? {{", indent);
+                let _ = writeln!(out, "{}    Some(byte) => tape[p] = byte.into(),", indent);
+                let _ = writeln!(out, "{}    None => {}", indent, eof);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+            MulLoop(targets, step) => {
+                let _ = writeln!(out, "{}if tape[p] != 0 {{", indent);
+                let _ = writeln!(out, "{}    let n = tape[p].wrapping_mul({});", indent, opt::inverse(*step) & mask);
+                for (offset, factor) in targets {
+                    let (check, cell) = if *offset >= 0 {
+                        (format!("tape.len() - p <= {}", offset), format!("tape[p + {}]", offset))
+                    } else {
+                        (format!("p < {}", -offset), format!("tape[p - {}]", -offset))
+                    };
+                    let _ = writeln!(out, "{}    if {} {{ {} }}", indent, check, oob);
+                    let _ = writeln!(out, "{}    {} = {}.wrapping_add(n.wrapping_mul({}));", indent, cell, cell, factor & mask);
+                }
+                let _ = writeln!(out, "{}    tape[p] = 0;", indent);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+            Halt => { let _ = writeln!(out, "{}return output.flush();", indent); },
+            SwitchTape(_) | Random | Push | Pop => unreachable!("rejected by `backend::supported`"),
+            Loop(body, _) if body.is_empty() => match config.empty_loop {
+                EmptyLoopMode::Hang => { let _ = writeln!(out, "{}if tape[p] != 0 {{ loop {{}} }}", indent); },
+                EmptyLoopMode::Error => {
+                    let _ = writeln!(out, "{}if tape[p] != 0 {{ return Err(fail(\"entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)\")); }}", indent);
+                },
+            },
+            Loop(body, _) => {
+                let _ = writeln!(out, "{}while tape[p] != 0 {{", indent);
+                block(body, config, depth + 1, out);
+                let _ = writeln!(out, "{}}}", indent);
+            },
+        }
+        i += 1;
+    }
+}
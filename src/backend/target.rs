@@ -0,0 +1,50 @@
+//! Platforms that code is generated for.
+
+/// The platform that generated code runs on, as far as code generation is concerned.
+///
+/// Generated code does not depend on the byte order of the platform.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Target triple such as `aarch64-linux-gnu`, or `None` for the host.
+    pub triple: Option<String>,
+    /// Size of a pointer in bits, which limits the size of the tape.
+    pub pointer_width: u32,
+    /// Whether the C standard library is available. Without it, the platform
+    /// has to provide functions for input, output and errors.
+    pub hosted: bool,
+}
+
+impl Target {
+    /// The platform this program runs on.
+    pub fn host() -> Self {
+        Self { triple: None, pointer_width: usize::BITS, hosted: true }
+    }
+
+    /// Parse a target triple of the form `arch-vendor-os-env`, where all but
+    /// the architecture are optional. Triples with a `none` or `elf` component,
+    /// such as `arm-none-eabi` and `riscv32-unknown-elf`, are bare-metal targets.
+    pub fn parse(triple: &str) -> Result<Self, String> {
+        let mut parts = triple.split('-');
+        let arch = parts.next().unwrap_or("");
+        let pointer_width = match arch {
+            "avr" | "msp430" => 16,
+            "i386" | "i486" | "i586" | "i686" | "x86" | "mips" | "mipsel" | "powerpc" | "sparc"
+                | "riscv32" | "wasm32" | "xtensa" | "m68k" => 32,
+            arch if arch.starts_with("arm") || arch.starts_with("thumb") => 32,
+            "x86_64" | "amd64" | "aarch64" | "arm64" | "riscv64" | "powerpc64" | "powerpc64le"
+                | "mips64" | "mips64el" | "s390x" | "sparc64" | "loongarch64" | "wasm64" => 64,
+            _ => return Err(format!("unknown architecture `{}` in target `{}`", arch, triple)),
+        };
+        let hosted = !parts.any(|part| part == "none" || part == "elf");
+        Ok(Self { triple: Some(triple.to_string()), pointer_width, hosted })
+    }
+
+    /// Check that a tape of the given number of cells fits in the address space.
+    pub fn check_tape_size(&self, tape_size: usize) -> Result<(), String> {
+        if self.pointer_width < usize::BITS && tape_size as u64 >= 1 << self.pointer_width {
+            let name = self.triple.as_deref().unwrap_or("the host");
+            return Err(format!("a tape of {} cells does not fit in the address space of {}", tape_size, name));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! WebAssembly generation, for distributing programs as portable WASI modules.
+//!
+//! The module imports `fd_read`, `fd_write` and `proc_exit` from `wasi_snapshot_preview1`
+//! and exports `_start` and `memory`, so it runs on any WASI runtime. There is no runtime
+//! built in to run it with: `bf compile --emit wasm` only writes the module, to run with
+//! an external runtime such as `wasmtime prog.wasm` or Node's `node:wasi`.
+
+use crate::{Instruction, opt, config::{Config, CellWidth, EofMode, EmptyLoopMode}};
+
+/// Scratch space for the I/O vector passed to WASI and the number of bytes transferred.
+const IOVEC: u32 = 0;
+const NBYTES: u32 = 8;
+/// The byte read by `get`.
+const INPUT: u32 = 12;
+/// Output buffer, flushed when full and before reading input.
+const OUTPUT: u32 = 16;
+const OUTPUT_SIZE: u32 = 4096;
+/// Error messages, followed by the tape.
+const MESSAGES: u32 = OUTPUT + OUTPUT_SIZE;
+
+const PAGE_SIZE: u32 = 65536;
+
+/// Error messages, as they are printed.
+const OUT_OF_BOUNDS: &str = "error: data pointer moved outside of the tape\n";
+const END_OF_FILE: &str = "error: unexpected end of file\n";
+const INFINITE_LOOP: &str = "error: entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)\n";
+/// All error messages, in the order they are stored in memory.
+const ERRORS: [&str; 3] = [OUT_OF_BOUNDS, END_OF_FILE, INFINITE_LOOP];
+
+// Indices of the imported and defined functions
+const FD_WRITE: u32 = 0;
+const FD_READ: u32 = 1;
+const PROC_EXIT: u32 = 2;
+const PUT: u32 = 3;
+const GET: u32 = 4;
+const FLUSH: u32 = 5;
+const FAIL: u32 = 6;
+const START: u32 = 7;
+
+/// The global holding the number of buffered output bytes.
+const OUTPUT_LEN: u32 = 0;
+
+// Locals of `_start`: the address of the current cell, the byte read, and the multiplier of a `MulLoop`
+const P: u32 = 0;
+const C: u32 = 1;
+const N: u32 = 2;
+
+mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0b;
+    pub const BR: u8 = 0x0c;
+    pub const BR_IF: u8 = 0x0d;
+    pub const RETURN: u8 = 0x0f;
+    pub const CALL: u8 = 0x10;
+    pub const DROP: u8 = 0x1a;
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const LOCAL_TEE: u8 = 0x22;
+    pub const GLOBAL_GET: u8 = 0x23;
+    pub const GLOBAL_SET: u8 = 0x24;
+    pub const I32_LOAD: u8 = 0x28;
+    pub const I32_LOAD8_U: u8 = 0x2d;
+    pub const I32_LOAD16_U: u8 = 0x2f;
+    pub const I32_STORE: u8 = 0x36;
+    pub const I32_STORE8: u8 = 0x3a;
+    pub const I32_STORE16: u8 = 0x3b;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I32_LT_S: u8 = 0x48;
+    pub const I32_LT_U: u8 = 0x49;
+    pub const I32_GE_U: u8 = 0x4f;
+    pub const I32_ADD: u8 = 0x6a;
+    pub const I32_SUB: u8 = 0x6b;
+    pub const I32_MUL: u8 = 0x6c;
+    /// Block type of blocks without results.
+    pub const VOID: u8 = 0x40;
+    pub const I32: u8 = 0x7f;
+}
+
+/// Instructions of a function body.
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn op(&mut self, op: u8) -> &mut Self {
+        self.0.push(op);
+        self
+    }
+
+    fn with(&mut self, op: u8, index: u32) -> &mut Self {
+        self.0.push(op);
+        unsigned(&mut self.0, index);
+        self
+    }
+
+    fn block(&mut self, op: u8) -> &mut Self {
+        self.0.extend([op, op::VOID]);
+        self
+    }
+
+    fn i32(&mut self, value: u32) -> &mut Self {
+        self.0.push(op::I32_CONST);
+        signed(&mut self.0, value as i32);
+        self
+    }
+
+    fn memory(&mut self, op: u8) -> &mut Self {
+        // Alignment hint and offset, cells are not necessarily aligned
+        self.0.extend([op, 0, 0]);
+        self
+    }
+}
+
+/// Generate a WASI module equivalent to `prog` under the given settings, or `None`
+/// if the tape does not fit in the 32-bit address space of WebAssembly.
+///
+/// Like the interpreter, the module checks that the data pointer stays on the tape,
+/// but it does not enforce a step limit.
+pub fn generate(prog: &[Instruction], config: &Config) -> Option<Vec<u8>> {
+    let width = match config.cell_width {
+        CellWidth::U8 => 1,
+        CellWidth::U16 => 2,
+        CellWidth::U32 => 4,
+        CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+    };
+    let messages = ERRORS.concat();
+    let tape = MESSAGES + messages.len() as u32;
+    let tape_end = (config.tape_size as u64).checked_mul(width as u64)
+        .and_then(|bytes| u32::try_from(tape as u64 + bytes).ok())?;
+    let pages = tape_end.div_ceil(PAGE_SIZE);
+
+    let mut generator = Generator { config, width, tape, tape_end, code: Code::default() };
+    generator.code.i32(tape).with(op::LOCAL_SET, P);
+    generator.block(prog);
+    generator.code.with(op::CALL, FLUSH).op(op::END);
+    let start = generator.code.0;
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    // Function types: fd_read and fd_write, proc_exit and put, get, flush and _start, fail
+    let mut types = vec![5];
+    types.extend([0x60, 4, op::I32, op::I32, op::I32, op::I32, 1, op::I32]);
+    types.extend([0x60, 1, op::I32, 0]);
+    types.extend([0x60, 0, 1, op::I32]);
+    types.extend([0x60, 0, 0]);
+    types.extend([0x60, 2, op::I32, op::I32, 0]);
+    section(&mut module, 1, &types);
+
+    let mut imports = vec![3];
+    for (name, ty) in [("fd_write", 0), ("fd_read", 0), ("proc_exit", 1)] {
+        string(&mut imports, "wasi_snapshot_preview1");
+        string(&mut imports, name);
+        imports.extend([0, ty]);
+    }
+    section(&mut module, 2, &imports);
+
+    // Types of put, get, flush, fail and _start
+    section(&mut module, 3, &[5, 1, 2, 3, 4, 3]);
+
+    let mut memory = vec![1, 0];
+    unsigned(&mut memory, pages);
+    section(&mut module, 5, &memory);
+
+    // The number of buffered output bytes
+    let mut globals = vec![1, op::I32, 1];
+    let mut init = Code::default();
+    init.i32(0).op(op::END);
+    globals.extend(init.0);
+    section(&mut module, 6, &globals);
+
+    let mut exports = vec![2];
+    string(&mut exports, "_start");
+    exports.push(0);
+    unsigned(&mut exports, START);
+    string(&mut exports, "memory");
+    exports.extend([2, 0]);
+    section(&mut module, 7, &exports);
+
+    let mut code = vec![5];
+    function(&mut code, &[], &put());
+    function(&mut code, &[], &get());
+    function(&mut code, &[1], &flush());
+    function(&mut code, &[], &fail());
+    function(&mut code, &[3], &start);
+    section(&mut module, 10, &code);
+
+    let mut data = vec![1, 0];
+    let mut offset = Code::default();
+    offset.i32(MESSAGES).op(op::END);
+    data.extend(offset.0);
+    string(&mut data, &messages);
+    section(&mut module, 11, &data);
+
+    Some(module)
+}
+
+struct Generator<'a> {
+    config: &'a Config,
+    /// Size of a cell in bytes.
+    width: u32,
+    /// Address of the first cell, and the end of the tape.
+    tape: u32,
+    tape_end: u32,
+    code: Code,
+}
+
+impl Generator<'_> {
+    fn block(&mut self, prog: &[Instruction]) {
+        use Instruction::*;
+        for instr in prog {
+            match instr {
+                IncPtr(x) => self.move_by(*x as isize),
+                DecPtr(x) => self.move_by(-(*x as isize)),
+                IncVal(x) => self.update(0, |code| { code.i32(*x as u32).op(op::I32_ADD); }),
+                DecVal(x) => self.update(0, |code| { code.i32(*x as u32).op(op::I32_SUB); }),
+                ClearVal => self.store(0, |code| { code.i32(0); }),
+                SetVal(x) => {
+                    let value = x & self.config.cell_width.mask();
+                    self.store(0, |code| { code.i32(value); });
+                },
+                Write => {
+                    self.load(0);
+                    self.code.with(op::CALL, PUT);
+                },
+                WriteConst(x) => { self.code.i32(*x as u32).with(op::CALL, PUT); },
+                Read => {
+                    self.code.with(op::CALL, GET).with(op::LOCAL_TEE, C).i32(0).op(op::I32_LT_S).block(op::IF);
+                    match self.config.eof {
+                        EofMode::Unchanged => {},
+                        EofMode::Zero => self.store(0, |code| { code.i32(0); }),
+                        EofMode::Max => {
+                            let max = self.config.cell_width.mask();
+                            self.store(0, |code| { code.i32(max); });
+                        },
+                        EofMode::Error => self.fail(END_OF_FILE),
+                    }
+                    self.code.op(op::ELSE);
+                    self.store(0, |code| { code.with(op::LOCAL_GET, C); });
+                    self.code.op(op::END);
+                },
+                MulLoop(targets, step) => {
+                    self.load(0);
+                    self.code.block(op::IF);
+                    self.load(0);
+                    self.code.i32(opt::inverse(*step)).op(op::I32_MUL).with(op::LOCAL_SET, N);
+                    for (offset, factor) in targets {
+                        self.check_offset(*offset);
+                        self.update(*offset, |code| {
+                            code.with(op::LOCAL_GET, N).i32(*factor).op(op::I32_MUL).op(op::I32_ADD);
+                        });
+                    }
+                    self.store(0, |code| { code.i32(0); });
+                    self.code.op(op::END);
+                },
+                Halt => { self.code.with(op::CALL, FLUSH).op(op::RETURN); },
+                SwitchTape(_) | Random | Push | Pop => unreachable!("rejected by `backend::supported`"),
+                Loop(body, _) if body.is_empty() => {
+                    self.load(0);
+                    self.code.block(op::IF);
+                    match self.config.empty_loop {
+                        EmptyLoopMode::Hang => { self.code.block(op::LOOP).with(op::BR, 0).op(op::END); },
+                        EmptyLoopMode::Error => self.fail(INFINITE_LOOP),
+                    }
+                    self.code.op(op::END);
+                },
+                Loop(body, _) => {
+                    self.code.block(op::BLOCK).block(op::LOOP);
+                    self.load(0);
+                    self.code.op(op::I32_EQZ).with(op::BR_IF, 1);
+                    self.block(body);
+                    self.code.with(op::BR, 0).op(op::END).op(op::END);
+                },
+            }
+        }
+    }
+
+    /// Move the data pointer by `offset` cells.
+    fn move_by(&mut self, offset: isize) {
+        self.check_offset(offset);
+        self.address(offset);
+        self.code.with(op::LOCAL_SET, P);
+    }
+
+    /// Fail unless the cell at `offset` from the current cell is on the tape.
+    fn check_offset(&mut self, offset: isize) {
+        let distance = offset.unsigned_abs() as u64 * self.width as u64;
+        if distance == 0 {
+            return;
+        }
+        if distance >= (self.tape_end - self.tape) as u64 {
+            // Never on the tape
+            self.fail(OUT_OF_BOUNDS);
+            return;
+        }
+
+        let distance = distance as u32;
+        self.code.with(op::LOCAL_GET, P);
+        if offset > 0 {
+            self.code.i32(self.tape_end - distance).op(op::I32_GE_U);
+        } else {
+            self.code.i32(self.tape + distance).op(op::I32_LT_U);
+        }
+        self.code.block(op::IF);
+        self.fail(OUT_OF_BOUNDS);
+        self.code.op(op::END);
+    }
+
+    /// Push the address of the cell at `offset` from the current cell.
+    fn address(&mut self, offset: isize) {
+        self.code.with(op::LOCAL_GET, P);
+        if offset != 0 {
+            self.code.i32((offset as i32).wrapping_mul(self.width as i32) as u32).op(op::I32_ADD);
+        }
+    }
+
+    fn load(&mut self, offset: isize) {
+        self.address(offset);
+        self.code.memory(match self.config.cell_width {
+            CellWidth::U8 => op::I32_LOAD8_U,
+            CellWidth::U16 => op::I32_LOAD16_U,
+            CellWidth::U32 => op::I32_LOAD,
+            CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+        });
+    }
+
+    /// Store the value pushed by `value` in the cell at `offset`, truncating it to the cell width.
+    fn store(&mut self, offset: isize, value: impl FnOnce(&mut Code)) {
+        self.address(offset);
+        value(&mut self.code);
+        self.store_op();
+    }
+
+    /// Replace the cell at `offset` by the result of `update` applied to its value.
+    fn update(&mut self, offset: isize, update: impl FnOnce(&mut Code)) {
+        self.address(offset);
+        self.load(offset);
+        update(&mut self.code);
+        self.store_op();
+    }
+
+    fn store_op(&mut self) {
+        self.code.memory(match self.config.cell_width {
+            CellWidth::U8 => op::I32_STORE8,
+            CellWidth::U16 => op::I32_STORE16,
+            CellWidth::U32 => op::I32_STORE,
+            CellWidth::Bit | CellWidth::Big => unreachable!("rejected by `backend::supported`"),
+        });
+    }
+
+    /// Print the message and exit.
+    fn fail(&mut self, message: &str) {
+        let index = ERRORS.iter().position(|m| *m == message).unwrap();
+        let offset: usize = ERRORS[..index].iter().map(|m| m.len()).sum();
+        self.code.i32(MESSAGES + offset as u32).i32(message.len() as u32).with(op::CALL, FAIL);
+    }
+}
+
+/// `put(byte)`: buffer a byte of output.
+fn put() -> Vec<u8> {
+    let mut code = Code::default();
+    code.with(op::GLOBAL_GET, OUTPUT_LEN).i32(OUTPUT_SIZE).op(op::I32_GE_U).block(op::IF)
+        .with(op::CALL, FLUSH).op(op::END);
+    code.with(op::GLOBAL_GET, OUTPUT_LEN).i32(OUTPUT).op(op::I32_ADD)
+        .with(op::LOCAL_GET, 0).memory(op::I32_STORE8);
+    code.with(op::GLOBAL_GET, OUTPUT_LEN).i32(1).op(op::I32_ADD).with(op::GLOBAL_SET, OUTPUT_LEN);
+    code.op(op::END);
+    code.0
+}
+
+/// `get() -> byte`: read a byte of input, or -1 at the end of input.
+fn get() -> Vec<u8> {
+    let mut code = Code::default();
+    // Make sure prompts are visible before blocking on input
+    code.with(op::CALL, FLUSH);
+    iovec(&mut code, |code| { code.i32(INPUT); }, |code| { code.i32(1); });
+    code.i32(0).i32(IOVEC).i32(1).i32(NBYTES).with(op::CALL, FD_READ);
+    code.block(op::IF).i32(-1i32 as u32).op(op::RETURN).op(op::END);
+    code.i32(NBYTES).memory(op::I32_LOAD).op(op::I32_EQZ).block(op::IF).i32(-1i32 as u32).op(op::RETURN).op(op::END);
+    code.i32(INPUT).memory(op::I32_LOAD8_U);
+    code.op(op::END);
+    code.0
+}
+
+/// `flush()`: write the buffered output to stdout.
+fn flush() -> Vec<u8> {
+    // The number of bytes written so far is local 0
+    let mut code = Code::default();
+    code.block(op::BLOCK).block(op::LOOP);
+    code.with(op::LOCAL_GET, 0).with(op::GLOBAL_GET, OUTPUT_LEN).op(op::I32_GE_U).with(op::BR_IF, 1);
+    iovec(
+        &mut code,
+        |code| { code.i32(OUTPUT).with(op::LOCAL_GET, 0).op(op::I32_ADD); },
+        |code| { code.with(op::GLOBAL_GET, OUTPUT_LEN).with(op::LOCAL_GET, 0).op(op::I32_SUB); },
+    );
+    code.i32(1).i32(IOVEC).i32(1).i32(NBYTES).with(op::CALL, FD_WRITE);
+    code.block(op::IF).i32(1).with(op::CALL, PROC_EXIT).op(op::END);
+    code.with(op::LOCAL_GET, 0).i32(NBYTES).memory(op::I32_LOAD).op(op::I32_ADD).with(op::LOCAL_SET, 0);
+    code.with(op::BR, 0).op(op::END).op(op::END);
+    code.i32(0).with(op::GLOBAL_SET, OUTPUT_LEN);
+    code.op(op::END);
+    code.0
+}
+
+/// `fail(message, len)`: flush the output, print the message to stderr and exit.
+fn fail() -> Vec<u8> {
+    let mut code = Code::default();
+    code.with(op::CALL, FLUSH);
+    iovec(&mut code, |code| { code.with(op::LOCAL_GET, 0); }, |code| { code.with(op::LOCAL_GET, 1); });
+    code.i32(2).i32(IOVEC).i32(1).i32(NBYTES).with(op::CALL, FD_WRITE).op(op::DROP);
+    code.i32(1).with(op::CALL, PROC_EXIT).op(op::UNREACHABLE);
+    code.op(op::END);
+    code.0
+}
+
+/// Fill in the I/O vector with the given buffer and length.
+fn iovec(code: &mut Code, buf: impl FnOnce(&mut Code), len: impl FnOnce(&mut Code)) {
+    code.i32(IOVEC);
+    buf(code);
+    code.memory(op::I32_STORE);
+    code.i32(IOVEC + 4);
+    len(code);
+    code.memory(op::I32_STORE);
+}
+
+/// Append a function body with the given number of `i32` locals.
+fn function(out: &mut Vec<u8>, locals: &[u32], code: &[u8]) {
+    let mut body = Vec::new();
+    unsigned(&mut body, locals.len() as u32);
+    for &count in locals {
+        unsigned(&mut body, count);
+        body.push(op::I32);
+    }
+    body.extend(code);
+    unsigned(out, body.len() as u32);
+    out.extend(body);
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    unsigned(out, contents.len() as u32);
+    out.extend(contents);
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    unsigned(out, s.len() as u32);
+    out.extend(s.as_bytes());
+}
+
+/// Append `value` in unsigned LEB128.
+fn unsigned(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Append `value` in signed LEB128.
+fn signed(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,243 @@
+//! Arbitrary-precision cells, which never wrap.
+//!
+//! Only the passes that do not depend on wrapping arithmetic are run on programs
+//! with these cells, see [`PassManager::configure`](crate::opt::PassManager::configure).
+
+use std::{cmp::Ordering, fmt};
+
+use crate::cell::Cell;
+
+/// A signed integer of any size.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant limb first, without trailing zeroes.
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    /// The lowest byte of the two's complement representation, which is what `.` writes.
+    pub fn low_byte(&self) -> u8 {
+        let low = self.limbs.first().map_or(0, |&limb| limb as u8);
+        if self.negative { low.wrapping_neg() } else { low }
+    }
+
+    /// Add `other` to this number.
+    pub fn add(&mut self, other: &BigInt) {
+        if self.negative == other.negative {
+            add_magnitude(&mut self.limbs, &other.limbs);
+            return;
+        }
+        match compare_magnitude(&self.limbs, &other.limbs) {
+            Ordering::Less => {
+                let mut limbs = other.limbs.clone();
+                sub_magnitude(&mut limbs, &self.limbs);
+                *self = BigInt { negative: other.negative, limbs };
+            },
+            _ => {
+                sub_magnitude(&mut self.limbs, &other.limbs);
+                self.normalize();
+            },
+        }
+    }
+
+    /// This number multiplied by a small factor.
+    pub fn mul(&self, factor: i64) -> BigInt {
+        let mut limbs = Vec::with_capacity(self.limbs.len() + 2);
+        let mut carry = 0u128;
+        for &limb in &self.limbs {
+            carry += limb as u128 * factor.unsigned_abs() as u128;
+            limbs.push(carry as u32);
+            carry >>= 32;
+        }
+        while carry > 0 {
+            limbs.push(carry as u32);
+            carry >>= 32;
+        }
+        let mut product = BigInt { negative: self.negative != (factor < 0), limbs };
+        product.normalize();
+        product
+    }
+
+    /// This number divided by a small divisor, if it is divisible by it.
+    pub fn div_exact(&self, divisor: i64) -> Option<BigInt> {
+        let d = divisor.unsigned_abs() as u128;
+        let mut limbs = vec![0; self.limbs.len()];
+        let mut rem = 0u128;
+        for (i, &limb) in self.limbs.iter().enumerate().rev() {
+            let n = (rem << 32) | limb as u128;
+            limbs[i] = (n / d) as u32;
+            rem = n % d;
+        }
+        if rem != 0 {
+            return None;
+        }
+        let mut quotient = BigInt { negative: self.negative != (divisor < 0), limbs };
+        quotient.normalize();
+        Some(quotient)
+    }
+
+    /// Parse a decimal number with an optional sign, as read by [`read_number`](crate::eval::read_number).
+    pub fn parse(number: &str) -> BigInt {
+        let (negative, digits) = match number.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, number.trim_start_matches('+')),
+        };
+        let mut n = BigInt::default();
+        for digit in digits.bytes() {
+            n = n.mul(10);
+            n.add(&BigInt::from((digit - b'0') as i64));
+        }
+        n.negative = negative && !n.is_zero();
+        n
+    }
+
+    fn normalize(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        if self.limbs.is_empty() {
+            self.negative = false;
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(x: i64) -> Self {
+        let mut n = BigInt { negative: x < 0, limbs: vec![x.unsigned_abs() as u32, (x.unsigned_abs() >> 32) as u32] };
+        n.normalize();
+        n
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Repeatedly divide by the largest power of ten that fits in a limb
+        const CHUNK: u64 = 1_000_000_000;
+        let mut limbs = self.limbs.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut rem = 0u64;
+            for limb in limbs.iter_mut().rev() {
+                let n = (rem << 32) | *limb as u64;
+                *limb = (n / CHUNK) as u32;
+                rem = n % CHUNK;
+            }
+            chunks.push(rem);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+
+        let mut digits = String::new();
+        match chunks.split_last() {
+            Some((first, rest)) => {
+                digits += &first.to_string();
+                for chunk in rest.iter().rev() {
+                    digits += &format!("{:09}", chunk);
+                }
+            },
+            None => digits += "0",
+        }
+        f.pad_integral(!self.negative, "", &digits)
+    }
+}
+
+fn compare_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &mut Vec<u32>, b: &[u32]) {
+    if a.len() < b.len() {
+        a.resize(b.len(), 0);
+    }
+    let mut carry = 0u64;
+    for (i, limb) in a.iter_mut().enumerate() {
+        carry += *limb as u64 + b.get(i).copied().unwrap_or(0) as u64;
+        *limb = carry as u32;
+        carry >>= 32;
+        if carry == 0 && i >= b.len() {
+            break;
+        }
+    }
+    if carry > 0 {
+        a.push(carry as u32);
+    }
+}
+
+/// Subtract `b` from `a`, where `a` is at least `b`.
+fn sub_magnitude(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0i64;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let d = *limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        *limb = d.rem_euclid(1 << 32) as u32;
+        borrow = (d < 0) as i64;
+        if borrow == 0 && i >= b.len() {
+            break;
+        }
+    }
+}
+
+impl Cell for BigInt {
+    fn is_zero(&self) -> bool {
+        BigInt::is_zero(self)
+    }
+
+    fn wrapping_add(mut self, x: u32) -> Self {
+        self.add(&BigInt::from(x as i64));
+        self
+    }
+
+    fn wrapping_sub(mut self, x: u32) -> Self {
+        self.add(&BigInt::from(-(x as i64)));
+        self
+    }
+
+    // Constants are folded with wrapping arithmetic, so large values are negative
+    fn from_constant(x: u32) -> Self {
+        BigInt::from(x as i32 as i64)
+    }
+
+    fn max() -> Self {
+        BigInt::from(-1)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        BigInt::from(byte as i64)
+    }
+
+    fn to_byte(&self) -> u8 {
+        self.low_byte()
+    }
+
+    fn to_u32(&self) -> u32 {
+        let low = self.limbs.first().copied().unwrap_or(0);
+        if self.negative { low.wrapping_neg() } else { low }
+    }
+
+    fn parse(number: &str) -> Self {
+        BigInt::parse(number)
+    }
+
+    fn signed(&self) -> impl fmt::Display {
+        self
+    }
+
+    fn iterations(&self, step: u32) -> Option<Self> {
+        // The counter only reaches zero if it is a positive multiple of the step
+        self.div_exact(step as i32 as i64).filter(|n| !n.is_negative())
+    }
+
+    fn add_product(mut self, iterations: &Self, factor: u32) -> Self {
+        self.add(&iterations.mul(factor as i32 as i64));
+        self
+    }
+}
@@ -0,0 +1,105 @@
+use std::{env, fs, process};
+
+use bf::{Instruction, config::Config, lexer::Lexer, backend::{self, Language, target::Target}};
+
+use crate::cli::Options;
+
+/// Compile the program to a native executable by generating C and running the system C compiler,
+/// or translate it to the requested language.
+pub fn build(prog: &[Instruction], config: &Config, opts: &Options) -> Result<(), String> {
+    let target = opts.target.clone().unwrap_or_else(Target::host);
+    if opts.target.is_some() && opts.emit.is_some_and(|lang| lang != Language::C) {
+        return Err("--target only applies to C and native executables".to_string());
+    }
+    // The listing is for reading, so it is printed unless it is written to a file
+    if opts.emit == Some(Language::Pseudo) && opts.output.is_none() {
+        print!("{}", backend::pseudo::generate(prog, config));
+        return Ok(());
+    }
+    target.check_tape_size(config.tape_size)?;
+    if !matches!(opts.emit, Some(Language::Json | Language::Pseudo)) {
+        backend::supported(prog, config)?;
+    }
+
+    // Bare-metal targets cannot link an executable without the platform's own code
+    let extension = match opts.emit {
+        Some(lang) => lang.extension(),
+        None if !target.hosted => "o",
+        None => "",
+    };
+    let output = match &opts.output {
+        Some(output) => output.clone(),
+        None => opts.path.with_extension(extension),
+    };
+    if output == opts.path {
+        return Err(format!("refusing to overwrite the program `{}`, use `-o` to name the output", output.display()));
+    }
+
+    if let Some(lang) = opts.emit {
+        let code = match lang {
+            Language::C => backend::c::generate_for(prog, config, &target).into_bytes(),
+            lang => lang.generate(prog, config)?,
+        };
+        return fs::write(&output, code)
+            .map_err(|e| format!("could not write `{}`: {}", output.display(), e));
+    }
+
+    let c = env::temp_dir().join(format!("bf-{}.c", process::id()));
+    fs::write(&c, backend::c::generate_for(prog, config, &target))
+        .map_err(|e| format!("could not write `{}`: {}", c.display(), e))?;
+
+    let mut cc = compiler(opts);
+    if !target.hosted {
+        cc.arg("-ffreestanding").arg("-c");
+    } else if opts.static_link {
+        cc.arg("-static");
+    }
+    cc.arg("-o").arg(&output).arg(&c);
+
+    let result = run(cc);
+    let _ = fs::remove_file(&c);
+    result
+}
+
+/// Write the optimized program as Brainfuck, to the output file or stdout.
+///
+/// With `--golf`, the program is written as short as possible, and the number of commands
+/// saved compared to `src` is printed to stderr.
+pub fn optimize(prog: &[Instruction], src: &str, config: &Config, opts: &Options) -> Result<(), String> {
+    let code = if opts.golf { backend::bf::golf(prog, config) } else { backend::bf::generate(prog, config) }
+        .ok_or("the optimized program outputs constants, which cannot be written as Brainfuck; disable the constprop pass")?;
+    if opts.golf {
+        let before = Lexer::with_config(src, config).filter(|(token, _)| token.command().is_some()).count();
+        let after = code.trim_end().chars().count();
+        eprintln!("{} commands, down from {} ({} bytes saved)", after, before, before.saturating_sub(after));
+    }
+    match &opts.output {
+        Some(output) => fs::write(output, code).map_err(|e| format!("could not write `{}`: {}", output.display(), e)),
+        None => {
+            print!("{}", code);
+            Ok(())
+        },
+    }
+}
+
+/// The C compiler in `$CC`, with the default and user-provided flags.
+/// Without `$CC`, this is `cc`, or the GCC cross compiler for the target.
+pub fn compiler(opts: &Options) -> process::Command {
+    let default = match opts.target.as_ref().and_then(|target| target.triple.as_ref()) {
+        Some(triple) => format!("{}-gcc", triple),
+        None => "cc".to_string(),
+    };
+    let mut cc = process::Command::new(env::var("CC").unwrap_or(default));
+    cc.arg("-O2").args(&opts.cc_flags);
+    cc
+}
+
+/// Run the C compiler, failing if it does not succeed.
+pub fn run(mut cc: process::Command) -> Result<(), String> {
+    let name = cc.get_program().to_string_lossy().into_owned();
+    match cc.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("`{}` failed with {}", name, status)),
+        Err(e) => Err(format!("could not run the C compiler `{}`: {}", name, e)),
+    }
+}
@@ -0,0 +1,203 @@
+//! Values of tape cells, whose type determines the cell width.
+
+use core::{fmt, hash::Hash};
+
+use alloc::boxed::Box;
+
+use crate::{opt, eval, tape::Tape};
+
+/// The value of a single cell, on which [`Context`](crate::eval::Context) is generic.
+///
+/// Constants in the intermediate representation are folded modulo 2^32, so cells
+/// narrower than that take them modulo their own width.
+pub trait Cell: Clone + Default + PartialEq + Hash + fmt::Display + Send + Sync + 'static {
+    fn is_zero(&self) -> bool;
+
+    fn wrapping_add(self, x: u32) -> Self;
+
+    fn wrapping_sub(self, x: u32) -> Self;
+
+    /// The constant `x`, as set by [`Instruction::SetVal`](crate::Instruction::SetVal).
+    fn from_constant(x: u32) -> Self;
+
+    /// The value all bits set, which `,` stores at the end of input with `eof = "max"`.
+    fn max() -> Self;
+
+    fn from_byte(byte: u8) -> Self;
+
+    /// The lowest byte, which is what `.` writes.
+    fn to_byte(&self) -> u8;
+
+    /// The lowest 32 bits, as stored in checkpoints and recordings.
+    fn to_u32(&self) -> u32;
+
+    /// The number read by [`eval::read_number`] in numeric I/O mode.
+    fn parse(number: &str) -> Self;
+
+    /// The value as a two's complement signed number, for numeric output with `--signed`.
+    fn signed(&self) -> impl fmt::Display;
+
+    /// Number of iterations of a multiply loop that subtracts the odd `step` from this
+    /// counter every iteration, or `None` if the counter never reaches zero.
+    fn iterations(&self, step: u32) -> Option<Self>;
+
+    /// The cell after adding `factor` for every one of the given `iterations`.
+    fn add_product(self, iterations: &Self, factor: u32) -> Self;
+
+    /// A tape of `len` cells in an anonymous memory mapping, if this cell supports it.
+    fn mapped(_len: usize) -> Option<Box<dyn Tape<Self>>> {
+        None
+    }
+}
+
+// Interpreters are instantiated in other crates, which should inline the arithmetic
+macro_rules! impl_cell {
+    ($($t:ty, $signed:ty;)*) => {$(
+        impl Cell for $t {
+            #[inline]
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            #[inline]
+            fn wrapping_add(self, x: u32) -> Self {
+                <$t>::wrapping_add(self, x as $t)
+            }
+
+            #[inline]
+            fn wrapping_sub(self, x: u32) -> Self {
+                <$t>::wrapping_sub(self, x as $t)
+            }
+
+            #[inline]
+            fn from_constant(x: u32) -> Self {
+                x as $t
+            }
+
+            #[inline]
+            fn max() -> Self {
+                <$t>::MAX
+            }
+
+            #[inline]
+            fn from_byte(byte: u8) -> Self {
+                byte as $t
+            }
+
+            #[inline]
+            fn to_byte(&self) -> u8 {
+                *self as u8
+            }
+
+            #[inline]
+            fn to_u32(&self) -> u32 {
+                *self as u32
+            }
+
+            #[inline]
+            fn parse(number: &str) -> Self {
+                eval::wrap_number(number) as $t
+            }
+
+            #[inline]
+            fn signed(&self) -> impl fmt::Display {
+                *self as $signed
+            }
+
+            #[inline]
+            fn iterations(&self, step: u32) -> Option<Self> {
+                Some(<$t>::wrapping_mul(*self, opt::inverse(step) as $t))
+            }
+
+            #[inline]
+            fn add_product(self, iterations: &Self, factor: u32) -> Self {
+                <$t>::wrapping_add(self, <$t>::wrapping_mul(*iterations, factor as $t))
+            }
+
+            #[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos")))]
+            fn mapped(len: usize) -> Option<Box<dyn Tape<Self>>> {
+                // SAFETY: zero is a valid integer.
+                let mapping = unsafe { crate::mmap::Mapping::<$t>::new(len) };
+                mapping.ok().map(|mapping| Box::new(crate::tape::Mapped::new(mapping)) as Box<dyn Tape<Self>>)
+            }
+        }
+    )*};
+}
+
+impl_cell! {
+    u8, i8;
+    u16, i16;
+    u32, i32;
+}
+
+/// A cell of a single bit, as in Boolfuck, where every change by an odd number flips it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bit(pub bool);
+
+impl fmt::Display for Bit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0 as u8)
+    }
+}
+
+impl Cell for Bit {
+    #[inline]
+    fn is_zero(&self) -> bool {
+        !self.0
+    }
+
+    #[inline]
+    fn wrapping_add(self, x: u32) -> Self {
+        Bit(self.0 ^ (x & 1 == 1))
+    }
+
+    #[inline]
+    fn wrapping_sub(self, x: u32) -> Self {
+        self.wrapping_add(x)
+    }
+
+    #[inline]
+    fn from_constant(x: u32) -> Self {
+        Bit(x & 1 == 1)
+    }
+
+    #[inline]
+    fn max() -> Self {
+        Bit(true)
+    }
+
+    #[inline]
+    fn from_byte(byte: u8) -> Self {
+        Bit(byte & 1 == 1)
+    }
+
+    #[inline]
+    fn to_byte(&self) -> u8 {
+        self.0 as u8
+    }
+
+    #[inline]
+    fn to_u32(&self) -> u32 {
+        self.0 as u32
+    }
+
+    #[inline]
+    fn parse(number: &str) -> Self {
+        Bit::from_constant(eval::wrap_number(number))
+    }
+
+    fn signed(&self) -> impl fmt::Display {
+        -(self.0 as i8)
+    }
+
+    #[inline]
+    fn iterations(&self, _step: u32) -> Option<Self> {
+        // The step is odd, so one iteration clears a set bit
+        Some(*self)
+    }
+
+    #[inline]
+    fn add_product(self, iterations: &Self, factor: u32) -> Self {
+        if iterations.0 { self.wrapping_add(factor) } else { self }
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fmt, hash::{DefaultHasher, Hash, Hasher}, io::{self, BufRead, Write}};
+
+use crate::Instruction;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The checkpoint file is not in the expected format.
+    Malformed(String),
+    /// The checkpoint was taken while running a different program, or with different settings.
+    ProgramMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Io(e) => write!(f, "{}", e),
+            Malformed(msg) => write!(f, "invalid checkpoint: {}", msg),
+            ProgramMismatch => write!(f, "the checkpoint was taken from a different program, or with different settings"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// The state of an interpreter in the middle of running a program, from which it can continue.
+#[derive(Clone, Debug, Default)]
+pub struct Checkpoint {
+    /// Hash of the optimized program, see [`program_hash`].
+    pub program: u64,
+    pub steps: u64,
+    /// Number of input bytes consumed so far.
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub max_ptr: usize,
+    /// Position of the next instruction: its index in the program, and in the bodies
+    /// of the loops it is nested in, outermost first.
+    pub pc: Vec<usize>,
+    /// The current tape followed by the other tapes in the order of switching to them,
+    /// with their data pointers. Trailing zero cells are left out.
+    pub tapes: Vec<(Vec<u32>, usize)>,
+    /// State of the random bytes of `?`, see [`Rng::state`](crate::random::Rng::state).
+    /// Older checkpoints do not have it.
+    pub random: Option<u64>,
+    /// The stack of the stack dialect, bottom first.
+    pub stack: Vec<u32>,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint in a plain text format:
+    ///
+    /// ```text
+    /// # bf checkpoint v1
+    /// program 5d1c0e3a8f7b2c46
+    /// steps 2000000000
+    /// input 12
+    /// output 40
+    /// max_ptr 7
+    /// pc 4 2
+    /// random 9e3779b97f4a7c15
+    /// stack 10 32
+    /// tape ptr 3
+    /// 0 1 72 104
+    /// ```
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "# bf checkpoint v1")?;
+        writeln!(w, "program {:016x}", self.program)?;
+        writeln!(w, "steps {}", self.steps)?;
+        writeln!(w, "input {}", self.input_bytes)?;
+        writeln!(w, "output {}", self.output_bytes)?;
+        writeln!(w, "max_ptr {}", self.max_ptr)?;
+        let pc: Vec<String> = self.pc.iter().map(usize::to_string).collect();
+        writeln!(w, "pc {}", pc.join(" "))?;
+        if let Some(random) = self.random {
+            writeln!(w, "random {:016x}", random)?;
+        }
+        if !self.stack.is_empty() {
+            let stack: Vec<String> = self.stack.iter().map(u32::to_string).collect();
+            writeln!(w, "stack {}", stack.join(" "))?;
+        }
+        for (cells, ptr) in &self.tapes {
+            writeln!(w, "tape ptr {}", ptr)?;
+            let cells: Vec<String> = cells.iter().map(u32::to_string).collect();
+            writeln!(w, "{}", cells.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Read a checkpoint written by [`Checkpoint::write`].
+    pub fn read(r: impl BufRead) -> Result<Self, Error> {
+        let mut lines = r.lines();
+        if lines.next().transpose()?.as_deref() != Some("# bf checkpoint v1") {
+            return Err(Error::Malformed("missing `# bf checkpoint v1` header".to_string()));
+        }
+
+        let mut checkpoint = Checkpoint::default();
+        while let Some(line) = lines.next().transpose()? {
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "program" => checkpoint.program = u64::from_str_radix(value, 16)
+                    .map_err(|_| Error::Malformed(format!("invalid program hash `{}`", value)))?,
+                "steps" => checkpoint.steps = parse(key, value)?,
+                "input" => checkpoint.input_bytes = parse(key, value)?,
+                "output" => checkpoint.output_bytes = parse(key, value)?,
+                "max_ptr" => checkpoint.max_ptr = parse(key, value)?,
+                "random" => checkpoint.random = Some(u64::from_str_radix(value, 16)
+                    .map_err(|_| Error::Malformed(format!("invalid random state `{}`", value)))?),
+                "stack" => checkpoint.stack = value.split_whitespace().map(|c| parse("cell", c)).collect::<Result<_, _>>()?,
+                "pc" => checkpoint.pc = value.split_whitespace().map(|i| parse(key, i)).collect::<Result<_, _>>()?,
+                "tape" => {
+                    let ptr = parse(key, value.trim_start_matches("ptr "))?;
+                    let cells = lines.next().transpose()?.unwrap_or_default();
+                    let cells = cells.split_whitespace().map(|c| parse("cell", c)).collect::<Result<_, _>>()?;
+                    checkpoint.tapes.push((cells, ptr));
+                },
+                _ => return Err(Error::Malformed(format!("unknown line `{}`", line))),
+            }
+        }
+
+        if checkpoint.pc.is_empty() || checkpoint.tapes.is_empty() {
+            return Err(Error::Malformed("missing program counter or tape".to_string()));
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value.parse().map_err(|_| Error::Malformed(format!("invalid {} `{}`", key, value)))
+}
+
+/// Hash identifying an optimized program and the cell width given by its mask,
+/// to check that a checkpoint belongs to it. This is only stable between runs of the same build.
+pub fn program_hash(prog: &[Instruction], mask: u32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    prog.hash(&mut hasher);
+    mask.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,770 @@
+use std::{ffi::{OsStr, OsString}, path::{Path, PathBuf}, time::Duration};
+
+use bf::{heatmap, output, diagnostic, symbolic::Goal, backend::{Language, target::Target}};
+
+use crate::completions::Shell;
+
+const USAGE: &str = "\
+Usage: bf [OPTIONS] <COMMAND> [ARGS]
+       bf [OPTIONS] <FILE>...
+
+Run a command on Brainfuck programs. Without a command, run the programs
+in the given files, the same as `bf run`. The options apply to every command
+and may be given before or after it. The program in a Markdown file ending
+in .md is the code in its fenced `brainfuck` or `bf` blocks.
+
+Commands:
+  run <FILE>...           Interpret the program in FILE, reading input from stdin and
+                          writing output to stdout. Given several files, or patterns with
+                          * and ? in the file name, run the programs one after another
+  compile <FILE>          Compile the program in FILE to a native executable using the
+                          C compiler in $CC [default: cc], or to another language;
+                          also available as `build`. This only builds the program:
+                          a WASI module runs on an external WASI runtime, such as
+                          `wasmtime FILE.wasm`
+  optimize <FILE>         Optimize the program in FILE and write it as Brainfuck again,
+                          to stdout or to the file given to --output
+  fmt <FILE>              Indent the program in FILE by loop depth, keeping its comments,
+                          and write it to stdout or to the file given to --output
+  minify <FILE>           Write the program in FILE with only its commands, on one line,
+                          to stdout or to the file given to --output
+  check <FILE>...         Report errors and warnings in the programs without running them
+  debug <FILE>            Step through the unoptimized program in FILE, with commands read
+                          from stdin; type `help` for a list of commands
+  repl                    Run snippets of Brainfuck read from stdin on the same tape,
+                          showing the tape after each
+  stats <FILE>            Count the instructions of the program in FILE before and after
+                          optimizing it
+  test <DIR>              Run every *.b, *.bf or *.md program in DIR that has a sibling
+                          .out file, with input from its sibling .in file, and compare
+                          its output
+  serve [ADDR]            Run programs sent to POST /run over HTTP on ADDR, as a form with
+                          the program and its input, and respond with their output as
+                          JSON [default: 127.0.0.1:8080, max steps: 100000000]
+  minimize <FILE>         Remove as much of the program in FILE as possible while the
+                          shell command given to --check still succeeds on it, and write
+                          the rest to stdout or to the file given to --output
+  explore <FILE>          Run the program on unknown input, following every path the input
+                          can take it along, to find input for which it writes the byte
+                          given to --writes, or reaches the instruction given to --reaches
+  diff-run <FILE> <FILE>  Run both programs on the same input, read from stdin or given by
+                          --input-bytes, and report where their outputs first differ
+  trace-dump <FILE>       Print the binary trace in FILE, written by --trace-file, as CSV
+                          to stdout or to the file given to --output
+  completions <SHELL>     Print a completion script for SHELL: bash, zsh or fish
+  help                    Print this help message and exit
+
+Options:
+      --tape-size <N>     Number of cells on the tape [default: 30000]
+      --tape-growth <STRATEGY>
+                          How the interpreter allocates the tape: fixed to allocate it
+                          up front, double, chunks[:N] or exact to grow it as it is
+                          used, N cells at a time, mmap to map it lazily in virtual
+                          memory, for huge tapes, or sparse to store only nonzero
+                          cells [default: fixed, N: 4096]
+      --initial-tape-size <N>
+                          Number of cells allocated initially when the tape grows
+                          [default: 1024]
+      --cell-width <N>    Bits per cell: 1, 8, 16 or 32, or big for cells that never
+                          wrap when built with the bignum feature [default: 8]
+      --eof <MODE>        Effect of `,` at end of input: unchanged, zero, max or error
+                          [default: unchanged]
+      --io <MODE>         How `.` and `,` represent cells: bytes, numeric for decimal
+                          numbers on their own line, bits for the lowest bit, eight
+                          to a byte, or unicode for characters in UTF-8, with cells
+                          of at least 16 bits [default: bytes]
+      --signed            Treat cells as signed numbers in numeric I/O
+      --flush <POLICY>    When to flush output: every-write, on-read for before every
+                          `,` and after every line, or on-exit to buffer all output
+                          [default: on-read]
+  -O, --opt-level <N>     Optimization level: 0 or 1 [default: 1]
+      --dialect <NAME>    Language dialect: standard, multitape for several tapes
+                          that `}` and `{` switch between, smbf for programs
+                          stored on the tape that can change themselves, which can
+                          only be run, boolfuck, which also selects 1-bit cells
+                          and bit I/O, or stack for a stack that `^` pushes the cell
+                          onto and `_` pops it from [default: standard]
+      --tapes <N>         Number of tapes in the multitape dialect [default: 2]
+      --halt-command      Accept `@` as a command that ends the program at once
+      --random-command    Accept `?` as a command that sets the cell to a random byte
+      --seed <N>          Seed of the random bytes of `?`, to repeat a run
+      --macros            Expand macros, defined by `{name: code}` and used by `{name}`,
+                          before parsing the program
+      --max-steps <N>     Abort after executing N instructions, 0 for no limit
+                          [default: 0, or 1000000000 for tests]
+      --empty-loop <MODE> Effect of entering `[]` with a nonzero cell: hang or error
+                          [default: hang]
+      --detect-loops      Stop the program when it gets in a state it was in before, as
+                          it would then repeat itself forever without reading input
+      --passes <LIST>     Comma-separated optimization passes to run, in order
+                          [default: cancel,canonicalize,clearloop,deadloop,mulloop,
+                          constprop,ranges,licm,dce]
+      --disable-pass <LIST>
+                          Comma-separated optimization passes not to run
+      --no-opt            Disable all optimizations, the same as -O0
+      --config <FILE>     Read settings from FILE instead of the default bf.toml files
+      --watch             Run the program again whenever its file changes, after the
+                          previous run finishes
+      --map-source        Map program files into memory rather than copying them, for
+                          very large generated programs; they must not change meanwhile
+      --raw               Read input unbuffered when stdin is a terminal
+      --input <SOURCE>    Where the program reads input from: stdin, or null for no input
+                          at all, such that every `,` reaches the end of input
+                          [default: stdin]
+      --prompt <TEXT>     Print TEXT to stderr whenever the program waits for input from
+                          a terminal, such as `? `
+      --listen <ADDR>     Wait for a TCP connection on ADDR, such as 127.0.0.1:8000, and
+                          read input from and write output to it instead of stdio
+      --connect <ADDR>    Connect to ADDR over TCP, and read input from and write output
+                          to the connection instead of stdio
+      --input-bytes <TEXT>
+                          Give TEXT to the program as its input instead of stdin
+      --input-bytes-hex <HEX>
+                          Give the bytes written as pairs of hex digits in HEX to the
+                          program as its input instead of stdin, e.g. 48690a
+      --output-format <FORMAT>
+                          How to show output: raw, hex for pairs of hex digits, or
+                          escaped to escape control characters and non-ASCII bytes,
+                          to inspect binary output safely [default: raw]
+      --encoding <NAME>   Show output bytes as the characters they stand for in another
+                          character set: cp437, latin1, or a file with 256 hexadecimal
+                          code points, one for every byte
+      --tee <FILE>        Also write the output of the program to FILE
+      --delay <MS>        Pause for MS milliseconds after every byte of output, to watch
+                          the output appear gradually
+      --step-delay <MS>   Pause for MS milliseconds after every executed instruction
+      --break-on-output <BYTE>
+                          Stop when the program writes BYTE, given as a single character,
+                          or as a number such as 10 or 0x0a, and show how far it got;
+                          the debugger pauses there instead
+      --heatmap <FORMAT>  Print cell access counts to stderr at exit: term or csv
+      --profile           Sample the running instruction every so often, and print the
+                          share of samples taken in every loop to stderr at exit
+      --profile-every <N> Number of steps between samples [default: 10000]
+      --flamegraph <FILE> Write the samples taken as for --profile to FILE as folded stacks,
+                          with the running loops as the stack, to draw as a flamegraph
+      --record <FILE>     Record tape snapshots to FILE, as an animated GIF if FILE
+                          ends in .gif, and as a plain text frame dump otherwise
+      --record-every <N>  Number of steps between snapshots [default: 1000]
+      --trace-file <FILE> Write the instruction, data pointer and cell after every step
+                          to FILE, as CSV if FILE ends in .csv, and in a compact binary
+                          format for `bf trace-dump` otherwise
+      --taint             Track which input bytes every output byte depends on, through
+                          the cells and the loops deciding what is written, and print
+                          this to stderr at exit
+      --checkpoint <FILE> Write the state of the interpreter to FILE every so often, to
+                          continue from with --resume if the run is interrupted
+      --checkpoint-every <N>
+                          Number of steps between checkpoints [default: 1000000000]
+      --resume <FILE>     Continue from the checkpoint in FILE, taken while running the
+                          same program with the same settings and input
+      --diagnostics <FORMAT>
+                          How to print parse errors and warnings: human, or json for
+                          one JSON object per line [default: human]
+      --report <FORMAT>   Print a summary of the run to stderr at exit: json
+      --opt-stats         Print the effect of every optimization pass to stderr
+      --partial-input <FILE>
+                          Specialize the program for input starting with the contents
+                          of FILE; the program then reads the rest of its input
+      --aot               Compile the program to a shared library with the C compiler,
+                          cached for later runs, and run it in-process
+      --check <CMD>       Command that decides whether a smaller program still reproduces
+                          the problem for `bf minimize`, run by sh with the path of the
+                          program as $1, e.g. 'bf \"$1\" | grep -q X'
+      --shorten           Also shorten the program with `bf minify`, by removing loops
+                          that never run and combining runs of opposite commands
+      --golf              Make the program written by `bf optimize` as short as possible,
+                          rather than as fast, and print the bytes saved to stderr
+      --writes <BYTE>     Look for input for which the program writes BYTE, given as a
+                          single character or as a number such as 0x00, with `bf explore`
+      --reaches <N>       Look for input for which the program reaches the instruction
+                          numbered N in the optimized program, as in --trace-file,
+                          with `bf explore`
+      --input-length <N>  Number of input bytes before the end of input for `bf explore`
+                          [default: 16]
+      --max-paths <N>     Number of paths through the program `bf explore` follows before
+                          giving up [default: 100000]
+  -o, --output <FILE>     Executable to build [default: FILE without its extension,
+                          or with the extension of the language given to --emit,
+                          or with .o for bare-metal targets], or file to write the
+                          optimized, formatted, minified or minimized program to
+      --emit <LANG>       What to build: a native executable, or a standalone program
+                          in another language: native, c, js, py, rs, wasm for a
+                          WASI module to run with an external WASI runtime, such as
+                          wasmtime, json for the optimized program itself when
+                          built with the json feature, or pseudo to print the
+                          optimized program as readable pseudo-C [default: native]
+      --target <TRIPLE>   Build C or a native executable for another platform, using
+                          the compiler TRIPLE-gcc unless CC is set; for bare-metal
+                          targets such as arm-none-eabi, build an object file
+      --static            Link the executable statically
+      --cc-flag <FLAG>    Pass FLAG to the C compiler, after the default -O2;
+                          may be given more than once
+      --strict            Exit with a distinct code for every class of failure, see below
+  -h, --help              Print this help message and exit
+  -V, --version           Print version information and exit
+
+Settings are read from the user-level bf.toml, then from bf.toml in the current
+directory or its ancestors, then from the BF_TAPE_SIZE, BF_TAPE_GROWTH,
+BF_INITIAL_TAPE_SIZE, BF_CELL_WIDTH, BF_EOF_MODE, BF_IO, BF_SIGNED, BF_FLUSH,
+BF_OPT_LEVEL, BF_DIALECT, BF_TAPES, BF_HALT_COMMAND, BF_RANDOM_COMMAND, BF_SEED,
+BF_MACROS, BF_MAX_STEPS, BF_EMPTY_LOOP, BF_PASSES and BF_DISABLED_PASSES
+environment variables, and finally from the command line.
+
+Exit status:
+  0  Success
+  1  Failure; with --strict, only failed tests or builds, or a failed check
+     of several programs
+  2  Invalid command line arguments or settings
+  3  Parse error, with --strict
+  4  Runtime error of the program, with --strict
+  5  Step limit exceeded, with --strict
+  6  I/O error, with --strict";
+
+/// Flags that set a configuration option, along with their `bf.toml` key.
+const SETTINGS: &[(&str, &str)] = &[
+    ("--tape-size", "tape_size"),
+    ("--tape-growth", "tape_growth"),
+    ("--initial-tape-size", "initial_tape_size"),
+    ("--cell-width", "cell_width"),
+    ("--eof", "eof"),
+    ("--io", "io"),
+    ("--flush", "flush"),
+    ("-O", "opt_level"),
+    ("--opt-level", "opt_level"),
+    ("--dialect", "dialect"),
+    ("--tapes", "tapes"),
+    ("--seed", "seed"),
+    ("--max-steps", "max_steps"),
+    ("--empty-loop", "empty_loop"),
+    ("--passes", "passes"),
+    ("--disable-pass", "disabled_passes"),
+];
+
+/// Settings for running a program.
+#[derive(Clone)]
+pub struct Options {
+    /// Path of the program to run, or of the directory of tests, or the address to serve on.
+    pub path: PathBuf,
+    /// Paths of all programs to run in order, if more than one.
+    pub paths: Vec<PathBuf>,
+    /// Run the program again whenever its file changes.
+    pub watch: bool,
+    /// Map program files into memory rather than reading them.
+    pub map_source: bool,
+    /// Switch the terminal to raw mode while running.
+    pub raw: bool,
+    /// Input given on the command line, to read instead of stdin. Empty for no input.
+    pub input: Option<Vec<u8>>,
+    /// Network connection to use instead of stdio.
+    pub socket: Option<Socket>,
+    /// Text to show when waiting for input from a terminal.
+    pub prompt: Option<String>,
+    /// Explicit configuration file, replacing the default lookup.
+    pub config: Option<PathBuf>,
+    /// How to show the output of the program.
+    pub output_format: output::Format,
+    /// Character set to show output bytes in.
+    pub encoding: Option<output::Encoding>,
+    /// File to write a copy of the output to.
+    pub tee: Option<PathBuf>,
+    /// Time to pause after every byte of output.
+    pub delay: Option<Duration>,
+    /// Time to pause after every executed instruction.
+    pub step_delay: Option<Duration>,
+    /// Byte whose output stops the program.
+    pub break_on_output: Option<u8>,
+    /// Print a heatmap of tape usage at exit.
+    pub heatmap: Option<heatmap::Format>,
+    /// Print a profile of the loops at exit.
+    pub profile: bool,
+    /// File to write profile samples to as folded stacks.
+    pub flamegraph: Option<PathBuf>,
+    /// Number of steps between profile samples.
+    pub profile_every: u64,
+    /// File to write tape snapshots to.
+    pub record: Option<PathBuf>,
+    /// Number of steps between tape snapshots.
+    pub record_every: u64,
+    /// File to write a trace of every step to.
+    pub trace_file: Option<PathBuf>,
+    /// Track the input bytes every output byte depends on, and print this at exit.
+    pub taint: bool,
+    /// File to periodically write the state of the interpreter to.
+    pub checkpoint: Option<PathBuf>,
+    /// Number of steps between checkpoints.
+    pub checkpoint_every: u64,
+    /// Checkpoint to continue from.
+    pub resume: Option<PathBuf>,
+    /// How to print parse errors and warnings.
+    pub diagnostics: diagnostic::Format,
+    /// Print a JSON run report at exit.
+    pub report: bool,
+    /// Print the effect of every optimization pass.
+    pub opt_stats: bool,
+    /// File with a known prefix of the input, to specialize the program for.
+    pub partial_input: Option<PathBuf>,
+    /// What `explore` looks for input for.
+    pub goal: Option<Goal>,
+    /// Length of the input for `explore`.
+    pub input_length: usize,
+    /// Number of paths `explore` follows.
+    pub max_paths: usize,
+    /// Shell command deciding whether a smaller program still reproduces a problem.
+    pub check: Option<String>,
+    /// Also shorten the program when minifying it.
+    pub shorten: bool,
+    /// Make the optimized program as short as possible.
+    pub golf: bool,
+    /// Path of the executable to build.
+    pub output: Option<PathBuf>,
+    /// Link the executable statically.
+    pub static_link: bool,
+    /// Extra flags for the C compiler.
+    pub cc_flags: Vec<String>,
+    /// Run the program compiled to a shared library instead of interpreting it.
+    pub aot: bool,
+    /// Language to translate the program to, instead of building an executable.
+    pub emit: Option<Language>,
+    /// Platform to build for, instead of the host.
+    pub target: Option<Target>,
+    /// Exit with a distinct code for every class of failure.
+    pub strict: bool,
+    /// Configuration options given on the command line, as `bf.toml` keys and values.
+    pub settings: Vec<(&'static str, String)>,
+}
+
+/// How to set up a TCP connection for the program to talk over.
+#[derive(Clone)]
+pub enum Socket {
+    /// Accept a single connection on this address.
+    Listen(String),
+    /// Connect to this address.
+    Connect(String),
+}
+
+/// The name of a subcommand, before its options are known.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Subcommand {
+    Run,
+    Compile,
+    Optimize,
+    Fmt,
+    Minify,
+    Check,
+    Debug,
+    Repl,
+    Stats,
+    Test,
+    Serve,
+    Minimize,
+    Explore,
+    DiffRun,
+    TraceDump,
+    Completions,
+    Help,
+}
+
+impl Subcommand {
+    fn parse(name: &str) -> Option<Self> {
+        use Subcommand::*;
+        Some(match name {
+            "run" => Run,
+            "compile" | "build" => Compile,
+            "optimize" => Optimize,
+            "fmt" => Fmt,
+            "minify" => Minify,
+            "check" => Check,
+            "debug" => Debug,
+            "repl" => Repl,
+            "stats" => Stats,
+            "test" => Test,
+            "serve" => Serve,
+            "minimize" => Minimize,
+            "explore" => Explore,
+            "diff-run" => DiffRun,
+            "trace-dump" => TraceDump,
+            "completions" => Completions,
+            "help" => Help,
+            _ => return None,
+        })
+    }
+
+    /// Whether the command takes any number of files, with wildcards.
+    fn takes_files(self) -> bool {
+        matches!(self, Subcommand::Run | Subcommand::Check)
+    }
+}
+
+/// What the command line asks us to do.
+pub enum Command {
+    Run(Options),
+    Compile(Options),
+    Optimize(Options),
+    Fmt(Options),
+    Minify(Options),
+    Check(Options),
+    Debug(Options),
+    Repl(Options),
+    Stats(Options),
+    Test(Options),
+    Serve(Options),
+    Minimize(Options),
+    Explore(Options),
+    DiffRun(Options),
+    TraceDump(Options),
+    /// Print a completion script for the shell.
+    Completions(Shell),
+    Help,
+    Version,
+}
+
+impl Command {
+    /// The options of the command, if it runs on a program.
+    pub fn options(&self) -> Option<&Options> {
+        use Command::*;
+        match self {
+            Run(opts) | Compile(opts) | Optimize(opts) | Fmt(opts) | Minify(opts) | Check(opts) | Debug(opts) | Repl(opts)
+            | Stats(opts) | Test(opts) | Serve(opts) | Minimize(opts) | Explore(opts) | DiffRun(opts) | TraceDump(opts) => Some(opts),
+            Completions(_) | Help | Version => None,
+        }
+    }
+
+    /// Whether to exit with a distinct code for every class of failure.
+    pub fn strict(&self) -> bool {
+        self.options().is_some_and(|opts| opts.strict)
+    }
+
+    /// Parse the command line arguments, excluding the program name.
+    /// Only paths may be other than valid UTF-8.
+    pub fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self, String> {
+        let mut paths = Vec::new();
+        let mut watch = false;
+        let mut map_source = false;
+        let mut raw = false;
+        let mut input = None;
+        let mut prompt = None;
+        let mut socket = None;
+        let mut config = None;
+        let mut output_format = output::Format::Raw;
+        let mut encoding = None;
+        let mut tee = None;
+        let mut delay = None;
+        let mut step_delay = None;
+        let mut break_on_output = None;
+        let mut heatmap = None;
+        let mut profile = false;
+        let mut profile_every = 10_000;
+        let mut flamegraph = None;
+        let mut record = None;
+        let mut record_every = 1000;
+        let mut trace_file = None;
+        let mut taint = false;
+        let mut checkpoint = None;
+        let mut checkpoint_every = 1_000_000_000;
+        let mut resume = None;
+        let mut diagnostics = diagnostic::Format::Human;
+        let mut report = false;
+        let mut opt_stats = false;
+        let mut partial_input = None;
+        let mut goal = None;
+        let mut input_length = 16;
+        let mut max_paths = 100_000;
+        let mut check = None;
+        let mut output = None;
+        let mut static_link = false;
+        let mut shorten = false;
+        let mut golf = false;
+        let mut cc_flags = Vec::new();
+        let mut aot = false;
+        let mut emit = None;
+        let mut target = None;
+        let mut strict = false;
+        let mut settings = Vec::new();
+
+        let mut subcommand = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`, and `-O1` for short flags
+            let (flag, inline) = match split_inline(&arg) {
+                Some((flag, value)) => (flag, Some(value)),
+                // Only paths can be other than UTF-8, and they are no flags
+                None => match arg.to_str() {
+                    Some(flag) => (flag, None),
+                    None if arg.as_encoded_bytes().starts_with(b"-") => return Err(format!("unknown flag `{}`", arg.display())),
+                    None => ("", None),
+                },
+            };
+            let mut value = |flag: &str| {
+                inline.clone().or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for `{}`", flag))
+            };
+            let mut text = |flag: &str| {
+                value(flag)?.into_string()
+                    .map_err(|value| format!("invalid UTF-8 in the value of `{}`: `{}`", flag, value.display()))
+            };
+
+            match flag {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-V" | "--version" => return Ok(Command::Version),
+                "--watch" => watch = true,
+                "--map-source" => map_source = true,
+                "--raw" => raw = true,
+                "--strict" => strict = true,
+                "--opt-stats" => opt_stats = true,
+                "--no-opt" => settings.push(("opt_level", "0".to_string())),
+                "--signed" => settings.push(("signed", "true".to_string())),
+                "--detect-loops" => settings.push(("detect_loops", "true".to_string())),
+                "--halt-command" => settings.push(("halt_command", "true".to_string())),
+                "--random-command" => settings.push(("random_command", "true".to_string())),
+                "--macros" => settings.push(("macros", "true".to_string())),
+                "--config" => config = Some(value(flag)?.into()),
+                "--input" => {
+                    input = match text(flag)?.as_str() {
+                        "stdin" => None,
+                        "null" => Some(Vec::new()),
+                        source => return Err(format!("invalid input source `{}`, expected stdin or null", source)),
+                    };
+                },
+                "--listen" => socket = Some(Socket::Listen(text(flag)?)),
+                "--connect" => socket = Some(Socket::Connect(text(flag)?)),
+                "--prompt" => prompt = Some(text(flag)?),
+                "--input-bytes" => input = Some(text(flag)?.into_bytes()),
+                "--input-bytes-hex" => input = Some(parse_hex(&text(flag)?)?),
+                "--output-format" => output_format = output::Format::parse(&text(flag)?)?,
+                "--encoding" => {
+                    let name = text(flag)?;
+                    encoding = match output::Encoding::preset(&name) {
+                        Some(encoding) => Some(encoding),
+                        None => {
+                            let src = std::fs::read_to_string(&name)
+                                .map_err(|e| format!("could not read encoding `{}`: {}", name, e))?;
+                            Some(output::Encoding::parse(&src).map_err(|e| format!("{}: {}", name, e))?)
+                        },
+                    };
+                },
+                "--tee" => tee = Some(value(flag)?.into()),
+                "--delay" => delay = Some(parse_millis(&text(flag)?)?),
+                "--step-delay" => step_delay = Some(parse_millis(&text(flag)?)?),
+                "--break-on-output" => break_on_output = Some(parse_byte(&text(flag)?)?),
+                "--heatmap" => heatmap = Some(heatmap::Format::parse(&text(flag)?)?),
+                "--diagnostics" => diagnostics = diagnostic::Format::parse(&text(flag)?)?,
+                "--report" => {
+                    let format = text(flag)?;
+                    if format != "json" {
+                        return Err(format!("invalid report format `{}`, expected json", format));
+                    }
+                    report = true;
+                },
+                "--partial-input" => partial_input = Some(value(flag)?.into()),
+                "--writes" => goal = Some(Goal::Write(parse_byte(&text(flag)?)?)),
+                "--reaches" => {
+                    let n = text(flag)?;
+                    goal = Some(Goal::Reach(n.parse().map_err(|_| format!("invalid instruction number `{}`", n))?));
+                },
+                "--input-length" => {
+                    let n = text(flag)?;
+                    input_length = n.parse().map_err(|_| format!("invalid input length `{}`", n))?;
+                },
+                "--max-paths" => {
+                    let n = text(flag)?;
+                    max_paths = match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid number of paths `{}`", n)),
+                    };
+                },
+                "--check" => check = Some(text(flag)?),
+                "-o" | "--output" => output = Some(value(flag)?.into()),
+                "--static" => static_link = true,
+                "--shorten" => shorten = true,
+                "--golf" => golf = true,
+                "--aot" => aot = true,
+                "--emit" => {
+                    emit = match text(flag)?.as_str() {
+                        "native" => None,
+                        lang => Some(Language::parse(lang)?),
+                    };
+                },
+                "--target" => target = Some(Target::parse(&text(flag)?)?),
+                "--cc-flag" => cc_flags.push(text(flag)?),
+                "--profile" => profile = true,
+                "--flamegraph" => flamegraph = Some(value(flag)?.into()),
+                "--profile-every" => {
+                    let n = text(flag)?;
+                    profile_every = match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid number of steps `{}`", n)),
+                    };
+                },
+                "--record" => record = Some(value(flag)?.into()),
+                "--record-every" => {
+                    let n = text(flag)?;
+                    record_every = match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid number of steps `{}`", n)),
+                    };
+                },
+                "--trace-file" => trace_file = Some(value(flag)?.into()),
+                "--taint" => taint = true,
+                "--checkpoint" => checkpoint = Some(value(flag)?.into()),
+                "--checkpoint-every" => {
+                    let n = text(flag)?;
+                    checkpoint_every = match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid number of steps `{}`", n)),
+                    };
+                },
+                "--resume" => resume = Some(value(flag)?.into()),
+                flag if flag.starts_with('-') => {
+                    let (_, key) = SETTINGS.iter()
+                        .find(|(name, _)| *name == flag)
+                        .ok_or_else(|| format!("unknown flag `{}`", flag))?;
+                    settings.push((*key, text(flag)?));
+                },
+                _ if subcommand.is_none() && paths.is_empty() && let Some(name) = arg.to_str().and_then(Subcommand::parse) => {
+                    subcommand = Some(name);
+                },
+                _ if subcommand.is_none_or(Subcommand::takes_files) => paths.extend(expand_glob(&arg)),
+                _ if subcommand == Some(Subcommand::DiffRun) && paths.len() < 2 => paths.push(arg.into()),
+                _ if !paths.is_empty() || subcommand == Some(Subcommand::Repl) => {
+                    return Err(format!("unexpected argument `{}`", arg.display()));
+                },
+                _ => paths.push(arg.into()),
+            }
+        }
+
+        let subcommand = subcommand.unwrap_or(Subcommand::Run);
+        let path = match paths.first() {
+            Some(path) => path.clone(),
+            None if subcommand == Subcommand::Serve => crate::serve::DEFAULT_ADDR.into(),
+            None if subcommand == Subcommand::Test => return Err("missing test directory".to_string()),
+            None if subcommand == Subcommand::Completions => return Err("missing shell".to_string()),
+            None if subcommand == Subcommand::DiffRun => return Err("missing programs to compare".to_string()),
+            None if subcommand == Subcommand::TraceDump => return Err("missing trace file".to_string()),
+            None if matches!(subcommand, Subcommand::Repl | Subcommand::Help) => "<stdin>".into(),
+            None => return Err("missing input file".to_string()),
+        };
+        if subcommand == Subcommand::DiffRun && paths.len() < 2 {
+            return Err("missing second program to compare".to_string());
+        }
+        let opts = Options {
+            path, paths, watch, map_source, raw, input, prompt, socket, config, output_format, encoding, tee, delay, step_delay,
+            break_on_output, heatmap, profile, profile_every, flamegraph, record, record_every, trace_file, taint, checkpoint, checkpoint_every, resume, diagnostics, report, opt_stats,
+            partial_input, goal, input_length, max_paths, check, shorten, golf, output, static_link, cc_flags, aot, emit, target, strict, settings,
+        };
+        Ok(match subcommand {
+            Subcommand::Run => Command::Run(opts),
+            Subcommand::Compile => Command::Compile(opts),
+            Subcommand::Optimize => Command::Optimize(opts),
+            Subcommand::Fmt => Command::Fmt(opts),
+            Subcommand::Minify => Command::Minify(opts),
+            Subcommand::Check => Command::Check(opts),
+            Subcommand::Debug => Command::Debug(opts),
+            Subcommand::Repl => Command::Repl(opts),
+            Subcommand::Stats => Command::Stats(opts),
+            Subcommand::Test => Command::Test(opts),
+            Subcommand::Serve => Command::Serve(opts),
+            Subcommand::Minimize => Command::Minimize(opts),
+            Subcommand::Explore => Command::Explore(opts),
+            Subcommand::DiffRun => Command::DiffRun(opts),
+            Subcommand::TraceDump => Command::TraceDump(opts),
+            Subcommand::Completions => Command::Completions(Shell::parse(&opts.path.to_string_lossy())?),
+            Subcommand::Help => Command::Help,
+        })
+    }
+}
+
+/// Split `--flag=value` into the flag and its value, and `-O1` into `-O` and `1`.
+fn split_inline(arg: &OsStr) -> Option<(&str, OsString)> {
+    let bytes = arg.as_encoded_bytes();
+    let (flag, value) = match bytes.iter().position(|&b| b == b'=') {
+        Some(i) if bytes.starts_with(b"--") => (&bytes[..i], &bytes[i + 1..]),
+        _ if bytes.starts_with(b"-O") && bytes.len() > 2 => bytes.split_at(2),
+        _ => return None,
+    };
+    // SAFETY: the value follows an ASCII character, so it is split off at a character boundary
+    let value = unsafe { OsStr::from_encoded_bytes_unchecked(value) };
+    Some((str::from_utf8(flag).ok()?, value.to_os_string()))
+}
+
+/// Parse a number of milliseconds.
+fn parse_millis(ms: &str) -> Result<Duration, String> {
+    ms.parse().map(Duration::from_millis)
+        .map_err(|_| format!("invalid number of milliseconds `{}`", ms))
+}
+
+/// Parse a byte given as a single character, or as a decimal or `0x` hexadecimal number.
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() => return Ok(c as u8),
+        _ => {},
+    }
+    let byte = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    byte.map_err(|_| format!("invalid byte `{}`, expected a single character or a number from 0 to 255", s))
+}
+
+/// The files matching a pattern with `*` and `?` wildcards in the file name, in order,
+/// or the pattern itself if it has no wildcards or matches nothing.
+fn expand_glob(pattern: &OsStr) -> Vec<PathBuf> {
+    let path = Path::new(pattern);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()).filter(|name| name.contains(['*', '?'])) else {
+        return vec![path.to_path_buf()];
+    };
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let Ok(entries) = std::fs::read_dir(dir.unwrap_or(Path::new("."))) else {
+        return vec![path.to_path_buf()];
+    };
+
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|file| !file.starts_with('.') && wildcard_match(&name.chars().collect::<Vec<_>>(), &file.chars().collect::<Vec<_>>()))
+        .map(|file| match dir {
+            Some(dir) => dir.join(file),
+            None => file.into(),
+        })
+        .collect();
+    if matches.is_empty() {
+        return vec![path.to_path_buf()];
+    }
+    matches.sort();
+    matches
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any sequence of characters and `?` any single one.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', rest)), _) => wildcard_match(rest, name) || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some(('?', rest)), Some((_, name))) => wildcard_match(rest, name),
+        (Some((p, rest)), Some((c, name))) => p == c && wildcard_match(rest, name),
+        _ => false,
+    }
+}
+
+/// Parse bytes written as pairs of hex digits, ignoring whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid hex bytes `{}`, expected pairs of hex digits", hex);
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    // `from_str_radix` also accepts a sign, so check for the digits first.
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid());
+    }
+    digits.chunks(2)
+        .map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()).ok_or_else(invalid))
+        .collect()
+}
+
+/// The help message.
+pub fn help() -> &'static str {
+    USAGE
+}
+
+/// The version message.
+pub fn version() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
@@ -0,0 +1,184 @@
+use crate::cli;
+
+/// Longest description shown next to a completion.
+const MAX_DESCRIPTION: usize = 80;
+
+/// Column at which the descriptions in the help message start, after the names of the entries.
+const DESCRIPTION_COLUMN: usize = 26;
+
+/// A shell to generate completions for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("invalid shell `{}`, expected bash, zsh or fish", s)),
+        }
+    }
+}
+
+/// A flag listed in the help message.
+struct Flag {
+    short: Option<String>,
+    long: String,
+    /// Whether the flag takes a value.
+    value: bool,
+    description: String,
+}
+
+/// The subcommands and flags in the help message, with short descriptions.
+/// Reading them from the help message keeps the completions in sync with it.
+fn parse_help() -> (Vec<(String, String)>, Vec<Flag>) {
+    let mut commands = Vec::new();
+    let mut flags = Vec::new();
+    let mut section = "";
+    // Entries start near the left, with descriptions continuing further in
+    let mut entries: Vec<(&str, String, String)> = Vec::new();
+    for line in cli::help().lines() {
+        if !line.starts_with(' ') {
+            section = line;
+            continue;
+        }
+        let mut words = line.split_whitespace().peekable();
+        let indent = line.len() - line.trim_start().len();
+        if indent < DESCRIPTION_COLUMN {
+            // The names of the entry, followed by its arguments
+            let mut spec = words.next().unwrap_or_default().to_string();
+            while let Some(word) = words.next_if(|word| word.starts_with(['-', '<', '['])) {
+                spec = format!("{} {}", spec, word);
+            }
+            entries.push((section, spec, words.collect::<Vec<_>>().join(" ")));
+        } else if let Some((_, _, description)) = entries.last_mut() {
+            *description = description.split_whitespace().chain(words).collect::<Vec<_>>().join(" ");
+        }
+    }
+
+    for (section, spec, description) in entries {
+        let description = summarize(&description);
+        match section {
+            "Commands:" => commands.push((spec.split_whitespace().next().unwrap().to_string(), description)),
+            "Options:" => {
+                let value = spec.contains('<');
+                let mut names = spec.split([',', ' ']).filter(|name| name.starts_with('-'));
+                let (first, second) = (names.next().unwrap().to_string(), names.next().map(str::to_string));
+                let (short, long) = match second {
+                    Some(long) => (Some(first), long),
+                    None => (None, first),
+                };
+                flags.push(Flag { short, long, value, description });
+            },
+            _ => {},
+        }
+    }
+    (commands, flags)
+}
+
+/// The first clause of a description, without defaults, shortened to fit next to a completion.
+fn summarize(description: &str) -> String {
+    let description = description.split(" [").next().unwrap();
+    // Cut before a list of values, or at the end of the first clause unless it is too short to say anything
+    let mut end = description.find(": ")
+        .or_else(|| ["; ", ". ", ", "].iter()
+            .filter_map(|sep| description.match_indices(sep).map(|(i, _)| i).find(|&i| i >= 20))
+            .min())
+        .unwrap_or(description.len());
+    if end > MAX_DESCRIPTION {
+        end = description[..MAX_DESCRIPTION].rfind(' ').unwrap_or(MAX_DESCRIPTION);
+    }
+    description[..end].trim_end_matches([',', '.']).to_string()
+}
+
+/// A completion script for `shell`, to be sourced by the shell, for example:
+///
+/// ```text
+/// bf completions bash > ~/.local/share/bash-completion/completions/bf
+/// bf completions zsh > ~/.zfunc/_bf
+/// bf completions fish > ~/.config/fish/completions/bf.fish
+/// ```
+pub fn generate(shell: Shell) -> String {
+    let (commands, flags) = parse_help();
+    match shell {
+        Shell::Bash => bash(&commands, &flags),
+        Shell::Zsh => zsh(&commands, &flags),
+        Shell::Fish => fish(&commands, &flags),
+    }
+}
+
+fn bash(commands: &[(String, String)], flags: &[Flag]) -> String {
+    let names: Vec<&str> = commands.iter().map(|(name, _)| name.as_str()).collect();
+    let all: Vec<&str> = flags.iter()
+        .flat_map(|flag| flag.short.iter().chain([&flag.long]))
+        .map(String::as_str)
+        .collect();
+    let with_value: Vec<&str> = flags.iter()
+        .filter(|flag| flag.value)
+        .flat_map(|flag| flag.short.iter().chain([&flag.long]))
+        .map(String::as_str)
+        .collect();
+
+    format!("\
+_bf() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"
+    case \"$prev\" in
+        {})
+            COMPREPLY=($(compgen -f -- \"$cur\"))
+            return
+            ;;
+    esac
+    if [[ \"$cur\" == -* ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -f -- \"$cur\"))
+    else
+        COMPREPLY=($(compgen -f -- \"$cur\"))
+    fi
+}}
+complete -o filenames -F _bf bf
+", with_value.join("|"), all.join(" "), names.join(" "))
+}
+
+fn zsh(commands: &[(String, String)], flags: &[Flag]) -> String {
+    let escape = |s: &str| s.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:");
+    let mut out = String::from("#compdef bf\n\n_bf() {\n    local -a commands\n    commands=(\n");
+    for (name, description) in commands {
+        out += &format!("        '{}:{}'\n", name, escape(description));
+    }
+    out += "    )\n    _arguments \\\n";
+    for flag in flags {
+        let action = if flag.value { ":value:_files" } else { "" };
+        for name in flag.short.iter().chain([&flag.long]) {
+            out += &format!("        '{}[{}]{}' \\\n", name, escape(&flag.description), action);
+        }
+    }
+    out += "        '1: :->first' \\\n        '*:file:_files'\n";
+    out += "    if [[ $state == first ]]; then\n        _describe 'command' commands\n        _files\n    fi\n}\n\n_bf \"$@\"\n";
+    out
+}
+
+fn fish(commands: &[(String, String)], flags: &[Flag]) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('\'', "\\'");
+    let mut out = String::new();
+    for (name, description) in commands {
+        out += &format!("complete -c bf -n __fish_use_subcommand -a {} -d '{}'\n", name, escape(description));
+    }
+    for flag in flags {
+        out += "complete -c bf";
+        if let Some(short) = &flag.short {
+            out += &format!(" -s {}", short.trim_start_matches('-'));
+        }
+        out += &format!(" -l {}", flag.long.trim_start_matches("--"));
+        if flag.value {
+            out += " -r";
+        }
+        out += &format!(" -d '{}'\n", escape(&flag.description));
+    }
+    out
+}
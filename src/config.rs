@@ -0,0 +1,555 @@
+#[cfg(feature = "std")]
+use core::fmt;
+
+use alloc::{format, string::{String, ToString}, vec::Vec};
+#[cfg(feature = "std")]
+use std::{env, fs, io, path::{Path, PathBuf}};
+
+/// Name of the configuration file, looked up in the current directory and its ancestors,
+/// and in the user's configuration directory.
+pub const FILE_NAME: &str = "bf.toml";
+
+/// Environment variables that set an option, along with their `bf.toml` key.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("BF_TAPE_SIZE", "tape_size"),
+    ("BF_TAPE_GROWTH", "tape_growth"),
+    ("BF_INITIAL_TAPE_SIZE", "initial_tape_size"),
+    ("BF_CELL_WIDTH", "cell_width"),
+    ("BF_EOF_MODE", "eof"),
+    ("BF_IO", "io"),
+    ("BF_SIGNED", "signed"),
+    ("BF_FLUSH", "flush"),
+    ("BF_OPT_LEVEL", "opt_level"),
+    ("BF_DIALECT", "dialect"),
+    ("BF_TAPES", "tapes"),
+    ("BF_HALT_COMMAND", "halt_command"),
+    ("BF_RANDOM_COMMAND", "random_command"),
+    ("BF_SEED", "seed"),
+    ("BF_MACROS", "macros"),
+    ("BF_MAX_STEPS", "max_steps"),
+    ("BF_EMPTY_LOOP", "empty_loop"),
+    ("BF_DETECT_LOOPS", "detect_loops"),
+    ("BF_PASSES", "passes"),
+    ("BF_DISABLED_PASSES", "disabled_passes"),
+];
+
+/// Width of a single tape cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellWidth {
+    U8,
+    U16,
+    U32,
+    /// A single bit, as in Boolfuck, which `+` and `-` both flip.
+    Bit,
+    /// Arbitrary-precision signed cells that never wrap, see [`bignum`](crate::bignum).
+    /// Requires the `bignum` feature.
+    Big,
+}
+
+impl CellWidth {
+    /// Mask selecting the bits of a cell, used for wrapping arithmetic.
+    /// Big cells do not wrap, but constants are still folded modulo 2^32.
+    pub fn mask(self) -> u32 {
+        match self {
+            CellWidth::Bit => 1,
+            CellWidth::U8 => u8::MAX as u32,
+            CellWidth::U16 => u16::MAX as u32,
+            CellWidth::U32 | CellWidth::Big => u32::MAX,
+        }
+    }
+}
+
+/// How the interpreter allocates the tape, which is never larger than the tape size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapeGrowth {
+    /// Allocate the whole tape up front.
+    Fixed,
+    /// Start small and double the tape whenever the data pointer moves past its end.
+    Double,
+    /// Start small and grow the tape by the given number of cells at a time.
+    Chunks(usize),
+    /// Start small and grow the tape to exactly the cells used.
+    Exact,
+    /// Reserve the whole tape in an anonymous memory mapping, in which the operating
+    /// system commits memory as pages are first used. Only supported on Linux and macOS.
+    Mapped,
+    /// Store only the nonzero cells, for programs that use a few cells far apart.
+    Sparse,
+}
+
+/// Default number of cells added at a time by [`TapeGrowth::Chunks`].
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// What `,` does when the input is exhausted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EofMode {
+    /// Leave the cell unchanged.
+    Unchanged,
+    /// Set the cell to zero.
+    Zero,
+    /// Set the cell to its maximum value, i.e. -1.
+    Max,
+    /// Abort with an error.
+    Error,
+}
+
+/// How `.` and `,` represent cell values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoMode {
+    /// Write the lowest byte of a cell, and read a single byte.
+    Bytes,
+    /// Write the value of a cell as a decimal number on its own line,
+    /// and read a decimal number, skipping leading whitespace.
+    Numeric,
+    /// Write the lowest bit of a cell, and read a single bit, as in Boolfuck. Every byte
+    /// holds eight bits, lowest first, and the last byte written is padded with zeros.
+    /// The conversion is left to [`input::Bits`](crate::input::Bits) and
+    /// [`output::Bits`](crate::output::Bits), so the evaluators read and write every bit as a byte.
+    Bits,
+    /// Write the value of a cell as the Unicode character it stands for, and read a character,
+    /// both encoded as UTF-8. Only for cells of at least 16 bits, which characters read are
+    /// wrapped to like constants.
+    Unicode,
+}
+
+/// When output is flushed, i.e. actually written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every `.`.
+    EveryWrite,
+    /// Flush before every `,`, so that prompts are visible, and after every line.
+    OnRead,
+    /// Buffer as much output as possible, and only flush it at exit.
+    OnExit,
+}
+
+/// What happens when an empty loop `[]` is entered, which never ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmptyLoopMode {
+    /// Loop forever, as the program says.
+    Hang,
+    /// Abort with an error.
+    Error,
+}
+
+/// The language variant to accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// Plain Brainfuck with its eight commands.
+    Standard,
+    /// Brainfuck with several independent tapes, where `}` switches to the next tape
+    /// and `{` to the previous one.
+    MultiTape,
+    /// Self-modifying Brainfuck, where the program is stored on the tape to the left of
+    /// the first cell, see [`smbf`](crate::smbf).
+    SelfModifying,
+    /// Boolfuck, with a tape of bits that `+` flips, `;` to write a bit and `,` to read one.
+    /// Selecting it also selects 1-bit cells and bit I/O, which no other cell width or
+    /// I/O mode can be set along with.
+    Boolfuck,
+    /// Brainfuck with a stack next to the tape, where `^` pushes the current cell onto
+    /// the stack and `_` pops the top of the stack into the current cell.
+    Stack,
+}
+
+/// Interpreter settings, resolved from defaults, configuration files,
+/// environment variables and the command line.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Number of cells on the tape.
+    pub tape_size: usize,
+    pub tape_growth: TapeGrowth,
+    /// Number of cells allocated initially when the tape grows.
+    pub initial_tape_size: usize,
+    pub cell_width: CellWidth,
+    pub eof: EofMode,
+    pub io: IoMode,
+    /// Treat cells as two's complement signed numbers in numeric I/O.
+    pub signed: bool,
+    pub flush: FlushPolicy,
+    /// `0` disables all optimizations.
+    pub opt_level: u8,
+    pub dialect: Dialect,
+    /// Number of tapes in the multi-tape dialect.
+    pub tapes: usize,
+    /// Accept `@` in any dialect, which ends the program at once.
+    pub halt_command: bool,
+    /// Accept `?` in any dialect, which sets the current cell to a random byte.
+    pub random_command: bool,
+    /// Seed of the random bytes of `?`, which differ between runs without one.
+    pub seed: Option<u64>,
+    /// Expand named macros in the source before parsing, see [`macros`](crate::macros).
+    pub macros: bool,
+    /// Abort after executing this many instructions.
+    pub max_steps: Option<u64>,
+    pub empty_loop: EmptyLoopMode,
+    /// Abort when the interpreter finds itself in a state it was in before, which it
+    /// would then repeat forever.
+    pub detect_loops: bool,
+    /// Names of the optimization passes to run, replacing the default pipeline.
+    pub passes: Option<Vec<String>>,
+    /// Names of optimization passes not to run.
+    pub disabled_passes: Vec<String>,
+    /// Whether `cell_width` was set, rather than left for the dialect to decide,
+    /// as [`Config::set`] records it.
+    pub cell_width_set: bool,
+    /// Whether `io` was set, rather than left for the dialect to decide.
+    pub io_set: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tape_size: 30_000,
+            tape_growth: TapeGrowth::Fixed,
+            initial_tape_size: 1024,
+            cell_width: CellWidth::U8,
+            eof: EofMode::Unchanged,
+            io: IoMode::Bytes,
+            signed: false,
+            flush: FlushPolicy::OnRead,
+            opt_level: 1,
+            dialect: Dialect::Standard,
+            tapes: 2,
+            halt_command: false,
+            random_command: false,
+            seed: None,
+            macros: false,
+            max_steps: None,
+            empty_loop: EmptyLoopMode::Hang,
+            detect_loops: false,
+            passes: None,
+            disabled_passes: Vec::new(),
+            cell_width_set: false,
+            io_set: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration file could not be read.
+    Io(PathBuf, io::Error),
+    /// Invalid contents at the given line of a configuration file.
+    Syntax(PathBuf, usize, String),
+    /// Invalid value of an environment variable.
+    Env(&'static str, String),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Io(path, e) => write!(f, "could not read `{}`: {}", path.display(), e),
+            Syntax(path, line, msg) => write!(f, "{}:{}: {}", path.display(), line, msg),
+            Env(var, msg) => write!(f, "{}: {}", var, msg),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// Set a single option by its configuration key, as used in `bf.toml`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tape_size" => {
+                self.tape_size = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid tape size `{}`", value)),
+                };
+            },
+            "tape_growth" => {
+                self.tape_growth = match value.split_once(':') {
+                    _ if value == "fixed" => TapeGrowth::Fixed,
+                    _ if value == "double" => TapeGrowth::Double,
+                    _ if value == "exact" => TapeGrowth::Exact,
+                    _ if value == "mmap" && cfg!(any(target_os = "linux", target_os = "macos")) => TapeGrowth::Mapped,
+                    _ if value == "sparse" => TapeGrowth::Sparse,
+                    _ if value == "chunks" => TapeGrowth::Chunks(DEFAULT_CHUNK_SIZE),
+                    Some(("chunks", n)) => match n.parse() {
+                        Ok(n) if n > 0 => TapeGrowth::Chunks(n),
+                        _ => return Err(format!("invalid chunk size `{}`", n)),
+                    },
+                    _ => return Err(format!("invalid tape growth `{}`, expected fixed, double, chunks[:N], exact, mmap or sparse", value)),
+                };
+            },
+            "initial_tape_size" => {
+                self.initial_tape_size = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid initial tape size `{}`", value)),
+                };
+            },
+            "cell_width" => {
+                self.cell_width = match value {
+                    "1" => CellWidth::Bit,
+                    "8" => CellWidth::U8,
+                    "16" => CellWidth::U16,
+                    "32" => CellWidth::U32,
+                    "big" if cfg!(feature = "bignum") => CellWidth::Big,
+                    "big" => return Err("arbitrary-precision cells require building with the `bignum` feature".to_string()),
+                    _ => return Err(format!("invalid cell width `{}`, expected 1, 8, 16, 32 or big", value)),
+                };
+                self.cell_width_set = true;
+            },
+            "eof" => {
+                self.eof = match value {
+                    "unchanged" => EofMode::Unchanged,
+                    "zero" => EofMode::Zero,
+                    "max" => EofMode::Max,
+                    "error" => EofMode::Error,
+                    _ => return Err(format!("invalid EOF mode `{}`, expected unchanged, zero, max or error", value)),
+                };
+            },
+            "io" => {
+                self.io = match value {
+                    "bytes" => IoMode::Bytes,
+                    "numeric" => IoMode::Numeric,
+                    "bits" => IoMode::Bits,
+                    "unicode" => IoMode::Unicode,
+                    _ => return Err(format!("invalid I/O mode `{}`, expected bytes, numeric, bits or unicode", value)),
+                };
+                self.io_set = true;
+            },
+            "signed" => {
+                self.signed = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value `{}` for signed, expected true or false", value)),
+                };
+            },
+            "flush" => {
+                self.flush = match value {
+                    "every-write" => FlushPolicy::EveryWrite,
+                    "on-read" => FlushPolicy::OnRead,
+                    "on-exit" => FlushPolicy::OnExit,
+                    _ => return Err(format!("invalid flush policy `{}`, expected every-write, on-read or on-exit", value)),
+                };
+            },
+            "opt_level" => {
+                self.opt_level = match value {
+                    "0" => 0,
+                    "1" => 1,
+                    _ => return Err(format!("invalid optimization level `{}`, expected 0 or 1", value)),
+                };
+            },
+            "dialect" => {
+                self.dialect = match value {
+                    "standard" => Dialect::Standard,
+                    "multitape" => Dialect::MultiTape,
+                    "smbf" => Dialect::SelfModifying,
+                    "boolfuck" => Dialect::Boolfuck,
+                    "stack" => Dialect::Stack,
+                    _ => return Err(format!("unknown dialect `{}`", value)),
+                };
+            },
+            "tapes" => {
+                self.tapes = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid number of tapes `{}`", value)),
+                };
+            },
+            "halt_command" => {
+                self.halt_command = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value `{}` for halt_command, expected true or false", value)),
+                };
+            },
+            "random_command" => {
+                self.random_command = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value `{}` for random_command, expected true or false", value)),
+                };
+            },
+            "macros" => {
+                self.macros = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value `{}` for macros, expected true or false", value)),
+                };
+            },
+            "seed" => {
+                self.seed = match value.parse() {
+                    Ok(seed) => Some(seed),
+                    Err(_) => return Err(format!("invalid seed `{}`", value)),
+                };
+            },
+            "max_steps" => {
+                // Zero means unlimited
+                self.max_steps = match value.parse() {
+                    Ok(0) => None,
+                    Ok(n) => Some(n),
+                    Err(_) => return Err(format!("invalid step limit `{}`", value)),
+                };
+            },
+            "empty_loop" => {
+                self.empty_loop = match value {
+                    "hang" => EmptyLoopMode::Hang,
+                    "error" => EmptyLoopMode::Error,
+                    _ => return Err(format!("invalid empty loop mode `{}`, expected hang or error", value)),
+                };
+            },
+            "detect_loops" => {
+                self.detect_loops = match value {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("invalid value `{}` for detect_loops, expected true or false", value)),
+                };
+            },
+            "passes" => self.passes = Some(pass_names(value)),
+            "disabled_passes" => self.disabled_passes = pass_names(value),
+            _ => return Err(format!("unknown option `{}`", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Apply the settings of a configuration file.
+    ///
+    /// Only a small subset of TOML is supported: `key = value` pairs,
+    /// where values are integers or strings, and `#` comments.
+    #[cfg(feature = "std")]
+    pub fn load(&mut self, path: &Path) -> Result<(), Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::Io(path.to_path_buf(), e))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let syntax = |msg: String| Error::Syntax(path.to_path_buf(), i + 1, msg);
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| syntax("expected `key = value`".to_string()))?;
+            let value = value.trim();
+            let value = value.strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+
+            self.set(key.trim(), value).map_err(syntax)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the user-level configuration file, followed by the project-local one,
+    /// such that project settings take precedence.
+    #[cfg(feature = "std")]
+    pub fn load_default_files(&mut self) -> Result<(), Error> {
+        if let Some(path) = user_config_file().filter(|p| p.is_file()) {
+            self.load(&path)?;
+        }
+
+        if let Some(path) = project_config_file() {
+            self.load(&path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply the settings given by `BF_*` environment variables.
+    #[cfg(feature = "std")]
+    pub fn load_env(&mut self) -> Result<(), Error> {
+        for (var, key) in ENV_VARS {
+            if let Ok(value) = env::var(var) {
+                self.set(key, value.trim()).map_err(|msg| Error::Env(var, msg))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the settings that follow from the dialect, unless they are set, and check
+    /// that the settings fit together, once all of them are applied.
+    ///
+    /// The dialect decides these only now, so that a later source selecting another
+    /// dialect does not keep the settings of an earlier one.
+    pub fn resolve(&mut self) -> Result<(), String> {
+        if self.dialect == Dialect::Boolfuck {
+            if !self.cell_width_set && self.cell_width == CellWidth::U8 {
+                self.cell_width = CellWidth::Bit;
+            }
+            if !self.io_set && self.io == IoMode::Bytes {
+                self.io = IoMode::Bits;
+            }
+        }
+        self.validate()
+    }
+
+    /// Check that the settings fit together, once all of them are applied.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dialect == Dialect::Boolfuck && self.cell_width != CellWidth::Bit {
+            return Err("the boolfuck dialect needs 1-bit cells".to_string());
+        }
+        if self.dialect == Dialect::Boolfuck && self.io != IoMode::Bits {
+            return Err("the boolfuck dialect needs bit I/O".to_string());
+        }
+        if self.io == IoMode::Unicode && matches!(self.cell_width, CellWidth::Bit | CellWidth::U8) {
+            return Err("Unicode I/O needs cells of at least 16 bits".to_string());
+        }
+        if self.macros && matches!(self.dialect, Dialect::MultiTape | Dialect::SelfModifying) {
+            return Err("macros are not supported in the multitape and smbf dialects".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Split a comma-separated list of pass names.
+fn pass_names(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Remove a trailing `#` comment, ignoring `#` inside strings.
+#[cfg(feature = "std")]
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {},
+        }
+    }
+    line
+}
+
+/// Find `bf.toml` in the current directory or one of its ancestors.
+#[cfg(feature = "std")]
+fn project_config_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Location of the user-level `bf.toml`.
+#[cfg(feature = "std")]
+fn user_config_file() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+
+    Some(dir.join("bf").join(FILE_NAME))
+}
@@ -0,0 +1,255 @@
+use std::io::{self, Read, Write};
+
+use bf::{Instruction, backend, cell::Cell, config::Config, eval::{Context, RunStatus}};
+
+/// Number of cells shown around the data pointer, unless given otherwise.
+const DEFAULT_TAPE_WINDOW: usize = 16;
+
+const HELP: &str = "\
+Commands:
+  s, step [N]     Execute the next N instructions [default: 1]
+  c, continue     Run until the program finishes
+  u, until <N>    Run until N instructions have been executed in total
+  w, where        Show the next instruction and the cells around the data pointer
+  t, tape [N]     Show N cells around the data pointer [default: 16]
+  watch <CELLS>   Show these cells after every stop, given as a cell or a range of cells,
+                  relative to the data pointer with @ptr, e.g. `watch 0..8` or `watch @ptr-2..@ptr+3`
+  unwatch [N]     Stop showing watch N, or all watches
+  h, help         Show this list of commands
+  q, quit         Stop debugging";
+
+/// Step through the program, reading commands from stdin and showing the state of the
+/// interpreter on stderr, while the program writes its output to stdout.
+///
+/// The program should not be optimized, so that every step executes a single command.
+/// Running pauses after the program writes `break_on_output`, if given.
+pub fn debug<C: Cell>(prog: &[Instruction], config: &Config, break_on_output: Option<u8>, rdr: &mut impl Read) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut ctx = Context::<C>::new(rdr, &mut stdout, config);
+    if let Some(byte) = break_on_output {
+        ctx.break_on_output(byte);
+    }
+    let mut finished = false;
+    let mut watches: Vec<Watch> = Vec::new();
+    eprintln!("type `help` for a list of commands");
+    show_position(&ctx, prog, config, &watches);
+
+    loop {
+        io::stdout().flush()?;
+        eprint!("(bf) ");
+        let mut line = String::new();
+        // Read through a new handle every time, as the program may read from stdin as well
+        if io::stdin().read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+
+        let mut words = line.split_whitespace();
+        let (command, arg) = (words.next(), words.next());
+        let fuel = match command {
+            None => continue,
+            Some("s" | "step") => match arg.map(str::parse).unwrap_or(Ok(1)) {
+                Ok(n) => n,
+                Err(_) => {
+                    eprintln!("invalid number of steps `{}`", arg.unwrap_or_default());
+                    continue;
+                },
+            },
+            Some("c" | "continue") => u64::MAX,
+            Some("u" | "until") => match arg.map(str::parse::<u64>) {
+                Some(Ok(n)) => n.saturating_sub(ctx.steps()),
+                _ => {
+                    eprintln!("expected the number of steps to run until");
+                    continue;
+                },
+            },
+            Some("w" | "where") => {
+                show_position(&ctx, prog, config, &watches);
+                continue;
+            },
+            Some("watch") => {
+                // Allow spaces in the expression, such as in `@ptr - 2`
+                let expr: String = line.split_whitespace().skip(1).collect();
+                match Watch::parse(&expr) {
+                    Ok(watch) => {
+                        watches.push(watch);
+                        show_watches(&ctx, &watches);
+                    },
+                    Err(e) => eprintln!("{}", e),
+                }
+                continue;
+            },
+            Some("unwatch") => {
+                match arg.map(str::parse::<usize>) {
+                    None => watches.clear(),
+                    Some(Ok(n)) if (1..=watches.len()).contains(&n) => {
+                        watches.remove(n - 1);
+                    },
+                    Some(_) => eprintln!("no watch `{}`, there are {}", arg.unwrap_or_default(), watches.len()),
+                }
+                continue;
+            },
+            Some("t" | "tape") => {
+                match arg.map(str::parse).unwrap_or(Ok(DEFAULT_TAPE_WINDOW)) {
+                    Ok(window) => show_tape(&ctx, window),
+                    Err(_) => eprintln!("invalid number of cells `{}`", arg.unwrap_or_default()),
+                }
+                continue;
+            },
+            Some("h" | "help") => {
+                eprintln!("{}", HELP);
+                continue;
+            },
+            Some("q" | "quit") => return Ok(()),
+            Some(command) => {
+                eprintln!("unknown command `{}`, type `help` for a list of commands", command);
+                continue;
+            },
+        };
+
+        if finished {
+            eprintln!("the program has finished");
+            continue;
+        }
+        let result = ctx.run_for(prog, fuel);
+        io::stdout().flush()?;
+        match result {
+            Ok(RunStatus::Finished) => {
+                finished = true;
+                eprintln!("the program finished after {} steps", ctx.steps());
+            },
+            Ok(RunStatus::BreakOnOutput) => {
+                eprintln!("the program wrote the byte {:#04x}", break_on_output.unwrap_or_default());
+                show_position(&ctx, prog, config, &watches);
+            },
+            Ok(_) => show_position(&ctx, prog, config, &watches),
+            Err(e) => {
+                finished = true;
+                eprintln!("error: {}", e);
+                show_tape(&ctx, DEFAULT_TAPE_WINDOW);
+                show_watches(&ctx, &watches);
+            },
+        }
+    }
+}
+
+/// A position on the tape, either fixed or relative to the data pointer.
+#[derive(Clone, Copy)]
+enum Bound {
+    Cell(usize),
+    Ptr(isize),
+}
+
+impl Bound {
+    fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid cell `{}`, expected a number or @ptr with an optional offset such as @ptr-2", s);
+        let Some(offset) = s.strip_prefix("@ptr") else {
+            return s.parse().map(Bound::Cell).map_err(|_| invalid());
+        };
+        match offset {
+            "" => Ok(Bound::Ptr(0)),
+            _ if offset.starts_with(['+', '-']) => offset.parse().map(Bound::Ptr).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The cell at this position, if it is on the tape.
+    fn resolve(self, ptr: usize) -> Option<usize> {
+        match self {
+            Bound::Cell(cell) => Some(cell),
+            Bound::Ptr(offset) => ptr.checked_add_signed(offset),
+        }
+    }
+}
+
+/// Cells to show after every stop, from `start` up to but not including `end`.
+struct Watch {
+    /// The expression as given.
+    expr: String,
+    start: Bound,
+    end: Bound,
+}
+
+impl Watch {
+    /// Parse a single cell such as `3` or `@ptr+1`, or a range such as `0..8`.
+    fn parse(expr: &str) -> Result<Self, String> {
+        if expr.is_empty() {
+            return Err("expected the cells to watch, such as `0..8` or `@ptr-2..@ptr+3`".to_string());
+        }
+        let (start, end) = match expr.split_once("..") {
+            Some((start, end)) => (Bound::parse(start)?, Bound::parse(end)?),
+            None => {
+                let cell = Bound::parse(expr)?;
+                let end = match cell {
+                    Bound::Cell(cell) => Bound::Cell(cell + 1),
+                    Bound::Ptr(offset) => Bound::Ptr(offset + 1),
+                };
+                (cell, end)
+            },
+        };
+        Ok(Watch { expr: expr.to_string(), start, end })
+    }
+}
+
+/// Show the cells of every watch, marking the cell the data pointer points at.
+fn show_watches<C: Cell>(ctx: &Context<C>, watches: &[Watch]) {
+    let tape = ctx.tape();
+    for (i, watch) in watches.iter().enumerate() {
+        // Cells left of the tape are left out
+        let start = watch.start.resolve(ctx.ptr()).unwrap_or(0).min(tape.len());
+        let end = watch.end.resolve(ctx.ptr()).unwrap_or(0).clamp(start, tape.len());
+        let cells: Vec<String> = (start..end)
+            .map(|i| if i == ctx.ptr() { format!("[{}]", tape.get(i)) } else { tape.get(i).to_string() })
+            .collect();
+        eprintln!("watch {}: {} = cells {}..{}: {}", i + 1, watch.expr, start, end, cells.join(" "));
+    }
+}
+
+/// Show the step count and the next instruction, along with the cells around the data pointer
+/// and the watched cells.
+fn show_position<C: Cell>(ctx: &Context<C>, prog: &[Instruction], config: &Config, watches: &[Watch]) {
+    let pc: Vec<String> = ctx.pc().iter().map(usize::to_string).collect();
+    let pc = if pc.is_empty() { "0".to_string() } else { pc.join(".") };
+    let next = match instruction_at(prog, ctx.pc()) {
+        Some(Instruction::Loop(..)) => "[".to_string(),
+        Some(Instruction::WriteConst(c)) => format!("write {}", c),
+        Some(instr) => backend::bf::generate(std::slice::from_ref(instr), config).unwrap_or_default().trim_end().to_string(),
+        // At the end of the body of a loop, about to test its condition again
+        None if !prog.is_empty() => "]".to_string(),
+        None => "end of program".to_string(),
+    };
+    eprintln!("step {}, next instruction at {}: {}", ctx.steps(), pc, next);
+    show_tape(ctx, DEFAULT_TAPE_WINDOW);
+    show_watches(ctx, watches);
+}
+
+/// The instruction at position `pc`, given as in [`Context::pc`], or `None` at the end of a loop body.
+fn instruction_at<'a>(prog: &'a [Instruction], pc: &[usize]) -> Option<&'a Instruction> {
+    let Some((&last, outer)) = pc.split_last() else {
+        return prog.first();
+    };
+    let mut body = prog;
+    for &i in outer {
+        match body.get(i) {
+            Some(Instruction::Loop(inner, _)) => body = inner,
+            _ => return None,
+        }
+    }
+    body.get(last)
+}
+
+/// Show `window` cells around the data pointer, marking the cell it points at,
+/// and the stack if it is not empty.
+pub fn show_tape<C: Cell>(ctx: &Context<C>, window: usize) {
+    let tape = ctx.tape();
+    let start = ctx.ptr().saturating_sub(window / 2);
+    let end = start.saturating_add(window).min(tape.len());
+    let cells: Vec<String> = (start..end)
+        .map(|i| if i == ctx.ptr() { format!("[{}]", tape.get(i)) } else { tape.get(i).to_string() })
+        .collect();
+    eprintln!("pointer at {}, cells {}..{}: {}", ctx.ptr(), start, end, cells.join(" "));
+    if !ctx.stack().is_empty() {
+        let stack: Vec<String> = ctx.stack().iter().map(C::to_string).collect();
+        eprintln!("stack, top last: {}", stack.join(" "));
+    }
+}
@@ -0,0 +1,117 @@
+use std::{env, io::{self, IsTerminal}};
+
+use crate::report::json_string;
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// How diagnostics are presented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Rendered for people, see [`Diagnostic::render`].
+    Human,
+    /// One JSON object per line, see [`Diagnostic::to_json`].
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("invalid diagnostics format `{}`, expected human or json", s)),
+        }
+    }
+}
+
+/// A message pointing at a single position in a source file.
+pub struct Diagnostic<'a> {
+    pub severity: Severity,
+    pub path: &'a str,
+    pub src: &'a str,
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+    pub label: &'a str,
+}
+
+impl Diagnostic<'_> {
+    /// Render the diagnostic, showing the offending source line with a caret
+    /// under the given column.
+    ///
+    /// ```text
+    /// error: `]` does not have a matching `[`
+    ///  --> prog.bf:1:3
+    ///   |
+    /// 1 | +-]
+    ///   |   ^ unmatched `]`
+    /// ```
+    pub fn render(&self, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color { format!("{}{}{}", style, text, RESET) } else { text.to_string() }
+        };
+
+        let src_line = self.src.lines().nth(self.line - 1).unwrap_or("");
+        // Keep tabs so the caret lines up with the source line
+        let indent: String = src_line.chars()
+            .take(self.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let line_no = self.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        let bar = paint(BLUE, "|");
+
+        let (style, severity) = match self.severity {
+            Severity::Error => (RED, "error"),
+            Severity::Warning => (YELLOW, "warning"),
+        };
+
+        let mut out = String::new();
+        out += &format!("{}{}\n", paint(style, severity), paint(BOLD, &format!(": {}", self.message)));
+        out += &format!("{}{} {}:{}:{}\n", gutter, paint(BLUE, "-->"), self.path, self.line, self.col);
+        out += &format!("{} {}\n", gutter, bar);
+        out += &format!("{} {} {}\n", paint(BLUE, &line_no), bar, src_line);
+        out += &format!("{} {} {}{}", gutter, bar, indent, paint(style, &format!("^ {}", self.label)));
+        out
+    }
+
+    /// The diagnostic as a single line of JSON, with the span of the offending character
+    /// as byte offsets into the source.
+    ///
+    /// ```text
+    /// {"severity":"error","message":"`]` does not have a matching `[`","label":"unmatched `]`","path":"prog.bf","line":1,"column":3,"span":{"start":2,"end":3}}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let line_start: usize = self.src.split_inclusive('\n').take(self.line - 1).map(str::len).sum();
+        let line = self.src[line_start..].lines().next().unwrap_or("");
+        // Errors at the end of a line or of the file get an empty span there
+        let (start, end) = match line.char_indices().nth(self.col.saturating_sub(1)) {
+            Some((i, c)) => (line_start + i, line_start + i + c.len_utf8()),
+            None => (line_start + line.len(), line_start + line.len()),
+        };
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!("{{\"severity\":\"{}\",\"message\":{},\"label\":{},\"path\":{},\"line\":{},\"column\":{},\"span\":{{\"start\":{},\"end\":{}}}}}",
+            severity, json_string(&self.message), json_string(self.label), json_string(self.path), self.line, self.col, start, end)
+    }
+}
+
+/// Whether diagnostics written to stderr should be colored.
+///
+/// Colors are disabled when stderr is not a terminal, or when `NO_COLOR` is set.
+pub fn use_color() -> bool {
+    io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
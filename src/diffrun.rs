@@ -0,0 +1,85 @@
+use std::io::{self, Write};
+
+use bf::{Instruction, config::Config, events::{Event, Executions}};
+
+/// What a program does next that shows in its output.
+#[derive(PartialEq)]
+enum Outcome {
+    Output(u8),
+    Halted,
+    /// The program failed with this error message.
+    Failed(String),
+}
+
+/// A program running until its next output.
+struct Run<'p> {
+    name: &'p str,
+    executions: Executions<'p>,
+}
+
+impl Run<'_> {
+    fn next(&mut self) -> Outcome {
+        loop {
+            match self.executions.next() {
+                Some(Event::Output(byte)) => return Outcome::Output(byte),
+                Some(Event::Error(e)) => return Outcome::Failed(e.to_string()),
+                Some(Event::Halted) | None => return Outcome::Halted,
+                Some(_) => {},
+            }
+        }
+    }
+
+    fn describe(&self, outcome: &Outcome) -> String {
+        let steps = self.executions.steps();
+        match outcome {
+            Outcome::Output(byte) => format!("{} wrote {} after {} steps", self.name, show(*byte), steps),
+            Outcome::Halted => format!("{} halted after {} steps", self.name, steps),
+            Outcome::Failed(e) => format!("{} failed after {} steps: {}", self.name, steps, e),
+        }
+    }
+}
+
+/// A byte as a hex number, followed by the character if it is printable.
+fn show(byte: u8) -> String {
+    match byte {
+        0x20..=0x7e => format!("{:#04x} `{}`", byte, byte as char),
+        _ => format!("{:#04x}", byte),
+    }
+}
+
+/// Run two programs on the same input, side by side, until their outputs differ,
+/// and describe where. Returns whether the outputs are the same.
+pub fn diff_run(programs: [(&str, &[Instruction]); 2], config: &Config, input: &[u8], w: &mut impl Write) -> io::Result<bool> {
+    let [mut a, mut b] = programs.map(|(name, prog)| {
+        let mut executions = Executions::new(prog, config);
+        executions.feed(input);
+        executions.feed_eof();
+        Run { name, executions }
+    });
+
+    let mut bytes = 0;
+    loop {
+        let (next_a, next_b) = (a.next(), b.next());
+        if next_a != next_b {
+            writeln!(w, "outputs differ at byte {}:", bytes)?;
+            writeln!(w, "  {}", a.describe(&next_a))?;
+            writeln!(w, "  {}", b.describe(&next_b))?;
+            return Ok(false);
+        }
+        match next_a {
+            Outcome::Output(_) => bytes += 1,
+            Outcome::Halted => {
+                writeln!(w, "outputs are the same, {} bytes", bytes)?;
+                writeln!(w, "  {} halted after {} steps", a.name, a.executions.steps())?;
+                writeln!(w, "  {} halted after {} steps", b.name, b.executions.steps())?;
+                return Ok(true);
+            },
+            Outcome::Failed(_) => {
+                writeln!(w, "outputs are the same, {} bytes, and both programs fail the same way", bytes)?;
+                writeln!(w, "  {}", a.describe(&next_a))?;
+                writeln!(w, "  {}", b.describe(&next_b))?;
+                return Ok(true);
+            },
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Compiling programs to Rust in build scripts, to embed them in applications without
+//! parsing them at runtime.
+//!
+//! ```text
+//! // build.rs
+//! fn main() {
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("hello.rs");
+//!     bf::embed::compile("hello.b", "hello", &bf::config::Config::default(), &out).unwrap();
+//! }
+//!
+//! // main.rs
+//! include!(concat!(env!("OUT_DIR"), "/hello.rs"));
+//!
+//! fn main() -> std::io::Result<()> {
+//!     hello(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())
+//! }
+//! ```
+
+use std::{fmt, fs, io, path::Path};
+
+use crate::{Program, opt::PassManager, config::Config, backend, literate};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The program at the given path could not be read.
+    Read(String, io::Error),
+    /// The program at the given path is not valid.
+    Program(String, crate::Error),
+    /// The program uses features that only the interpreter supports.
+    Unsupported(String, String),
+    /// The generated Rust could not be written to the given path.
+    Write(String, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Read(path, e) => write!(f, "could not read `{}`: {}", path, e),
+            Program(path, e) => write!(f, "{}: {}", path, e),
+            Unsupported(path, msg) => write!(f, "{}: {}", path, msg),
+            Write(path, e) => write!(f, "could not write `{}`: {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read(_, e) | Error::Write(_, e) => Some(e),
+            Error::Program(_, e) => Some(e),
+            Error::Unsupported(..) => None,
+        }
+    }
+}
+
+/// Compile the program at `path` to a Rust function named `name`, and write it to `out`
+/// for the crate to `include!`. See [`backend::rust::generate_fn`] for the function.
+///
+/// The program is parsed and optimized under `config`, and is read as literate Brainfuck
+/// if its name ends in `.md`. Cargo is told to run the build script again when it changes.
+pub fn compile(path: impl AsRef<Path>, name: &str, config: &Config, out: impl AsRef<Path>) -> Result<(), Error> {
+    let path = path.as_ref();
+    let code = generate(path, name, config)?;
+    println!("cargo::rerun-if-changed={}", path.display());
+    let out = out.as_ref();
+    fs::write(out, code).map_err(|e| Error::Write(out.display().to_string(), e))
+}
+
+/// The Rust function named `name` for the program at `path`, as [`compile`] writes it.
+pub fn generate(path: impl AsRef<Path>, name: &str, config: &Config) -> Result<String, Error> {
+    let path = path.as_ref();
+    let display = path.display().to_string();
+    let mut src = fs::read_to_string(path).map_err(|e| Error::Read(display.clone(), e))?;
+    if path.extension().is_some_and(|ext| ext == "md") {
+        src = literate::extract(&src);
+    }
+
+    let mut ir = Program::parse_with(&src, config.clone())
+        .map_err(|e| Error::Program(display.clone(), e))?
+        .into_ir();
+    PassManager::from_config(config)
+        .expect("checked by `Program::parse_with`")
+        .run(&mut ir);
+    backend::supported(&ir, config).map_err(|msg| Error::Unsupported(display, msg))?;
+    Ok(backend::rust::generate_fn(&ir, config, name))
+}
@@ -0,0 +1,953 @@
+use core::fmt;
+
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::{collections::VecDeque, fs, io::{self, Read, Write}, hash::{DefaultHasher, Hash, Hasher}, path::PathBuf, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+#[cfg(feature = "std")]
+use crate::{Instruction, cell::Cell, config::{Config, Dialect, EofMode, EmptyLoopMode, FlushPolicy, IoMode}, tape::{self, Tape}, heatmap::Heatmap, profile::Profile, frames::Recorder, input::FromFn, output::ToFn, checkpoint::{self, Checkpoint}, random::Rng, report::ResourceReport, tracefile::{Op, TraceWriter}};
+
+#[derive(Debug)]
+pub enum Error {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The program read past the end of input, with `eof = "error"`.
+    UnexpectedEof,
+    /// The data pointer moved outside of the tape.
+    PointerOutOfBounds,
+    /// The program executed more than the given number of steps.
+    StepLimit(u64),
+    /// The program entered an empty loop with a nonzero cell.
+    InfiniteLoop,
+    /// The input is not a number, in numeric I/O mode.
+    InvalidNumber(String),
+    /// The input is not UTF-8, in Unicode I/O mode.
+    InvalidUtf8(Vec<u8>),
+    /// The program wrote a value that is not a Unicode scalar value, in Unicode I/O mode.
+    InvalidCodepoint(u32),
+    /// The run was stopped through a [`CancelToken`].
+    Cancelled,
+    /// The program wrote the byte given to [`Context::break_on_output`], after this many steps.
+    BreakOnOutput(u8, u64),
+    /// The state after the first number of steps, when `detect_loops` is set, recurred
+    /// after the second, such that the program would repeat the steps in between forever.
+    StateRepeated(u64, u64),
+    /// A self-modifying program ran the bracket at this position of its code, which has no partner.
+    UnmatchedBracket(usize),
+    /// The program pushed onto a full stack, in the stack dialect.
+    StackOverflow,
+    /// The program popped from an empty stack, in the stack dialect.
+    StackUnderflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            #[cfg(feature = "std")]
+            Io(e) => write!(f, "{}", e),
+            UnexpectedEof => write!(f, "unexpected end of file"),
+            PointerOutOfBounds => write!(f, "data pointer moved outside of the tape"),
+            StepLimit(n) => write!(f, "step limit of {} exceeded", n),
+            InfiniteLoop => write!(f, "entered an empty loop `[]` with a nonzero cell, which never ends (intentional infinite loop?)"),
+            InvalidNumber(s) => write!(f, "expected a number as input, found `{}`", s),
+            InvalidUtf8(bytes) => write!(f, "expected UTF-8 as input, found the bytes {:02x?}", bytes),
+            InvalidCodepoint(value) => write!(f, "cannot write {:#x}, which is not a Unicode scalar value", value),
+            Cancelled => write!(f, "execution was cancelled"),
+            BreakOnOutput(byte, steps) => write!(f, "stopped after writing the byte {:#04x} at step {}", byte, steps),
+            StateRepeated(first, again) => write!(f, "the program never ends: its state at step {} recurs at step {}, without reading input in between", first, again),
+            UnmatchedBracket(pos) => write!(f, "the bracket at position {} of the code has no partner", pos),
+            StackOverflow => write!(f, "pushed onto a full stack, which holds as many values as the tape holds cells"),
+            StackUnderflow => write!(f, "popped from an empty stack"),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Number of steps between checks whether a run was cancelled.
+#[cfg(feature = "std")]
+const CANCEL_INTERVAL: u64 = 1 << 16;
+
+/// Number of steps between hashes of the state, to detect infinite loops.
+#[cfg(feature = "std")]
+const LOOP_CHECK_INTERVAL: u64 = 1 << 14;
+
+/// Detection of a state that recurs, using Brent's cycle detection on the hashes of
+/// the states at every check. This finds a cycle of any length, while keeping only one
+/// earlier state.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct LoopDetector {
+    /// Hash of the saved state and the step count at which it was saved.
+    saved: Option<(u64, u64)>,
+    /// Number of checks since the state was saved.
+    checks: u64,
+}
+
+#[cfg(feature = "std")]
+impl LoopDetector {
+    /// Compare the state with hash `hash` after `steps` to the saved state, returning the
+    /// step count of the saved state if they are the same.
+    fn check(&mut self, hash: u64, steps: u64) -> Option<u64> {
+        match self.saved {
+            Some((saved, at)) if saved == hash => return Some(at),
+            // Save the state after every power of two checks
+            Some(_) if !(self.checks + 1).is_power_of_two() => self.checks += 1,
+            _ => {
+                self.saved = Some((hash, steps));
+                self.checks += 1;
+            },
+        }
+        None
+    }
+}
+
+/// A handle to stop a running program from another thread, given to [`Context::cancel_on`].
+///
+/// Clones share the same flag, so one clone can be kept by the host while another is
+/// given to the interpreter. The interpreter checks it every few thousand steps and then
+/// fails with [`Error::Cancelled`].
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+#[cfg(feature = "std")]
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop every run holding this token at its next check.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Allow runs holding this token to continue again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why [`Context::run_for`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The program ran to completion.
+    Finished,
+    /// The program used up its fuel, and continues on the next call.
+    OutOfFuel,
+    /// Reading input would block, and the program continues with the `,` that
+    /// tried to read on the next call.
+    NeedsInput,
+    /// The program wrote the byte given to [`Context::break_on_output`], and continues
+    /// with the next instruction on the next call.
+    BreakOnOutput,
+}
+
+/// Why running (part of) a program stopped early.
+#[cfg(feature = "std")]
+enum Stop {
+    Error(Error),
+    /// The step count reached the fuel limit or the next checkpoint before executing
+    /// the instruction at this position, given innermost first.
+    Pause(Vec<usize>),
+    /// Reading input at this position would block.
+    NeedsInput(Vec<usize>),
+    /// The program ran `@`.
+    Halt,
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for Stop {
+    fn from(e: Error) -> Self {
+        Stop::Error(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Stop {
+    fn from(e: io::Error) -> Self {
+        Stop::Error(Error::Io(e))
+    }
+}
+
+/// Counts the bytes read through it, so that a resumed run can skip them.
+#[cfg(feature = "std")]
+struct CountingReader<'r, R: ?Sized> {
+    rdr: &'r mut R,
+    bytes: u64,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read + ?Sized> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.rdr.read(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+/// The input and output of a program, for when they are not streams already.
+///
+/// ```text
+/// let mut output = Vec::new();
+/// let mut io = Io::from_buffers(b"input", &mut output);
+/// Context::<u8>::with_io(&mut io, &config).eval(&prog)?;
+/// ```
+#[cfg(feature = "std")]
+pub struct Io<'a> {
+    rdr: Box<dyn Read + 'a>,
+    wtr: Box<dyn Write + 'a>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Io<'a> {
+    pub fn new(rdr: impl Read + 'a, wtr: impl Write + 'a) -> Self {
+        Self { rdr: Box::new(rdr), wtr: Box::new(wtr) }
+    }
+
+    /// Read every input byte by calling `read`, which returns `None` at the end of input,
+    /// and pass every output byte to `write`.
+    pub fn from_fns(read: impl FnMut() -> Option<u8> + 'a, write: impl FnMut(u8) + 'a) -> Self {
+        Self::new(FromFn::new(read), ToFn::new(write))
+    }
+
+    /// Read the input from `input`, and append the output to `output`.
+    pub fn from_buffers(input: &'a [u8], output: &'a mut Vec<u8>) -> Self {
+        Self::new(input, output)
+    }
+}
+
+/// Interpreter with cells of type `C`, which determines the cell width.
+///
+/// The input and output are of any type by default. An interpreter created by
+/// [`Context::with_typed_io`] keeps their types instead, so that it is `Send` when they
+/// are, to run it on another thread.
+#[cfg(feature = "std")]
+pub struct Context<'a, C: Cell, R: Read + ?Sized = dyn Read + 'a, W: Write + ?Sized = dyn Write + 'a> {
+    rdr: &'a mut R,
+    wtr: &'a mut W,
+    tape: Box<dyn Tape<C>>,
+    ptr: usize,
+    /// The other tapes and their data pointers, in the order of switching to the next tape.
+    others: VecDeque<(Box<dyn Tape<C>>, usize)>,
+    /// Number of cells the tape may grow to.
+    tape_size: usize,
+    eof: EofMode,
+    io: IoMode,
+    signed: bool,
+    flush: FlushPolicy,
+    /// Number of instructions executed so far.
+    steps: u64,
+    max_steps: Option<u64>,
+    empty_loop: EmptyLoopMode,
+    /// Highest position of the data pointer so far.
+    max_ptr: usize,
+    /// Number of bytes written so far.
+    output_bytes: u64,
+    /// Number of bytes read so far.
+    input_bytes: u64,
+    /// Time spent running so far.
+    elapsed: Duration,
+    /// Access counts per cell, if requested.
+    heatmap: Option<Heatmap>,
+    /// Periodic tape snapshots, if requested.
+    recorder: Option<Recorder>,
+    /// Periodic samples of the position, if requested.
+    profile: Option<Profile>,
+    /// Step count at which the next sample is due.
+    next_sample: u64,
+    /// Detection of infinite loops, if configured.
+    loops: Option<LoopDetector>,
+    /// Step count at which to check for an infinite loop next.
+    next_loop_check: u64,
+    /// File to write checkpoints to and the number of steps between them, if requested.
+    checkpoints: Option<(PathBuf, u64)>,
+    /// Step count at which the next checkpoint is due.
+    next_checkpoint: u64,
+    /// Step count at which the fuel given to [`Context::run_for`] runs out.
+    fuel_end: u64,
+    /// Token to check for cancellation, if any.
+    cancel: Option<CancelToken>,
+    /// Step count at which to check the cancellation token next.
+    next_cancel_check: u64,
+    /// Step count at which to pause for a checkpoint, a sample, to check for cancellation, or when running out of fuel.
+    next_pause: u64,
+    /// Position to continue from, after pausing or restoring a checkpoint.
+    resume: Vec<usize>,
+    /// Time to pause after every step, if requested.
+    step_delay: Option<Duration>,
+    /// Trace of every step, if requested.
+    tracer: Option<TraceWriter<'a>>,
+    /// Byte whose output pauses the run, if requested.
+    break_on_output: Option<u8>,
+    /// Whether the run pauses because that byte was written.
+    output_break: bool,
+    /// Source of the bytes of `?`.
+    rng: Rng,
+    /// The stack of the stack dialect, with its top last.
+    stack: Vec<C>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, C: Cell> Context<'a, C> {
+    pub fn new(rdr: &'a mut impl Read, wtr: &'a mut impl Write, config: &Config) -> Self {
+        Context::with_typed_io(rdr as &mut dyn Read, wtr as &mut dyn Write, config)
+    }
+
+    /// Create an interpreter reading and writing through `io`.
+    pub fn with_io(io: &'a mut Io<'_>, config: &Config) -> Self {
+        Self::new(&mut io.rdr, &mut io.wtr, config)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, C: Cell, R: Read + ?Sized, W: Write + ?Sized> Context<'a, C, R, W> {
+    /// Create an interpreter that keeps the types of its input and output, see [`Context`].
+    pub fn with_typed_io(rdr: &'a mut R, wtr: &'a mut W, config: &Config) -> Self {
+        Self {
+            rdr,
+            wtr,
+            tape: tape::new(config),
+            ptr: 0,
+            others: match config.dialect {
+                Dialect::MultiTape => (1..config.tapes).map(|_| (tape::new(config), 0)).collect(),
+                Dialect::Standard | Dialect::SelfModifying | Dialect::Boolfuck | Dialect::Stack => VecDeque::new(),
+            },
+            tape_size: config.tape_size,
+            eof: config.eof,
+            io: config.io,
+            signed: config.signed,
+            flush: config.flush,
+            steps: 0,
+            max_steps: config.max_steps,
+            empty_loop: config.empty_loop,
+            max_ptr: 0,
+            output_bytes: 0,
+            input_bytes: 0,
+            elapsed: Duration::ZERO,
+            heatmap: None,
+            recorder: None,
+            profile: None,
+            next_sample: u64::MAX,
+            loops: config.detect_loops.then(LoopDetector::default),
+            next_loop_check: if config.detect_loops { LOOP_CHECK_INTERVAL } else { u64::MAX },
+            checkpoints: None,
+            next_checkpoint: u64::MAX,
+            fuel_end: u64::MAX,
+            cancel: None,
+            next_cancel_check: u64::MAX,
+            next_pause: u64::MAX,
+            resume: Vec::new(),
+            step_delay: None,
+            tracer: None,
+            break_on_output: None,
+            output_break: false,
+            rng: Rng::new(config.seed),
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn max_ptr(&self) -> usize {
+        self.max_ptr
+    }
+
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes
+    }
+
+    /// Position of the data pointer on the current tape.
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    /// The resources used so far, also after the program failed.
+    pub fn resources(&self) -> ResourceReport {
+        ResourceReport {
+            steps: self.steps,
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            max_ptr: self.max_ptr,
+            peak_tape_size: self.tape.peak_size() + self.others.iter().map(|(tape, _)| tape.peak_size()).sum::<usize>(),
+            wall_time: self.elapsed,
+        }
+    }
+
+    /// The current tape.
+    pub fn tape(&self) -> &dyn Tape<C> {
+        &*self.tape
+    }
+
+    /// The stack of the stack dialect, with its top last.
+    pub fn stack(&self) -> &[C] {
+        &self.stack
+    }
+
+    /// Position of the next instruction after [`Context::run_for`] paused: its index in the
+    /// program, and in the bodies of the loops it is nested in, outermost first.
+    /// Empty at the start of the program.
+    pub fn pc(&self) -> &[usize] {
+        &self.resume
+    }
+
+    /// Start counting reads and writes of every cell.
+    pub fn record_heatmap(&mut self) {
+        self.heatmap = Some(Heatmap::new());
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    /// Start sampling the position every `every` steps, to see which loops the run spends its time in.
+    pub fn profile_every(&mut self, every: u64) {
+        self.profile = Some(Profile::new(every));
+        self.next_sample = self.steps.saturating_add(every);
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Pause for `delay` after every step, showing the output so far, to watch the program run.
+    pub fn throttle(&mut self, delay: Duration) {
+        self.step_delay = Some(delay);
+    }
+
+    /// Record every step in a trace. The trace numbers the instructions of the program
+    /// given to the first call to [`Context::run_for`] or [`Context::eval`].
+    pub fn trace_to(&mut self, tracer: TraceWriter<'a>) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Pause right after the program writes `byte`, which makes [`Context::run_for`] return
+    /// [`RunStatus::BreakOnOutput`] and [`Context::eval`] fail, unless the program finishes
+    /// right after it.
+    pub fn break_on_output(&mut self, byte: u8) {
+        self.break_on_output = Some(byte);
+    }
+
+    /// Start recording a snapshot of the tape every `every` steps.
+    pub fn record_frames(&mut self, every: u64) {
+        let mut recorder = Recorder::new(every);
+        recorder.snapshot(self.steps, self.ptr, &*self.tape);
+        self.recorder = Some(recorder);
+    }
+
+    /// Stop recording, returning the recorded frames including the final state.
+    pub fn take_frames(&mut self) -> Option<Recorder> {
+        let mut recorder = self.recorder.take()?;
+        recorder.snapshot(self.steps, self.ptr, &*self.tape);
+        Some(recorder)
+    }
+
+    /// Start writing a checkpoint to `path` every `every` steps, replacing the previous one.
+    pub fn checkpoint_every(&mut self, path: impl Into<PathBuf>, every: u64) {
+        self.next_checkpoint = self.steps.saturating_add(every);
+        self.checkpoints = Some((path.into(), every));
+    }
+
+    /// Stop running with [`Error::Cancelled`] once `token` is cancelled, which is checked
+    /// every few thousand steps. The program can be continued where it stopped with
+    /// [`Context::run_for`] after [resetting](CancelToken::reset) the token.
+    pub fn cancel_on(&mut self, token: CancelToken) {
+        self.next_cancel_check = self.steps.saturating_add(CANCEL_INTERVAL);
+        self.cancel = Some(token);
+    }
+
+    /// Continue running `prog` from a checkpoint taken while running it, on the next call to
+    /// [`Context::eval`] on this new context. The input consumed before the checkpoint is read again and skipped,
+    /// so the same input must be given.
+    pub fn restore(&mut self, prog: &[Instruction], checkpoint: Checkpoint) -> Result<(), checkpoint::Error> {
+        if checkpoint.program != checkpoint::program_hash(prog, C::max().to_u32()) || checkpoint.tapes.len() != self.others.len() + 1 {
+            return Err(checkpoint::Error::ProgramMismatch);
+        }
+        let mut body = prog;
+        for (depth, &i) in checkpoint.pc.iter().enumerate() {
+            match body.get(i) {
+                Some(Instruction::Loop(inner, _)) if depth + 1 < checkpoint.pc.len() => body = inner,
+                Some(_) if depth + 1 == checkpoint.pc.len() => {},
+                // Paused at the start of an iteration of an empty loop
+                None if depth > 0 && depth + 1 == checkpoint.pc.len() && i == body.len() => {},
+                _ => return Err(checkpoint::Error::Malformed("program counter outside of the program".to_string())),
+            }
+        }
+
+        // Fill in every tape in turn, growing it as needed, ending up at the first tape again
+        for (cells, ptr) in checkpoint.tapes {
+            self.tape.reach(cells.len().max(ptr + 1) - 1).map_err(|_| checkpoint::Error::ProgramMismatch)?;
+            for (cell, value) in cells.into_iter().enumerate() {
+                self.tape.set(cell, C::from_constant(value));
+            }
+            self.ptr = ptr;
+            self.switch_tape(1);
+        }
+
+        io::copy(&mut (&mut *self.rdr).take(checkpoint.input_bytes), &mut io::sink())?;
+        self.steps = checkpoint.steps;
+        self.input_bytes = checkpoint.input_bytes;
+        self.output_bytes = checkpoint.output_bytes;
+        self.max_ptr = checkpoint.max_ptr;
+        self.resume = checkpoint.pc;
+        if let Some(random) = checkpoint.random {
+            self.rng = Rng::new(Some(random));
+        }
+        self.stack = checkpoint.stack.into_iter().map(C::from_constant).collect();
+        if let Some((_, every)) = self.checkpoints {
+            self.next_checkpoint = self.steps.saturating_add(every);
+        }
+        Ok(())
+    }
+
+    /// The current state, with the next instruction at position `pc`.
+    fn checkpoint(&self, prog: &[Instruction], pc: Vec<usize>) -> Checkpoint {
+        Checkpoint {
+            program: checkpoint::program_hash(prog, C::max().to_u32()),
+            steps: self.steps,
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            max_ptr: self.max_ptr,
+            pc,
+            tapes: std::iter::once((&self.tape, self.ptr)).chain(self.others.iter().map(|(tape, ptr)| (tape, *ptr)))
+                .map(|(tape, ptr)| (tape.to_vec().iter().map(C::to_u32).collect(), ptr))
+                .collect(),
+            random: Some(self.rng.state()),
+            stack: self.stack.iter().map(C::to_u32).collect(),
+        }
+    }
+
+    /// Write a checkpoint, replacing the previous one only once it is complete.
+    fn write_checkpoint(&mut self, prog: &[Instruction], pc: Vec<usize>) -> io::Result<()> {
+        let Some((path, every)) = &self.checkpoints else { return Ok(()) };
+        // Output written before the checkpoint must not be lost if we are interrupted
+        self.wtr.flush()?;
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+        self.checkpoint(prog, pc).write(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.next_checkpoint = self.steps.saturating_add(*every);
+        Ok(())
+    }
+
+    /// Run the program to completion, returning the resources used so far.
+    pub fn eval(&mut self, prog: &[Instruction]) -> Result<ResourceReport, Error> {
+        match self.run_for(prog, u64::MAX)? {
+            RunStatus::NeedsInput => Err(Error::Io(io::ErrorKind::WouldBlock.into())),
+            RunStatus::BreakOnOutput => Err(Error::BreakOnOutput(self.break_on_output.unwrap_or_default(), self.steps)),
+            _ => Ok(self.resources()),
+        }
+    }
+
+    /// Run the program for about `fuel` steps, continuing where the previous call stopped.
+    ///
+    /// This allows interleaving execution with an event loop, with a reader that fails
+    /// with [`io::ErrorKind::WouldBlock`] when no input is available. The program only
+    /// pauses between instructions and before iterations of loops, so it may run a few
+    /// more steps than given. In numeric I/O mode, the reader should only block between
+    /// numbers. After the program finishes, the next call runs it again from the start.
+    pub fn run_for(&mut self, prog: &[Instruction], fuel: u64) -> Result<RunStatus, Error> {
+        let start = Instant::now();
+        self.fuel_end = self.steps.saturating_add(fuel);
+        if let Some(tracer) = &mut self.tracer {
+            tracer.index(prog);
+        }
+        let status = self.run(prog);
+        self.elapsed += start.elapsed();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        status
+    }
+
+    /// Run the program until it finishes or stops before the end of the fuel.
+    fn run(&mut self, prog: &[Instruction]) -> Result<RunStatus, Error> {
+        loop {
+            self.next_pause = self.next_checkpoint.min(self.next_sample).min(self.next_loop_check)
+                .min(self.next_cancel_check).min(self.fuel_end);
+            let resume = std::mem::take(&mut self.resume);
+            match self.exec(prog, &resume) {
+                Ok(()) | Err(Stop::Halt) => {
+                    self.output_break = false;
+                    return Ok(RunStatus::Finished);
+                },
+                Err(Stop::Error(e)) => return Err(e),
+                Err(Stop::NeedsInput(mut pc)) => {
+                    pc.reverse();
+                    self.resume = pc;
+                    return Ok(RunStatus::NeedsInput);
+                },
+                Err(Stop::Pause(mut pc)) => {
+                    pc.reverse();
+                    if self.steps >= self.next_checkpoint {
+                        self.write_checkpoint(prog, pc.clone())?;
+                    }
+                    if let Some(profile) = &mut self.profile && self.steps >= self.next_sample {
+                        profile.sample(&pc);
+                        self.next_sample = self.steps.saturating_add(profile.every());
+                    }
+                    if self.loops.is_some() && self.steps >= self.next_loop_check {
+                        self.next_loop_check = self.steps.saturating_add(LOOP_CHECK_INTERVAL);
+                        let hash = self.state_hash(&pc);
+                        if let Some(loops) = &mut self.loops && let Some(first) = loops.check(hash, self.steps) {
+                            return Err(Error::StateRepeated(first, self.steps));
+                        }
+                    }
+                    self.resume = pc;
+                    if let Some(cancel) = &self.cancel && self.steps >= self.next_cancel_check {
+                        self.next_cancel_check = self.steps.saturating_add(CANCEL_INTERVAL);
+                        if cancel.is_cancelled() {
+                            return Err(Error::Cancelled);
+                        }
+                    }
+                    if std::mem::take(&mut self.output_break) {
+                        // Show the output up to the break
+                        self.wtr.flush()?;
+                        return Ok(RunStatus::BreakOnOutput);
+                    }
+                    if self.steps >= self.fuel_end {
+                        return Ok(RunStatus::OutOfFuel);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Run `prog`, starting at position `resume` if it is not empty.
+    fn exec(&mut self, prog: &[Instruction], resume: &[usize]) -> Result<(), Stop> {
+        let (start, inner) = resume.split_first().map_or((0, &[][..]), |(&i, inner)| (i, inner));
+        for (i, instr) in prog.iter().enumerate().skip(start) {
+            use Instruction::*;
+            if i == start && !inner.is_empty() {
+                // Continue in the middle of an iteration of this loop
+                let Loop(body, _) = instr else { unreachable!("checked by `Context::restore`") };
+                self.exec(body, inner).map_err(|stop| at(stop, i))?;
+                self.traced_step(instr, Op::LoopTest)?;
+                self.run_loop(instr, body).map_err(|stop| at(stop, i))?;
+                self.traced_step(instr, Op::Loop)?;
+                continue;
+            }
+            if self.steps >= self.next_pause {
+                return Err(Stop::Pause(vec![i]));
+            }
+
+            if let Some(heatmap) = &mut self.heatmap {
+                match instr {
+                    IncPtr(_) | DecPtr(_) | WriteConst(_) | SwitchTape(_) | Halt => {},
+                    IncVal(_) | DecVal(_) | ClearVal | SetVal(_) | Read | Random | Pop => heatmap.write(self.ptr),
+                    Write | Push | Loop(..) => heatmap.read(self.ptr),
+                    MulLoop(targets, _) => {
+                        heatmap.read(self.ptr);
+                        if !self.tape.get(self.ptr).is_zero() {
+                            for (offset, _) in targets {
+                                if let Some(cell) = self.ptr.checked_add_signed(*offset).filter(|&c| c < self.tape_size) {
+                                    heatmap.write(cell);
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+
+            match instr {
+                IncPtr(x) => {
+                    self.ptr += *x;
+                    self.tape.reach(self.ptr)?;
+                    self.max_ptr = self.max_ptr.max(self.ptr);
+                },
+                DecPtr(x) => {
+                    self.ptr = self.ptr.checked_sub(*x)
+                        .ok_or(Error::PointerOutOfBounds)?;
+                },
+                IncVal(x) => self.tape.set(self.ptr, self.tape.get(self.ptr).wrapping_add(*x as u32)),
+                DecVal(x) => self.tape.set(self.ptr, self.tape.get(self.ptr).wrapping_sub(*x as u32)),
+                ClearVal => self.tape.set(self.ptr, C::default()),
+                SetVal(x) => self.tape.set(self.ptr, C::from_constant(*x)),
+                WriteConst(x) => self.write(&[*x])?,
+                Write if self.io == IoMode::Numeric => {
+                    let value = self.tape.get(self.ptr);
+                    let line = if self.signed {
+                        format!("{}\n", value.signed())
+                    } else {
+                        format!("{}\n", value)
+                    };
+                    self.write(line.as_bytes())?;
+                },
+                Write if self.io == IoMode::Unicode => {
+                    let value = self.tape.get(self.ptr).to_u32();
+                    let c = char::from_u32(value).ok_or(Error::InvalidCodepoint(value))?;
+                    self.write(c.encode_utf8(&mut [0; 4]).as_bytes())?;
+                },
+                Write => {
+                    // Only the lowest byte of wider cells is written
+                    self.write(&[self.tape.get(self.ptr).to_byte()])?;
+                },
+                Read => {
+                    // Make sure prompts are visible before blocking on input
+                    if self.flush != FlushPolicy::OnExit {
+                        self.wtr.flush()?;
+                    }
+                    let mut rdr = CountingReader { rdr: &mut *self.rdr, bytes: 0 };
+                    let input = match self.io {
+                        IoMode::Bytes | IoMode::Bits => read_byte(&mut rdr).map(|byte| byte.map(C::from_byte)),
+                        IoMode::Numeric => read_number(&mut rdr).map(|number| number.map(|number| C::parse(&number))),
+                        IoMode::Unicode => read_char(&mut rdr).map(|c| c.map(|c| C::from_constant(c as u32))),
+                    };
+                    self.input_bytes += rdr.bytes;
+                    let input = match input {
+                        Err(Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => return Err(Stop::NeedsInput(vec![i])),
+                        input => input?,
+                    };
+                    match input {
+                        Some(value) => self.tape.set(self.ptr, value),
+                        None => match self.eof {
+                            EofMode::Unchanged => {},
+                            EofMode::Zero => self.tape.set(self.ptr, C::default()),
+                            EofMode::Max => self.tape.set(self.ptr, C::max()),
+                            EofMode::Error => return Err(Error::UnexpectedEof.into()),
+                        },
+                    }
+                },
+                SwitchTape(x) => self.switch_tape(*x),
+                Random => self.tape.set(self.ptr, C::from_byte(self.rng.byte())),
+                Push => {
+                    if self.stack.len() >= self.tape_size {
+                        return Err(Error::StackOverflow.into());
+                    }
+                    self.stack.push(self.tape.get(self.ptr));
+                },
+                Pop => {
+                    let value = self.stack.pop().ok_or(Error::StackUnderflow)?;
+                    self.tape.set(self.ptr, value);
+                },
+                Halt => {
+                    self.traced_step(instr, Op::Halt)?;
+                    return Err(Stop::Halt);
+                },
+                MulLoop(targets, step) => {
+                    let counter = self.tape.get(self.ptr);
+                    if !counter.is_zero() {
+                        let Some(iterations) = counter.iterations(*step) else {
+                            // The counter never reaches zero, so the loop runs until the step limit
+                            loop {
+                                if self.steps >= self.next_pause {
+                                    return Err(Stop::Pause(vec![i]));
+                                }
+                                self.traced_step(instr, Op::MulLoop)?;
+                            }
+                        };
+                        for (offset, factor) in targets {
+                            let cell = self.tape.shift(self.ptr, *offset)?;
+                            self.max_ptr = self.max_ptr.max(cell);
+                            self.tape.set(cell, self.tape.get(cell).add_product(&iterations, *factor));
+                        }
+                        self.tape.set(self.ptr, C::default());
+                    }
+                },
+                Loop(inner, _) => {
+                    if inner.is_empty() && !self.tape.get(self.ptr).is_zero() && self.empty_loop == EmptyLoopMode::Error {
+                        return Err(Error::InfiniteLoop.into());
+                    }
+                    self.run_loop(instr, inner).map_err(|stop| at(stop, i))?;
+                }
+            }
+
+            self.traced_step(instr, Op::of(instr))?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the `body` of the loop `instr` while the cell at the data pointer is nonzero.
+    fn run_loop(&mut self, instr: &Instruction, body: &[Instruction]) -> Result<(), Stop> {
+        while !self.tape.get(self.ptr).is_zero() {
+            // Also pause in loops without instructions to pause before
+            if self.steps >= self.next_pause {
+                return Err(Stop::Pause(vec![0]));
+            }
+            self.exec(body, &[])?;
+            // Every re-test of the loop condition is a read and a step as well
+            if let Some(heatmap) = &mut self.heatmap {
+                heatmap.read(self.ptr);
+            }
+            self.traced_step(instr, Op::LoopTest)?;
+        }
+        Ok(())
+    }
+
+    /// Hash of everything that determines how the program continues from position `pc`:
+    /// the tapes up to the furthest position reached, the data pointers, the stack, the
+    /// amount of input read and the state of the random bytes of `?`.
+    fn state_hash(&self, pc: &[usize]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        pc.hash(&mut hasher);
+        self.input_bytes.hash(&mut hasher);
+        self.rng.hash(&mut hasher);
+        self.stack.hash(&mut hasher);
+        for (tape, ptr) in std::iter::once((&self.tape, self.ptr)).chain(self.others.iter().map(|(tape, ptr)| (tape, *ptr))) {
+            ptr.hash(&mut hasher);
+            for cell in 0..tape.len().min(self.max_ptr + 1) {
+                tape.get(cell).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Write and count output, flushing it if requested.
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.wtr.write_all(bytes)?;
+        self.output_bytes += bytes.len() as u64;
+        if self.break_on_output.is_some_and(|byte| bytes.contains(&byte)) {
+            // Pause before the next instruction
+            self.output_break = true;
+            self.next_pause = self.steps;
+        }
+        if self.flush == FlushPolicy::EveryWrite {
+            self.wtr.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Switch to the tape `n` tapes further, wrapping around.
+    fn switch_tape(&mut self, n: isize) {
+        if self.others.is_empty() {
+            return;
+        }
+        let n = n.rem_euclid(self.others.len() as isize + 1) as usize;
+        for _ in 0..n {
+            let (tape, ptr) = self.others.pop_front().unwrap();
+            let tape = std::mem::replace(&mut self.tape, tape);
+            self.others.push_back((tape, std::mem::replace(&mut self.ptr, ptr)));
+        }
+    }
+
+    /// Count a step that executed `instr`, recording it in the trace if requested.
+    fn traced_step(&mut self, instr: &Instruction, op: Op) -> Result<(), Error> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(self.steps + 1, instr, op, self.ptr, self.tape.get(self.ptr).to_u32())?;
+        }
+        self.step()
+    }
+
+    /// Count an executed instruction, enforcing the step limit.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(Error::StepLimit(self.steps - 1));
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.step(self.steps, self.ptr, &*self.tape);
+        }
+        if let Some(delay) = self.step_delay {
+            self.wtr.flush()?;
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+}
+
+/// Add the index of the loop `i` that was running to the position of a pause.
+#[cfg(feature = "std")]
+fn at(stop: Stop, i: usize) -> Stop {
+    match stop {
+        Stop::Pause(mut pc) => {
+            pc.push(i);
+            Stop::Pause(pc)
+        },
+        Stop::NeedsInput(mut pc) => {
+            pc.push(i);
+            Stop::NeedsInput(pc)
+        },
+        stop => stop,
+    }
+}
+
+/// Read a single byte, or `None` at the end of input.
+#[cfg(feature = "std")]
+pub fn read_byte(rdr: &mut impl Read) -> Result<Option<u8>, Error> {
+    let mut input = [0u8; 1];
+    Ok((rdr.read(&mut input)? == 1).then_some(input[0]))
+}
+
+/// Read a single character encoded as UTF-8, or `None` at the end of input.
+#[cfg(feature = "std")]
+pub fn read_char(rdr: &mut impl Read) -> Result<Option<char>, Error> {
+    let Some(first) = read_byte(rdr)? else {
+        return Ok(None);
+    };
+    let len = match first.leading_ones() {
+        0 => 1,
+        n @ 2..=4 => n as usize,
+        _ => return Err(Error::InvalidUtf8(vec![first])),
+    };
+    let mut bytes = vec![first];
+    while bytes.len() < len {
+        match read_byte(rdr)? {
+            Some(byte) => bytes.push(byte),
+            None => return Err(Error::InvalidUtf8(bytes)),
+        }
+    }
+    match core::str::from_utf8(&bytes) {
+        Ok(s) => Ok(s.chars().next()),
+        Err(_) => Err(Error::InvalidUtf8(bytes)),
+    }
+}
+
+/// Read a decimal number with an optional sign, skipping leading whitespace,
+/// or `None` at the end of input. The character after the number is consumed.
+#[cfg(feature = "std")]
+pub fn read_number(rdr: &mut impl Read) -> Result<Option<String>, Error> {
+    let mut number = String::new();
+    while let Some(byte) = read_byte(rdr)? {
+        match byte {
+            _ if byte.is_ascii_whitespace() && number.is_empty() => {},
+            b'0'..=b'9' => number.push(byte as char),
+            b'-' | b'+' if number.is_empty() => number.push(byte as char),
+            _ if number.ends_with(|c: char| c.is_ascii_digit()) => return Ok(Some(number)),
+            _ => {
+                number.push(byte as char);
+                return Err(Error::InvalidNumber(number));
+            },
+        }
+    }
+
+    match number.as_str() {
+        "" => Ok(None),
+        "-" | "+" => Err(Error::InvalidNumber(number)),
+        _ => Ok(Some(number)),
+    }
+}
+
+/// The value of a number read by [`read_number`] modulo 2^32, like wrapping arithmetic on a cell.
+pub fn wrap_number(number: &str) -> u32 {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number.trim_start_matches('+')),
+    };
+    let value = digits.bytes().fold(0u32, |n, d| n.wrapping_mul(10).wrapping_add((d - b'0') as u32));
+    if negative { value.wrapping_neg() } else { value }
+}
@@ -0,0 +1,336 @@
+//! Execution as an iterator of events, to drive a program from the outside.
+//!
+//! This evaluator only needs `alloc`, unlike [`Context`](crate::eval::Context).
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
+
+use crate::{Instruction, opt, config::{Config, Dialect, EofMode, EmptyLoopMode}, eval::Error, random::Rng, taint::Taint};
+
+/// Something a running program did that its host may want to react to.
+#[derive(Debug)]
+pub enum Event {
+    /// The program wrote a byte.
+    Output(u8),
+    /// The program waits for input at a `,`. It continues once input is given
+    /// with [`Executions::feed`] or [`Executions::feed_eof`], and yields this event
+    /// again until then.
+    NeedsInput,
+    /// The program entered a loop, whose body runs at least once.
+    LoopEntered,
+    /// The program ran to completion.
+    Halted,
+    /// The program failed and cannot continue.
+    Error(Error),
+}
+
+/// A running program, which runs until its next [`Event`] on every call to [`Iterator::next`].
+///
+/// ```text
+/// use bf::{lexer::Lexer, parser::Parser, config::Config, events::{Event, Executions}};
+///
+/// let prog = Parser::new(Lexer::new(",[.,]")).parse().unwrap();
+/// let mut executions = Executions::new(&prog, &Config::default());
+/// let mut output = Vec::new();
+/// let mut input = b"hi".iter();
+/// while let Some(event) = executions.next() {
+///     match event {
+///         Event::Output(byte) => output.push(byte),
+///         Event::NeedsInput => match input.next() {
+///             Some(&byte) => executions.feed(&[byte]),
+///             None => executions.feed_eof(),
+///         },
+///         _ => {},
+///     }
+/// }
+/// assert_eq!(output, b"hi");
+/// ```
+///
+/// Input and output are always bytes, whatever the configured I/O mode.
+/// The tape grows as it is used, up to the configured tape size.
+pub struct Executions<'p> {
+    /// The bodies being run, innermost last, with the position of the next instruction in each.
+    frames: Vec<(&'p [Instruction], usize)>,
+    /// All tapes with their data pointers; only one without the multi-tape dialect.
+    tapes: Vec<(Vec<u32>, usize)>,
+    /// Index of the current tape.
+    current: usize,
+    tape_size: usize,
+    /// Mask selecting the bits of a cell, determined by the cell width.
+    mask: u32,
+    eof: EofMode,
+    empty_loop: EmptyLoopMode,
+    max_steps: Option<u64>,
+    steps: u64,
+    /// Input given but not read yet.
+    input: VecDeque<u8>,
+    /// Whether the end of input was given.
+    input_closed: bool,
+    /// Error to report after the event of the instruction that caused it.
+    error: Option<Error>,
+    /// Whether the program halted or failed.
+    done: bool,
+    taint: Option<Taint>,
+    /// Source of the bytes of `?`.
+    rng: Rng,
+    /// The stack of the stack dialect, with its top last.
+    stack: Vec<u32>,
+}
+
+impl<'p> Executions<'p> {
+    pub fn new(prog: &'p [Instruction], config: &Config) -> Self {
+        let tapes = match config.dialect {
+            Dialect::MultiTape => config.tapes,
+            Dialect::Standard | Dialect::SelfModifying | Dialect::Boolfuck | Dialect::Stack => 1,
+        };
+        Self {
+            frames: vec![(prog, 0)],
+            tapes: vec![(vec![0], 0); tapes.max(1)],
+            current: 0,
+            tape_size: config.tape_size,
+            mask: config.cell_width.mask(),
+            eof: config.eof,
+            empty_loop: config.empty_loop,
+            max_steps: config.max_steps,
+            steps: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            error: None,
+            done: false,
+            taint: None,
+            rng: Rng::new(config.seed),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Keep track of the input bytes every cell and output byte depends on, from now on.
+    pub fn track_taint(&mut self) {
+        self.taint = Some(Taint::new(self.tapes.len()));
+    }
+
+    /// The input bytes every cell and output byte depends on, if tracked.
+    pub fn taint(&self) -> Option<&Taint> {
+        self.taint.as_ref()
+    }
+
+    /// Give input to the program, to read after any input given before.
+    pub fn feed(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// End the input, after any input given before.
+    pub fn feed_eof(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Number of instructions executed so far, counted as by [`Context::steps`](crate::eval::Context::steps).
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Position of the data pointer on the current tape.
+    pub fn ptr(&self) -> usize {
+        self.tapes[self.current].1
+    }
+
+    /// The cells of the current tape used so far.
+    pub fn tape(&self) -> &[u32] {
+        &self.tapes[self.current].0
+    }
+
+    fn cell(&mut self) -> &mut u32 {
+        let (tape, ptr) = &mut self.tapes[self.current];
+        &mut tape[*ptr]
+    }
+
+    /// Make sure the current tape includes `cell`, growing it if needed.
+    fn reach(&mut self, cell: usize) -> Result<(), Error> {
+        if cell >= self.tape_size {
+            return Err(Error::PointerOutOfBounds);
+        }
+        let tape = &mut self.tapes[self.current].0;
+        if cell >= tape.len() {
+            tape.resize(cell + 1, 0);
+        }
+        Ok(())
+    }
+
+    /// Count an executed instruction, enforcing the step limit.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        match self.max_steps {
+            Some(max) if self.steps > max => Err(Error::StepLimit(self.steps - 1)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Execute the next instruction, returning the event it caused, if any.
+    fn advance(&mut self) -> Result<Option<Event>, Error> {
+        let depth = self.frames.len();
+        let (body, i) = self.frames[depth - 1];
+        let Some(instr) = body.get(i) else {
+            if depth == 1 {
+                return Ok(Some(Event::Halted));
+            }
+            // Test the condition of the loop again, and leave it if the cell is zero
+            self.step()?;
+            let (tape, ptr) = (self.current, self.ptr());
+            if *self.cell() != 0 {
+                self.frames[depth - 1].1 = 0;
+                if let Some(taint) = &mut self.taint {
+                    taint.repeat(tape, ptr);
+                }
+            } else {
+                if let Some(taint) = &mut self.taint {
+                    taint.leave();
+                }
+                self.frames.pop();
+                self.frames[depth - 2].1 += 1;
+                self.step()?;
+            }
+            return Ok(None);
+        };
+
+        use Instruction::*;
+        let mut event = None;
+        if let Some(taint) = &mut self.taint {
+            let (tape, ptr) = (self.current, self.tapes[self.current].1);
+            match instr {
+                IncVal(_) | DecVal(_) => taint.update(tape, ptr),
+                ClearVal | SetVal(_) | Random => taint.set(tape, ptr),
+                Push => taint.push(tape, ptr),
+                Pop => taint.pop(tape, ptr),
+                Write => taint.write(self.tapes[tape].0[ptr] as u8, Some((tape, ptr))),
+                WriteConst(x) => taint.write(*x, None),
+                Read => match (self.input.front(), self.eof) {
+                    (Some(_), _) => taint.read(tape, ptr),
+                    (None, EofMode::Zero | EofMode::Max) if self.input_closed => taint.set(tape, ptr),
+                    _ => {},
+                },
+                MulLoop(targets, _) if self.tapes[tape].0[ptr] != 0 => {
+                    // Targets out of bounds fail the instruction, and are not tainted
+                    let targets = targets.iter().filter_map(|(offset, _)| ptr.checked_add_signed(*offset));
+                    taint.multiply(tape, ptr, targets.filter(|&cell| cell < self.tape_size));
+                },
+                Loop(..) if self.tapes[tape].0[ptr] != 0 => taint.enter(tape, ptr),
+                _ => {},
+            }
+        }
+        match instr {
+            IncPtr(x) => {
+                let ptr = self.ptr() + x;
+                self.reach(ptr)?;
+                self.tapes[self.current].1 = ptr;
+            },
+            DecPtr(x) => {
+                self.tapes[self.current].1 = self.ptr().checked_sub(*x).ok_or(Error::PointerOutOfBounds)?;
+            },
+            IncVal(x) => *self.cell() = self.cell().wrapping_add(*x as u32) & self.mask,
+            DecVal(x) => *self.cell() = self.cell().wrapping_sub(*x as u32) & self.mask,
+            ClearVal => *self.cell() = 0,
+            SetVal(x) => *self.cell() = *x & self.mask,
+            // Only the lowest byte of wider cells is written
+            Write => event = Some(Event::Output(*self.cell() as u8)),
+            WriteConst(x) => event = Some(Event::Output(*x)),
+            Read => match self.input.pop_front() {
+                Some(byte) => *self.cell() = byte as u32 & self.mask,
+                None if !self.input_closed => return Ok(Some(Event::NeedsInput)),
+                None => match self.eof {
+                    EofMode::Unchanged => {},
+                    EofMode::Zero => *self.cell() = 0,
+                    EofMode::Max => *self.cell() = self.mask,
+                    EofMode::Error => return Err(Error::UnexpectedEof),
+                },
+            },
+            SwitchTape(x) => self.current = (self.current as isize + x).rem_euclid(self.tapes.len() as isize) as usize,
+            Random => *self.cell() = self.rng.byte() as u32 & self.mask,
+            Push => {
+                if self.stack.len() >= self.tape_size {
+                    return Err(Error::StackOverflow);
+                }
+                let value = *self.cell();
+                self.stack.push(value);
+            },
+            Pop => *self.cell() = self.stack.pop().ok_or(Error::StackUnderflow)?,
+            Halt => {
+                self.step()?;
+                return Ok(Some(Event::Halted));
+            },
+            MulLoop(targets, step) => {
+                let counter = *self.cell();
+                if counter != 0 {
+                    let iterations = counter.wrapping_mul(opt::inverse(*step)) & self.mask;
+                    let ptr = self.ptr();
+                    for (offset, factor) in targets {
+                        let cell = ptr.checked_add_signed(*offset).ok_or(Error::PointerOutOfBounds)?;
+                        self.reach(cell)?;
+                        let tape = &mut self.tapes[self.current].0;
+                        tape[cell] = tape[cell].wrapping_add(iterations.wrapping_mul(*factor)) & self.mask;
+                    }
+                    *self.cell() = 0;
+                }
+            },
+            Loop(inner, _) => {
+                if *self.cell() != 0 {
+                    if inner.is_empty() && self.empty_loop == EmptyLoopMode::Error {
+                        return Err(Error::InfiniteLoop);
+                    }
+                    self.frames.push((inner, 0));
+                    return Ok(Some(Event::LoopEntered));
+                }
+            },
+        }
+
+        self.frames[depth - 1].1 += 1;
+        match self.step() {
+            // Output written just before reaching the step limit is not lost
+            Err(e) if event.is_some() => self.error = Some(e),
+            result => result?,
+        }
+        Ok(event)
+    }
+}
+
+impl Iterator for Executions<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let result = match self.error.take() {
+                Some(e) => Err(e),
+                None => self.advance(),
+            };
+            match result {
+                Ok(None) => continue,
+                Ok(Some(event)) => {
+                    self.done = matches!(event, Event::Halted);
+                    return Some(event);
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Event::Error(e));
+                },
+            }
+        }
+    }
+}
+
+/// Run a program to completion on the given input, returning its output.
+///
+/// The end of `input` is the end of input to the program.
+pub fn run(prog: &[Instruction], config: &Config, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut executions = Executions::new(prog, config);
+    executions.feed(input);
+    executions.feed_eof();
+    let mut output = Vec::new();
+    for event in executions {
+        match event {
+            Event::Output(byte) => output.push(byte),
+            Event::Error(e) => return Err(e),
+            _ => {},
+        }
+    }
+    Ok(output)
+}
@@ -0,0 +1,45 @@
+/// Indentation of every level of loop nesting.
+const INDENT: &str = "    ";
+
+/// Indent every line of the program by the depth of the loops it is in, keeping the
+/// code and comments on every line as they are.
+///
+/// Trailing whitespace is removed, as are leading and trailing blank lines, and
+/// consecutive blank lines are collapsed into one. A line starting with `]` is
+/// indented as the line of the matching `[`:
+///
+/// ```text
+/// ++[
+///     >+< move one over
+/// ]
+/// ```
+pub fn format(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut depth = 0usize;
+    let mut blank = false;
+    for line in src.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+
+        let closing = line.chars().take_while(|&c| c == ']').count();
+        out += &INDENT.repeat(depth.saturating_sub(closing));
+        out += line;
+        out.push('\n');
+
+        for c in line.chars() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth = depth.saturating_sub(1),
+                _ => {},
+            }
+        }
+    }
+    out
+}
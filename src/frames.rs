@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+
+use crate::{cell::Cell, tape::Tape};
+
+/// Maximum number of cells shown in a frame.
+const MAX_CELLS: usize = 128;
+
+/// A snapshot of the tape after some number of steps.
+pub struct Frame {
+    pub step: u64,
+    pub ptr: usize,
+    pub cells: Vec<u32>,
+}
+
+/// Records tape snapshots every fixed number of steps, for exporting as an animation.
+pub struct Recorder {
+    every: u64,
+    frames: Vec<Frame>,
+    /// Highest pointer position seen so far, which determines the number of cells to record.
+    max_ptr: usize,
+}
+
+impl Recorder {
+    pub fn new(every: u64) -> Self {
+        Self { every, frames: Vec::new(), max_ptr: 0 }
+    }
+
+    /// Record a snapshot if `step` is a multiple of the recording interval.
+    pub fn step<C: Cell>(&mut self, step: u64, ptr: usize, tape: &dyn Tape<C>) {
+        self.max_ptr = self.max_ptr.max(ptr);
+        if step.is_multiple_of(self.every) {
+            self.snapshot(step, ptr, tape);
+        }
+    }
+
+    /// Record a snapshot unconditionally, e.g. of the final state.
+    pub fn snapshot<C: Cell>(&mut self, step: u64, ptr: usize, tape: &dyn Tape<C>) {
+        self.max_ptr = self.max_ptr.max(ptr);
+        let len = (self.max_ptr + 1).min(MAX_CELLS).min(tape.len());
+        self.frames.push(Frame { step, ptr, cells: (0..len).map(|cell| tape.get(cell).to_u32()).collect() });
+    }
+
+    /// Write the recorded frames in a plain text format, one frame per block:
+    ///
+    /// ```text
+    /// frame 1000 ptr 2
+    /// 0 0 72 104 88
+    /// ```
+    pub fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "# bf tape frames v1")?;
+        for frame in &self.frames {
+            writeln!(w, "frame {} ptr {}", frame.step, frame.ptr)?;
+            let cells: Vec<String> = frame.cells.iter().map(u32::to_string).collect();
+            writeln!(w, "{}", cells.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Write the recorded frames as a looping animated GIF.
+    ///
+    /// Every cell is drawn as a block whose brightness is its (lowest byte) value,
+    /// with a red marker underneath the cell at the data pointer.
+    pub fn write_gif(&self, w: &mut impl Write) -> io::Result<()> {
+        let cells = self.frames.iter().map(|f| f.cells.len()).max().unwrap_or(1);
+        let width = (cells * CELL_SIZE) as u16;
+        let height = (CELL_SIZE + MARKER_SIZE) as u16;
+
+        // Header, logical screen descriptor and global color table
+        w.write_all(b"GIF89a")?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        w.write_all(&[0xE6, BACKGROUND, 0])?;
+        for i in 0..PALETTE_SIZE {
+            w.write_all(&palette(i))?;
+        }
+
+        // Loop the animation forever
+        w.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        for frame in &self.frames {
+            // Graphic control extension with a 100ms frame delay
+            w.write_all(&[0x21, 0xF9, 0x04, 0x00, 10, 0, 0, 0])?;
+            // Image descriptor covering the whole screen
+            w.write_all(&[0x2C, 0, 0, 0, 0])?;
+            w.write_all(&width.to_le_bytes())?;
+            w.write_all(&height.to_le_bytes())?;
+            w.write_all(&[0])?;
+
+            let pixels = render(frame, width as usize, height as usize);
+            write_lzw(w, &pixels)?;
+        }
+
+        w.write_all(&[0x3B])
+    }
+}
+
+/// Size in pixels of the square block of one cell.
+const CELL_SIZE: usize = 12;
+/// Height in pixels of the pointer marker.
+const MARKER_SIZE: usize = 4;
+/// Number of colors in the palette; cell values use all but the last two.
+const PALETTE_SIZE: u8 = 128;
+const BACKGROUND: u8 = PALETTE_SIZE - 2;
+const MARKER: u8 = PALETTE_SIZE - 1;
+/// Number of bits of a palette index, used as the LZW minimum code size.
+const COLOR_BITS: u8 = 7;
+
+fn palette(i: u8) -> [u8; 3] {
+    match i {
+        BACKGROUND => [0, 0, 0],
+        MARKER => [220, 40, 40],
+        _ => {
+            let v = 40 + (i as u32 * 215 / (BACKGROUND as u32 - 1)) as u8;
+            [v / 3, v / 2 + v / 3, v]
+        }
+    }
+}
+
+fn render(frame: &Frame, width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = vec![BACKGROUND; width * height];
+    for (i, &value) in frame.cells.iter().enumerate() {
+        let color = ((value & 0xFF) * (BACKGROUND as u32 - 1) / 255) as u8;
+        // Leave a one pixel gap between cells
+        for y in 1..CELL_SIZE - 1 {
+            for x in 1..CELL_SIZE - 1 {
+                pixels[y * width + i * CELL_SIZE + x] = color;
+            }
+        }
+    }
+
+    // The pointer may be beyond the last cell shown
+    if frame.ptr < width / CELL_SIZE {
+        for y in CELL_SIZE..CELL_SIZE + MARKER_SIZE {
+            for x in 1..CELL_SIZE - 1 {
+                pixels[y * width + frame.ptr * CELL_SIZE + x] = MARKER;
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Write pixels as GIF image data without actual compression.
+///
+/// Every pixel is emitted as a literal code, and the code table is reset before it
+/// would grow past 8-bit codes, so every code is exactly one byte.
+fn write_lzw(w: &mut impl Write, pixels: &[u8]) -> io::Result<()> {
+    let clear = 1u8 << COLOR_BITS;
+    let end = clear + 1;
+    let run = (1usize << COLOR_BITS) - 2;
+
+    let mut codes = Vec::with_capacity(pixels.len() + pixels.len() / run + 2);
+    for chunk in pixels.chunks(run) {
+        codes.push(clear);
+        codes.extend_from_slice(chunk);
+    }
+    codes.push(end);
+
+    w.write_all(&[COLOR_BITS])?;
+    for block in codes.chunks(255) {
+        w.write_all(&[block.len() as u8])?;
+        w.write_all(block)?;
+    }
+    w.write_all(&[0])
+}
@@ -0,0 +1,115 @@
+use std::io::{self, Write};
+
+/// How to present the heatmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Colored blocks, for viewing in a terminal.
+    Term,
+    /// One `cell,reads,writes` row per cell.
+    Csv,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "term" => Ok(Format::Term),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("invalid heatmap format `{}`, expected term or csv", s)),
+        }
+    }
+}
+
+/// Number of cells shown per row of the terminal heatmap.
+const ROW_WIDTH: usize = 32;
+
+/// Read and write counts for every cell of the tape.
+///
+/// Testing a cell in a loop condition and outputting it count as reads,
+/// every modification of a cell counts as a write.
+///
+/// The counts only cover the cells up to the last accessed cell, so that they grow with
+/// the part of the tape that is used, however large the tape is.
+#[derive(Default)]
+pub struct Heatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&mut self, cell: usize) {
+        self.reach(cell);
+        self.reads[cell] += 1;
+    }
+
+    pub fn write(&mut self, cell: usize) {
+        self.reach(cell);
+        self.writes[cell] += 1;
+    }
+
+    /// Make room for the counts of `cell`.
+    fn reach(&mut self, cell: usize) {
+        if cell >= self.reads.len() {
+            self.reads.resize(cell + 1, 0);
+            self.writes.resize(cell + 1, 0);
+        }
+    }
+
+    /// Number of cells up to and including the last accessed cell.
+    fn used(&self) -> usize {
+        self.reads.len()
+    }
+
+    pub fn print(&self, format: Format, w: &mut impl Write) -> io::Result<()> {
+        match format {
+            Format::Term => self.print_term(w),
+            Format::Csv => self.print_csv(w),
+        }
+    }
+
+    fn print_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "cell,reads,writes")?;
+        for i in 0..self.used() {
+            writeln!(w, "{},{},{}", i, self.reads[i], self.writes[i])?;
+        }
+        Ok(())
+    }
+
+    /// Print rows of colored blocks, going from dark blue for cold cells
+    /// to bright red for the hottest cell, on a logarithmic scale.
+    fn print_term(&self, w: &mut impl Write) -> io::Result<()> {
+        let used = self.used();
+        let total = |i: usize| self.reads[i] + self.writes[i];
+        let max = (0..used).map(total).max().unwrap_or(0);
+        let scale = ((max + 1) as f64).ln();
+
+        for start in (0..used).step_by(ROW_WIDTH) {
+            write!(w, "{:>6} ", start)?;
+            for i in start..used.min(start + ROW_WIDTH) {
+                let heat = if total(i) == 0 { 0.0 } else { ((total(i) + 1) as f64).ln() / scale };
+                let (r, g, b) = color(heat);
+                write!(w, "\x1b[48;2;{};{};{}m  ", r, g, b)?;
+            }
+            writeln!(w, "\x1b[0m")?;
+        }
+
+        let hottest = (0..used).max_by_key(|&i| total(i)).unwrap_or(0);
+        writeln!(w, "{} cells used, hottest cell #{} with {} reads and {} writes",
+            used, hottest, self.reads.get(hottest).unwrap_or(&0), self.writes.get(hottest).unwrap_or(&0))
+    }
+}
+
+/// Map a heat in `0.0..=1.0` to a color from dark blue, through yellow, to red.
+fn color(heat: f64) -> (u8, u8, u8) {
+    let lerp = |a: f64, b: f64, t: f64| (a + (b - a) * t) as u8;
+    if heat < 0.5 {
+        let t = heat * 2.0;
+        (lerp(20.0, 240.0, t), lerp(20.0, 220.0, t), lerp(80.0, 40.0, t))
+    } else {
+        let t = (heat - 0.5) * 2.0;
+        (lerp(240.0, 230.0, t), lerp(220.0, 30.0, t), lerp(40.0, 30.0, t))
+    }
+}
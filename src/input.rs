@@ -0,0 +1,88 @@
+//! Adapters for the input of a program.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Prints a prompt to stderr whenever reading has to wait for more input,
+/// i.e. when all input read so far has been consumed.
+pub struct Prompted<R> {
+    inner: BufReader<R>,
+    prompt: String,
+}
+
+impl<R: Read> Prompted<R> {
+    pub fn new(inner: R, prompt: String) -> Self {
+        Self { inner: BufReader::new(inner), prompt }
+    }
+}
+
+impl<R: Read> Read for Prompted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner.buffer().is_empty() {
+            let mut stderr = io::stderr();
+            stderr.write_all(self.prompt.as_bytes())?;
+            stderr.flush()?;
+        }
+        let available = self.inner.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.inner.consume(n);
+        Ok(n)
+    }
+}
+
+/// Reads the bytes returned by a function, until it returns `None` at the end of input.
+pub struct FromFn<F> {
+    read: F,
+}
+
+impl<F: FnMut() -> Option<u8>> FromFn<F> {
+    pub fn new(read: F) -> Self {
+        Self { read }
+    }
+}
+
+impl<F: FnMut() -> Option<u8>> Read for FromFn<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(first) = buf.first_mut() else { return Ok(0) };
+        // Only ask for one byte at a time, as the next may not be available yet
+        match (self.read)() {
+            Some(byte) => {
+                *first = byte;
+                Ok(1)
+            },
+            None => Ok(0),
+        }
+    }
+}
+
+/// Splits every byte read into its eight bits, lowest first, each read as the byte `0` or `1`,
+/// for [`IoMode::Bits`](crate::config::IoMode::Bits).
+pub struct Bits<R> {
+    inner: R,
+    /// The byte being split, and the number of its bits still to be read.
+    byte: u8,
+    left: u32,
+}
+
+impl<R: Read> Bits<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, byte: 0, left: 0 }
+    }
+}
+
+impl<R: Read> Read for Bits<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(first) = buf.first_mut() else { return Ok(0) };
+        if self.left == 0 {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                return Ok(0);
+            }
+            (self.byte, self.left) = (byte[0], 8);
+        }
+        *first = self.byte & 1;
+        self.byte >>= 1;
+        self.left -= 1;
+        Ok(1)
+    }
+}
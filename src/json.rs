@@ -0,0 +1,264 @@
+//! The intermediate representation as JSON, to analyze programs with external tools
+//! and to read them back.
+//!
+//! Every instruction is written as serde does by default for an externally tagged enum:
+//! a unit variant as its name, and any other variant as an object with its name as the only key.
+//! This is the format that `serde_json` uses with the derives of the `serde` feature, so both
+//! read each other's output; this module only covers it without depending on serde.
+//!
+//! ```text
+//! [{"IncVal":2},{"Loop":[[{"DecVal":1},"Write"],0]},"ClearVal",{"MulLoop":[[[1,3],[-1,2]],1]}]
+//! ```
+
+use core::fmt::{self, Write as _};
+
+use alloc::{boxed::Box, format, string::{String, ToString}, vec::Vec};
+
+use crate::Instruction;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The text is not valid JSON, at the given byte offset.
+    Syntax { offset: usize, message: String },
+    /// The JSON does not describe a program.
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Syntax { offset, message } => write!(f, "invalid JSON at byte {}: {}", offset, message),
+            Invalid(msg) => write!(f, "invalid program: {}", msg),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Write the program as a JSON array of instructions.
+pub fn to_json(prog: &[Instruction]) -> String {
+    let mut out = String::new();
+    write_block(prog, &mut out);
+    out
+}
+
+fn write_block(prog: &[Instruction], out: &mut String) {
+    out.push('[');
+    for (i, instr) in prog.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_instruction(instr, out);
+    }
+    out.push(']');
+}
+
+fn write_instruction(instr: &Instruction, out: &mut String) {
+    use Instruction::*;
+    let _ = match instr {
+        IncPtr(x) => write!(out, "{{\"IncPtr\":{}}}", x),
+        DecPtr(x) => write!(out, "{{\"DecPtr\":{}}}", x),
+        IncVal(x) => write!(out, "{{\"IncVal\":{}}}", x),
+        DecVal(x) => write!(out, "{{\"DecVal\":{}}}", x),
+        ClearVal => write!(out, "\"ClearVal\""),
+        SetVal(x) => write!(out, "{{\"SetVal\":{}}}", x),
+        Write => write!(out, "\"Write\""),
+        WriteConst(x) => write!(out, "{{\"WriteConst\":{}}}", x),
+        Read => write!(out, "\"Read\""),
+        SwitchTape(x) => write!(out, "{{\"SwitchTape\":{}}}", x),
+        Halt => write!(out, "\"Halt\""),
+        Random => write!(out, "\"Random\""),
+        Push => write!(out, "\"Push\""),
+        Pop => write!(out, "\"Pop\""),
+        MulLoop(targets, step) => {
+            let targets: Vec<String> = targets.iter().map(|(offset, factor)| format!("[{},{}]", offset, factor)).collect();
+            write!(out, "{{\"MulLoop\":[[{}],{}]}}", targets.join(","), step)
+        },
+        Loop(body, shift) => {
+            *out += "{\"Loop\":[";
+            write_block(body, out);
+            match shift {
+                Some(shift) => write!(out, ",{}]}}", shift),
+                None => write!(out, ",null]}}"),
+            }
+        },
+    };
+}
+
+/// A parsed JSON value, with only the types used to describe programs.
+enum Value {
+    Null,
+    Number(i128),
+    String(String),
+    Array(Vec<Value>),
+    /// An object with a single key, as used for tagged variants.
+    Tagged(String, Box<Value>),
+}
+
+/// Read a program written by [`to_json`].
+pub fn from_json(json: &str) -> Result<Vec<Instruction>, Error> {
+    let mut parser = ValueParser { json: json.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < json.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    block(&value)
+}
+
+fn block(value: &Value) -> Result<Vec<Instruction>, Error> {
+    match value {
+        Value::Array(items) => items.iter().map(instruction).collect(),
+        _ => Err(Error::Invalid("expected an array of instructions".to_string())),
+    }
+}
+
+fn instruction(value: &Value) -> Result<Instruction, Error> {
+    use Instruction::*;
+    Ok(match value {
+        Value::String(name) => match name.as_str() {
+            "ClearVal" => ClearVal,
+            "Write" => Write,
+            "Read" => Read,
+            "Halt" => Halt,
+            "Random" => Random,
+            "Push" => Push,
+            "Pop" => Pop,
+            _ => return Err(Error::Invalid(format!("unknown instruction `{}`", name))),
+        },
+        Value::Tagged(name, value) => match (name.as_str(), &**value) {
+            ("IncPtr", x) => IncPtr(number(x)?),
+            ("DecPtr", x) => DecPtr(number(x)?),
+            ("IncVal", x) => IncVal(number(x)?),
+            ("DecVal", x) => DecVal(number(x)?),
+            ("SetVal", x) => SetVal(number(x)?),
+            ("WriteConst", x) => WriteConst(number(x)?),
+            ("SwitchTape", x) => SwitchTape(number(x)?),
+            ("MulLoop", Value::Array(fields)) if fields.len() == 2 => {
+                let Value::Array(targets) = &fields[0] else {
+                    return Err(Error::Invalid("expected an array of multiply loop targets".to_string()));
+                };
+                let targets = targets.iter()
+                    .map(|target| match target {
+                        Value::Array(pair) if pair.len() == 2 => Ok((number(&pair[0])?, number(&pair[1])?)),
+                        _ => Err(Error::Invalid("expected an offset and a factor".to_string())),
+                    })
+                    .collect::<Result<_, _>>()?;
+                MulLoop(targets, number(&fields[1])?)
+            },
+            ("Loop", Value::Array(fields)) if fields.len() == 2 => {
+                let shift = match &fields[1] {
+                    Value::Null => None,
+                    shift => Some(number(shift)?),
+                };
+                Loop(block(&fields[0])?, shift)
+            },
+            (name, _) => return Err(Error::Invalid(format!("unknown instruction or invalid fields for `{}`", name))),
+        },
+        _ => return Err(Error::Invalid("expected an instruction".to_string())),
+    })
+}
+
+fn number<T: TryFrom<i128>>(value: &Value) -> Result<T, Error> {
+    match value {
+        Value::Number(n) => T::try_from(*n).map_err(|_| Error::Invalid(format!("number {} is out of range", n))),
+        _ => Err(Error::Invalid("expected a number".to_string())),
+    }
+}
+
+struct ValueParser<'a> {
+    json: &'a [u8],
+    pos: usize,
+}
+
+impl ValueParser<'_> {
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax { offset: self.pos, message: message.to_string() }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.json.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `c` after any whitespace, or fail.
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.json.get(self.pos) != Some(&c) {
+            return Err(self.error(&format!("expected `{}`", c as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.json.get(self.pos) {
+            Some(b'n') if self.json[self.pos..].starts_with(b"null") => {
+                self.pos += 4;
+                Ok(Value::Null)
+            },
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.json.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.json.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        },
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                self.skip_whitespace();
+                let key = self.string()?;
+                self.expect(b':')?;
+                let value = self.value()?;
+                self.expect(b'}')?;
+                Ok(Value::Tagged(key, Box::new(value)))
+            },
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                self.pos += 1;
+                while self.json.get(self.pos).is_some_and(u8::is_ascii_digit) {
+                    self.pos += 1;
+                }
+                // The digits are plain ASCII
+                core::str::from_utf8(&self.json[start..self.pos]).unwrap().parse()
+                    .map(Value::Number)
+                    .map_err(|_| Error::Syntax { offset: start, message: "invalid integer".to_string() })
+            },
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    /// A string without escapes, as instruction names do not need them.
+    fn string(&mut self) -> Result<String, Error> {
+        if self.json.get(self.pos) != Some(&b'"') {
+            return Err(self.error("expected a string"));
+        }
+        let start = self.pos + 1;
+        let len = self.json[start..].iter().position(|&b| b == b'"' || b == b'\\')
+            .ok_or_else(|| self.error("unterminated string"))?;
+        self.pos = start + len;
+        if self.json[self.pos] == b'\\' {
+            return Err(self.error("escapes are not supported"));
+        }
+        self.pos += 1;
+        Ok(String::from_utf8_lossy(&self.json[start..start + len]).into_owned())
+    }
+}
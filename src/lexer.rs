@@ -1,5 +1,7 @@
+use crate::config::{Config, Dialect};
+
 #[derive(Clone, Copy, Debug)]
-pub enum Token {
+pub enum Token<'src> {
     Gt,
     Lt,
     Plus,
@@ -8,61 +10,189 @@ pub enum Token {
     Comma,
     LSquare,
     RSquare,
+    /// Only in Boolfuck, where it takes the place of `.`.
+    Semicolon,
+    /// Only in the multi-tape dialect.
+    LCurly,
+    /// Only in the multi-tape dialect.
+    RCurly,
+    /// Only in the stack dialect.
+    Caret,
+    /// Only in the stack dialect.
+    Underscore,
+    /// Only with the [`halt_command`](Config::halt_command) extension.
+    At,
+    /// Only with the [`random_command`](Config::random_command) extension.
+    Question,
+    /// Text between commands on a single line, without the whitespace around it.
+    /// Only with [`Lexer::with_trivia`].
+    Comment(&'src str),
+    /// Whitespace between commands and comments, including line breaks.
+    /// Only with [`Lexer::with_trivia`].
+    Whitespace(&'src str),
+}
+
+impl Token<'_> {
+    /// The character of a command, or `None` for comments and whitespace.
+    pub fn command(&self) -> Option<char> {
+        use Token::*;
+        Some(match self {
+            Gt => '>',
+            Lt => '<',
+            Plus => '+',
+            Minus => '-',
+            Dot => '.',
+            Comma => ',',
+            LSquare => '[',
+            RSquare => ']',
+            Semicolon => ';',
+            LCurly => '{',
+            RCurly => '}',
+            At => '@',
+            Question => '?',
+            Caret => '^',
+            Underscore => '_',
+            Comment(_) | Whitespace(_) => return None,
+        })
+    }
 }
 
+/// Position of a token in the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Column number in characters, starting at 1.
+    pub col: usize,
+    /// Offset in bytes from the start of the source.
+    pub offset: usize,
+}
+
+/// The characters that are commands, by dialect and extensions.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Commands {
+    dialect: Dialect,
+    /// Whether `@` is a command.
+    halt: bool,
+    /// Whether `?` is a command.
+    random: bool,
+}
+
+impl Commands {
+    pub(crate) fn from_config(config: &Config) -> Self {
+        Self { dialect: config.dialect, halt: config.halt_command, random: config.random_command }
+    }
+
+    /// The token of `c`, or `None` if it is not a command.
+    pub(crate) fn token(self, c: char) -> Option<Token<'static>> {
+        use Token::*;
+        Some(match c {
+            '>' => Gt,
+            '<' => Lt,
+            '+' => Plus,
+            // Boolfuck has no `-`, and writes with `;`
+            '-' if self.dialect != Dialect::Boolfuck => Minus,
+            '.' if self.dialect != Dialect::Boolfuck => Dot,
+            ';' if self.dialect == Dialect::Boolfuck => Semicolon,
+            ',' => Comma,
+            '[' => LSquare,
+            ']' => RSquare,
+            '{' if self.dialect == Dialect::MultiTape => LCurly,
+            '}' if self.dialect == Dialect::MultiTape => RCurly,
+            '^' if self.dialect == Dialect::Stack => Caret,
+            '_' if self.dialect == Dialect::Stack => Underscore,
+            '@' if self.halt => At,
+            '?' if self.random => Question,
+            _ => return None,
+        })
+    }
+}
+
+/// Splits the source into tokens, along with their positions.
 pub struct Lexer<'src> {
     /// The input program as a string.
     src: &'src str,
-    /// Index of the current character in the source string.
+    /// Byte offset of the current character in the source string.
     current: usize,
     /// Line number of the current character.
     line: usize,
     /// Column number of the current character.
     col: usize,
+    commands: Commands,
+    /// Whether to yield comments and whitespace rather than skipping them.
+    trivia: bool,
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(src: &'src str) -> Self {
-        Self { src, current: 0, line: 1, col: 1 }
+        Self::with_dialect(src, Dialect::Standard)
     }
 
-    /// Get the next character and consume it.
-    fn consume(&mut self) -> Option<char> {
-        if let Some(c) = self.src.chars().nth(self.current) {
-            self.current += 1;
-            self.col += 1;
-            Some(c)
+    /// Create a lexer that also recognizes the commands of the given dialect.
+    pub fn with_dialect(src: &'src str, dialect: Dialect) -> Self {
+        let commands = Commands { dialect, halt: false, random: false };
+        Self { src, current: 0, line: 1, col: 1, commands, trivia: false }
+    }
+
+    /// Create a lexer that recognizes the commands of the configured dialect and extensions.
+    pub fn with_config(src: &'src str, config: &Config) -> Self {
+        Self { commands: Commands::from_config(config), ..Self::with_dialect(src, config.dialect) }
+    }
+
+    /// Also yield [`Token::Comment`] and [`Token::Whitespace`], so that the tokens
+    /// together make up the whole source, for tools that rewrite it.
+    pub fn with_trivia(mut self) -> Self {
+        self.trivia = true;
+        self
+    }
+
+    /// Get the next character and consume it, along with its position.
+    fn consume(&mut self) -> Option<(char, Span)> {
+        let c = self.src[self.current..].chars().next()?;
+        let span = Span { line: self.line, col: self.col, offset: self.current };
+        self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
         } else {
-            None
+            self.col += 1;
+        }
+        Some((c, span))
+    }
+
+    /// Consume the characters up to byte offset `end`, returning the source from `start`.
+    fn consume_until(&mut self, start: usize, end: usize) -> &'src str {
+        while self.current < end {
+            self.consume();
         }
+        &self.src[start..end]
     }
 }
 
 impl<'src> Iterator for Lexer<'src> {
-    type Item = Token;
+    type Item = (Token<'src>, Span);
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(c) = self.consume() {
+        while let Some((c, span)) = self.consume() {
             use Token::*;
-            let token = match c {
-                '>' => Gt,
-                '<' => Lt,
-                '+' => Plus,
-                '-' => Minus,
-                '.' => Dot,
-                ',' => Comma,
-                '[' => LSquare,
-                ']' => RSquare,
+            let token = match self.commands.token(c) {
+                Some(token) => token,
                 // Skip unknown tokens
-                '\n' => {
-                    self.line += 1;
-                    self.col = 1;
-                    continue
-                }
-                _ => continue,
+                _ if !self.trivia => continue,
+                _ if c.is_whitespace() => {
+                    let rest = &self.src[self.current..];
+                    let len = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                    Whitespace(self.consume_until(span.offset, self.current + len))
+                },
+                _ => {
+                    // Up to the next command or the end of the line, leaving the whitespace before it
+                    let rest = &self.src[self.current..];
+                    let len = rest.find(|c| c == '\n' || self.commands.token(c).is_some()).unwrap_or(rest.len());
+                    Comment(self.consume_until(span.offset, self.current + rest[..len].trim_end().len()))
+                },
             };
 
-            return Some(token);
+            return Some((token, span));
         };
 
         None
@@ -0,0 +1,289 @@
+//! A Brainfuck interpreter with an optimizing compiler to an intermediate representation.
+//!
+//! Without the default `std` feature, the lexer, parser, optimizer and the evaluator in
+//! [`events`] only need `alloc`.
+//!
+//! Programs, configurations, pass pipelines, tapes and [`events::Executions`] are `Send`
+//! and `Sync`, so one parsed and optimized program can be shared by many threads that each
+//! run it in their own context. An [`eval::Context`] borrows its input and output, and can
+//! move to another thread when they can, if it is created by [`eval::Context::with_typed_io`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use core::fmt;
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{lexer::Lexer, parser::Parser, config::Config};
+
+pub mod lexer;
+pub mod macros;
+pub mod literate;
+pub mod parser;
+pub mod opt;
+#[cfg(feature = "std")]
+pub mod term;
+#[cfg(feature = "std")]
+pub mod diagnostic;
+pub mod config;
+pub mod program;
+pub mod eval;
+pub mod cell;
+pub mod tape;
+pub mod events;
+pub mod symbolic;
+pub mod ranges;
+pub mod taint;
+pub mod smbf;
+pub mod random;
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod heatmap;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod frames;
+#[cfg(feature = "std")]
+pub mod tracefile;
+#[cfg(feature = "std")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos")))]
+pub mod mmap;
+pub mod report;
+pub mod partial;
+pub mod packed;
+#[cfg(feature = "std")]
+pub mod backend;
+#[cfg(feature = "std")]
+pub mod embed;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "bignum")]
+pub mod bignum;
+#[cfg(feature = "json")]
+pub mod json;
+
+pub use program::Program;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    /// `>`
+    ///
+    /// Increment the data pointer by one.
+    IncPtr(usize),
+    /// `<`
+    ///
+    /// Decrement the data pointer by one.
+    DecPtr(usize),
+    /// `+`
+    ///
+    /// Increment the byte at the data pointer by one.
+    IncVal(u8),
+    /// `-`
+    ///
+    /// Decrement the byte at the data pointer by one.
+    DecVal(u8),
+    /// `[+]` `[-]`
+    ///
+    /// Reset the byte at the data pointer to zero.
+    ClearVal,
+    /// `[-]+++`
+    ///
+    /// Set the cell at the data pointer to a constant, wrapped to the cell width.
+    SetVal(u32),
+    /// `.`
+    ///
+    /// Output the byte at the data pointer.
+    Write,
+    /// `[-]+++.`
+    ///
+    /// Output a constant byte, whose value is known at compile time.
+    WriteConst(u8),
+    /// `,`
+    ///
+    /// Accept one byte of input, storing its value in the byte at the data pointer.
+    Read,
+    /// `}` `{`
+    ///
+    /// Switch to the tape the given number of tapes further, wrapping around.
+    /// Every tape has its own data pointer. Only in the multi-tape dialect.
+    SwitchTape(isize),
+    /// `^`
+    ///
+    /// Push the value of the cell at the data pointer onto the stack. The stack holds at
+    /// most as many values as the tape holds cells. Only in the stack dialect.
+    Push,
+    /// `_`
+    ///
+    /// Pop the value on top of the stack into the cell at the data pointer.
+    /// Only in the stack dialect.
+    Pop,
+    /// `@`
+    ///
+    /// End the program at once, as if it reached its end.
+    /// Only with the [`halt_command`](config::Config::halt_command) extension.
+    Halt,
+    /// `?`
+    ///
+    /// Set the cell at the data pointer to a random byte.
+    /// Only with the [`random_command`](config::Config::random_command) extension.
+    Random,
+    /// `[->+++>--<<]`
+    ///
+    /// Closed form of a balanced loop that only subtracts a constant from its counter,
+    /// and adds constants to cells at fixed offsets. The second field is the (odd)
+    /// amount subtracted from the counter per iteration. For every offset and factor,
+    /// the factor times the number of iterations is added to the cell at that offset,
+    /// after which the counter is zero.
+    MulLoop(Vec<(isize, u32)>, u32),
+    /// `[ ... ]`
+    ///
+    /// While the byte at the data pointer is zero, repeat all instructions until the matching `]`.
+    /// Otherwise, jump forward to the command after the matching `]`.
+    ///
+    /// Also holds the net pointer shift of a single iteration, if it is fixed.
+    /// This is kept up to date by the optimizer, see [`opt::annotate_shifts`].
+    Loop(Vec<Instruction>, Option<isize>),
+}
+
+impl Instruction {
+    /// Create a loop, computing its net pointer shift from the (already annotated) body.
+    pub fn new_loop(body: Vec<Instruction>) -> Self {
+        let shift = opt::shift(&body);
+        Instruction::Loop(body, shift)
+    }
+}
+
+/// Any error of this crate, to handle the errors of its modules together.
+///
+/// Every variant shows the error it wraps as it is, and reports the same source.
+#[derive(Debug)]
+pub enum Error {
+    Parse(parser::Error),
+    Macro(macros::Error),
+    /// An invalid configuration value, or an unknown optimization pass.
+    InvalidConfig(String),
+    #[cfg(feature = "std")]
+    Config(config::Error),
+    Runtime(eval::Error),
+    #[cfg(feature = "std")]
+    Checkpoint(checkpoint::Error),
+    #[cfg(feature = "json")]
+    Json(json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Parse(e) => write!(f, "{}", e),
+            Macro(e) => write!(f, "{}", e),
+            InvalidConfig(msg) => write!(f, "{}", msg),
+            #[cfg(feature = "std")]
+            Config(e) => write!(f, "{}", e),
+            Runtime(e) => write!(f, "{}", e),
+            #[cfg(feature = "std")]
+            Checkpoint(e) => write!(f, "{}", e),
+            #[cfg(feature = "json")]
+            Json(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Parse(e) => e.source(),
+            Error::Macro(e) => e.source(),
+            Error::InvalidConfig(_) => None,
+            #[cfg(feature = "std")]
+            Error::Config(e) => e.source(),
+            Error::Runtime(e) => e.source(),
+            #[cfg(feature = "std")]
+            Error::Checkpoint(e) => e.source(),
+            #[cfg(feature = "json")]
+            Error::Json(e) => e.source(),
+        }
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(e: parser::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl From<macros::Error> for Error {
+    fn from(e: macros::Error) -> Self {
+        Error::Macro(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<eval::Error> for Error {
+    fn from(e: eval::Error) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<checkpoint::Error> for Error {
+    fn from(e: checkpoint::Error) -> Self {
+        Error::Checkpoint(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<json::Error> for Error {
+    fn from(e: json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// Parse, optimize and run the program `src` on the given input, returning its output.
+///
+/// The end of `input` is the end of input to the program, and input and output are always
+/// bytes, as with [`events::run`]. Self-modifying programs are run by [`smbf::run`] instead.
+pub fn run(src: &str, config: &Config, input: &[u8]) -> Result<Vec<u8>, Error> {
+    if config.dialect == config::Dialect::SelfModifying {
+        return Ok(smbf::run(src, config, input)?);
+    }
+    let expansion = config.macros.then(|| macros::expand(src)).transpose()?;
+    let src = expansion.as_ref().map_or(src, macros::Expansion::src);
+    let mut prog = Parser::new(Lexer::with_config(src, config)).parse()
+        .map_err(|e| e.relocate(|pos| expansion.as_ref().map_or(pos, |expansion| expansion.origin(pos))))?;
+    opt::PassManager::from_config(config).map_err(Error::InvalidConfig)?.run(&mut prog);
+    Ok(events::run(&prog, config, input)?)
+}
+
+// Checked here rather than left to inference, so that a change that breaks sharing programs
+// between threads fails to build
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Instruction>();
+    assert_send_sync::<Vec<Instruction>>();
+    assert_send_sync::<config::Config>();
+    assert_send_sync::<opt::PassManager>();
+    assert_send_sync::<alloc::boxed::Box<dyn tape::Tape<u32>>>();
+    assert_send_sync::<events::Executions<'static>>();
+    assert_send_sync::<eval::Error>();
+    assert_send_sync::<Error>();
+    #[cfg(feature = "std")]
+    {
+        const fn assert_send<T: Send + ?Sized>() {}
+        assert_send::<eval::Context<'static, u8, dyn std::io::Read + Send, dyn std::io::Write + Send>>();
+    }
+};
@@ -0,0 +1,65 @@
+//! Literate Brainfuck: programs in the fenced `brainfuck` code blocks of a Markdown file.
+//!
+//! ````text
+//! Print `H`, which is 8 times 9:
+//!
+//! ```brainfuck
+//! ++++++++[>+++++++++<-]>.
+//! ```
+//! ````
+//!
+//! The blocks of a file together make up the program, in order. Fences of three or more
+//! backticks or tildes are recognized, with `brainfuck` or `bf` as the language.
+
+use alloc::string::String;
+
+/// The program in the Markdown source `markdown`.
+///
+/// Every line outside of the `brainfuck` blocks is left empty rather than removed, so that
+/// lines and columns in the program are the same as in the Markdown.
+pub fn extract(markdown: &str) -> String {
+    let mut program = String::with_capacity(markdown.len());
+    // The fence of the current code block, and whether it holds Brainfuck
+    let mut fence: Option<(&str, bool)> = None;
+    for line in markdown.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        match fence {
+            Some((open, _)) if closes(content, open) => fence = None,
+            Some((_, true)) => program.push_str(content),
+            Some((_, false)) => {},
+            None => fence = opens(content),
+        }
+        program.push_str(&line[content.len()..]);
+    }
+    program
+}
+
+/// The fence that `line` opens a code block with, if it does, and whether the block is Brainfuck.
+fn opens(line: &str) -> Option<(&str, bool)> {
+    let trimmed = indented(line)?;
+    let c = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = trimmed.len() - trimmed.trim_start_matches(c).len();
+    let (fence, info) = trimmed.split_at(len);
+    // A backtick fence cannot have backticks in its info string
+    if len < 3 || (c == '`' && info.contains('`')) {
+        return None;
+    }
+    let language = info.split_whitespace().next().unwrap_or("");
+    Some((fence, matches!(language, "brainfuck" | "bf")))
+}
+
+/// Whether `line` closes a code block that was opened with `fence`.
+fn closes(line: &str, fence: &str) -> bool {
+    let Some(trimmed) = indented(line) else {
+        return false;
+    };
+    let c = fence.chars().next().unwrap();
+    let rest = trimmed.trim_start_matches(c);
+    trimmed.len() - rest.len() >= fence.len() && rest.trim().is_empty()
+}
+
+/// `line` without its indentation, if it is indented by at most three spaces.
+fn indented(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    (line.len() - trimmed.len() <= 3).then_some(trimmed)
+}
@@ -0,0 +1,196 @@
+//! Named macros in the source, with the [`macros`](crate::config::Config::macros) option.
+//!
+//! `{name: body}` defines a macro and `{name}` pastes its body, so that a program can
+//! name the code it repeats:
+//!
+//! ```text
+//! {double: [->++<]>[-<+>]<}
+//! +++{double}{double}.
+//! ```
+//!
+//! Macros are expanded before lexing. A body may use the macros defined before it, but
+//! not define any, and a macro must be defined before it is used, which rules out
+//! recursion. Names are made of letters, digits and `_`.
+
+use core::fmt;
+
+use alloc::{collections::BTreeMap, format, string::{String, ToString}, vec, vec::Vec};
+
+/// Line and column of a character in the source.
+type Pos = (usize, usize);
+
+#[derive(Debug)]
+pub enum Error {
+    /// A `{` at the given line and column that is never closed.
+    Unclosed { line: usize, col: usize },
+    /// A `}` at the given line and column outside of a macro.
+    UnexpectedClose { line: usize, col: usize },
+    /// A `{` at the given line and column that is not followed by a valid name.
+    InvalidName { line: usize, col: usize },
+    /// A use of a macro that is not defined before it.
+    Undefined { name: String, line: usize, col: usize },
+    /// A definition of a macro that is already defined.
+    Redefined { name: String, line: usize, col: usize },
+    /// A definition at the given line and column inside the body of another.
+    NestedDefinition { line: usize, col: usize },
+}
+
+impl Error {
+    /// Line and column of the offending `{` or `}`.
+    pub fn pos(&self) -> (usize, usize) {
+        use Error::*;
+        match *self {
+            Unclosed { line, col } |
+            UnexpectedClose { line, col } |
+            InvalidName { line, col } |
+            Undefined { line, col, .. } |
+            Redefined { line, col, .. } |
+            NestedDefinition { line, col } => (line, col),
+        }
+    }
+
+    /// Short description of the problem, without position information.
+    pub fn message(&self) -> String {
+        use Error::*;
+        match self {
+            Unclosed { .. } => "macro is never closed with `}`".to_string(),
+            UnexpectedClose { .. } => "`}` is not the end of a macro".to_string(),
+            InvalidName { .. } => "expected a macro name of letters, digits and `_` after `{`".to_string(),
+            Undefined { name, .. } => format!("macro `{}` is not defined before it is used", name),
+            Redefined { name, .. } => format!("macro `{}` is already defined", name),
+            NestedDefinition { .. } => "macros cannot be defined inside another macro".to_string(),
+        }
+    }
+
+    /// Label to put under the offending brace.
+    pub fn label(&self) -> &'static str {
+        use Error::*;
+        match self {
+            Unclosed { .. } => "this `{` is never closed",
+            UnexpectedClose { .. } => "unmatched `}`",
+            InvalidName { .. } => "invalid macro",
+            Undefined { .. } => "undefined macro",
+            Redefined { .. } => "second definition",
+            NestedDefinition { .. } => "nested definition",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.pos();
+        write!(f, "{} at line {}, column {}", self.message(), line, col)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Source with its macros expanded, which remembers where every character came from.
+#[derive(Clone, Debug)]
+pub struct Expansion {
+    src: String,
+    /// Position in the original source of every character, by line and column of the expansion.
+    origins: Vec<Vec<Pos>>,
+}
+
+impl Expansion {
+    /// The expanded source.
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// The expanded source, leaving out where it came from.
+    pub fn into_src(self) -> String {
+        self.src
+    }
+
+    /// Line and column in the original source of the character at `line` and `col` in the
+    /// expansion. Characters of a macro body are where the body is defined.
+    pub fn origin(&self, (line, col): (usize, usize)) -> (usize, usize) {
+        self.origins.get(line.wrapping_sub(1))
+            .and_then(|line| line.get(col.wrapping_sub(1)))
+            .copied()
+            .unwrap_or((line, col))
+    }
+}
+
+/// Expand the macros in `src`, leaving out their definitions.
+pub fn expand(src: &str) -> Result<Expansion, Error> {
+    let mut chars = Vec::with_capacity(src.len());
+    let (mut line, mut col) = (1, 1);
+    for c in src.chars() {
+        chars.push((c, (line, col)));
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+
+    let mut out = Vec::with_capacity(chars.len());
+    expand_block(&chars, 0, None, &mut BTreeMap::new(), &mut out)?;
+
+    let mut expansion = Expansion { src: String::with_capacity(out.len()), origins: vec![Vec::new()] };
+    for (c, pos) in out {
+        expansion.src.push(c);
+        expansion.origins.last_mut().unwrap().push(pos);
+        if c == '\n' {
+            expansion.origins.push(Vec::new());
+        }
+    }
+    Ok(expansion)
+}
+
+/// Expand `chars` from index `i` into `out`, up to the `}` ending the body of the definition
+/// whose `{` is at `open`, or up to the end without one. Returns the index after the block.
+fn expand_block(
+    chars: &[(char, Pos)],
+    mut i: usize,
+    open: Option<Pos>,
+    macros: &mut BTreeMap<String, Vec<(char, Pos)>>,
+    out: &mut Vec<(char, Pos)>,
+) -> Result<usize, Error> {
+    while let Some(&(c, pos)) = chars.get(i) {
+        let (line, col) = pos;
+        match c {
+            '{' => {
+                let end = chars[i + 1..].iter()
+                    .position(|&(c, _)| matches!(c, ':' | '{' | '}'))
+                    .map(|len| i + 1 + len)
+                    .ok_or(Error::Unclosed { line, col })?;
+                let name: String = chars[i + 1..end].iter().map(|&(c, _)| c).collect();
+                let name = name.trim();
+                if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(Error::InvalidName { line, col });
+                }
+                match chars[end].0 {
+                    '}' => {
+                        let body = macros.get(name).ok_or_else(|| Error::Undefined { name: name.to_string(), line, col })?;
+                        out.extend_from_slice(body);
+                        i = end + 1;
+                    },
+                    ':' if open.is_some() => return Err(Error::NestedDefinition { line, col }),
+                    ':' if macros.contains_key(name) => return Err(Error::Redefined { name: name.to_string(), line, col }),
+                    ':' => {
+                        let mut body = Vec::new();
+                        i = expand_block(chars, end + 1, Some(pos), macros, &mut body)?;
+                        macros.insert(name.to_string(), body);
+                    },
+                    _ => return Err(Error::InvalidName { line, col }),
+                }
+            },
+            '}' if open.is_some() => return Ok(i + 1),
+            '}' => return Err(Error::UnexpectedClose { line, col }),
+            c => {
+                out.push((c, pos));
+                i += 1;
+            },
+        }
+    }
+
+    match open {
+        Some((line, col)) => Err(Error::Unclosed { line, col }),
+        None => Ok(i),
+    }
+}
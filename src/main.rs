@@ -1,115 +1,894 @@
-mod lexer;
-mod parser;
-mod opt;
+/// Call the generic function `$f` with the cell type of the configured cell width.
+macro_rules! with_cell {
+    ($width:expr, $($f:ident)::+($($arg:expr),* $(,)?)) => {
+        match $width {
+            bf::config::CellWidth::Bit => $($f)::+::<bf::cell::Bit>($($arg),*),
+            bf::config::CellWidth::U8 => $($f)::+::<u8>($($arg),*),
+            bf::config::CellWidth::U16 => $($f)::+::<u16>($($arg),*),
+            bf::config::CellWidth::U32 => $($f)::+::<u32>($($arg),*),
+            #[cfg(feature = "bignum")]
+            bf::config::CellWidth::Big => $($f)::+::<bf::bignum::BigInt>($($arg),*),
+            #[cfg(not(feature = "bignum"))]
+            bf::config::CellWidth::Big => unreachable!("big cells are rejected by the configuration without the bignum feature"),
+        }
+    };
+}
+
+mod cli;
+mod suite;
+mod build;
+mod serve;
+mod format;
+mod minify;
+mod stats;
+mod diffrun;
+mod minimize;
+mod debug;
+mod repl;
+mod completions;
+#[cfg(unix)]
+mod aot;
+
+use std::{borrow::Cow, env, fmt, fs, io::{self, IsTerminal, Read, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, process::ExitCode, thread, time::{Duration, Instant}};
 
-use std::{env, fs, io::{self, Read, Write}};
+use bf::{Instruction, cell::Cell, lexer::Lexer, literate, macros, parser::{Parser, Warning}, opt, term::RawMode, diagnostic::{self, Diagnostic, Severity}, config::{self, Config, CellWidth, Dialect, FlushPolicy, IoMode}, eval::{self, Context}, checkpoint::{self, Checkpoint}, trace::{self, event}, report::{self, Report}, tracefile::{self, TraceReader, TraceWriter}, symbolic::{self, Goal, Limits, Outcome}, ranges, events::{Event, Executions}, smbf, partial, packed::{self, Machine, Packed}, input::Prompted, output};
 
-use crate::{lexer::Lexer, parser::Parser, opt::*};
+use crate::cli::Command;
 
+/// Time between checks for changes of the program in watch mode.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Size of the output buffer when only flushing at exit.
+const OUTPUT_BUFFER_SIZE: usize = 1 << 16;
+
+/// Errors reported by the command-line interface.
 #[derive(Debug)]
-pub enum Instruction {
-    /// `>`
-    ///
-    /// Increment the data pointer by one.
-    IncPtr(usize),
-    /// `<`
-    ///
-    /// Decrement the data pointer by one.
-    DecPtr(usize),
-    /// `+`
-    ///
-    /// Increment the byte at the data pointer by one.
-    IncVal(u8),
-    /// `-`
-    ///
-    /// Decrement the byte at the data pointer by one.
-    DecVal(u8),
-    /// `[+]` `[-]`
-    ///
-    /// Reset the byte at the data pointer to zero.
-    ClearVal,
-    /// `.`
-    ///
-    /// Output the byte at the data pointer.
-    Write,
-    /// `,`
-    ///
-    /// Accept one byte of input, storing its value in the byte at the data pointer.
-    Read,
-    /// `[ ... ]`
-    ///
-    /// While the byte at the data pointer is zero, repeat all instructions until the matching `]`.
-    /// Otherwise, jump forward to the command after the matching `]`.
-    Loop(Vec<Instruction>),
-}
-
-struct Context<'a> {
-    rdr: Box<&'a mut dyn Read>,
-    wtr: Box<&'a mut dyn Write>,
-    tape: [u8; 64],
-    ptr: usize,
-}
-
-impl<'a> Context<'a> {
-    fn new(rdr: &'a mut impl Read, wtr: &'a mut impl Write) -> Self {
-        Self {
-            rdr: Box::new(rdr),
-            wtr: Box::new(wtr),
-            tape: [0u8; 64],
-            ptr: 0,
-        }
-    }
-
-    fn eval(&mut self, prog: &Vec<Instruction>) -> io::Result<()> {
-        for instr in prog {
-            use Instruction::*;
-            match instr {
-                IncPtr(x) => self.ptr += *x,
-                DecPtr(x) => self.ptr -= *x,
-                IncVal(x) => self.tape[self.ptr] = self.tape[self.ptr].wrapping_add(*x),
-                DecVal(x) => self.tape[self.ptr] = self.tape[self.ptr].wrapping_sub(*x),
-                ClearVal => self.tape[self.ptr] = 0,
-                Write  => {
-                    self.wtr.write(&[self.tape[self.ptr]])?;
-                },
-                Read => {
-                    let mut input = [0u8; 1];
-                    self.rdr.read_exact(&mut input)?;
-                    self.tape[self.ptr] = input[0];
-                },
-                Loop(inner) => {
-                    while self.tape[self.ptr] != 0 {
-                        self.eval(inner)?;
-                    }
-                }
+enum Error {
+    /// A parse error or an error in the macros of the program, along with its fully rendered
+    /// diagnostic pointing into the source.
+    Diagnostic(bf::Error, String),
+    /// The program file could not be read.
+    File(PathBuf, io::Error),
+    /// The checkpoint to resume from could not be read.
+    Checkpoint(PathBuf, checkpoint::Error),
+    Config(config::Error),
+    Io(io::Error),
+    Runtime(eval::Error),
+    /// A natively compiled program failed with the given message.
+    Native(String),
+    /// Some tests of a test suite failed.
+    TestsFailed,
+    /// The programs given to `diff-run` wrote different output.
+    OutputsDiffer,
+    /// Some of several programs that were run failed, this many.
+    ProgramsFailed(usize),
+    /// Minimizing a program failed.
+    Minimize(String),
+    /// Building a native executable failed.
+    Build(String),
+    /// Invalid command line arguments.
+    Usage(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Diagnostic(_, report) => write!(f, "{}", report),
+            File(path, e) => write!(f, "error: could not read `{}`: {}", path.display(), e),
+            Checkpoint(path, e) => write!(f, "error: could not resume from `{}`: {}", path.display(), e),
+            Config(e) => write!(f, "error: {}", e),
+            Io(e) => write!(f, "error: {}", e),
+            Runtime(e) => write!(f, "error: {}", e),
+            Native(msg) => write!(f, "error: {}", msg),
+            TestsFailed => write!(f, "error: some tests failed"),
+            OutputsDiffer => write!(f, "error: the outputs of the programs differ"),
+            ProgramsFailed(n) => write!(f, "error: {} of the programs failed", n),
+            Minimize(msg) => write!(f, "error: could not minimize the program: {}", msg),
+            Build(msg) => write!(f, "error: {}", msg),
+            Usage(msg) => write!(f, "error: {}\n\nFor more information, try `bf --help`.", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use Error::*;
+        match self {
+            Diagnostic(e, _) => Some(e),
+            File(_, e) | Io(e) => Some(e),
+            Checkpoint(_, e) => Some(e),
+            Config(e) => Some(e),
+            Runtime(e) => Some(e),
+            Native(_) | TestsFailed | OutputsDiffer | ProgramsFailed(_) | Minimize(_) | Build(_) | Usage(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Single-line description of the error, without formatting.
+    fn summary(&self) -> String {
+        match self {
+            Error::Diagnostic(e, _) => e.to_string(),
+            e => e.to_string().trim_start_matches("error: ").to_string(),
+        }
+    }
+
+    /// The exit code for this error, distinguishing the class of failure in strict mode.
+    fn exit_code(&self, strict: bool) -> u8 {
+        use Error::*;
+        match self {
+            Usage(_) => 2,
+            _ if !strict => 1,
+            Config(_) => 2,
+            Diagnostic(..) => 3,
+            Runtime(eval::Error::StepLimit(_)) => 5,
+            Runtime(eval::Error::Io(_) | eval::Error::UnexpectedEof) | File(..) | Checkpoint(..) | Io(_) => 6,
+            Runtime(_) | Native(_) => 4,
+            TestsFailed | OutputsDiffer | ProgramsFailed(_) | Minimize(_) | Build(_) => 1,
+        }
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<eval::Error> for Error {
+    fn from(e: eval::Error) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn run(command: &Command) -> Result<(), Error> {
+    let opts = match command {
+        Command::Help => {
+            println!("{}", cli::help());
+            return Ok(());
+        },
+        Command::Version => {
+            println!("{}", cli::version());
+            return Ok(());
+        },
+        Command::Completions(shell) => {
+            print!("{}", completions::generate(*shell));
+            return Ok(());
+        },
+        command => command.options().unwrap(),
+    };
+
+    // Resolve settings, with later sources taking precedence
+    let mut config = Config::default();
+    match &opts.config {
+        Some(path) => config.load(Path::new(path))?,
+        None => config.load_default_files()?,
+    }
+    config.load_env()?;
+    for (key, value) in &opts.settings {
+        config.set(key, value).map_err(Error::Usage)?;
+    }
+
+    config.resolve().map_err(Error::Usage)?;
+    if config.dialect == Dialect::SelfModifying && !matches!(command, Command::Run(_)) {
+        return Err(Error::Usage("programs in the smbf dialect can only be run".to_string()));
+    }
+
+    if let Command::Test(_) = command {
+        return match suite::run(Path::new(&opts.path), &config)? {
+            true => Ok(()),
+            false => Err(Error::TestsFailed),
+        };
+    }
+
+    if let Command::Serve(_) = command {
+        let addr = opts.path.to_str().ok_or_else(|| Error::Usage(format!("invalid address `{}`", opts.path.display())))?;
+        return Ok(serve::serve(addr, &config)?);
+    }
+
+    if let Command::Run(_) = command && opts.paths.len() > 1 {
+        if opts.watch {
+            return Err(Error::Usage("`--watch` only supports a single program".to_string()));
+        }
+        return run_all(opts, &config);
+    }
+
+    if let Command::Run(_) = command && opts.watch {
+        return watch(opts, &config);
+    }
+
+    if let Command::Check(_) = command {
+        return check_all(opts, &config);
+    }
+
+    if let Command::Repl(_) = command {
+        return Ok(with_cell!(config.cell_width, repl::repl(&config, &mut input(opts, None)?))?);
+    }
+
+    if let Command::DiffRun(_) = command {
+        return diff_run(opts, &config);
+    }
+
+    if let Command::TraceDump(_) = command {
+        return trace_dump(opts);
+    }
+
+    let path = &opts.path;
+    let src = read_program(path, opts)?;
+
+    if let Command::Compile(_) = command {
+        let passes = opt::PassManager::from_config(&config).map_err(Error::Usage)?;
+        let prog = compile(opts, &config, &src, passes, &mut Report::default())?;
+        return build::build(&prog, &config, opts).map_err(Error::Build);
+    }
+
+    if let Command::Optimize(_) = command {
+        let passes = opt::PassManager::source_level().configure(&config).map_err(Error::Usage)?;
+        let prog = compile(opts, &config, &src, passes, &mut Report::default())?;
+        return build::optimize(&prog, &expanded(&config, &src), &config, opts).map_err(Error::Build);
+    }
+
+    if let Command::Fmt(_) = command && is_markdown(Path::new(path)) {
+        return Err(Error::Usage("`fmt` does not support Markdown files, as it would format the text around the code".to_string()));
+    }
+
+    if let Command::Fmt(_) = command {
+        // Only format valid programs, as the indentation follows the loops
+        compile(opts, &config, &src, opt::PassManager::for_level(0), &mut Report::default())?;
+        let formatted = format::format(&src);
+        return match &opts.output {
+            Some(path) => fs::write(path, formatted).map_err(Error::Io),
+            None => {
+                print!("{}", formatted);
+                Ok(())
+            },
+        };
+    }
+
+    if let Command::Minify(_) = command {
+        // Only minify valid programs, as shortening skips over whole loops
+        compile(opts, &config, &src, opt::PassManager::for_level(0), &mut Report::default())?;
+        let minified = minify::minify(&expanded(&config, &src), &config, opts.shorten);
+        return match &opts.output {
+            Some(path) => fs::write(path, minified + "\n").map_err(Error::Io),
+            None => {
+                println!("{}", minified);
+                Ok(())
+            },
+        };
+    }
+
+    if let Command::Debug(_) = command {
+        let prog = compile(opts, &config, &src, opt::PassManager::for_level(0), &mut Report::default())?;
+        return Ok(with_cell!(config.cell_width, debug::debug(&prog, &config, opts.break_on_output, &mut input(opts, None)?))?);
+    }
+
+    if let Command::Minimize(_) = command {
+        let check = opts.check.as_deref().ok_or_else(|| Error::Usage("`minimize` needs a command to --check programs with".to_string()))?;
+        // Only minimize valid programs, as the chunks removed keep the loops balanced
+        compile(opts, &config, &src, opt::PassManager::for_level(0), &mut Report::default())?;
+        let minimized = minimize::minimize(&expanded(&config, &src), &config, check, Path::new(path)).map_err(Error::Minimize)?;
+        return match &opts.output {
+            Some(path) => fs::write(path, minimized + "\n").map_err(Error::Io),
+            None => {
+                println!("{}", minimized);
+                Ok(())
+            },
+        };
+    }
+
+    if let Command::Explore(_) = command {
+        let goal = opts.goal.ok_or_else(|| Error::Usage("`explore` needs a goal, given by --writes or --reaches".to_string()))?;
+        if config.cell_width == CellWidth::Big || config.io != IoMode::Bytes {
+            return Err(Error::Usage("`explore` is not supported with big cells, or numeric, bit or Unicode I/O".to_string()));
+        }
+        let passes = opt::PassManager::from_config(&config).map_err(Error::Usage)?;
+        let prog = compile(opts, &config, &src, passes, &mut Report::default())?;
+        return explore(&prog, &config, opts, goal);
+    }
+
+    if let Command::Stats(_) = command {
+        let passes = opt::PassManager::from_config(&config).map_err(Error::Usage)?;
+        let parsed = compile(opts, &config, &src, opt::PassManager::for_level(0), &mut Report::default())?;
+        let mut optimized = parsed.clone();
+        passes.run(&mut optimized);
+        return Ok(stats::write(&parsed, &optimized, &mut io::stdout())?);
+    }
+
+    run_and_report(opts, &config, &src)
+}
+
+/// Run the program, printing a report afterwards if requested.
+fn run_and_report(opts: &cli::Options, config: &Config, src: &str) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut report = Report::default();
+    let result = run_program(opts, config, src, &mut report);
+
+    if opts.report {
+        report.wall_time = start.elapsed();
+        report.error = result.as_ref().err().map(Error::summary);
+        report.write_json(&mut io::stderr())?;
+    }
+
+    result
+}
+
+/// Run several programs one after another, with a header before and the time taken after each.
+fn run_all(opts: &cli::Options, config: &Config) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut failed = 0;
+    for (i, path) in opts.paths.iter().enumerate() {
+        let opts = cli::Options { path: path.clone(), ..opts.clone() };
+        eprintln!("{}==> {} <==", if i > 0 { "\n" } else { "" }, path.display());
+
+        let program_start = Instant::now();
+        let result = read_program(path, &opts).and_then(|src| run_and_report(&opts, config, &src));
+        io::stdout().flush()?;
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            failed += 1;
+        }
+        eprintln!("({:.3}s)", program_start.elapsed().as_secs_f64());
+    }
+
+    eprintln!("\nran {} programs in {:.3}s, {} failed", opts.paths.len(), start.elapsed().as_secs_f64(), failed);
+    match failed {
+        0 => Ok(()),
+        n => Err(Error::ProgramsFailed(n)),
+    }
+}
+
+/// Check every program for errors and warnings, without running them.
+fn check_all(opts: &cli::Options, config: &Config) -> Result<(), Error> {
+    let check = |path: &PathBuf| {
+        let opts = cli::Options { path: path.clone(), ..opts.clone() };
+        let src = read_program(path, &opts)?;
+        compile(&opts, config, &src, opt::PassManager::for_level(0), &mut Report::default()).map(drop)
+    };
+    if opts.paths.len() == 1 {
+        return check(&opts.path);
+    }
+
+    let mut failed = 0;
+    for path in &opts.paths {
+        if let Err(e) = check(path) {
+            eprintln!("{}", e);
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(Error::ProgramsFailed(n)),
+    }
+}
+
+/// Run the program again every time its file changes, until interrupted.
+fn watch(opts: &cli::Options, config: &Config) -> Result<(), Error> {
+    let path = &opts.path;
+    let modified = || fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    loop {
+        let last_modified = modified();
+        let result = read_program(&opts.path, opts).and_then(|src| run_and_report(opts, config, &src));
+        io::stdout().flush()?;
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+
+        while modified() == last_modified {
+            thread::sleep(WATCH_INTERVAL);
+        }
+        eprintln!("\n--- `{}` changed, running it again ---\n", opts.path.display());
+    }
+}
+
+/// Run the two programs on the same input and compare their output.
+fn diff_run(opts: &cli::Options, config: &Config) -> Result<(), Error> {
+    if config.cell_width == CellWidth::Big || config.io != IoMode::Bytes {
+        return Err(Error::Usage("`diff-run` is not supported with big cells, or numeric, bit or Unicode I/O".to_string()));
+    }
+    let mut progs = Vec::new();
+    for path in &opts.paths {
+        let opts = cli::Options { path: path.clone(), ..opts.clone() };
+        let src = read_program(path, &opts)?;
+        let passes = opt::PassManager::from_config(config).map_err(Error::Usage)?;
+        progs.push(compile(&opts, config, &src, passes, &mut Report::default())?);
+    }
+    let mut input_bytes = Vec::new();
+    input(opts, None)?.read_to_end(&mut input_bytes)?;
+
+    let names = [opts.paths[0].to_string_lossy(), opts.paths[1].to_string_lossy()];
+    let programs = [(&*names[0], &progs[0][..]), (&*names[1], &progs[1][..])];
+    match diffrun::diff_run(programs, config, &input_bytes, &mut io::stdout())? {
+        true => Ok(()),
+        false => Err(Error::OutputsDiffer),
+    }
+}
+
+/// Look for input for which the program reaches `goal`, and describe it.
+fn explore(prog: &[Instruction], config: &Config, opts: &cli::Options, goal: Goal) -> Result<(), Error> {
+    let limits = Limits {
+        input_len: opts.input_length,
+        max_paths: opts.max_paths,
+        max_steps: config.max_steps.unwrap_or(Limits::default().max_steps),
+        ..Limits::default()
+    };
+    let exploration = symbolic::explore(prog, config, goal, limits);
+    let goal = match goal {
+        Goal::Write(byte) => format!("writes the byte {:#04x}", byte),
+        Goal::Reach(pc) => format!("reaches instruction {}", pc),
+    };
+    match exploration.outcome {
+        Outcome::Found(input) => {
+            println!("the program {} with the input \"{}\"", goal, input.escape_ascii());
+            if let Some(path) = &opts.output {
+                fs::write(path, input)?;
             }
+        },
+        Outcome::Impossible => println!("the program never {} with {} bytes of input", goal, opts.input_length),
+        Outcome::Unknown => println!("found no input for which the program {} on the {} paths followed, \
+            but there may be more", goal, exploration.paths),
+    }
+    Ok(())
+}
+
+/// Print a binary trace as CSV.
+fn trace_dump(opts: &cli::Options) -> Result<(), Error> {
+    let file = fs::File::open(&opts.path).map_err(|e| Error::File(opts.path.clone(), e))?;
+    let records = TraceReader::new(io::BufReader::new(file)).map_err(|e| Error::File(opts.path.clone(), e))?;
+    let mut out: Box<dyn Write> = match &opts.output {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    writeln!(out, "{}", tracefile::CSV_HEADER)?;
+    for record in records {
+        record.map_err(|e| Error::File(opts.path.clone(), e))?.write_csv(&mut out)?;
+    }
+    Ok(out.flush()?)
+}
+
+/// Read the program in the file at `path`, which is the code in the `brainfuck` blocks of
+/// Markdown files ending in `.md`, see [`literate`]. Other files are mapped into memory
+/// with `--map-source` where that is supported.
+fn read_program(path: &Path, opts: &cli::Options) -> Result<Source, Error> {
+    let markdown = is_markdown(path);
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if opts.map_source && !markdown {
+        // SAFETY: `--map-source` promises that the file does not change while it is mapped
+        let mapped = unsafe { bf::mmap::Source::open(path) };
+        return mapped.map(Source::Mapped).map_err(|e| Error::File(path.to_path_buf(), e));
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = opts;
+    let src = fs::read_to_string(path).map_err(|e| Error::File(path.to_path_buf(), e))?;
+    match markdown {
+        true => Ok(Source::Read(literate::extract(&src))),
+        false => Ok(Source::Read(src)),
+    }
+}
+
+/// The source of a program, as [`read_program`] gives it.
+enum Source {
+    Read(String),
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    Mapped(bf::mmap::Source),
+}
+
+impl std::ops::Deref for Source {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Source::Read(src) => src,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Source::Mapped(src) => src,
         }
+    }
+}
 
-        Ok(())
+/// Whether the file at `path` is Markdown, read as literate Brainfuck.
+fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md"))
+}
+
+/// The program `src` with its macros expanded, once [`compile`] has checked that they expand.
+fn expanded<'src>(config: &Config, src: &'src str) -> Cow<'src, str> {
+    match config.macros {
+        true => Cow::Owned(macros::expand(src).expect("checked by `compile`").into_src()),
+        false => Cow::Borrowed(src),
     }
 }
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    let src = fs::read_to_string(&args[1])
-        .map_err(|e| e.to_string())?;
+/// Render a diagnostic in the requested format.
+fn render(diagnostic: &Diagnostic, opts: &cli::Options) -> String {
+    match opts.diagnostics {
+        diagnostic::Format::Human => diagnostic.render(diagnostic::use_color()),
+        diagnostic::Format::Json => diagnostic.to_json(),
+    }
+}
 
+/// Parse and optimize the program, collecting statistics in `report`.
+fn compile(opts: &cli::Options, config: &Config, src: &str, passes: opt::PassManager, report: &mut Report) -> Result<Vec<Instruction>, Error> {
     // Parse
-    let lexer = Lexer::new(&src);
+    let span = trace::span("parse");
+    let expansion = match config.macros {
+        true => Some(macros::expand(src).map_err(|e| {
+            let (line, col) = e.pos();
+            let diagnostic = Diagnostic { severity: Severity::Error, path: &opts.path.to_string_lossy(), src, line, col, message: e.message(), label: e.label() };
+            Error::Diagnostic(bf::Error::Macro(e), render(&diagnostic, opts))
+        })?),
+        false => None,
+    };
+    // Diagnostics point into the source as written, rather than into its expansion
+    let origin = |pos| expansion.as_ref().map_or(pos, |expansion| expansion.origin(pos));
+    let expanded = expansion.as_ref().map_or(src, macros::Expansion::src);
+    let lexer = Lexer::with_config(expanded, config);
     let mut parser = Parser::new(lexer);
-    let mut prog = parser.parse()
-        .map_err(|e| e.to_string())?;
+    let mut prog = parser.parse().map_err(|e| {
+        let e = e.relocate(origin);
+        let (line, col) = e.pos();
+        let diagnostic = Diagnostic { severity: Severity::Error, path: &opts.path.to_string_lossy(), src, line, col, message: e.message(), label: e.label() };
+        Error::Diagnostic(bf::Error::Parse(e), render(&diagnostic, opts))
+    })?;
+    let warnings = ranges::warnings(&prog, expanded, config);
+    for warning in parser.warnings().iter().chain(&warnings) {
+        print_warning(opts, src, origin(warning.pos()), warning);
+    }
+    event!(Debug, "parse", "parsed {} top-level instructions", prog.len());
+    drop(span);
+
+    // Specialize for a known prefix of the input
+    if let Some(path) = &opts.partial_input {
+        if config.cell_width == CellWidth::Big || config.io != IoMode::Bytes {
+            return Err(Error::Usage("`--partial-input` is not supported with big cells, or numeric, bit or Unicode I/O".to_string()));
+        }
+        let span = trace::span("partial");
+        let input = fs::read(path).map_err(|e| Error::File(path.clone(), e))?;
+        prog = partial::specialize(&prog, &input, config);
+        event!(Debug, "partial", "{} top-level instructions remain after consuming {} input bytes", prog.len(), input.len());
+        drop(span);
+    }
 
     // Optimize
-    cancel(&mut prog);
-    clearloop(&mut prog);
+    let span = trace::span("optimize");
+    report.passes = passes.run(&mut prog);
+    if opts.opt_stats {
+        report::write_pass_stats(&report.passes, &mut io::stderr())?;
+    }
+    event!(Debug, "optimize", "{} top-level instructions remain", prog.len());
+    drop(span);
+
+    Ok(prog)
+}
+
+/// Print a warning at `(line, col)` in `src` in the requested format.
+fn print_warning(opts: &cli::Options, src: &str, (line, col): (usize, usize), warning: &Warning) {
+    let diagnostic = Diagnostic { severity: Severity::Warning, path: &opts.path.to_string_lossy(), src, line, col, message: warning.message(), label: warning.label() };
+    match opts.diagnostics {
+        diagnostic::Format::Human => eprintln!("{}\n", render(&diagnostic, opts)),
+        diagnostic::Format::Json => eprintln!("{}", render(&diagnostic, opts)),
+    }
+}
+
+/// Pack the program straight from its source for the fast interpreter, rather than parsing
+/// it into instructions, if it runs at `-O0` and nothing else needs the instructions.
+fn pack(opts: &cli::Options, config: &Config, src: &str) -> Result<Option<Packed>, Error> {
+    let instructions_needed = config.opt_level > 0 || config.macros || opts.partial_input.is_some() || opts.opt_stats || opts.aot || opts.taint;
+    if instructions_needed || observed(opts) || !Machine::<u8>::supports(config) {
+        return Ok(None);
+    }
+
+    let span = trace::span("parse");
+    let mut parser = packed::Parser::new(Lexer::with_config(src, config));
+    let packed = parser.parse().map_err(|e| {
+        let (line, col) = e.pos();
+        let diagnostic = Diagnostic { severity: Severity::Error, path: &opts.path.to_string_lossy(), src, line, col, message: e.message(), label: e.label() };
+        Error::Diagnostic(bf::Error::Parse(e), render(&diagnostic, opts))
+    })?;
+    let Some(packed) = packed else {
+        return Ok(None);
+    };
+    // The analysis of the ranges needs the instructions, to warn as `compile` does
+    let prog = Parser::new(Lexer::with_config(src, config)).parse().unwrap_or_default();
+    let warnings = ranges::warnings(&prog, src, config);
+    for warning in parser.warnings().iter().chain(&warnings) {
+        print_warning(opts, src, warning.pos(), warning);
+    }
+    event!(Debug, "parse", "packed {} words", packed.words().len());
+    drop(span);
+    Ok(Some(packed))
+}
+
+/// Whether anything observes the run as it goes, which only the interpreter on instructions supports.
+fn observed(opts: &cli::Options) -> bool {
+    opts.step_delay.is_some() || opts.break_on_output.is_some() || opts.heatmap.is_some() || opts.profile
+        || opts.flamegraph.is_some() || opts.record.is_some() || opts.trace_file.is_some() || opts.checkpoint.is_some() || opts.resume.is_some()
+}
+
+/// Parse, optimize and interpret the program, collecting statistics in `report`.
+fn run_program(opts: &cli::Options, config: &Config, src: &str, report: &mut Report) -> Result<(), Error> {
+    if config.dialect == Dialect::SelfModifying {
+        return run_self_modifying(src, config, opts);
+    }
+
+    let passes = opt::PassManager::from_config(config).map_err(Error::Usage)?;
+    let packed = pack(opts, config, src)?;
+    let prog = match packed {
+        Some(_) => Vec::new(),
+        None => compile(opts, config, src, passes, report)?,
+    };
+
+    if opts.aot {
+        let interpreter_only = opts.checkpoint.is_some() || opts.resume.is_some() || opts.delay.is_some()
+            || opts.step_delay.is_some() || opts.break_on_output.is_some() || opts.trace_file.is_some() || opts.taint || opts.profile || opts.flamegraph.is_some() || opts.output_format != bf::output::Format::Raw || opts.input.is_some()
+            || opts.encoding.is_some() || opts.tee.is_some() || opts.socket.is_some();
+        if interpreter_only {
+            return Err(Error::Usage("`--checkpoint`, `--resume`, `--delay`, `--step-delay`, `--break-on-output`, `--trace-file`, `--taint`, `--profile`, `--flamegraph`, `--output-format`, `--encoding`, `--tee` and I/O other than stdio are not supported with `--aot`".to_string()));
+        }
+        #[cfg(unix)]
+        return aot::run(&prog, config, opts).map_err(Error::Build)?.map_err(Error::Native);
+        #[cfg(not(unix))]
+        return Err(Error::Usage("`--aot` is only supported on Unix".to_string()));
+    }
+
+    // Talk over the network instead of stdio if requested
+    let stream = connect(opts)?;
+
+    // Switch to unbuffered input for interactive programs, restored when dropped
+    let _raw_mode = if opts.raw && opts.input.is_none() && stream.is_none() && io::stdin().is_terminal() {
+        Some(RawMode::enable()?)
+    } else {
+        None
+    };
+
+    if opts.taint {
+        return track_taint(&prog, config, opts, stream.as_ref());
+    }
+
+    // Checkpoints and recordings only hold the lowest 32 bits of every cell
+    if config.cell_width == CellWidth::Big && (opts.record.is_some() || opts.checkpoint.is_some() || opts.resume.is_some()) {
+        return Err(Error::Usage("`--record`, `--checkpoint` and `--resume` are not supported with big cells".to_string()));
+    }
+    with_cell!(config.cell_width, interpret(&prog, packed.as_ref(), config, opts, stream.as_ref(), report))
+}
+
+/// Interpret the program with cells of type `C`, collecting statistics in `report`.
+/// A program that is already `packed` is run by the fast interpreter instead.
+fn interpret<C: Cell>(prog: &[Instruction], packed: Option<&Packed>, config: &Config, opts: &cli::Options, stream: Option<&TcpStream>, report: &mut Report) -> Result<(), Error> {
+    let mut rdr = input(opts, stream)?;
+    let mut wtr = output(opts, config, stream)?;
+    // The program reads and writes every bit as a byte
+    if config.io == IoMode::Bits {
+        rdr = Box::new(bf::input::Bits::new(rdr));
+        wtr = Box::new(output::Bits::new(wtr));
+    }
+    let span = trace::span("eval");
+
+    // Without anything observing the run, use the fast interpreter
+    let packed = match packed {
+        Some(packed) => Some(Cow::Borrowed(packed)),
+        None if !observed(opts) && Machine::<C>::supports(config) => Packed::lower(prog).map(Cow::Owned),
+        None => None,
+    };
+    if let Some(packed) = packed {
+        event!(Debug, "eval", "running {} packed words with the fast interpreter", packed.words().len());
+        let mut machine = Machine::<C>::new(&mut rdr, &mut wtr, config);
+        let result = machine.run(&packed);
+        drop(span);
+        report.resources = machine.resources();
+        drop(machine);
+        result?;
+        wtr.flush()?;
+        return Ok(());
+    }
+
+    let mut ctx = Context::<C>::new(&mut rdr, &mut wtr, config);
+    if let Some(delay) = opts.step_delay {
+        ctx.throttle(delay);
+    }
+    if let Some(byte) = opts.break_on_output {
+        ctx.break_on_output(byte);
+    }
+    if opts.heatmap.is_some() {
+        ctx.record_heatmap();
+    }
+    if opts.profile || opts.flamegraph.is_some() {
+        ctx.profile_every(opts.profile_every);
+    }
+    if opts.record.is_some() {
+        ctx.record_frames(opts.record_every);
+    }
+    if let Some(path) = &opts.trace_file {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        ctx.trace_to(TraceWriter::new(file, path.ends_with(".csv"))?);
+    }
+    if let Some(path) = &opts.checkpoint {
+        ctx.checkpoint_every(path, opts.checkpoint_every);
+    }
+    if let Some(path) = &opts.resume {
+        let file = fs::File::open(path).map_err(|e| Error::File(path.clone(), e))?;
+        Checkpoint::read(io::BufReader::new(file))
+            .and_then(|checkpoint| ctx.restore(prog, checkpoint))
+            .map_err(|e| Error::Checkpoint(path.clone(), e))?;
+    }
+    let result = ctx.eval(prog);
+    drop(span);
 
-    // Interpret
-    let mut rdr = io::stdin();
-    let mut wtr = io::stdout();
-    let mut ctx = Context::new(&mut rdr, &mut wtr);
-    ctx.eval(&mut prog)
-        .map_err(|e| e.to_string())?;
+    report.resources = ctx.resources();
+    result?;
+
+    if let (Some(path), Some(frames)) = (&opts.record, ctx.take_frames()) {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        if path.ends_with(".gif") {
+            frames.write_gif(&mut file)?;
+        } else {
+            frames.write_text(&mut file)?;
+        }
+        file.flush()?;
+    }
+
+    if let (Some(format), Some(heatmap)) = (opts.heatmap, ctx.heatmap()) {
+        heatmap.print(format, &mut io::stderr())?;
+    }
+    if let Some(profile) = ctx.profile() && opts.profile {
+        profile.print(prog, config, &mut io::stderr())?;
+    }
+    if let (Some(path), Some(profile)) = (&opts.flamegraph, ctx.profile()) {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        profile.write_folded(prog, config, &mut file)?;
+        file.flush()?;
+    }
+    drop(ctx);
+    wtr.flush()?;
 
     Ok(())
 }
+
+/// Interpret the program with the evaluator of [`events`], keeping track of the input bytes
+/// every output byte depends on, and print these to stderr at exit.
+fn track_taint(prog: &[Instruction], config: &Config, opts: &cli::Options, stream: Option<&TcpStream>) -> Result<(), Error> {
+    if config.cell_width == CellWidth::Big || config.io != IoMode::Bytes {
+        return Err(Error::Usage("`--taint` is not supported with big cells, or numeric, bit or Unicode I/O".to_string()));
+    }
+    let context_only = opts.checkpoint.is_some() || opts.resume.is_some() || opts.step_delay.is_some() || opts.break_on_output.is_some()
+        || opts.heatmap.is_some() || opts.profile || opts.flamegraph.is_some() || opts.record.is_some() || opts.trace_file.is_some();
+    if context_only {
+        return Err(Error::Usage("`--checkpoint`, `--resume`, `--step-delay`, `--break-on-output`, `--heatmap`, `--profile`, `--flamegraph`, `--record` and `--trace-file` are not supported with `--taint`".to_string()));
+    }
+
+    let mut input = io::BufReader::new(input(opts, stream)?).bytes();
+    let mut wtr = output(opts, config, stream)?;
+    let mut executions = Executions::new(prog, config);
+    executions.track_taint();
+    let mut result = Ok(());
+    while let Some(event) = executions.next() {
+        match event {
+            Event::Output(byte) => wtr.write_all(&[byte])?,
+            Event::NeedsInput => {
+                wtr.flush()?;
+                match input.next() {
+                    Some(byte) => executions.feed(&[byte?]),
+                    None => executions.feed_eof(),
+                }
+            },
+            Event::Error(e) => result = Err(Error::Runtime(e)),
+            _ => {},
+        }
+    }
+    wtr.flush()?;
+
+    if let Some(taint) = executions.taint() {
+        eprint!("{}", taint);
+    }
+    result
+}
+
+/// Run a program in the self-modifying dialect, which is not compiled, with the evaluator of [`smbf`].
+fn run_self_modifying(src: &str, config: &Config, opts: &cli::Options) -> Result<(), Error> {
+    if config.cell_width == CellWidth::Big || config.io != IoMode::Bytes {
+        return Err(Error::Usage("the smbf dialect is not supported with big cells, or numeric, bit or Unicode I/O".to_string()));
+    }
+    let compiled_only = opts.aot || opts.taint || opts.partial_input.is_some() || opts.checkpoint.is_some() || opts.resume.is_some()
+        || opts.step_delay.is_some() || opts.break_on_output.is_some() || opts.heatmap.is_some() || opts.profile
+        || opts.flamegraph.is_some() || opts.record.is_some() || opts.trace_file.is_some();
+    if compiled_only {
+        return Err(Error::Usage("`--aot`, `--taint`, `--partial-input`, `--checkpoint`, `--resume`, `--step-delay`, `--break-on-output`, `--heatmap`, `--profile`, `--flamegraph`, `--record` and `--trace-file` are not supported with the smbf dialect".to_string()));
+    }
+
+    let stream = connect(opts)?;
+    let _raw_mode = if opts.raw && opts.input.is_none() && stream.is_none() && io::stdin().is_terminal() {
+        Some(RawMode::enable()?)
+    } else {
+        None
+    };
+
+    let mut input = io::BufReader::new(input(opts, stream.as_ref())?).bytes();
+    let mut wtr = output(opts, config, stream.as_ref())?;
+    let mut executions = smbf::Executions::new(src, config);
+    let mut result = Ok(());
+    while let Some(event) = executions.next() {
+        match event {
+            Event::Output(byte) => wtr.write_all(&[byte])?,
+            Event::NeedsInput => {
+                wtr.flush()?;
+                match input.next() {
+                    Some(byte) => executions.feed(&[byte?]),
+                    None => executions.feed_eof(),
+                }
+            },
+            Event::Error(e) => result = Err(Error::Runtime(e)),
+            _ => {},
+        }
+    }
+    wtr.flush()?;
+    result
+}
+
+/// Set up the network connection to use instead of stdio, if any.
+fn connect(opts: &cli::Options) -> io::Result<Option<TcpStream>> {
+    match &opts.socket {
+        Some(cli::Socket::Listen(addr)) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("listening on {}", listener.local_addr()?);
+            let (stream, peer) = listener.accept()?;
+            eprintln!("accepted connection from {}", peer);
+            Ok(Some(stream))
+        },
+        Some(cli::Socket::Connect(addr)) => TcpStream::connect(addr).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Where the interpreted program reads its input from.
+fn input<'a>(opts: &'a cli::Options, stream: Option<&TcpStream>) -> io::Result<Box<dyn io::Read + 'a>> {
+    Ok(match (&opts.input, stream, &opts.prompt) {
+        (Some(bytes), _, _) => Box::new(&bytes[..]),
+        (None, Some(stream), _) => Box::new(stream.try_clone()?),
+        (None, None, Some(prompt)) if io::stdin().is_terminal() => Box::new(Prompted::new(io::stdin(), prompt.clone())),
+        (None, None, _) => Box::new(io::stdin()),
+    })
+}
+
+/// Where the interpreted program writes its output.
+fn output(opts: &cli::Options, config: &Config, stream: Option<&TcpStream>) -> io::Result<Box<dyn Write>> {
+    let wtr: Box<dyn Write> = match stream {
+        // Buffer lines, like standard output
+        Some(stream) => Box::new(io::LineWriter::new(stream.try_clone()?)),
+        None => Box::new(io::stdout()),
+    };
+    let wtr: Box<dyn Write> = match opts.delay {
+        Some(delay) => Box::new(output::Throttled::new(wtr, delay)),
+        // Standard output is line buffered, so buffer whole lines as well
+        None if config.flush == FlushPolicy::OnExit => Box::new(io::BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, wtr)),
+        None => wtr,
+    };
+    let wtr: Box<dyn Write> = match (opts.output_format, &opts.encoding) {
+        (output::Format::Raw, Some(encoding)) => Box::new(output::Encoded::new(wtr, encoding.clone())),
+        (output::Format::Raw, None) => wtr,
+        // Hex and escaped output show the bytes themselves, whatever the encoding
+        (format, _) => Box::new(output::Formatted::new(wtr, format)),
+    };
+    // Capture the output as the program writes it, before formatting
+    Ok(match &opts.tee {
+        Some(path) => Box::new(output::Tee::new(wtr, io::BufWriter::new(fs::File::create(path)?))),
+        None => wtr,
+    })
+}
+
+fn main() -> ExitCode {
+    let command = Command::parse(env::args_os().skip(1)).map_err(Error::Usage);
+    let strict = command.as_ref().is_ok_and(Command::strict);
+    match command.and_then(|command| run(&command)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(e.exit_code(strict))
+        }
+    }
+}
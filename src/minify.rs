@@ -0,0 +1,91 @@
+use bf::{config::{CellWidth, Config, Dialect}, lexer::Lexer};
+
+use crate::minimize::matching;
+
+/// Write the valid program `src` with only its commands, on a single line.
+///
+/// With `shorten`, the following changes are made as well, over and over until none
+/// applies. They do not change what the program writes, although like the optimizer,
+/// canceling `><` and `<>` hides a data pointer moving outside of the tape.
+///
+/// - Loops at the start of the program, and loops directly after another loop, are removed,
+///   as their cell is always zero.
+/// - Runs of `+` and `-`, of `>` and `<`, and of `}` and `{` are replaced by their net effect,
+///   going the shorter way around when cells or tapes wrap around.
+/// - `+` and `-` at the end of the program are removed, as their effect cannot be observed.
+pub fn minify(src: &str, config: &Config, shorten: bool) -> String {
+    let mut program: Vec<char> = Lexer::with_config(src, config)
+        .filter_map(|(token, _)| token.command())
+        .collect();
+    if shorten {
+        loop {
+            let shorter = shorten_once(&program, config);
+            if shorter.len() == program.len() {
+                break;
+            }
+            program = shorter;
+        }
+    }
+    program.into_iter().collect()
+}
+
+fn shorten_once(program: &[char], config: &Config) -> Vec<char> {
+    // Values of a cell, or `None` if cells do not wrap around
+    let cells = match config.cell_width {
+        CellWidth::Big => None,
+        width => Some(width.mask() as i64 + 1),
+    };
+    let tapes = match config.dialect {
+        Dialect::MultiTape => Some(config.tapes as i64),
+        _ => None,
+    };
+
+    let mut out = Vec::with_capacity(program.len());
+    let mut i = 0;
+    while i < program.len() {
+        let c = program[i];
+        let run = |up: char, down: char| {
+            let len = program[i..].iter().take_while(|&&c| c == up || c == down).count();
+            let net = program[i..i + len].iter().map(|&c| if c == up { 1 } else { -1 }).sum::<i64>();
+            (len, net)
+        };
+        let (len, net, up, down, wrap) = match c {
+            '[' if out.is_empty() || out.last() == Some(&']') => {
+                i = matching(program, i) + 1;
+                continue;
+            },
+            '+' | '-' => {
+                let (len, net) = run('+', '-');
+                (len, net, '+', '-', cells)
+            },
+            '>' | '<' => {
+                let (len, net) = run('>', '<');
+                (len, net, '>', '<', None)
+            },
+            '}' | '{' => {
+                let (len, net) = run('}', '{');
+                (len, net, '}', '{', tapes)
+            },
+            c => {
+                out.push(c);
+                i += 1;
+                continue;
+            },
+        };
+
+        // Boolfuck has no `-`, but its cells wrap around after two steps
+        let net = match wrap {
+            Some(n) if net.rem_euclid(n) <= n - net.rem_euclid(n) => net.rem_euclid(n),
+            Some(n) => net.rem_euclid(n) - n,
+            None => net,
+        };
+        let c = if net >= 0 { up } else { down };
+        out.extend(std::iter::repeat_n(c, net.unsigned_abs() as usize));
+        i += len;
+    }
+
+    while matches!(out.last(), Some('+' | '-')) {
+        out.pop();
+    }
+    out
+}
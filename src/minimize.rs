@@ -0,0 +1,145 @@
+use std::{env, fs, path::{Path, PathBuf}, process};
+
+use bf::{config::Config, lexer::Lexer};
+
+/// Shrinks a program while a shell command keeps accepting it.
+struct Minimizer<'a> {
+    /// The command, run by the shell with the path of the candidate as `$1`.
+    check: &'a str,
+    /// File the candidates are written to.
+    file: PathBuf,
+    /// Number of times the command was run.
+    checks: usize,
+}
+
+impl Minimizer<'_> {
+    /// Whether the command succeeds on `program`.
+    fn accepts(&mut self, program: &[char]) -> Result<bool, String> {
+        fs::write(&self.file, program.iter().collect::<String>())
+            .map_err(|e| format!("could not write `{}`: {}", self.file.display(), e))?;
+        self.checks += 1;
+        let status = process::Command::new("sh").arg("-c").arg(self.check).arg("bf").arg(&self.file)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map_err(|e| format!("could not run the check: {}", e))?;
+        Ok(status.success())
+    }
+
+    /// Remove every chunk of `size` commands with balanced brackets that the command
+    /// accepts the program without. Returns whether any was removed.
+    fn remove_chunks(&mut self, program: &mut Vec<char>, size: usize) -> Result<bool, String> {
+        let mut removed = false;
+        let mut start = 0;
+        while start < program.len() {
+            let end = (start + size).min(program.len());
+            let candidate: Vec<char> = program[..start].iter().chain(&program[end..]).copied().collect();
+            if balanced(&program[start..end]) && self.accepts(&candidate)? {
+                *program = candidate;
+                removed = true;
+            } else {
+                start += size;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Replace every loop the command accepts the program without by its body.
+    /// Returns whether any was replaced.
+    fn unwrap_loops(&mut self, program: &mut Vec<char>) -> Result<bool, String> {
+        let mut unwrapped = false;
+        let mut open = 0;
+        while let Some(i) = program[open..].iter().position(|&c| c == '[') {
+            let start = open + i;
+            let end = matching(program, start);
+            let mut candidate = program.clone();
+            candidate.remove(end);
+            candidate.remove(start);
+            if self.accepts(&candidate)? {
+                *program = candidate;
+                unwrapped = true;
+            } else {
+                open = start + 1;
+            }
+        }
+        Ok(unwrapped)
+    }
+}
+
+/// Whether every bracket in `chunk` has its partner in it as well.
+fn balanced(chunk: &[char]) -> bool {
+    let mut depth = 0usize;
+    for &c in chunk {
+        match c {
+            '[' => depth += 1,
+            ']' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {},
+        }
+    }
+    depth == 0
+}
+
+/// Position of the `]` matching the `[` at `open` in a balanced program.
+pub fn matching(program: &[char], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, &c) in program.iter().enumerate().skip(open) {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 1 => return i,
+            ']' => depth -= 1,
+            _ => {},
+        }
+    }
+    unreachable!("the brackets of the program are balanced")
+}
+
+/// Find a smaller version of the valid program `src` for which the shell command
+/// `check` still succeeds, given the path of the program as `$1`.
+///
+/// This first drops the comments, and then removes ever smaller chunks of commands,
+/// and loops around their bodies, until removing any single command or loop makes
+/// the command fail.
+pub fn minimize(src: &str, config: &Config, check: &str, path: &Path) -> Result<String, String> {
+    let extension = path.extension().map_or("b".into(), |ext| ext.to_string_lossy());
+    let file = env::temp_dir().join(format!("bf-minimize-{}.{}", process::id(), extension));
+    let mut minimizer = Minimizer { check, file, checks: 0 };
+    let program = commands(src, config);
+    let len = program.len();
+    let result = run(&mut minimizer, program);
+    let _ = fs::remove_file(&minimizer.file);
+    let program = result?;
+
+    eprintln!("minimized {} commands to {} with {} checks", len, program.len(), minimizer.checks);
+    Ok(program.into_iter().collect())
+}
+
+fn run(minimizer: &mut Minimizer, mut program: Vec<char>) -> Result<Vec<char>, String> {
+    if !minimizer.accepts(&program)? {
+        return Err("the check fails for the program without comments, so there is nothing to minimize".to_string());
+    }
+
+    loop {
+        let mut size = program.len() / 2;
+        while size > 1 {
+            if !minimizer.remove_chunks(&mut program, size)? {
+                size /= 2;
+            }
+        }
+        let removed = minimizer.remove_chunks(&mut program, 1)?;
+        let unwrapped = minimizer.unwrap_loops(&mut program)?;
+        if !removed && !unwrapped {
+            return Ok(program);
+        }
+    }
+}
+
+/// The commands of the program, without comments.
+fn commands(src: &str, config: &Config) -> Vec<char> {
+    Lexer::with_config(src, config)
+        .filter_map(|(token, _)| token.command())
+        .collect()
+}
@@ -0,0 +1,148 @@
+//! Tape cells in an anonymous memory mapping, and source files mapped into memory.
+//!
+//! The mapping reserves address space for the whole tape, but the operating system
+//! only commits a page of memory when it is first touched, and provides it zeroed.
+//! A mapped source file is lexed without copying it into memory of its own. Its pages
+//! are read from disk once up front, to check that the file is valid UTF-8, but as they
+//! are backed by the file, the operating system can drop them again when memory is short.
+
+use std::{fs::File, io, ops::{Deref, DerefMut}, os::fd::AsRawFd, path::Path, ptr::NonNull};
+
+/// A zero-initialized slice of cells in its own memory mapping.
+pub struct Mapping<T> {
+    ptr: NonNull<T>,
+    len: usize,
+}
+
+impl<T> Mapping<T> {
+    /// Map `len` zeroed cells, without committing memory for them.
+    ///
+    /// # Safety
+    ///
+    /// A value of `T` with all bits zero must be valid, and `T` must not need to be dropped.
+    pub unsafe fn new(len: usize) -> io::Result<Self> {
+        let bytes = len.checked_mul(size_of::<T>())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::OutOfMemory, "tape too large"))?;
+        // SAFETY: an anonymous mapping at an address chosen by the kernel does not alias any memory.
+        let ptr = unsafe {
+            sys::mmap(std::ptr::null_mut(), bytes.max(1), sys::PROT_READ | sys::PROT_WRITE, sys::MAP_PRIVATE | sys::MAP_ANONYMOUS | sys::MAP_NORESERVE, -1, 0)
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: NonNull::new(ptr.cast()).unwrap(), len })
+    }
+}
+
+// SAFETY: the mapping is owned like a `Box<[T]>`, and only accessed through `&self` or `&mut self`.
+unsafe impl<T: Send> Send for Mapping<T> {}
+// SAFETY: as above.
+unsafe impl<T: Sync> Sync for Mapping<T> {}
+
+impl<T> Deref for Mapping<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the mapping holds `len` cells, which are zero and thus valid as promised to `new`.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for Mapping<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: as above, and the mapping is owned by `self`.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for Mapping<T> {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `new` with this size and is no longer used.
+        unsafe { sys::munmap(self.ptr.as_ptr().cast(), (self.len * size_of::<T>()).max(1)) };
+    }
+}
+
+/// A source file mapped read-only into memory, to lex it without copying it first.
+pub struct Source {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Source {
+    /// Map the file at `path`, which must be valid UTF-8.
+    /// This reads the whole file, to check it before it is lexed.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed or truncated while it is mapped.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::OutOfMemory, "file too large"))?;
+        // Empty mappings are not allowed
+        if len == 0 {
+            return Ok(Self { ptr: NonNull::dangling(), len });
+        }
+        // SAFETY: a private read-only mapping at an address chosen by the kernel does not alias
+        // any memory, and stays valid after the file is closed.
+        let ptr = unsafe { sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let source = Self { ptr: NonNull::new(ptr.cast()).unwrap(), len };
+        std::str::from_utf8(source.bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(source)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the mapping holds `len` bytes, which do not change as promised to `open`.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+// SAFETY: the mapping is owned like a `Box<str>`, and never written to.
+unsafe impl Send for Source {}
+// SAFETY: as above.
+unsafe impl Sync for Source {}
+
+impl Deref for Source {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // SAFETY: checked to be UTF-8 by `open`.
+        unsafe { std::str::from_utf8_unchecked(self.bytes()) }
+    }
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: the mapping was created by `open` with this size and is no longer used.
+            unsafe { sys::munmap(self.ptr.as_ptr().cast(), self.len) };
+        }
+    }
+}
+
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const MAP_PRIVATE: c_int = 2;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub const MAP_ANONYMOUS: c_int = 0x1000;
+    /// Do not reserve swap space for the whole mapping up front.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const MAP_NORESERVE: c_int = 0x4000;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub const MAP_NORESERVE: c_int = 0;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    unsafe extern "C" {
+        // `off_t` is the size of a pointer on the platforms we support
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: isize) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
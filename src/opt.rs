@@ -1,35 +1,401 @@
-use crate::Instruction;
+use alloc::{collections::{BTreeMap, BTreeSet}, format, string::{String, ToString}, sync::Arc, vec::Vec};
+#[cfg(feature = "std")]
+use std::{sync::Mutex, time::Instant};
+
+use crate::{Instruction, packed::Packed, config::{Config, CellWidth, IoMode}, trace::event, report::PassEffect};
+
+/// An optimization pass rewriting a program in place.
+///
+/// Passes can be added to a [`PassManager`] directly, or [registered](register) by name
+/// so they can be selected through the `passes` configuration option.
+/// A pipeline of passes is shared by all threads that optimize programs with it.
+pub trait Pass: Send + Sync {
+    /// Rewrite the program, returning whether it changed.
+    fn run(&self, ir: &mut Vec<Instruction>) -> bool;
+}
+
+/// A built-in pass, which does not report whether it changed the program.
+pub type PassFn = fn(&mut Vec<Instruction>);
+
+impl Pass for PassFn {
+    fn run(&self, ir: &mut Vec<Instruction>) -> bool {
+        // The packed encoding is a single small allocation, unlike a copy of the program
+        // with every loop body in its own vector
+        match Packed::lower(ir) {
+            Some(old) => {
+                self(ir);
+                Packed::lower(ir).is_none_or(|new| new != old)
+            },
+            None => {
+                let old = ir.clone();
+                self(ir);
+                *ir != old
+            },
+        }
+    }
+}
+
+/// All built-in optimization passes by name, in the order in which the default pipeline runs them.
+pub const PASSES: &[(&str, PassFn)] = &[
+    ("cancel", cancel),
+    ("canonicalize", canonicalize),
+    ("clearloop", clearloop),
+    ("deadloop", deadloop),
+    ("mulloop", mulloop),
+    ("constprop", constprop),
+    ("ranges", ranges),
+    ("licm", licm),
+    ("dce", dce),
+];
+
+/// Passes registered by name, in addition to the built-in passes.
+#[cfg(feature = "std")]
+static REGISTRY: Mutex<Vec<(String, Arc<dyn Pass>)>> = Mutex::new(Vec::new());
+
+/// Register a custom pass under the given name, replacing an earlier pass of that name.
+#[cfg(feature = "std")]
+pub fn register(name: &str, pass: impl Pass + 'static) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|(other, _)| other != name);
+    registry.push((name.to_string(), Arc::new(pass)));
+}
+
+/// Find a built-in or registered pass by name.
+pub fn lookup(name: &str) -> Option<Arc<dyn Pass>> {
+    if let Some(&(_, pass)) = PASSES.iter().find(|(other, _)| *other == name) {
+        return Some(Arc::new(pass));
+    }
+    #[cfg(feature = "std")]
+    {
+        let registry = REGISTRY.lock().unwrap();
+        let (_, pass) = registry.iter().find(|(other, _)| other == name)?;
+        Some(pass.clone())
+    }
+    #[cfg(not(feature = "std"))]
+    None
+}
+
+/// How much to optimize a program, as set by the `opt_level` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    /// Level `0`, which runs the program as written.
+    None,
+    /// Level `1`, which runs the configured passes, or all built-in passes by default.
+    Aggressive,
+}
+
+impl Level {
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Level::None => 0,
+            Level::Aggressive => 1,
+        }
+    }
+}
+
+/// Maximum number of times the default pipeline is run.
+const MAX_ITERATIONS: usize = 4;
+/// Maximum number of times the peephole passes are run within one run of the default pipeline.
+const PEEPHOLE_ITERATIONS: usize = 16;
+/// The cheap local passes, which can expose new opportunities for each other.
+const PEEPHOLE_PASSES: &[&str] = &["cancel", "canonicalize", "clearloop", "deadloop"];
+/// The passes whose result can be written as Brainfuck again, see [`PassManager::source_level`].
+const SOURCE_PASSES: &[&str] = &["cancel", "canonicalize", "clearloop", "deadloop", "ranges", "dce"];
+
+/// A named pass in a [`PassManager`] pipeline.
+struct Entry {
+    name: String,
+    pass: Arc<dyn Pass>,
+    enabled: bool,
+}
+
+/// A single step of a [`PassManager`] pipeline.
+enum Stage {
+    Pass(Entry),
+    /// A nested pipeline, run up to its own number of iterations.
+    Group(PassManager),
+}
+
+/// Runs an ordered pipeline of named optimization passes.
+pub struct PassManager {
+    stages: Vec<Stage>,
+    /// Maximum number of times the pipeline is run;
+    /// it stops early once a run no longer changes the program.
+    max_iterations: usize,
+}
+
+impl PassManager {
+    /// An empty pipeline.
+    pub fn new() -> Self {
+        Self { stages: Vec::new(), max_iterations: 1 }
+    }
+
+    /// The pipeline of the given optimization level, where `0` runs no passes
+    /// and `1` runs all of [`PASSES`] until the program no longer changes.
+    /// The peephole passes are iterated on their own first, as they are cheap
+    /// and can enable each other.
+    pub fn for_level(opt_level: u8) -> Self {
+        let mut pm = Self::new();
+        if opt_level > 0 {
+            let mut peephole = Self::new();
+            peephole.set_max_iterations(PEEPHOLE_ITERATIONS);
+            for &(name, pass) in PASSES {
+                if PEEPHOLE_PASSES.contains(&name) {
+                    peephole.add(name, pass);
+                } else {
+                    pm.add(name, pass);
+                }
+            }
+            pm.stages.insert(0, Stage::Group(peephole));
+            pm.set_max_iterations(MAX_ITERATIONS);
+        }
+        pm
+    }
+
+    /// The passes of the default pipeline whose result can be written as Brainfuck again,
+    /// without introducing instructions such as [`Instruction::WriteConst`].
+    pub fn source_level() -> Self {
+        let mut pm = Self::new();
+        let mut peephole = Self::new();
+        peephole.set_max_iterations(PEEPHOLE_ITERATIONS);
+        for &(name, pass) in PASSES.iter().filter(|(name, _)| SOURCE_PASSES.contains(name)) {
+            if PEEPHOLE_PASSES.contains(&name) {
+                peephole.add(name, pass);
+            } else {
+                pm.add(name, pass);
+            }
+        }
+        pm.stages.insert(0, Stage::Group(peephole));
+        pm.set_max_iterations(MAX_ITERATIONS);
+        pm
+    }
+
+    /// The pipeline of the configured optimization level and passes, without the disabled passes.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        // The passes are known at every level, so that the same names are accepted at level `0`
+        Self::for_level(config.opt_level.max(1)).configure(config)
+    }
+
+    /// Apply the configured optimization level and passes to this pipeline:
+    /// remove all passes at level `0`, replace them by the configured passes if any,
+    /// and disable the configured disabled passes, as well as the passes that rely on
+    /// wrapping arithmetic for big cells, on cells of at least a byte, or on byte output
+    /// for numeric I/O.
+    pub fn configure(mut self, config: &Config) -> Result<Self, String> {
+        if let Some(names) = &config.passes {
+            self.stages.clear();
+            for name in names {
+                let pass = lookup(name).ok_or_else(|| format!("unknown pass `{}`", name))?;
+                self.stages.push(Stage::Pass(Entry { name: name.clone(), pass, enabled: true }));
+            }
+        }
+        for name in &config.disabled_passes {
+            self.set_enabled(name, false)?;
+        }
+        if config.cell_width == CellWidth::Big {
+            // These assume that cells wrap: `[-]` does not end for a negative cell without it
+            for name in ["clearloop", "mulloop", "constprop"] {
+                let _ = self.set_enabled(name, false);
+            }
+        }
+        if config.cell_width == CellWidth::Bit {
+            // These take constants up to a byte as they are
+            for name in ["constprop", "ranges"] {
+                let _ = self.set_enabled(name, false);
+            }
+        }
+        if matches!(config.io, IoMode::Numeric | IoMode::Unicode) {
+            // Constant output is folded into bytes
+            let _ = self.set_enabled("constprop", false);
+        }
+        // Only now, so that unknown passes are reported at every level
+        if config.opt_level == 0 {
+            return Ok(Self::new());
+        }
+        Ok(self)
+    }
+
+    /// Append a pass to the pipeline.
+    pub fn add(&mut self, name: &str, pass: impl Pass + 'static) -> &mut Self {
+        self.stages.push(Stage::Pass(Entry { name: name.to_string(), pass: Arc::new(pass), enabled: true }));
+        self
+    }
+
+    /// Append a nested pipeline, which is run up to its own maximum number of iterations
+    /// every time this pipeline runs.
+    pub fn add_group(&mut self, group: PassManager) -> &mut Self {
+        self.stages.push(Stage::Group(group));
+        self
+    }
+
+    /// Run the pipeline up to `n` times, stopping early once the program no longer changes.
+    pub fn set_max_iterations(&mut self, n: usize) -> &mut Self {
+        self.max_iterations = n.max(1);
+        self
+    }
+
+    /// Enable or disable every pass with the given name, including those in nested pipelines.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        if self.set_enabled_in_stages(name, enabled) {
+            Ok(())
+        } else {
+            Err(format!("unknown pass `{}`", name))
+        }
+    }
+
+    fn set_enabled_in_stages(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for stage in &mut self.stages {
+            match stage {
+                Stage::Pass(entry) if entry.name == name => {
+                    entry.enabled = enabled;
+                    found = true;
+                },
+                Stage::Pass(_) => {},
+                Stage::Group(group) => found |= group.set_enabled_in_stages(name, enabled),
+            }
+        }
+        found
+    }
+
+    /// Run all enabled passes in order, returning the effect of every pass that was run.
+    pub fn run(&self, bf: &mut Vec<Instruction>) -> Vec<PassEffect> {
+        let mut effects = Vec::new();
+        self.run_into(bf, &mut effects);
+        effects
+    }
+
+    /// Run the pipeline, collecting the effect of every pass, and returning whether
+    /// the program changed.
+    fn run_into(&self, bf: &mut Vec<Instruction>, effects: &mut Vec<PassEffect>) -> bool {
+        let mut changed = false;
+        for iteration in 1..=self.max_iterations {
+            let mut iteration_changed = false;
+            for stage in &self.stages {
+                iteration_changed |= match stage {
+                    Stage::Pass(entry) if entry.enabled => {
+                        let before = size(bf);
+                        #[cfg(feature = "std")]
+                        let start = Instant::now();
+                        let pass_changed = entry.pass.run(bf);
+                        // Keep the cached loop shifts valid for the next pass
+                        annotate_shifts(bf);
+                        // Passes are not timed without a clock
+                        #[cfg(feature = "std")]
+                        let time = start.elapsed();
+                        #[cfg(not(feature = "std"))]
+                        let time = core::time::Duration::ZERO;
+                        effects.push(PassEffect { name: entry.name.clone(), before, after: size(bf), changed: pass_changed, time });
+                        pass_changed
+                    },
+                    Stage::Pass(_) => false,
+                    Stage::Group(group) => group.run_into(bf, effects),
+                };
+            }
+
+            changed |= iteration_changed;
+            if !iteration_changed {
+                event!(Debug, "opt", "reached a fixpoint after {} iterations", iteration);
+                break;
+            }
+        }
+        changed
+    }
+}
+
+impl Pass for PassManager {
+    fn run(&self, ir: &mut Vec<Instruction>) -> bool {
+        self.run_into(ir, &mut Vec::new())
+    }
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::for_level(1)
+    }
+}
+
+/// Total number of instructions, including those in loop bodies.
+pub fn size(bf: &[Instruction]) -> usize {
+    fold(bf, 0, &mut |n, _| n + 1)
+}
+
+/// Combine all instructions into a single value, visiting them in order,
+/// with every loop before the instructions in its body.
+pub fn fold<T>(bf: &[Instruction], init: T, f: &mut impl FnMut(T, &Instruction) -> T) -> T {
+    bf.iter().fold(init, |acc, instr| {
+        let acc = f(acc, instr);
+        match instr {
+            Instruction::Loop(inner, _) => fold(inner, acc, f),
+            _ => acc,
+        }
+    })
+}
+
+/// Rebuild the program from the innermost loops outwards, replacing every instruction
+/// by the instructions `f` returns for it. Loops are given to `f` with their body already
+/// rebuilt and their shift updated, so `f` only needs to handle a single instruction:
+///
+/// ```text
+/// // Write every cell twice
+/// let bf = opt::map_instructions(bf, &mut |instr| match instr {
+///     Instruction::Write => vec![Instruction::Write, Instruction::Write],
+///     instr => vec![instr],
+/// });
+/// ```
+pub fn map_instructions(bf: Vec<Instruction>, f: &mut impl FnMut(Instruction) -> Vec<Instruction>) -> Vec<Instruction> {
+    bf.into_iter()
+        .flat_map(|instr| match instr {
+            Instruction::Loop(inner, _) => {
+                let inner = map_instructions(inner, f);
+                f(Instruction::new_loop(inner))
+            },
+            instr => f(instr),
+        })
+        .collect()
+}
 
 /// Cancel out adjacent increments and decrements.
 ///
 /// `><` `<>` `+-` `-+`
 pub fn cancel(bf: &mut Vec<Instruction>) {
     // Go from back to front, to reduce the number of shifts when removing
-    let mut i = bf.len() - 1;
+    let mut i = bf.len();
 
     while i > 0 {
+        i -= 1;
         use Instruction::*;
-        if let Loop(instr) = &mut bf[i] {
+        if let Loop(instr, _) = &mut bf[i] {
             // Recurse
             cancel(instr);
-            i -= 1;
-        } else {
-            let r = &bf[i];
-            let l = &bf[i - 1];
+        } else if i + 1 < bf.len() {
+            let l = &bf[i];
+            let r = &bf[i + 1];
             match (l, r) {
                 (IncPtr(x), DecPtr(y)) |
                 (DecPtr(x), IncPtr(y)) if x == y => {
+                    event!(Trace, "opt::cancel", "cancelled {:?} {:?}", l, r);
+                    // The instructions around the removed pair are compared next
+                    bf.remove(i + 1);
                     bf.remove(i);
-                    bf.remove(i - 1);
                 }
                 (IncVal(x), DecVal(y)) |
                 (DecVal(x), IncVal(y)) if x == y => {
+                    event!(Trace, "opt::cancel", "cancelled {:?} {:?}", l, r);
+                    bf.remove(i + 1);
                     bf.remove(i);
-                    bf.remove(i - 1);
                 }
-                _ => {
-                    i -= 1;
-                },
+                (SwitchTape(x), SwitchTape(y)) => {
+                    event!(Trace, "opt::cancel", "combined {:?} {:?}", l, r);
+                    let n = x + y;
+                    bf.remove(i + 1);
+                    if n == 0 {
+                        bf.remove(i);
+                    } else {
+                        bf[i] = SwitchTape(n);
+                    }
+                }
+                _ => {},
             }
         }
     }
@@ -40,10 +406,11 @@ pub fn cancel(bf: &mut Vec<Instruction>) {
 pub fn clearloop(bf: &mut Vec<Instruction>) {
     for x in bf {
         use Instruction::*;
-        if let Loop(instr) = x {
+        if let Loop(instr, _) = x {
             match instr[..] {
                 [IncVal(1)] |
                 [DecVal(1)] => {
+                    event!(Trace, "opt::clearloop", "replaced {:?} by ClearVal", instr);
                     *x = ClearVal;
                 },
                 _ => {
@@ -54,3 +421,636 @@ pub fn clearloop(bf: &mut Vec<Instruction>) {
         }
     }
 }
+
+/// Remove loops that never run.
+///
+/// These are loops at the start of the program, where every cell is zero,
+/// and loops directly after another loop or a clear, which leave the cell at zero.
+/// The latter is commonly used for comments, e.g. `[-][this is a comment]`.
+pub fn deadloop(bf: &mut Vec<Instruction>) {
+    use Instruction::*;
+    let leading = bf.iter().take_while(|instr| matches!(instr, Loop(..) | MulLoop(..))).count();
+    if leading > 0 {
+        event!(Trace, "opt::deadloop", "removed {} loops at the start of the program", leading);
+        bf.drain(..leading);
+    }
+    remove_dead_loops(bf);
+}
+
+fn remove_dead_loops(bf: &mut Vec<Instruction>) {
+    use Instruction::*;
+    let mut zero = false;
+    bf.retain_mut(|instr| {
+        let dead = zero && matches!(instr, Loop(..) | MulLoop(..));
+        if dead {
+            event!(Trace, "opt::deadloop", "removed loop after a loop");
+        } else if let Loop(body, _) = instr {
+            // Recurse
+            remove_dead_loops(body);
+        }
+        zero = matches!(instr, Loop(..) | MulLoop(..) | ClearVal);
+        !dead
+    });
+}
+
+/// Remove code that the [range analysis](crate::ranges) shows to be useless.
+///
+/// These are loops that never run, clears and sets of cells that already hold the value,
+/// and code after loops that never end. Loops that run exactly once are replaced by their
+/// body. Unlike [`deadloop`], this also finds loops that never run because of arithmetic
+/// or loops earlier in the program, as in `+[-]>[...]`.
+pub fn ranges(bf: &mut Vec<Instruction>) {
+    if let Some(analysis) = crate::ranges::analyze(bf) {
+        simplify(bf, &analysis, &mut Vec::new());
+    }
+}
+
+fn simplify(bf: &mut Vec<Instruction>, analysis: &crate::ranges::Analysis, pc: &mut Vec<usize>) {
+    use Instruction::*;
+    use crate::ranges::Value;
+    let old = core::mem::take(bf);
+    for (i, mut instr) in old.into_iter().enumerate() {
+        pc.push(i);
+        let fact = analysis.fact(pc).copied();
+        if let Loop(body, _) = &mut instr {
+            simplify(body, analysis, pc);
+        }
+        pc.pop();
+
+        let Some(fact) = fact else {
+            // The rest of the block is never reached either
+            event!(Trace, "opt::ranges", "removed unreachable code");
+            break;
+        };
+        match instr {
+            Loop(..) | MulLoop(..) if fact.never_runs() => {
+                event!(Trace, "opt::ranges", "removed loop that never runs");
+            },
+            ClearVal if fact.cell == Value::ZERO => {},
+            SetVal(x) if x <= u8::MAX as u32 && fact.cell == Value::Range(x as u8, x as u8) => {},
+            Loop(body, _) if fact.iterations() == (1, Some(1)) => {
+                event!(Trace, "opt::ranges", "replaced loop that runs once by its body");
+                bf.extend(body);
+            },
+            instr => bf.push(instr),
+        }
+    }
+}
+
+/// Net movement of the data pointer after running `bf` once,
+/// or `None` if it depends on the number of iterations of an unbalanced loop,
+/// or if `bf` switches tapes.
+///
+/// Uses the cached shifts of nested loops, so these must be up to date.
+pub fn shift(bf: &[Instruction]) -> Option<isize> {
+    let mut total = 0;
+    for instr in bf {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => total += *x as isize,
+            DecPtr(x) => total -= *x as isize,
+            Loop(_, shift) if *shift != Some(0) => return None,
+            SwitchTape(_) => return None,
+            _ => {},
+        }
+    }
+    Some(total)
+}
+
+/// Recompute the cached net pointer shift of every loop, innermost first.
+pub fn annotate_shifts(bf: &mut [Instruction]) {
+    for instr in bf {
+        if let Instruction::Loop(body, shift) = instr {
+            annotate_shifts(body);
+            *shift = self::shift(body);
+        }
+    }
+}
+
+/// Add every cell offset that `bf`, starting at `offset`, reads or writes to `touched`.
+/// All loops in `bf` must be balanced.
+pub(crate) fn touched(bf: &[Instruction], mut offset: isize, touched: &mut BTreeSet<isize>) {
+    for instr in bf {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => offset += *x as isize,
+            DecPtr(x) => offset -= *x as isize,
+            Loop(inner, _) => {
+                touched.insert(offset);
+                self::touched(inner, offset, touched);
+            },
+            MulLoop(targets, _) => {
+                touched.insert(offset);
+                touched.extend(targets.iter().map(|(o, _)| offset + o));
+            },
+            WriteConst(_) => {},
+            _ => {
+                touched.insert(offset);
+            },
+        }
+    }
+}
+
+/// Remove trailing instructions that only change the tape.
+///
+/// Changes to cells after the last output or input of the program cannot be observed.
+/// Trailing pointer movements are replaced by a move to the highest and lowest cell they
+/// reach, so that a data pointer moving outside of the tape is still reported.
+/// The program ends at its first `@` outside of loops, which is then the end of the program.
+pub fn dce(bf: &mut Vec<Instruction>) {
+    use Instruction::*;
+    if let Some(halt) = bf.iter().position(|instr| matches!(instr, Halt)) {
+        bf.truncate(halt);
+    }
+    let start = bf.iter()
+        .rposition(|instr| !matches!(instr, IncPtr(_) | DecPtr(_) | IncVal(_) | DecVal(_) | ClearVal | SetVal(_) | Random))
+        .map_or(0, |i| i + 1);
+    if start == bf.len() {
+        return;
+    }
+
+    event!(Trace, "opt::dce", "removed {} trailing instructions", bf.len() - start);
+    let (mut offset, mut min, mut max) = (0, 0, 0);
+    for instr in bf.drain(start..) {
+        match instr {
+            IncPtr(x) => offset += x as isize,
+            DecPtr(x) => offset -= x as isize,
+            _ => {},
+        }
+        min = min.min(offset);
+        max = max.max(offset);
+    }
+
+    if max > 0 {
+        bf.push(IncPtr(max as usize));
+    }
+    if min < 0 {
+        bf.push(DecPtr((max - min) as usize));
+    }
+}
+
+/// Hoist constant sets out of balanced loops.
+///
+/// If the body of a balanced loop sets a cell other than the loop counter to a constant,
+/// e.g. `>[-]++<`, and does not otherwise use that cell, every iteration after the first
+/// sets it to the same value again. Such a loop `[A]` is rewritten to `[>[-]++<[A']]`,
+/// where `A'` is the body without the set. The outer loop runs at most once, because
+/// the inner loop only ends when the loop counter is zero.
+pub fn licm(bf: &mut Vec<Instruction>) {
+    for x in bf {
+        use Instruction::*;
+        if let Loop(body, shift) = x {
+            // Recurse
+            licm(body);
+
+            if *shift != Some(0) {
+                continue;
+            }
+
+            // Do not hoist out of the outer loop of an earlier hoist again
+            if let [sets @ .., Loop(..)] = &body[..]
+                && sets.iter().all(|instr| matches!(instr, IncPtr(_) | DecPtr(_) | ClearVal | SetVal(_)))
+            {
+                continue;
+            }
+
+            if let Some(hoisted) = hoist_constant_sets(body) {
+                event!(Trace, "opt::licm", "hoisted {:?} out of a loop", hoisted);
+                let inner = core::mem::take(body);
+                body.extend(hoisted);
+                body.push(Loop(inner, Some(0)));
+            }
+        }
+    }
+}
+
+/// Remove constant sets of cells that are not otherwise used from the body of a balanced loop,
+/// returning instructions that perform these sets once.
+fn hoist_constant_sets(body: &mut Vec<Instruction>) -> Option<Vec<Instruction>> {
+    use Instruction::*;
+
+    // Indices of the arithmetic instructions per offset, and the offsets used in any other way
+    let mut arith: BTreeMap<isize, Vec<usize>> = BTreeMap::new();
+    let mut other = BTreeSet::from([0]);
+    let mut offset = 0;
+    for (i, instr) in body.iter().enumerate() {
+        match instr {
+            IncPtr(x) => offset += *x as isize,
+            DecPtr(x) => offset -= *x as isize,
+            IncVal(_) | DecVal(_) | ClearVal | SetVal(_) => arith.entry(offset).or_default().push(i),
+            Write | Read | Random | Push | Pop => { other.insert(offset); },
+            WriteConst(_) => {},
+            MulLoop(targets, _) => {
+                other.insert(offset);
+                other.extend(targets.iter().map(|(o, _)| offset + o));
+            },
+            Loop(inner, _) => {
+                other.insert(offset);
+                touched(inner, offset, &mut other);
+            },
+            // Balanced loops do not switch tapes
+            SwitchTape(_) => return None,
+            // The sets must not happen before the program ends
+            Halt => return None,
+        }
+    }
+
+    // A cell is set to a constant if its first use is a clear
+    let sets: Vec<(isize, Vec<usize>)> = arith.into_iter()
+        .filter(|(offset, indices)| !other.contains(offset) && matches!(body[indices[0]], ClearVal | SetVal(_)))
+        .collect();
+
+    if sets.is_empty() {
+        return None;
+    }
+
+    // Take the instructions out of the body, keeping only those from the last clear,
+    // as earlier instructions on the cell are overwritten anyway
+    let mut remove: Vec<usize> = sets.iter().flat_map(|(_, indices)| indices.iter().copied()).collect();
+    remove.sort_unstable();
+    let mut taken: Vec<Option<Instruction>> = Vec::new();
+    taken.resize_with(body.len(), || None);
+    for &i in remove.iter().rev() {
+        taken[i] = Some(body.remove(i));
+    }
+
+    let mut hoisted = Vec::new();
+    for (offset, indices) in sets {
+        let last_clear = indices.iter().rposition(|&i| matches!(taken[i], Some(ClearVal | SetVal(_)))).unwrap();
+        hoisted.push(if offset > 0 { IncPtr(offset as usize) } else { DecPtr(-offset as usize) });
+        hoisted.extend(indices[last_clear..].iter().filter_map(|&i| taken[i].take()));
+        hoisted.push(if offset > 0 { DecPtr(offset as usize) } else { IncPtr(-offset as usize) });
+    }
+
+    Some(hoisted)
+}
+
+/// Rewrite every run of pointer movements and cell arithmetic into canonical form.
+///
+/// In canonical form the cells of a run are visited in ascending order of their offset,
+/// with a single operation per cell, after which the pointer moves to its final position.
+/// For example, `>+<-->+<` becomes `-->++<`.
+pub fn canonicalize(bf: &mut Vec<Instruction>) {
+    use Instruction::*;
+    let mut out = Vec::with_capacity(bf.len());
+    let mut block: Option<Block> = None;
+
+    for instr in core::mem::take(bf) {
+        if matches!(instr, IncPtr(_) | DecPtr(_) | IncVal(_) | DecVal(_) | ClearVal | SetVal(_)) {
+            block.get_or_insert_with(|| Block::new(Cell::Unknown(0))).apply(instr);
+            continue;
+        }
+
+        if let Some(mut run) = block.take() {
+            run.flush();
+            out.append(&mut run.out);
+        }
+
+        match instr {
+            Loop(mut body, shift) => {
+                // Recurse
+                canonicalize(&mut body);
+                out.push(Loop(body, shift));
+            },
+            instr => out.push(instr),
+        }
+    }
+
+    if let Some(mut run) = block.take() {
+        run.flush();
+        out.append(&mut run.out);
+    }
+
+    *bf = out;
+}
+
+/// What is known about a cell during constant propagation,
+/// relative to the tape as left by the instructions emitted so far.
+#[derive(Clone, Copy)]
+enum Cell {
+    /// Unknown value, with a pending (wrapping) addition.
+    Unknown(u32),
+    /// Known value, and whether it still has to be written to the tape.
+    Known(u32, bool),
+}
+
+/// State of constant propagation through a straight-line block.
+/// Offsets are relative to the data pointer at the start of the block.
+struct Block {
+    cells: BTreeMap<isize, Cell>,
+    /// State of cells that have not been used in this block.
+    default: Cell,
+    /// Logical position of the data pointer.
+    pos: isize,
+    /// Position of the data pointer after the instructions emitted so far.
+    cur: isize,
+    out: Vec<Instruction>,
+}
+
+impl Block {
+    fn new(default: Cell) -> Self {
+        Self { cells: BTreeMap::new(), default, pos: 0, cur: 0, out: Vec::new() }
+    }
+
+    fn get(&self, offset: isize) -> Cell {
+        self.cells.get(&offset).copied().unwrap_or(self.default)
+    }
+
+    fn move_to(&mut self, offset: isize) {
+        use Instruction::*;
+        match offset - self.cur {
+            0 => {},
+            d if d > 0 => self.out.push(IncPtr(d as usize)),
+            d => self.out.push(DecPtr(-d as usize)),
+        }
+        self.cur = offset;
+    }
+
+    /// Emit the pending changes of the cell at `offset`.
+    fn flush_cell(&mut self, offset: isize) {
+        use Instruction::*;
+        match self.get(offset) {
+            Cell::Unknown(0) | Cell::Known(_, false) => {},
+            Cell::Unknown(delta) => {
+                self.move_to(offset);
+                // Emit the shortest sequence of increments or decrements
+                let (mut n, up) = if delta <= u32::MAX / 2 { (delta, true) } else { (delta.wrapping_neg(), false) };
+                while n > 0 {
+                    let x = n.min(u8::MAX as u32) as u8;
+                    self.out.push(if up { IncVal(x) } else { DecVal(x) });
+                    n -= x as u32;
+                }
+                self.cells.insert(offset, Cell::Unknown(0));
+            },
+            Cell::Known(value, true) => {
+                self.move_to(offset);
+                self.out.push(if value == 0 { ClearVal } else { SetVal(value) });
+                self.cells.insert(offset, Cell::Known(value, false));
+            },
+        }
+    }
+
+    /// Emit all pending changes, and move to the logical position of the data pointer.
+    fn flush(&mut self) {
+        let offsets: Vec<isize> = self.cells.keys().copied().collect();
+        for offset in offsets {
+            self.flush_cell(offset);
+        }
+        self.move_to(self.pos);
+    }
+
+    /// Set the cell at the data pointer to a constant.
+    fn set(&mut self, value: u32) {
+        let cell = match self.get(self.pos) {
+            Cell::Known(old, false) if old == value => Cell::Known(value, false),
+            _ => Cell::Known(value, true),
+        };
+        self.cells.insert(self.pos, cell);
+    }
+
+    /// Add a (wrapping) constant to the cell at the data pointer.
+    fn add(&mut self, x: u32) {
+        let cell = match self.get(self.pos) {
+            Cell::Unknown(delta) => Cell::Unknown(delta.wrapping_add(x)),
+            Cell::Known(value, _) => Cell::Known(value.wrapping_add(x), true),
+        };
+        self.cells.insert(self.pos, cell);
+    }
+
+    /// Record the effect of a pointer movement or cell arithmetic instruction.
+    fn apply(&mut self, instr: Instruction) {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => self.pos += x as isize,
+            DecPtr(x) => self.pos -= x as isize,
+            IncVal(x) => self.add(x as u32),
+            DecVal(x) => self.add((x as u32).wrapping_neg()),
+            ClearVal => self.set(0),
+            SetVal(x) => self.set(x),
+            _ => unreachable!("not an arithmetic instruction: {:?}", instr),
+        }
+    }
+}
+
+/// Propagate known cell values through straight-line code.
+///
+/// Consecutive changes to the same cell are combined, and only written to the tape
+/// when a later instruction depends on them. Outputting a cell with a known value
+/// becomes a [`Instruction::WriteConst`], and loops whose counter is known to be
+/// zero are removed. All cells are known to be zero at the start of the program,
+/// and the loop counter is known to be zero after every loop.
+///
+/// Loops that run a small known number of times are unrolled, see [`unroll_count`],
+/// so that constants built by nested loops are folded as well.
+pub fn constprop(bf: &mut Vec<Instruction>) {
+    *bf = propagate(core::mem::take(bf), Cell::Known(0, false));
+}
+
+fn propagate(bf: Vec<Instruction>, default: Cell) -> Vec<Instruction> {
+    let mut block = Block::new(default);
+    // Instructions still to be processed, in reverse order, so unrolled loop bodies can be pushed
+    let mut pending: Vec<Instruction> = bf.into_iter().rev().collect();
+    while let Some(instr) = pending.pop() {
+        use Instruction::*;
+        match instr {
+            IncPtr(_) | DecPtr(_) | IncVal(_) | DecVal(_) | ClearVal | SetVal(_) => block.apply(instr),
+            Write => match block.get(block.pos) {
+                Cell::Known(value, _) => block.out.push(WriteConst(value as u8)),
+                Cell::Unknown(_) => {
+                    block.flush_cell(block.pos);
+                    block.move_to(block.pos);
+                    block.out.push(Write);
+                },
+            },
+            WriteConst(x) => block.out.push(WriteConst(x)),
+            Halt => {
+                block.flush();
+                block.out.push(Halt);
+            },
+            SwitchTape(x) => {
+                // Nothing is known about the other tape
+                block.flush();
+                let mut out = core::mem::take(&mut block.out);
+                out.push(SwitchTape(x));
+                block = Block::new(Cell::Unknown(0));
+                block.out = out;
+            },
+            Read => {
+                // The cell may be left unchanged at the end of input
+                block.flush_cell(block.pos);
+                block.move_to(block.pos);
+                block.out.push(Read);
+                block.cells.insert(block.pos, Cell::Unknown(0));
+            },
+            Random | Pop => {
+                // The cell is overwritten, so its pending changes are dropped
+                block.move_to(block.pos);
+                block.out.push(instr);
+                block.cells.insert(block.pos, Cell::Unknown(0));
+            },
+            Push => {
+                // The value stays known, but has to be on the tape to be pushed
+                block.flush_cell(block.pos);
+                block.move_to(block.pos);
+                block.out.push(Push);
+            },
+            MulLoop(targets, step) => {
+                if let Cell::Known(counter, _) = block.get(block.pos) {
+                    // The number of iterations is known, so only the additions remain
+                    let iterations = counter.wrapping_mul(inverse(step));
+                    let pos = block.pos;
+                    for (offset, factor) in targets {
+                        block.pos = pos + offset;
+                        block.add(iterations.wrapping_mul(factor));
+                    }
+                    block.pos = pos;
+                    block.set(0);
+                    continue;
+                }
+
+                // Only the counter and the targets are affected
+                block.flush();
+                block.out.push(MulLoop(targets.clone(), step));
+                block.cells.insert(block.pos, Cell::Known(0, false));
+                for (offset, _) in targets {
+                    block.cells.insert(block.pos + offset, Cell::Unknown(0));
+                }
+            },
+            Loop(body, shift) => {
+                if let Cell::Known(counter, _) = block.get(block.pos)
+                    && let Some(n) = unroll_count(&body, shift, counter)
+                {
+                    event!(Trace, "opt::constprop", "unrolled loop running {} times", n);
+                    for _ in 0..n {
+                        pending.extend(body.iter().rev().cloned());
+                    }
+                    continue;
+                }
+
+                block.flush();
+                if matches!(block.get(block.pos), Cell::Known(0, _)) {
+                    event!(Trace, "opt::constprop", "removed loop that never runs");
+                    continue;
+                }
+
+                block.out.push(Loop(propagate(body, Cell::Unknown(0)), shift));
+                // Continue from the loop exit, where the counter is zero
+                let out = core::mem::take(&mut block.out);
+                block = Block::new(Cell::Unknown(0));
+                block.out = out;
+                block.cells.insert(0, Cell::Known(0, false));
+            },
+        }
+    }
+
+    block.flush();
+    block.out
+}
+
+/// Maximum number of iterations of a loop to unroll.
+const UNROLL_ITERATIONS: u32 = 16;
+/// Maximum number of instructions of an unrolled loop.
+const UNROLL_SIZE: usize = 256;
+
+/// Number of iterations of a loop whose counter starts at `counter`,
+/// if it is small enough to unroll the loop.
+///
+/// This is the case if the loop is balanced, and its body decrements the counter
+/// by one and does not otherwise change it. The counter must be below the size
+/// of the smallest cell, so the number of iterations is the same for every cell width.
+fn unroll_count(body: &[Instruction], shift: Option<isize>, counter: u32) -> Option<u32> {
+    if shift != Some(0) || counter > UNROLL_ITERATIONS || counter as usize * size(body) > UNROLL_SIZE {
+        return None;
+    }
+
+    let mut delta = 0u32;
+    let mut offset = 0;
+    for instr in body {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => offset += *x as isize,
+            DecPtr(x) => offset -= *x as isize,
+            IncVal(x) if offset == 0 => delta = delta.wrapping_add(*x as u32),
+            DecVal(x) if offset == 0 => delta = delta.wrapping_sub(*x as u32),
+            ClearVal | SetVal(_) | Read | Random | Pop if offset == 0 => return None,
+            MulLoop(targets, _) if offset == 0 || targets.iter().any(|(o, _)| offset + o == 0) => return None,
+            Loop(inner, shift) => {
+                // Nested loops are balanced, as the loop itself is
+                debug_assert_eq!(*shift, Some(0));
+                let mut cells = BTreeSet::new();
+                touched(inner, offset, &mut cells);
+                if offset == 0 || cells.contains(&0) {
+                    return None;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    (delta == 1u32.wrapping_neg()).then_some(counter)
+}
+
+/// Multiplicative inverse of an odd number modulo 2^32.
+///
+/// Since 2^32 is a multiple of every cell size, this is also the inverse modulo the cell size.
+pub fn inverse(x: u32) -> u32 {
+    debug_assert!(x % 2 == 1, "{} has no inverse", x);
+    // Newton's method, doubling the number of correct bits every iteration
+    let mut inv = x;
+    for _ in 0..5 {
+        inv = inv.wrapping_mul(2u32.wrapping_sub(x.wrapping_mul(inv)));
+    }
+    inv
+}
+
+/// Replace counting loops by their closed form.
+///
+/// A balanced loop that only moves the pointer and adds constants to cells,
+/// such as `[->+++>--<<]`, and subtracts an odd constant from its counter,
+/// always terminates; the number of iterations follows from the initial value of the counter.
+/// See [`Instruction::MulLoop`].
+pub fn mulloop(bf: &mut Vec<Instruction>) {
+    for x in bf {
+        use Instruction::*;
+        if let Loop(body, shift) = x {
+            if *shift != Some(0) {
+                // Recurse
+                mulloop(body);
+                continue;
+            }
+
+            match counting_loop(body) {
+                Some((targets, step)) => {
+                    event!(Trace, "opt::mulloop", "replaced loop by MulLoop({:?}, {})", targets, step);
+                    *x = MulLoop(targets, step);
+                },
+                None => mulloop(body),
+            }
+        }
+    }
+}
+
+/// The targets and counter step of a balanced loop body, if it is a counting loop.
+fn counting_loop(body: &[Instruction]) -> Option<(Vec<(isize, u32)>, u32)> {
+    let mut deltas: BTreeMap<isize, u32> = BTreeMap::new();
+    let mut offset = 0;
+    for instr in body {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => offset += *x as isize,
+            DecPtr(x) => offset -= *x as isize,
+            IncVal(x) => { let d = deltas.entry(offset).or_default(); *d = d.wrapping_add(*x as u32); },
+            DecVal(x) => { let d = deltas.entry(offset).or_default(); *d = d.wrapping_sub(*x as u32); },
+            _ => return None,
+        }
+    }
+
+    // Only an odd step is guaranteed to reach zero, for every cell width
+    let step = deltas.remove(&0)?.wrapping_neg();
+    if step % 2 == 0 {
+        return None;
+    }
+
+    let targets = deltas.into_iter().filter(|&(_, d)| d != 0).collect();
+    Some((targets, step))
+}
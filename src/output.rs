@@ -0,0 +1,283 @@
+//! Adapters for the output of a program.
+
+use std::{io::{self, Write}, thread, time::Duration};
+
+/// Writes one byte at a time, flushing and pausing after every byte,
+/// so that output appears gradually.
+pub struct Throttled<W> {
+    inner: W,
+    delay: Duration,
+}
+
+impl<W: Write> Throttled<W> {
+    pub fn new(inner: W, delay: Duration) -> Self {
+        Self { inner, delay }
+    }
+}
+
+impl<W: Write> Write for Throttled<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(&byte) = buf.first() else { return Ok(0) };
+        self.inner.write_all(&[byte])?;
+        self.inner.flush()?;
+        thread::sleep(self.delay);
+        Ok(1)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Packs the lowest bit of every byte written into bytes of eight bits, lowest first,
+/// for [`IoMode::Bits`](crate::config::IoMode::Bits).
+///
+/// Flushing does not write a byte that is not complete yet. The last one is padded with
+/// zeros and written when this is dropped, ignoring any error.
+pub struct Bits<W: Write> {
+    inner: W,
+    /// The bits of the byte being packed so far, and their number.
+    byte: u8,
+    len: u32,
+}
+
+impl<W: Write> Bits<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, byte: 0, len: 0 }
+    }
+}
+
+impl<W: Write> Write for Bits<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &bit in buf {
+            self.byte |= (bit & 1) << self.len;
+            self.len += 1;
+            if self.len == 8 {
+                self.inner.write_all(&[self.byte])?;
+                (self.byte, self.len) = (0, 0);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for Bits<W> {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            let _ = self.inner.write_all(&[self.byte]);
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+/// How output bytes are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Write the bytes as they are.
+    Raw,
+    /// Write every byte as a pair of hex digits, starting a new line after every newline byte.
+    Hex,
+    /// Write printable ASCII characters and newlines as they are, and other bytes as
+    /// escape sequences like `\t` and `\x1b`.
+    Escaped,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "raw" => Ok(Format::Raw),
+            "hex" => Ok(Format::Hex),
+            "escaped" => Ok(Format::Escaped),
+            _ => Err(format!("invalid output format `{}`, expected raw, hex or escaped", s)),
+        }
+    }
+}
+
+/// Shows the bytes written through it in a format that is safe to print on a terminal.
+pub struct Formatted<W> {
+    inner: W,
+    format: Format,
+}
+
+impl<W: Write> Formatted<W> {
+    pub fn new(inner: W, format: Format) -> Self {
+        Self { inner, format }
+    }
+}
+
+impl<W: Write> Write for Formatted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out = Vec::with_capacity(buf.len() * 4);
+        for &byte in buf {
+            match self.format {
+                Format::Raw => out.push(byte),
+                Format::Hex => {
+                    write!(out, "{:02x}", byte)?;
+                    out.push(if byte == b'\n' { b'\n' } else { b' ' });
+                },
+                Format::Escaped => match byte {
+                    b'\n' | b' '..=b'~' if byte != b'\\' => out.push(byte),
+                    b'\\' => out.extend_from_slice(b"\\\\"),
+                    b'\t' => out.extend_from_slice(b"\\t"),
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    _ => write!(out, "\\x{:02x}", byte)?,
+                },
+            }
+        }
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes everything to a copy as well, e.g. to capture output in a file while showing it.
+pub struct Tee<W, C> {
+    inner: W,
+    copy: C,
+}
+
+impl<W: Write, C: Write> Tee<W, C> {
+    pub fn new(inner: W, copy: C) -> Self {
+        Self { inner, copy }
+    }
+}
+
+impl<W: Write, C: Write> Write for Tee<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.copy.flush()
+    }
+}
+
+/// Upper half of code page 437, the character set of the original IBM PC.
+const CP437: &str = "\
+    ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// The character every output byte stands for, in the character set a program was written for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encoding {
+    table: [char; 256],
+}
+
+impl Encoding {
+    /// A built-in character set: `cp437`, or `latin1` for ISO 8859-1.
+    /// Both map the lower half to ASCII.
+    pub fn preset(name: &str) -> Option<Self> {
+        let mut table: [char; 256] = std::array::from_fn(|byte| byte as u8 as char);
+        match name {
+            "cp437" => table[128..].iter_mut().zip(CP437.chars()).for_each(|(c, cp437)| *c = cp437),
+            "latin1" => {},
+            _ => return None,
+        }
+        Some(Self { table })
+    }
+
+    /// Parse a table of 256 characters, one for every byte in order, written as hexadecimal
+    /// code points like `263a`, `0x263a` or `U+263A` and separated by whitespace.
+    /// Lines may have `#` comments.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut chars = Vec::with_capacity(256);
+        for word in src.lines().flat_map(|line| line.split('#').next().unwrap_or_default().split_whitespace()) {
+            let hex = word.strip_prefix("0x").or_else(|| word.strip_prefix("U+")).unwrap_or(word);
+            let c = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                .ok_or_else(|| format!("invalid code point `{}`", word))?;
+            chars.push(c);
+        }
+        let n = chars.len();
+        let table = chars.try_into().map_err(|_| format!("expected 256 code points, found {}", n))?;
+        Ok(Self { table })
+    }
+}
+
+/// Writes every byte as the UTF-8 encoding of the character it stands for.
+pub struct Encoded<W> {
+    inner: W,
+    encoding: Encoding,
+}
+
+impl<W: Write> Encoded<W> {
+    pub fn new(inner: W, encoding: Encoding) -> Self {
+        Self { inner, encoding }
+    }
+}
+
+impl<W: Write> Write for Encoded<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let out: String = buf.iter().map(|&byte| self.encoding.table[byte as usize]).collect();
+        self.inner.write_all(out.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fails once more than a given number of bytes are written, to bound the output of a program.
+pub struct Limited<W> {
+    inner: W,
+    remaining: u64,
+    limit: u64,
+}
+
+impl<W: Write> Limited<W> {
+    pub fn new(inner: W, limit: u64) -> Self {
+        Self { inner, remaining: limit, limit }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::other(format!("output limit of {} bytes exceeded", self.limit)));
+        }
+        let n = self.inner.write(buf)?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Passes every byte written to a function.
+pub struct ToFn<F> {
+    write: F,
+}
+
+impl<F: FnMut(u8)> ToFn<F> {
+    pub fn new(write: F) -> Self {
+        Self { write }
+    }
+}
+
+impl<F: FnMut(u8)> Write for ToFn<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        buf.iter().for_each(|&byte| (self.write)(byte));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
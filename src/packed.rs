@@ -0,0 +1,413 @@
+//! A dense encoding of optimized programs as `u32` words, run by the fast interpreter.
+//!
+//! Every instruction is a word with its opcode in the top 5 bits and its operand in the
+//! other 27. An operand that does not fit is written as all ones, and follows in the next
+//! word. Loops become jumps over their body: `[` skips forward past its `]` when the cell
+//! is zero, and `]` back to the start of the body otherwise, both by the same distance.
+//! A multiply loop is followed by its step, and by the offset and factor of every target.
+//!
+//! Compared to the nested vectors of [`Instruction`], the whole program is one block of
+//! memory that is read front to back, which suits huge generated programs. Programs that
+//! are not optimized can be packed by the [`Parser`] while they are lexed, without building
+//! any instructions at all.
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::{io::{Read, Write}, time::{Duration, Instant}};
+
+use crate::{Instruction, lexer::{Lexer, Span, Token}, parser::{Error as ParseError, Warning}};
+#[cfg(feature = "std")]
+use crate::{cell::Cell, config::{Config, Dialect, EofMode, EmptyLoopMode, FlushPolicy, IoMode}, eval::{Error, read_byte}, random::Rng, report::ResourceReport, tape::{self, Tape}};
+
+const OPCODE_SHIFT: u32 = 27;
+/// Operand that stands for the operand in the next word.
+const WIDE: u32 = (1 << OPCODE_SHIFT) - 1;
+
+const INC_PTR: u32 = 0;
+const DEC_PTR: u32 = 1;
+const INC_VAL: u32 = 2;
+const DEC_VAL: u32 = 3;
+const CLEAR: u32 = 4;
+const SET: u32 = 5;
+const WRITE: u32 = 6;
+const WRITE_CONST: u32 = 7;
+const READ: u32 = 8;
+const PUSH: u32 = 9;
+const POP: u32 = 10;
+const HALT: u32 = 11;
+const RANDOM: u32 = 12;
+/// Operand: the number of targets.
+const MUL_LOOP: u32 = 13;
+const EMPTY_LOOP: u32 = 14;
+/// Operand: the distance from the end of this word to the end of the matching `]`.
+const OPEN: u32 = 15;
+/// Operand: the distance from the end of this word back to the start of the loop body.
+const CLOSE: u32 = 16;
+
+/// A program encoded as words.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packed {
+    code: Vec<u32>,
+}
+
+impl Packed {
+    /// Encode the program, or return `None` if it switches tapes, or has an offset that
+    /// does not fit in 32 bits.
+    pub fn lower(prog: &[Instruction]) -> Option<Self> {
+        let mut code = Vec::with_capacity(prog.len());
+        lower(prog, &mut code)?;
+        Some(Self { code })
+    }
+
+    /// The encoded program.
+    pub fn words(&self) -> &[u32] {
+        &self.code
+    }
+}
+
+fn push(code: &mut Vec<u32>, opcode: u32, operand: u32) {
+    if operand < WIDE {
+        code.push(opcode << OPCODE_SHIFT | operand);
+    } else {
+        code.push(opcode << OPCODE_SHIFT | WIDE);
+        code.push(operand);
+    }
+}
+
+fn lower(prog: &[Instruction], code: &mut Vec<u32>) -> Option<()> {
+    for instr in prog {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => push(code, INC_PTR, u32::try_from(*x).ok()?),
+            DecPtr(x) => push(code, DEC_PTR, u32::try_from(*x).ok()?),
+            IncVal(x) => push(code, INC_VAL, *x as u32),
+            DecVal(x) => push(code, DEC_VAL, *x as u32),
+            ClearVal => push(code, CLEAR, 0),
+            SetVal(x) => push(code, SET, *x),
+            Write => push(code, WRITE, 0),
+            WriteConst(x) => push(code, WRITE_CONST, *x as u32),
+            Read => push(code, READ, 0),
+            Push => push(code, PUSH, 0),
+            Pop => push(code, POP, 0),
+            Halt => push(code, HALT, 0),
+            Random => push(code, RANDOM, 0),
+            SwitchTape(_) => return None,
+            MulLoop(targets, step) => {
+                push(code, MUL_LOOP, targets.len() as u32);
+                code.push(*step);
+                for (offset, factor) in targets {
+                    code.push(i32::try_from(*offset).ok()? as u32);
+                    code.push(*factor);
+                }
+            },
+            Loop(body, _) if body.is_empty() => push(code, EMPTY_LOOP, 0),
+            Loop(body, _) => {
+                // Jumps are relative, so the `[` is filled in once the body is encoded
+                let open = code.len();
+                code.push(0);
+                lower(body, code)?;
+                let distance = match code.len() - open {
+                    distance if distance < WIDE as usize => distance,
+                    // Both brackets take two words
+                    distance => distance + 1,
+                };
+                let distance = u32::try_from(distance).ok()?;
+                if distance < WIDE {
+                    code[open] = OPEN << OPCODE_SHIFT | distance;
+                } else {
+                    code[open] = OPEN << OPCODE_SHIFT | WIDE;
+                    code.insert(open + 1, distance);
+                }
+                push(code, CLOSE, distance);
+            },
+        }
+    }
+    Some(())
+}
+
+/// Packs a program in a single pass over its tokens, with every `[` filled in once its
+/// `]` is found, as [`Packed::lower`] would pack the program the [parser](crate::parser)
+/// returns for the same source.
+pub struct Parser<'src> {
+    lexer: Lexer<'src>,
+    warnings: Vec<Warning>,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(lexer: Lexer<'src>) -> Self {
+        Self { lexer, warnings: Vec::new() }
+    }
+
+    /// Warnings found while parsing so far.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Pack the whole program, or return `None` if it switches tapes.
+    pub fn parse(&mut self) -> Result<Option<Packed>, ParseError> {
+        let mut code = Vec::new();
+        // Index of the `[` of every unclosed loop, along with its position
+        let mut open: Vec<(usize, Span)> = Vec::new();
+
+        for (token, span) in self.lexer.by_ref() {
+            use Token::*;
+            let (opcode, operand) = match token {
+                Gt    => (INC_PTR, 1),
+                Lt    => (DEC_PTR, 1),
+                Plus  => (INC_VAL, 1),
+                Minus => (DEC_VAL, 1),
+                Dot | Semicolon => (WRITE, 0),
+                Comma => (READ, 0),
+                Caret => (PUSH, 0),
+                Underscore => (POP, 0),
+                At => (HALT, 0),
+                Question => (RANDOM, 0),
+                LCurly | RCurly => return Ok(None),
+                Comment(_) | Whitespace(_) => continue,
+                LSquare => {
+                    open.push((code.len(), span));
+                    code.push(0);
+                    continue;
+                },
+                RSquare => {
+                    let Some((start, Span { line, col, .. })) = open.pop() else {
+                        return Err(ParseError::MissingLoopOpen { line: span.line, col: span.col });
+                    };
+                    if start + 1 == code.len() {
+                        self.warnings.push(Warning::EmptyLoop { line, col });
+                        code[start] = EMPTY_LOOP << OPCODE_SHIFT;
+                        continue;
+                    }
+                    let distance = match code.len() - start {
+                        distance if distance < WIDE as usize => distance,
+                        // Both brackets take two words
+                        distance => distance + 1,
+                    };
+                    let Ok(distance) = u32::try_from(distance) else {
+                        return Ok(None);
+                    };
+                    if distance < WIDE {
+                        code[start] = OPEN << OPCODE_SHIFT | distance;
+                    } else {
+                        code[start] = OPEN << OPCODE_SHIFT | WIDE;
+                        code.insert(start + 1, distance);
+                    }
+                    (CLOSE, distance)
+                },
+            };
+
+            push(&mut code, opcode, operand);
+        }
+
+        // Report the innermost unclosed loop, as every loop around it is unclosed as well
+        match open.last() {
+            Some(&(_, Span { line, col, .. })) => Err(ParseError::MissingLoopEnd { line, col, count: open.len() }),
+            None => Ok(Some(Packed { code })),
+        }
+    }
+}
+
+/// The fast interpreter, for [`Packed`] programs with cells of type `C`.
+///
+/// It counts steps like [`Context`](crate::eval::Context) and enforces the step limit, but
+/// leaves out everything that observes the run, such as heatmaps, traces and checkpoints.
+/// See [`Machine::supports`] for the settings it runs programs with.
+#[cfg(feature = "std")]
+pub struct Machine<'a, C: Cell> {
+    rdr: &'a mut dyn Read,
+    wtr: &'a mut dyn Write,
+    tape: Box<dyn Tape<C>>,
+    ptr: usize,
+    eof: EofMode,
+    flush: FlushPolicy,
+    empty_loop: EmptyLoopMode,
+    tape_size: usize,
+    steps: u64,
+    /// Step limit, or `u64::MAX` without one.
+    max_steps: u64,
+    max_ptr: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    elapsed: Duration,
+    rng: Rng,
+    stack: Vec<C>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, C: Cell> Machine<'a, C> {
+    pub fn new(rdr: &'a mut impl Read, wtr: &'a mut impl Write, config: &Config) -> Self {
+        Self {
+            rdr,
+            wtr,
+            tape: tape::new(config),
+            ptr: 0,
+            eof: config.eof,
+            flush: config.flush,
+            empty_loop: config.empty_loop,
+            tape_size: config.tape_size,
+            steps: 0,
+            max_steps: config.max_steps.unwrap_or(u64::MAX),
+            max_ptr: 0,
+            input_bytes: 0,
+            output_bytes: 0,
+            elapsed: Duration::ZERO,
+            rng: Rng::new(config.seed),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Whether the machine runs programs with these settings as the interpreter does.
+    /// It has a single tape, reads and writes bytes, and does not detect infinite loops.
+    pub fn supports(config: &Config) -> bool {
+        config.dialect != Dialect::MultiTape && matches!(config.io, IoMode::Bytes | IoMode::Bits) && !config.detect_loops
+    }
+
+    /// The resources used so far, also after the program failed.
+    pub fn resources(&self) -> ResourceReport {
+        ResourceReport {
+            steps: self.steps,
+            input_bytes: self.input_bytes,
+            output_bytes: self.output_bytes,
+            max_ptr: self.max_ptr,
+            peak_tape_size: self.tape.peak_size(),
+            wall_time: self.elapsed,
+        }
+    }
+
+    /// Run the program to completion, returning the resources used.
+    pub fn run(&mut self, packed: &Packed) -> Result<ResourceReport, Error> {
+        let start = Instant::now();
+        let result = self.exec(&packed.code);
+        self.elapsed += start.elapsed();
+        result.map(|()| self.resources())
+    }
+
+    fn exec(&mut self, code: &[u32]) -> Result<(), Error> {
+        let mut pc = 0;
+        while let Some(&word) = code.get(pc) {
+            pc += 1;
+            let mut operand = word & WIDE;
+            if operand == WIDE {
+                operand = code[pc];
+                pc += 1;
+            }
+
+            match word >> OPCODE_SHIFT {
+                INC_PTR => {
+                    self.ptr += operand as usize;
+                    self.tape.reach(self.ptr)?;
+                    self.max_ptr = self.max_ptr.max(self.ptr);
+                },
+                DEC_PTR => {
+                    self.ptr = self.ptr.checked_sub(operand as usize)
+                        .ok_or(Error::PointerOutOfBounds)?;
+                },
+                INC_VAL => self.tape.set(self.ptr, self.tape.get(self.ptr).wrapping_add(operand)),
+                DEC_VAL => self.tape.set(self.ptr, self.tape.get(self.ptr).wrapping_sub(operand)),
+                CLEAR => self.tape.set(self.ptr, C::default()),
+                SET => self.tape.set(self.ptr, C::from_constant(operand)),
+                WRITE => self.write(self.tape.get(self.ptr).to_byte())?,
+                WRITE_CONST => self.write(operand as u8)?,
+                READ => {
+                    // Make sure prompts are visible before blocking on input
+                    if self.flush != FlushPolicy::OnExit {
+                        self.wtr.flush()?;
+                    }
+                    match read_byte(&mut self.rdr)? {
+                        Some(byte) => {
+                            self.input_bytes += 1;
+                            self.tape.set(self.ptr, C::from_byte(byte));
+                        },
+                        None => match self.eof {
+                            EofMode::Unchanged => {},
+                            EofMode::Zero => self.tape.set(self.ptr, C::default()),
+                            EofMode::Max => self.tape.set(self.ptr, C::max()),
+                            EofMode::Error => return Err(Error::UnexpectedEof),
+                        },
+                    }
+                },
+                PUSH => {
+                    if self.stack.len() >= self.tape_size {
+                        return Err(Error::StackOverflow);
+                    }
+                    self.stack.push(self.tape.get(self.ptr));
+                },
+                POP => {
+                    let value = self.stack.pop().ok_or(Error::StackUnderflow)?;
+                    self.tape.set(self.ptr, value);
+                },
+                HALT => return self.step(),
+                RANDOM => self.tape.set(self.ptr, C::from_byte(self.rng.byte())),
+                MUL_LOOP => {
+                    let targets = &code[pc + 1..pc + 1 + 2 * operand as usize];
+                    let step = code[pc];
+                    pc += 1 + targets.len();
+                    let counter = self.tape.get(self.ptr);
+                    if !counter.is_zero() {
+                        let Some(iterations) = counter.iterations(step) else {
+                            // The counter never reaches zero, so the loop runs until the step limit
+                            loop {
+                                self.step()?;
+                            }
+                        };
+                        for target in targets.chunks_exact(2) {
+                            let cell = self.tape.shift(self.ptr, target[0] as i32 as isize)?;
+                            self.max_ptr = self.max_ptr.max(cell);
+                            self.tape.set(cell, self.tape.get(cell).add_product(&iterations, target[1]));
+                        }
+                        self.tape.set(self.ptr, C::default());
+                    }
+                },
+                EMPTY_LOOP => {
+                    if !self.tape.get(self.ptr).is_zero() {
+                        if self.empty_loop == EmptyLoopMode::Error {
+                            return Err(Error::InfiniteLoop);
+                        }
+                        loop {
+                            self.step()?;
+                        }
+                    }
+                },
+                OPEN => {
+                    // A loop that does not run is a single step; one that runs is counted by its `]`
+                    if !self.tape.get(self.ptr).is_zero() {
+                        continue;
+                    }
+                    pc += operand as usize;
+                },
+                CLOSE => {
+                    // Every test of the loop condition is a step
+                    self.step()?;
+                    if !self.tape.get(self.ptr).is_zero() {
+                        pc -= operand as usize;
+                        continue;
+                    }
+                },
+                opcode => unreachable!("invalid opcode {}", opcode),
+            }
+
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    /// Count an executed instruction, enforcing the step limit.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(Error::StepLimit(self.steps - 1));
+        }
+        Ok(())
+    }
+
+    /// Write and count a byte of output, flushing it if requested.
+    fn write(&mut self, byte: u8) -> Result<(), Error> {
+        self.wtr.write_all(&[byte])?;
+        self.output_bytes += 1;
+        if self.flush == FlushPolicy::EveryWrite {
+            self.wtr.flush()?;
+        }
+        Ok(())
+    }
+}
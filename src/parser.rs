@@ -1,83 +1,181 @@
-use std::fmt;
+use core::fmt;
 
-use crate::{Instruction, lexer::{Lexer, Token}};
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+use crate::{Instruction, lexer::{Lexer, Span, Token}};
 
 pub struct Parser<'src> {
     lexer: Lexer<'src>,
+    warnings: Vec<Warning>,
 }
 
 #[derive(Debug)]
 pub enum Error {
-    MissingLoopOpen(),
-    MissingLoopEnd(),
+    /// A `]` at the given line and column without a matching `[`.
+    MissingLoopOpen { line: usize, col: usize },
+    /// A `[` at the given line and column that is never closed,
+    /// along with the total number of unclosed `[`.
+    MissingLoopEnd { line: usize, col: usize, count: usize },
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Error {
+    /// Line and column of the offending bracket.
+    pub fn pos(&self) -> (usize, usize) {
+        use Error::*;
+        match *self {
+            MissingLoopOpen { line, col } |
+            MissingLoopEnd { line, col, .. } => (line, col),
+        }
+    }
+
+    /// Short description of the problem, without position information.
+    pub fn message(&self) -> String {
+        use Error::*;
+        match self {
+            MissingLoopOpen { .. } => "`]` does not have a matching `[`".to_string(),
+            MissingLoopEnd { count: 1, .. } => "found 1 unclosed `[`".to_string(),
+            MissingLoopEnd { count, .. } => format!("found {} unclosed `[`", count),
+        }
+    }
+
+    /// The same error at the position `f` gives for its position, such as the position in
+    /// the source of a [macro expansion](crate::macros::Expansion::origin).
+    pub fn relocate(self, f: impl Fn((usize, usize)) -> (usize, usize)) -> Self {
+        use Error::*;
+        let (line, col) = f(self.pos());
+        match self {
+            MissingLoopOpen { .. } => MissingLoopOpen { line, col },
+            MissingLoopEnd { count, .. } => MissingLoopEnd { line, col, count },
+        }
+    }
+
+    /// Label to put under the offending bracket.
+    pub fn label(&self) -> &'static str {
         use Error::*;
         match self {
-            MissingLoopOpen() => write!(f, "`]` at column {} does not have a matching `[`", 0),
-            MissingLoopEnd() => write!(f, "found {} unclosed `[`", 0),
+            MissingLoopOpen { .. } => "unmatched `]`",
+            MissingLoopEnd { .. } => "this `[` is never closed",
         }
     }
 }
 
-impl<'src> Parser<'src> {
-    pub fn new(lexer: Lexer<'src>) -> Self {
-        Self { lexer }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, col) = self.pos();
+        write!(f, "{} at line {}, column {}", self.message(), line, col)
     }
+}
 
-    pub fn parse(&mut self) -> Result<Vec<Instruction>, Error> {
-        let mut bf = Vec::new();
+impl core::error::Error for Error {}
 
-        while let Some(c) = self.lexer.next() {
-            use Token::*;
-            use Instruction::*;
-            let instr = match c {
-                Gt    => IncPtr(1),
-                Lt    => DecPtr(1),
-                Plus  => IncVal(1),
-                Minus => DecVal(1),
-                Dot   => Write,
-                Comma => Read,
-                LSquare => {
-                    Loop(self.parse_loop()?)
-                },
-                RSquare => {
-                    return Err(Error::MissingLoopOpen());
-                },
-            };
+/// Suspicious code that is nonetheless valid.
+#[derive(Clone, Debug)]
+pub enum Warning {
+    /// An empty loop `[]` at the given line and column,
+    /// which either never runs or never ends.
+    EmptyLoop { line: usize, col: usize },
+    /// A loop whose cell is always zero when it is reached, see [`ranges`](crate::ranges).
+    NeverRuns { line: usize, col: usize },
+    /// A loop whose cell is never zero when it is tested, see [`ranges`](crate::ranges).
+    NeverEnds { line: usize, col: usize },
+}
+
+impl Warning {
+    /// Line and column of the offending code.
+    pub fn pos(&self) -> (usize, usize) {
+        match *self {
+            Warning::EmptyLoop { line, col }
+            | Warning::NeverRuns { line, col }
+            | Warning::NeverEnds { line, col } => (line, col),
+        }
+    }
+
+    /// Short description of the problem, without position information.
+    pub fn message(&self) -> String {
+        match self {
+            Warning::EmptyLoop { .. } => "empty loop never ends if its cell is nonzero".to_string(),
+            Warning::NeverRuns { .. } => "loop never runs, as its cell is always zero here".to_string(),
+            Warning::NeverEnds { .. } => "loop never ends, as its cell is never zero when tested".to_string(),
+        }
+    }
 
-            bf.push(instr);
+    /// The same warning at the position `f` gives for its position, as with [`Error::relocate`].
+    pub fn relocate(self, f: impl Fn((usize, usize)) -> (usize, usize)) -> Self {
+        let (line, col) = f(self.pos());
+        match self {
+            Warning::EmptyLoop { .. } => Warning::EmptyLoop { line, col },
+            Warning::NeverRuns { .. } => Warning::NeverRuns { line, col },
+            Warning::NeverEnds { .. } => Warning::NeverEnds { line, col },
         }
+    }
+
+    /// Label to put under the offending code.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Warning::EmptyLoop { .. } | Warning::NeverEnds { .. } => "intentional infinite loop?",
+            Warning::NeverRuns { .. } => "dead code",
+        }
+    }
+}
 
-        Ok(bf)
+impl<'src> Parser<'src> {
+    pub fn new(lexer: Lexer<'src>) -> Self {
+        Self { lexer, warnings: Vec::new() }
     }
 
-    fn parse_loop(&mut self) -> Result<Vec<Instruction>, Error> {
-        let mut bf = Vec::new();
+    /// Warnings found while parsing so far.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
 
-        while let Some(c) = self.lexer.next() {
+    /// Parse the whole program, without recursing into loops.
+    pub fn parse(&mut self) -> Result<Vec<Instruction>, Error> {
+        // Instructions are collected on a single stack, from which the body of every loop
+        // is moved into a vector of its own once it is closed, so that every loop body is
+        // allocated once at its final size rather than grown as it is parsed
+        let mut stack = Vec::new();
+        // Where the body of every unclosed `[` starts on the stack, along with its position
+        let mut open: Vec<(usize, Span)> = Vec::new();
+
+        for (token, span) in self.lexer.by_ref() {
             use Token::*;
             use Instruction::*;
-            let instr = match c {
+            let instr = match token {
                 Gt    => IncPtr(1),
                 Lt    => DecPtr(1),
                 Plus  => IncVal(1),
                 Minus => DecVal(1),
-                Dot   => Write,
+                Dot | Semicolon => Write,
                 Comma => Read,
+                LCurly => SwitchTape(-1),
+                RCurly => SwitchTape(1),
+                Caret => Push,
+                Underscore => Pop,
+                At => Halt,
+                Question => Random,
+                Comment(_) | Whitespace(_) => continue,
                 LSquare => {
-                    Loop(self.parse_loop()?)
+                    open.push((stack.len(), span));
+                    continue;
                 },
                 RSquare => {
-                    return Ok(bf);
+                    let Some((start, Span { line, col, .. })) = open.pop() else {
+                        return Err(Error::MissingLoopOpen { line: span.line, col: span.col });
+                    };
+                    if start == stack.len() {
+                        self.warnings.push(Warning::EmptyLoop { line, col });
+                    }
+                    Instruction::new_loop(stack.split_off(start))
                 },
             };
 
-            bf.push(instr);
+            stack.push(instr);
         }
 
-        Err(Error::MissingLoopEnd())
+        // Report the innermost unclosed loop, as every loop around it is unclosed as well
+        match open.last() {
+            Some(&(_, Span { line, col, .. })) => Err(Error::MissingLoopEnd { line, col, count: open.len() }),
+            None => Ok(stack),
+        }
     }
 }
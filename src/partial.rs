@@ -0,0 +1,137 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{Instruction, opt, config::Config};
+
+/// Maximum number of instructions executed at compile time,
+/// to guard against programs that do not terminate.
+const MAX_STEPS: u64 = 10_000_000;
+
+/// A position in the program: the instructions of a loop body (or the whole program),
+/// and the index of the next instruction to execute in it.
+struct Frame<'a> {
+    body: &'a [Instruction],
+    index: usize,
+}
+
+/// Specialize a program for a known prefix of its input.
+///
+/// The program is executed at compile time until it finishes, needs more input than
+/// the prefix provides, or would fail at runtime. The result prints all output produced
+/// so far as constants, restores the tape and data pointer, and then continues with the
+/// remainder of the program, reading further input at runtime.
+pub fn specialize(prog: &[Instruction], input: &[u8], config: &Config) -> Vec<Instruction> {
+    use Instruction::*;
+    let mask = config.cell_width.mask();
+    let mut tape = vec![0u32; config.tape_size];
+    let mut ptr = 0usize;
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let mut stack = vec![Frame { body: prog, index: 0 }];
+    let mut steps = 0;
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(instr) = frame.body.get(frame.index) else {
+            // End of a loop body: re-test the loop, or end of the program
+            stack.pop();
+            if let Some(parent) = stack.last_mut() {
+                if tape[ptr] == 0 {
+                    parent.index += 1;
+                } else if let Loop(body, _) = &parent.body[parent.index] {
+                    stack.push(Frame { body, index: 0 });
+                }
+            }
+            continue;
+        };
+
+        steps += 1;
+        if steps > MAX_STEPS {
+            break;
+        }
+
+        match instr {
+            IncPtr(x) => match ptr.checked_add(*x).filter(|&p| p < tape.len()) {
+                Some(p) => ptr = p,
+                None => break,
+            },
+            DecPtr(x) => match ptr.checked_sub(*x) {
+                Some(p) => ptr = p,
+                None => break,
+            },
+            IncVal(x) => tape[ptr] = tape[ptr].wrapping_add(*x as u32) & mask,
+            DecVal(x) => tape[ptr] = tape[ptr].wrapping_sub(*x as u32) & mask,
+            ClearVal => tape[ptr] = 0,
+            SetVal(x) => tape[ptr] = *x & mask,
+            Write => output.push(tape[ptr] as u8),
+            WriteConst(x) => output.push(*x),
+            // The other tapes are not tracked
+            SwitchTape(_) => break,
+            // The byte is only known at runtime
+            Random => break,
+            // The residual program only restores the tape, not the stack
+            Push | Pop => break,
+            // The program ends here, so the output so far is all of it
+            Halt => {
+                stack.clear();
+                break;
+            },
+            Read => match input.next() {
+                Some(&byte) => tape[ptr] = byte as u32 & mask,
+                // The rest of the input is only known at runtime
+                None => break,
+            },
+            MulLoop(targets, step) => {
+                if tape[ptr] != 0 {
+                    let iterations = tape[ptr].wrapping_mul(opt::inverse(*step)) & mask;
+                    let cells: Option<Vec<usize>> = targets.iter()
+                        .map(|(offset, _)| ptr.checked_add_signed(*offset).filter(|&c| c < tape.len()))
+                        .collect();
+                    let Some(cells) = cells else { break };
+                    for (cell, (_, factor)) in cells.into_iter().zip(targets) {
+                        tape[cell] = tape[cell].wrapping_add(iterations.wrapping_mul(*factor)) & mask;
+                    }
+                    tape[ptr] = 0;
+                }
+            },
+            Loop(body, _) => {
+                if tape[ptr] != 0 {
+                    stack.push(Frame { body, index: 0 });
+                    continue;
+                }
+            },
+        }
+
+        stack.last_mut().unwrap().index += 1;
+    }
+
+    // Print the output produced so far
+    let mut residual: Vec<Instruction> = output.into_iter().map(WriteConst).collect();
+    if stack.is_empty() {
+        return residual;
+    }
+
+    // Restore the tape and the data pointer
+    let mut cur = 0;
+    for (cell, &value) in tape.iter().enumerate().filter(|&(_, &v)| v != 0) {
+        residual.push(IncPtr(cell - cur));
+        residual.push(SetVal(value));
+        cur = cell;
+    }
+    if ptr >= cur {
+        residual.push(IncPtr(ptr - cur));
+    } else {
+        residual.push(DecPtr(cur - ptr));
+    }
+
+    // Continue with the rest of the innermost loop body, followed by the loop itself
+    // to re-test its condition, and so on for every enclosing loop
+    let (innermost, enclosing) = stack.split_last().unwrap();
+    residual.extend(innermost.body[innermost.index..].iter().cloned());
+    for frame in enclosing.iter().rev() {
+        // The current instruction of an enclosing frame is the loop that was interrupted
+        residual.push(frame.body[frame.index].clone());
+        residual.extend(frame.body[frame.index + 1..].iter().cloned());
+    }
+
+    residual.retain(|instr| !matches!(instr, IncPtr(0) | DecPtr(0)));
+    residual
+}
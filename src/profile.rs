@@ -0,0 +1,140 @@
+//! A sampling profiler, which attributes the time of a run to the loops of the program.
+//!
+//! Instead of counting every instruction, the interpreter pauses every so many steps and
+//! notes which instruction it was about to execute, which costs next to nothing between
+//! samples. Loops are identified by their position in the intermediate representation,
+//! and shown as the Brainfuck they run.
+//!
+//! The samples can also be written as folded stacks for flamegraph tools, with the loops
+//! running as the stack. Every line holds the frames separated by `;`, and the number of
+//! samples taken there:
+//!
+//! ```text
+//! main;8 [>++++++++[>+>+<<-]<-];8.9 [>+>+<<-] 41
+//! ```
+
+use std::{collections::HashMap, io::{self, Write}, slice};
+
+use crate::{Instruction, backend, config::Config};
+
+/// Longest code of a loop shown in the profile.
+const MAX_CODE: usize = 40;
+
+/// Samples of the position of a run, taken every fixed number of steps.
+pub struct Profile {
+    /// Number of steps between samples.
+    every: u64,
+    /// Number of samples taken inside every loop, not counting its nested loops, by the
+    /// position of the loop. The empty position stands for the top level of the program.
+    samples: HashMap<Vec<usize>, u64>,
+    total: u64,
+}
+
+impl Profile {
+    pub fn new(every: u64) -> Self {
+        Self { every, samples: HashMap::new(), total: 0 }
+    }
+
+    /// Number of steps between samples.
+    pub fn every(&self) -> u64 {
+        self.every
+    }
+
+    /// Take a sample at `pc`, the indices of the loops running and of the next instruction
+    /// in the innermost of them, outermost first.
+    pub fn sample(&mut self, pc: &[usize]) {
+        let loops = &pc[..pc.len().saturating_sub(1)];
+        match self.samples.get_mut(loops) {
+            Some(count) => *count += 1,
+            None => {
+                self.samples.insert(loops.to_vec(), 1);
+            },
+        }
+        self.total += 1;
+    }
+
+    /// Number of samples taken.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Samples taken in every loop `(position, self, total)`: directly in its body, and
+    /// also in nested loops. Sorted by the samples in the body, hottest first.
+    pub fn loops(&self) -> Vec<(Vec<usize>, u64, u64)> {
+        let mut loops: HashMap<&[usize], (u64, u64)> = HashMap::new();
+        for (pc, &count) in &self.samples {
+            loops.entry(pc).or_default().0 += count;
+            for depth in 0..=pc.len() {
+                loops.entry(&pc[..depth]).or_default().1 += count;
+            }
+        }
+        let mut loops: Vec<_> = loops.into_iter()
+            .map(|(pc, (own, total))| (pc.to_vec(), own, total))
+            .collect();
+        loops.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)).then(a.0.cmp(&b.0)));
+        loops
+    }
+
+    /// Print a table of the loops of `prog` the samples were taken in, hottest first.
+    pub fn print(&self, prog: &[Instruction], config: &Config, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "{} samples, one every {} steps", self.total, self.every)?;
+        if self.total == 0 {
+            return Ok(());
+        }
+        writeln!(w, "{:>7} {:>7}  {:<12} code", "self", "total", "loop")?;
+        let percent = |count: u64| count as f64 * 100.0 / self.total as f64;
+        for (pc, own, total) in self.loops() {
+            let (name, code) = match find(prog, &pc) {
+                _ if pc.is_empty() => ("top level".to_string(), String::new()),
+                Some(instr) => (name(&pc), code(instr, config)),
+                None => continue,
+            };
+            writeln!(w, "{:>6.1}% {:>6.1}%  {:<12} {}", percent(own), percent(total), name, code)?;
+        }
+        Ok(())
+    }
+
+    /// Write the samples as folded stacks, with a frame for every loop of `prog` running.
+    pub fn write_folded(&self, prog: &[Instruction], config: &Config, w: &mut impl Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.samples.iter().collect();
+        stacks.sort();
+        for (pc, count) in stacks {
+            write!(w, "main")?;
+            for depth in 1..=pc.len() {
+                let Some(instr) = find(prog, &pc[..depth]) else { break };
+                write!(w, ";{} {}", name(&pc[..depth]), code(instr, config))?;
+            }
+            writeln!(w, " {}", count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The position of a loop, as the indices leading to it separated by dots.
+fn name(pc: &[usize]) -> String {
+    pc.iter().map(usize::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// The instruction at position `pc` in `prog`.
+fn find<'p>(prog: &'p [Instruction], pc: &[usize]) -> Option<&'p Instruction> {
+    let (&i, inner) = pc.split_first()?;
+    let instr = prog.get(i)?;
+    match (instr, inner) {
+        (_, []) => Some(instr),
+        (Instruction::Loop(body, _), inner) => find(body, inner),
+        _ => None,
+    }
+}
+
+/// The code of a loop as Brainfuck, shortened to fit a line.
+fn code(instr: &Instruction, config: &Config) -> String {
+    let Some(code) = backend::bf::generate(slice::from_ref(instr), config) else {
+        // Constant output cannot be written as Brainfuck
+        return "[...]".to_string();
+    };
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() <= MAX_CODE {
+        return code;
+    }
+    format!("{}...", &code[..MAX_CODE - 3])
+}
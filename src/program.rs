@@ -0,0 +1,124 @@
+//! A parsed program along with its configuration, as the main entry point of the library.
+
+use core::str::FromStr;
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{Error, Instruction, lexer::Lexer, macros, parser::{Parser, Warning}, opt::{Level, PassManager}, config::Config, events};
+#[cfg(feature = "std")]
+use crate::{config::CellWidth, eval::{Context, Io}, report::ResourceReport};
+
+/// A program ready to run, with the configuration it was parsed with.
+///
+/// ```text
+/// use bf::{Program, opt::Level, eval::Io};
+///
+/// let mut output = Vec::new();
+/// Program::parse(",[.,]")?.optimize(Level::Aggressive).run(&mut Io::from_buffers(b"hi", &mut output))?;
+/// let output = "++++++[>++++++++<-]>.".parse::<Program>()?.output(b"")?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Program {
+    ir: Vec<Instruction>,
+    config: Config,
+    warnings: Vec<Warning>,
+}
+
+impl Program {
+    /// Parse `src` with the default configuration.
+    pub fn parse(src: &str) -> Result<Self, Error> {
+        Self::parse_with(src, Config::default())
+    }
+
+    /// Parse `src` in the configured dialect, keeping the configuration to optimize and run it with.
+    pub fn parse_with(src: &str, mut config: Config) -> Result<Self, Error> {
+        config.resolve().map_err(Error::InvalidConfig)?;
+        // Check the configured passes now, so that optimizing cannot fail
+        PassManager::from_config(&Config { opt_level: Level::Aggressive.as_u8(), ..config.clone() })
+            .map_err(Error::InvalidConfig)?;
+        // Positions in the expanded source are reported where they are in `src`
+        let expansion = config.macros.then(|| macros::expand(src)).transpose()?;
+        let origin = |pos| expansion.as_ref().map_or(pos, |expansion| expansion.origin(pos));
+        let src = expansion.as_ref().map_or(src, macros::Expansion::src);
+        let mut parser = Parser::new(Lexer::with_config(src, &config));
+        let ir = parser.parse().map_err(|e| e.relocate(origin))?;
+        let warnings = parser.warnings().iter().cloned()
+            .chain(crate::ranges::warnings(&ir, src, &config))
+            .map(|warning| warning.relocate(origin))
+            .collect();
+        Ok(Self { ir, warnings, config })
+    }
+
+    /// Optimize the program as the `opt_level` option does.
+    pub fn optimize(mut self, level: Level) -> Self {
+        let config = Config { opt_level: level.as_u8(), ..self.config.clone() };
+        PassManager::from_config(&config)
+            .expect("checked by `Program::parse_with`")
+            .run(&mut self.ir);
+        self
+    }
+
+    /// Rewrite the program with a closure, as [`opt::map_instructions`](crate::opt::map_instructions) does.
+    pub fn map_instructions(mut self, mut f: impl FnMut(Instruction) -> Vec<Instruction>) -> Self {
+        self.ir = crate::opt::map_instructions(self.ir, &mut f);
+        self
+    }
+
+    /// Run the program to completion, reading and writing through `io`.
+    #[cfg(feature = "std")]
+    pub fn run(&self, io: &mut Io<'_>) -> Result<ResourceReport, Error> {
+        let resources = match self.config.cell_width {
+            CellWidth::Bit => Context::<crate::cell::Bit>::with_io(io, &self.config).eval(&self.ir)?,
+            CellWidth::U8 => Context::<u8>::with_io(io, &self.config).eval(&self.ir)?,
+            CellWidth::U16 => Context::<u16>::with_io(io, &self.config).eval(&self.ir)?,
+            CellWidth::U32 => Context::<u32>::with_io(io, &self.config).eval(&self.ir)?,
+            #[cfg(feature = "bignum")]
+            CellWidth::Big => Context::<crate::bignum::BigInt>::with_io(io, &self.config).eval(&self.ir)?,
+            #[cfg(not(feature = "bignum"))]
+            CellWidth::Big => return Err(Error::InvalidConfig("arbitrary-precision cells require building with the `bignum` feature".into())),
+        };
+        Ok(resources)
+    }
+
+    /// Run the program to completion on the given input, returning its output, as [`events::run`] does.
+    pub fn output(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(events::run(&self.ir, &self.config, input)?)
+    }
+
+    /// The intermediate representation of the program.
+    pub fn ir(&self) -> &[Instruction] {
+        &self.ir
+    }
+
+    pub fn into_ir(self) -> Vec<Instruction> {
+        self.ir
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Warnings found while parsing.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+}
+
+/// Parse a program with the default configuration, as [`Program::parse`].
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self, Error> {
+        Self::parse(src)
+    }
+}
+
+/// Parse a program from a source file that may not be valid UTF-8, which only matters
+/// for the comments in it, with the default configuration.
+impl TryFrom<&[u8]> for Program {
+    type Error = Error;
+
+    fn try_from(src: &[u8]) -> Result<Self, Error> {
+        Self::parse(&String::from_utf8_lossy(src))
+    }
+}
@@ -0,0 +1,49 @@
+//! The random bytes of `?`, with the [`random_command`](crate::config::Config::random_command)
+//! extension.
+//!
+//! The generator is SplitMix64, which is small and gives the same bytes for the same seed
+//! everywhere, so that a run with a [`seed`](crate::config::Config::seed) can be repeated.
+
+/// Added to the state for every number, the golden ratio in 64 bits.
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A generator of random bytes.
+#[derive(Clone, Debug, Hash)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator giving the bytes of `seed`, or different bytes on every run without one.
+    ///
+    /// Without the `std` feature there is nothing to take a seed from, so no seed is the seed `0`.
+    pub fn new(seed: Option<u64>) -> Self {
+        Self { state: seed.unwrap_or_else(entropy) }
+    }
+
+    /// The current state, from which [`Rng::new`] continues with the same bytes as this generator.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// The next random byte.
+    pub fn byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as u8
+    }
+}
+
+/// A seed that differs between runs, from the random keys of the standard library's hash maps.
+#[cfg(feature = "std")]
+fn entropy() -> u64 {
+    use std::hash::{BuildHasher, RandomState};
+    RandomState::new().hash_one(0u8)
+}
+
+#[cfg(not(feature = "std"))]
+fn entropy() -> u64 {
+    0
+}
@@ -0,0 +1,383 @@
+//! Abstract interpretation of the values that cells can hold.
+//!
+//! Instead of running a program on one input, the analysis runs it on all input at once,
+//! keeping a range of possible values for every cell. Loop bodies are run until the ranges
+//! at the start of the loop no longer grow, after a few runs moving the bounds that keep
+//! growing straight to the end, so that this does not take long. This shows which loops
+//! never run, as their cell is always zero when they are reached, which loops never end,
+//! as their cell is never zero when it is tested, and how often a loop runs.
+//!
+//! Ranges are kept within the values of a byte, so that they hold for every cell width
+//! of at least a byte, including big cells: a change that may take a cell past either end makes it unknown.
+//! Positions on the tape are relative to the start of the program, and known as long as
+//! loops are balanced. After an unbalanced loop, nothing is known about the tape anymore
+//! other than that the current cell is zero.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{Instruction, lexer::{Lexer, Token}, parser::Warning, config::{Config, CellWidth}};
+
+/// Number of runs of a loop body after which growing ranges are widened.
+const WIDEN_AFTER: usize = 2;
+/// Maximum number of instructions to interpret before the analysis gives up.
+const BUDGET: usize = 1 << 20;
+
+/// The possible values of a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    /// A value from the first to the second number, inclusive.
+    Range(u8, u8),
+    /// Any value the cell can hold.
+    Any,
+}
+
+impl Value {
+    pub const ZERO: Value = Value::Range(0, 0);
+
+    /// A single value, which is unknown if it does not fit in a byte.
+    fn constant(x: u32) -> Self {
+        u8::try_from(x).map_or(Value::Any, |x| Value::Range(x, x))
+    }
+
+    pub fn is_zero(self) -> bool {
+        self == Value::ZERO
+    }
+
+    pub fn may_be_zero(self) -> bool {
+        !matches!(self, Value::Range(min, _) if min > 0)
+    }
+
+    /// The values that are not zero, or `None` if the value is always zero.
+    fn nonzero(self) -> Option<Self> {
+        match self {
+            Value::Range(_, 0) => None,
+            Value::Range(0, max) => Some(Value::Range(1, max)),
+            value => Some(value),
+        }
+    }
+
+    /// The smallest range holding both values.
+    fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Value::Range(a, b), Value::Range(c, d)) => Value::Range(a.min(c), b.max(d)),
+            _ => Value::Any,
+        }
+    }
+
+    /// Like [`join`](Self::join), but a bound of this range that `other` extends
+    /// goes to the end of the byte at once.
+    fn widen(self, other: Self) -> Self {
+        match (self, other) {
+            (Value::Range(a, b), Value::Range(c, d)) => {
+                Value::Range(if c < a { 0 } else { a }, if d > b { u8::MAX } else { b })
+            },
+            _ => Value::Any,
+        }
+    }
+
+    fn add(self, x: u8) -> Self {
+        match self {
+            Value::Range(min, max) if max.checked_add(x).is_some() => Value::Range(min + x, max + x),
+            _ => Value::Any,
+        }
+    }
+
+    fn sub(self, x: u8) -> Self {
+        match self {
+            Value::Range(min, max) if min >= x => Value::Range(min - x, max - x),
+            _ => Value::Any,
+        }
+    }
+}
+
+/// What is known about an instruction, over every time it runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fact {
+    /// Value of the current cell before the instruction runs.
+    pub cell: Value,
+    /// For a loop, the value of its cell after its body runs,
+    /// or `None` if the body never runs to its end.
+    pub after_body: Option<Value>,
+    /// For a loop, whether it is balanced and its body only changes its cell by
+    /// subtracting one, so that it runs as many times as the value of its cell.
+    pub counts_down: bool,
+}
+
+impl Fact {
+    /// Whether a loop never runs its body.
+    pub fn never_runs(&self) -> bool {
+        self.cell.is_zero()
+    }
+
+    /// Whether a loop never ends, as its cell is never zero when tested after its body.
+    pub fn never_ends(&self) -> bool {
+        !self.cell.may_be_zero() && self.after_body.is_some_and(|cell| !cell.may_be_zero())
+    }
+
+    /// The least and greatest number of times a loop runs its body,
+    /// without a greatest number if it is not known.
+    pub fn iterations(&self) -> (u32, Option<u32>) {
+        if self.never_runs() {
+            return (0, Some(0));
+        }
+        if let (true, Value::Range(min, max)) = (self.counts_down, self.cell) {
+            return (min as u32, Some(max as u32));
+        }
+        let min = if self.cell.may_be_zero() { 0 } else { 1 };
+        match self.after_body {
+            None | Some(Value::ZERO) => (min, Some(1)),
+            Some(_) => (min, None),
+        }
+    }
+}
+
+/// The possible values of the cells at some point in the program.
+#[derive(Clone, Debug, PartialEq)]
+struct State {
+    /// Position of the data pointer.
+    pos: isize,
+    cells: BTreeMap<isize, Value>,
+    /// Value of the cells not in `cells`.
+    default: Value,
+}
+
+impl State {
+    /// A state in which nothing is known, except that the current cell has `value`.
+    fn unknown(value: Value) -> Self {
+        Self { pos: 0, cells: BTreeMap::from([(0, value)]), default: Value::Any }
+    }
+
+    fn get(&self, pos: isize) -> Value {
+        self.cells.get(&pos).copied().unwrap_or(self.default)
+    }
+
+    fn current(&self) -> Value {
+        self.get(self.pos)
+    }
+
+    fn set(&mut self, value: Value) {
+        self.cells.insert(self.pos, value);
+    }
+
+    /// Combine every cell of two states at the same position.
+    fn merge(&self, other: &Self, f: fn(Value, Value) -> Value) -> Self {
+        debug_assert_eq!(self.pos, other.pos);
+        let cells = self.cells.keys().chain(other.cells.keys())
+            .map(|&pos| (pos, f(self.get(pos), other.get(pos))))
+            .collect();
+        Self { pos: self.pos, cells, default: f(self.default, other.default) }
+    }
+}
+
+struct Analyzer {
+    facts: BTreeMap<Vec<usize>, Fact>,
+    /// Position of the current instruction.
+    pc: Vec<usize>,
+    /// Number of instructions that may still be interpreted.
+    budget: usize,
+}
+
+impl Analyzer {
+    /// Run `bf` from `state`, returning the state after it, or `None` if the end
+    /// is never reached, or the budget ran out.
+    fn run(&mut self, bf: &[Instruction], mut state: State) -> Option<State> {
+        for (i, instr) in bf.iter().enumerate() {
+            self.budget = self.budget.checked_sub(1)?;
+            self.pc.push(i);
+            let next = self.step(instr, state);
+            self.pc.pop();
+            state = next?;
+        }
+        Some(state)
+    }
+
+    /// Add what was seen of the current instruction to what was seen before.
+    fn record(&mut self, cell: Value, after_body: Option<Value>, counts_down: bool) {
+        let fact = self.facts.entry(self.pc.clone())
+            .or_insert(Fact { cell, after_body: None, counts_down });
+        fact.cell = fact.cell.join(cell);
+        fact.after_body = match (fact.after_body, after_body) {
+            (Some(a), Some(b)) => Some(a.join(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn step(&mut self, instr: &Instruction, mut state: State) -> Option<State> {
+        use Instruction::*;
+        let cell = state.current();
+        match instr {
+            IncPtr(x) => state.pos += *x as isize,
+            DecPtr(x) => state.pos -= *x as isize,
+            IncVal(x) => state.set(cell.add(*x)),
+            DecVal(x) => state.set(cell.sub(*x)),
+            ClearVal => state.set(Value::ZERO),
+            SetVal(x) => state.set(Value::constant(*x)),
+            Write | WriteConst(_) => {},
+            Read => state.set(Value::Any),
+            Random => state.set(Value::Range(0, u8::MAX)),
+            Push => {},
+            // The stack is not tracked
+            Pop => state.set(Value::Any),
+            Halt => {
+                self.record(cell, None, false);
+                return None;
+            },
+            // Nothing is known about the other tape
+            SwitchTape(_) => state = State::unknown(Value::Any),
+            MulLoop(targets, _) => {
+                if !cell.is_zero() {
+                    for (offset, _) in targets {
+                        state.cells.insert(state.pos + offset, Value::Any);
+                    }
+                }
+                state.set(Value::ZERO);
+            },
+            Loop(body, shift) => return self.run_loop(body, *shift, state),
+        }
+        self.record(cell, None, false);
+        Some(state)
+    }
+
+    fn run_loop(&mut self, body: &[Instruction], shift: Option<isize>, entry: State) -> Option<State> {
+        let cell = entry.current();
+        let mut after_body: Option<Value> = None;
+        let mut join_after_body = |value: Value| after_body = Some(after_body.map_or(value, |v| v.join(value)));
+
+        let exit = if shift == Some(0) {
+            // The state at the test of the loop, at first and after every run of the body
+            let mut head = entry;
+            for run in 1.. {
+                let Some(cell) = head.current().nonzero() else { break };
+                let mut inside = head.clone();
+                inside.set(cell);
+                let Some(out) = self.run(body, inside) else { break };
+                join_after_body(out.current());
+                let next = head.merge(&out, if run > WIDEN_AFTER { Value::widen } else { Value::join });
+                if next == head {
+                    break;
+                }
+                head = next;
+            }
+            head.current().may_be_zero().then(|| {
+                head.set(Value::ZERO);
+                head
+            })
+        } else {
+            // The first run starts from the entry, after which the position is unknown
+            if let Some(cell) = cell.nonzero() {
+                let mut inside = entry;
+                inside.set(cell);
+                if let Some(out) = self.run(body, inside) {
+                    join_after_body(out.current());
+                    if !out.current().is_zero() && let Some(out) = self.run(body, State::unknown(Value::Any)) {
+                        join_after_body(out.current());
+                    }
+                }
+            }
+            (cell.may_be_zero() || after_body.is_some_and(Value::may_be_zero)).then(|| State::unknown(Value::ZERO))
+        };
+
+        self.record(cell, after_body, shift == Some(0) && counts_down(body));
+        exit
+    }
+}
+
+/// Whether a balanced loop body only changes the loop counter by subtracting one.
+fn counts_down(body: &[Instruction]) -> bool {
+    let (mut offset, mut delta) = (0isize, 0i32);
+    for instr in body {
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => offset += *x as isize,
+            DecPtr(x) => offset -= *x as isize,
+            IncVal(x) if offset == 0 => delta += *x as i32,
+            DecVal(x) if offset == 0 => delta -= *x as i32,
+            Write | WriteConst(_) => {},
+            IncVal(_) | DecVal(_) | ClearVal | SetVal(_) | Read if offset != 0 => {},
+            MulLoop(targets, _) if offset != 0 && targets.iter().all(|(o, _)| offset + o != 0) => {},
+            _ => return false,
+        }
+    }
+    delta == -1
+}
+
+/// What is known about the instructions of a program.
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    /// Facts by the position of the instruction: the indices leading to it through
+    /// the loops it is in, outermost first. Instructions that never run have no facts.
+    facts: BTreeMap<Vec<usize>, Fact>,
+}
+
+impl Analysis {
+    /// What is known about the instruction at position `pc`,
+    /// or `None` if the instruction never runs.
+    pub fn fact(&self, pc: &[usize]) -> Option<&Fact> {
+        self.facts.get(pc)
+    }
+}
+
+/// Analyze the program, in which every cell starts at zero.
+/// Returns `None` if the program is too large to analyze.
+pub fn analyze(prog: &[Instruction]) -> Option<Analysis> {
+    let mut analyzer = Analyzer { facts: BTreeMap::new(), pc: Vec::new(), budget: BUDGET };
+    let start = State { pos: 0, cells: BTreeMap::new(), default: Value::ZERO };
+    analyzer.run(prog, start);
+    (analyzer.budget > 0).then_some(Analysis { facts: analyzer.facts })
+}
+
+/// Warnings about the loops of `prog`, the program parsed from `src` without optimizing it.
+///
+/// These are loops that never run, except those at the start of the program or directly
+/// after another loop, as these are commonly used for comments, and loops that never end,
+/// except empty loops, which the parser warns about. There are none for 1-bit cells,
+/// which the ranges do not hold for.
+pub fn warnings(prog: &[Instruction], src: &str, config: &Config) -> Vec<Warning> {
+    if config.cell_width == CellWidth::Bit {
+        return Vec::new();
+    }
+    let Some(analysis) = analyze(prog) else {
+        return Vec::new();
+    };
+
+    // Index of every loop to warn about, counting the loops of the program in order
+    let mut found = Vec::new();
+    find_warnings(prog, &analysis, &mut Vec::new(), &mut 0, &mut found);
+    if found.is_empty() {
+        return Vec::new();
+    }
+
+    let opens: Vec<_> = Lexer::with_config(src, config)
+        .filter(|(token, _)| matches!(token, Token::LSquare))
+        .map(|(_, span)| (span.line, span.col))
+        .collect();
+    found.into_iter()
+        .filter_map(|(index, warning)| {
+            let &(line, col) = opens.get(index)?;
+            Some(warning(line, col))
+        })
+        .collect()
+}
+
+/// A warning at a line and column.
+type MakeWarning = fn(usize, usize) -> Warning;
+
+fn find_warnings(bf: &[Instruction], analysis: &Analysis, pc: &mut Vec<usize>, loops: &mut usize, found: &mut Vec<(usize, MakeWarning)>) {
+    for (i, instr) in bf.iter().enumerate() {
+        let Instruction::Loop(body, _) = instr else { continue };
+        let index = *loops;
+        *loops += 1;
+        pc.push(i);
+        if let Some(fact) = analysis.fact(pc) {
+            let comment = (i > 0 && matches!(bf[i - 1], Instruction::Loop(..)))
+                || (pc.len() == 1 && bf[..i].iter().all(|instr| matches!(instr, Instruction::Loop(..))));
+            if fact.never_runs() && !comment {
+                found.push((index, |line, col| Warning::NeverRuns { line, col }));
+            } else if fact.never_ends() && !body.is_empty() {
+                found.push((index, |line, col| Warning::NeverEnds { line, col }));
+            }
+        }
+        find_warnings(body, analysis, pc, loops, found);
+        pc.pop();
+    }
+}
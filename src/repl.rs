@@ -0,0 +1,50 @@
+use std::io::{self, Read, Write};
+
+use bf::{lexer::Lexer, parser::{self, Parser}, cell::Cell, config::Config, eval::Context};
+
+use crate::debug::show_tape;
+
+/// Number of cells shown around the data pointer after every snippet.
+const TAPE_WINDOW: usize = 16;
+
+/// Run snippets of Brainfuck read from stdin one after another on the same tape, showing
+/// the cells around the data pointer on stderr after each, until the end of input.
+///
+/// A snippet with unclosed loops continues on the next line. Snippets are not optimized,
+/// as the optimizer assumes that programs start on an empty tape.
+pub fn repl<C: Cell>(config: &Config, rdr: &mut impl Read) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut ctx = Context::<C>::new(rdr, &mut stdout, config);
+    eprintln!("enter Brainfuck to run it, unclosed loops continue on the next line; press Ctrl-D to quit");
+
+    let mut src = String::new();
+    loop {
+        eprint!("{}", if src.is_empty() { "bf> " } else { "... " });
+        // Read through a new handle every time, as the program may read from stdin as well
+        if io::stdin().read_line(&mut src)? == 0 {
+            eprintln!();
+            return Ok(());
+        }
+
+        let prog = match Parser::new(Lexer::with_config(&src, config)).parse() {
+            Ok(prog) => prog,
+            Err(parser::Error::MissingLoopEnd { .. }) => continue,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                src.clear();
+                continue;
+            },
+        };
+        src.clear();
+        if prog.is_empty() {
+            continue;
+        }
+
+        let result = ctx.eval(&prog);
+        io::stdout().flush()?;
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+        show_tape(&ctx, TAPE_WINDOW);
+    }
+}
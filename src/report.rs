@@ -0,0 +1,133 @@
+use core::{fmt::Write as _, time::Duration};
+
+use alloc::{string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+/// Number of instructions before and after running an optimization pass.
+pub struct PassEffect {
+    pub name: String,
+    pub before: usize,
+    pub after: usize,
+    /// Whether the pass changed the program.
+    pub changed: bool,
+    pub time: Duration,
+}
+
+/// Write a table summarizing the effect of every pass, combining repeated runs of a pass.
+///
+/// ```text
+/// pass          runs  changed  removed       time
+/// cancel           2        1       12    0.021ms
+/// ```
+#[cfg(feature = "std")]
+pub fn write_pass_stats(passes: &[PassEffect], w: &mut impl Write) -> io::Result<()> {
+    if passes.is_empty() {
+        return writeln!(w, "no optimization passes were run");
+    }
+
+    // Combine the runs of every pass, in order of the first run
+    let mut rows: Vec<(&str, usize, usize, isize, Duration)> = Vec::new();
+    for pass in passes {
+        let i = match rows.iter().position(|row| row.0 == pass.name) {
+            Some(i) => i,
+            None => {
+                rows.push((&pass.name, 0, 0, 0, Duration::ZERO));
+                rows.len() - 1
+            },
+        };
+        let row = &mut rows[i];
+        row.1 += 1;
+        row.2 += pass.changed as usize;
+        row.3 += pass.before as isize - pass.after as isize;
+        row.4 += pass.time;
+    }
+
+    let width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max("total".len());
+    writeln!(w, "{:<width$}  {:>4}  {:>7}  {:>7}  {:>9}", "pass", "runs", "changed", "removed", "time")?;
+    for (name, runs, changed, removed, time) in &rows {
+        writeln!(w, "{:<width$}  {:>4}  {:>7}  {:>7}  {:>7.3}ms", name, runs, changed, removed, time.as_secs_f64() * 1000.0)?;
+    }
+
+    let removed: isize = rows.iter().map(|row| row.3).sum();
+    let time: Duration = rows.iter().map(|row| row.4).sum();
+    let before = passes.first().map_or(0, |pass| pass.before);
+    let after = passes.last().map_or(before, |pass| pass.after);
+    writeln!(w, "{:<width$}  {:>4}  {:>7}  {:>7}  {:>7.3}ms", "total", passes.len(), "", removed, time.as_secs_f64() * 1000.0)?;
+    writeln!(w, "{} instructions before, {} after", before, after)
+}
+
+/// Resources used by running a program, as returned by [`Context::eval`](crate::eval::Context::eval).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceReport {
+    /// Number of instructions executed, counted as by [`Context::steps`](crate::eval::Context::steps).
+    pub steps: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Highest position of the data pointer.
+    pub max_ptr: usize,
+    /// Largest number of cells held in memory, over all tapes.
+    pub peak_tape_size: usize,
+    /// Time spent running the program, including waiting for input.
+    pub wall_time: Duration,
+}
+
+/// Machine-readable summary of a run.
+#[derive(Default)]
+pub struct Report {
+    /// Error message if the run failed.
+    pub error: Option<String>,
+    pub resources: ResourceReport,
+    /// Time taken by everything, including parsing and optimizing the program.
+    pub wall_time: Duration,
+    pub passes: Vec<PassEffect>,
+}
+
+impl Report {
+    /// Write the report as a single line of JSON.
+    #[cfg(feature = "std")]
+    pub fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut json = String::new();
+        let status = if self.error.is_some() { "error" } else { "ok" };
+        let _ = write!(json, "{{\"status\":\"{}\",\"error\":", status);
+        match &self.error {
+            Some(e) => json += &json_string(e),
+            None => json += "null",
+        }
+        let resources = &self.resources;
+        let _ = write!(json, ",\"steps\":{},\"max_pointer\":{},\"peak_tape_size\":{},\"input_bytes\":{},\"output_bytes\":{},\"eval_time_ms\":{:.3},\"wall_time_ms\":{:.3}",
+            resources.steps, resources.max_ptr, resources.peak_tape_size, resources.input_bytes, resources.output_bytes,
+            resources.wall_time.as_secs_f64() * 1000.0, self.wall_time.as_secs_f64() * 1000.0);
+
+        json += ",\"passes\":[";
+        for (i, pass) in self.passes.iter().enumerate() {
+            if i > 0 {
+                json += ",";
+            }
+            let _ = write!(json, "{{\"name\":{},\"instructions_before\":{},\"instructions_after\":{},\"changed\":{},\"time_ms\":{:.3}}}",
+                json_string(&pass.name), pass.before, pass.after, pass.changed, pass.time.as_secs_f64() * 1000.0);
+        }
+        json += "]}";
+
+        writeln!(w, "{}", json)
+    }
+}
+
+/// Quote and escape a string for use in JSON.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,196 @@
+use std::{fmt::Write as _, io::{self, BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+use bf::{Instruction, lexer::Lexer, parser::Parser, opt, cell::Cell, config::Config, eval::Context, output, report::json_string};
+
+/// Address to listen on, unless given otherwise.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// Step limit for every run, unless configured otherwise.
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+/// Largest accepted request body, holding the program and its input.
+const MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Largest output of a single run.
+const MAX_OUTPUT_SIZE: u64 = 1 << 20;
+
+/// Largest accepted request line or header.
+const MAX_LINE_SIZE: u64 = 8 << 10;
+
+/// The parts of a request we care about.
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// A response to send back.
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn error(status: &'static str, msg: &str) -> Self {
+        Response { status, content_type: "text/plain; charset=utf-8", body: format!("{}\n", msg) }
+    }
+}
+
+/// Serve `POST /run` requests on `addr` until the process is killed, running every request
+/// on its own thread.
+///
+/// The request body is a form with the `program` to run and optionally its `input`,
+/// as sent by HTML forms:
+///
+/// ```text
+/// program=%2C%5B.%2C%5D&input=hello
+/// ```
+///
+/// The response is a JSON object with the output of the program, as UTF-8 with invalid
+/// sequences replaced, along with any error and the number of steps executed:
+///
+/// ```text
+/// {"status":"ok","error":null,"output":"hello","steps":21}
+/// ```
+pub fn serve(addr: &str, config: &Config) -> io::Result<()> {
+    let mut config = config.clone();
+    config.max_steps.get_or_insert(DEFAULT_MAX_STEPS);
+
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("listening on http://{}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &config) {
+                eprintln!("error: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Handle a single request on a connection.
+fn handle(stream: TcpStream, config: &Config) -> io::Result<()> {
+    let mut rdr = BufReader::new(&stream);
+    let response = match read_request(&mut rdr)? {
+        Ok(request) => match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/run") => run(&request.body, config),
+            (_, "/run") => Response::error("405 Method Not Allowed", "use POST to run a program"),
+            _ => Response::error("404 Not Found", "not found, use POST /run to run a program"),
+        },
+        Err(response) => response,
+    };
+
+    let mut wtr = io::BufWriter::new(&stream);
+    write!(wtr, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, response.content_type, response.body.len(), response.body)?;
+    wtr.flush()
+}
+
+/// Read a request, or the response to reject it with.
+fn read_request(rdr: &mut impl BufRead) -> io::Result<Result<Request, Response>> {
+    let mut line = String::new();
+    rdr.by_ref().take(MAX_LINE_SIZE).read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(Err(Response::error("400 Bad Request", "invalid request line")));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if rdr.by_ref().take(MAX_LINE_SIZE).read_line(&mut line)? == 0 {
+            return Ok(Err(Response::error("400 Bad Request", "incomplete headers")));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = match value.trim().parse() {
+                Ok(n) => n,
+                Err(_) => return Ok(Err(Response::error("400 Bad Request", "invalid Content-Length"))),
+            };
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Err(Response::error("413 Content Too Large", &format!("the request body is limited to {} bytes", MAX_BODY_SIZE))));
+    }
+    let mut body = vec![0; content_length];
+    rdr.read_exact(&mut body)?;
+    Ok(Ok(Request { method, path, body }))
+}
+
+/// Run the program in a form, describing the result.
+fn run(body: &[u8], config: &Config) -> Response {
+    let mut program = None;
+    let mut input = Vec::new();
+    for field in body.split(|&b| b == b'&') {
+        let (name, value) = match field.iter().position(|&b| b == b'=') {
+            Some(i) => (&field[..i], percent_decode(&field[i + 1..])),
+            None => (field, Vec::new()),
+        };
+        match name {
+            b"program" => program = Some(value),
+            b"input" => input = value,
+            _ => {},
+        }
+    }
+    let Some(program) = program else {
+        return Response::error("400 Bad Request", "missing `program` field");
+    };
+    let program = String::from_utf8_lossy(&program);
+
+    let mut json = String::new();
+    let (error, output, steps) = match Parser::new(Lexer::with_config(&program, config)).parse() {
+        Ok(mut prog) => {
+            if let Err(e) = opt::PassManager::from_config(config).map(|passes| passes.run(&mut prog)) {
+                return Response::error("500 Internal Server Error", &e);
+            }
+            let mut rdr = &input[..];
+            let mut output = output::Limited::new(Vec::new(), MAX_OUTPUT_SIZE);
+            let (result, steps) = with_cell!(config.cell_width, eval(&prog, config, &mut rdr, &mut output));
+            (result.err().map(|e| e.to_string()), output.into_inner(), steps)
+        },
+        Err(e) => (Some(e.to_string()), Vec::new(), 0),
+    };
+
+    let status = if error.is_some() { "error" } else { "ok" };
+    let _ = writeln!(json, "{{\"status\":\"{}\",\"error\":{},\"output\":{},\"steps\":{}}}",
+        status, error.as_deref().map_or("null".to_string(), json_string), json_string(&String::from_utf8_lossy(&output)), steps);
+    Response { status: "200 OK", content_type: "application/json", body: json }
+}
+
+/// Run the program with cells of type `C`, returning the result and the number of steps.
+fn eval<C: Cell>(prog: &[Instruction], config: &Config, rdr: &mut impl Read, wtr: &mut impl Write) -> (Result<(), bf::eval::Error>, u64) {
+    let mut ctx = Context::<C>::new(rdr, wtr, config);
+    (ctx.eval(prog).map(drop), ctx.steps())
+}
+
+/// Decode a form field, in which spaces are written as `+` and other bytes may be escaped as `%XX`.
+fn percent_decode(s: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (s[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            },
+            (b'+', _) => out.push(b' '),
+            (byte, _) => out.push(byte),
+        }
+        i += 1;
+    }
+    out
+}
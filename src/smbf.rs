@@ -0,0 +1,230 @@
+//! Self-modifying Brainfuck, where the program is stored on the tape, in the cells to the
+//! left of the one the data pointer starts at.
+//!
+//! The instruction pointer moves over the cells of the program, which the program can read
+//! and change like any other cell by moving the data pointer to the left. Cells that do not
+//! hold a command are comments, and the program ends when the instruction pointer reaches
+//! the cell the data pointer started at. Brackets are matched every time a loop is entered
+//! or repeated, as the program may write them while it runs.
+//!
+//! Such a program cannot be parsed or optimized ahead of time, so it is run by this
+//! evaluator of its own, which only needs `alloc`, rather than by [`Context`](crate::eval::Context).
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{config::{Config, EofMode, EmptyLoopMode}, eval::Error, events::Event, random::Rng};
+
+/// A running self-modifying program, which runs until its next [`Event`] on every call
+/// to [`Iterator::next`], like [`events::Executions`](crate::events::Executions).
+///
+/// Input and output are always bytes, whatever the configured I/O mode.
+pub struct Executions {
+    /// The program, followed by the data cells used so far.
+    memory: Vec<u32>,
+    /// Number of cells holding the program, which is the position of the first data cell.
+    code_len: usize,
+    /// Position of the next command.
+    ip: usize,
+    /// Position of the data pointer.
+    ptr: usize,
+    tape_size: usize,
+    /// Mask selecting the bits of a cell, determined by the cell width.
+    mask: u32,
+    eof: EofMode,
+    empty_loop: EmptyLoopMode,
+    halt_command: bool,
+    random_command: bool,
+    /// Source of the bytes of `?`.
+    rng: Rng,
+    max_steps: Option<u64>,
+    steps: u64,
+    /// Input given but not read yet.
+    input: VecDeque<u8>,
+    /// Whether the end of input was given.
+    input_closed: bool,
+    /// Error to report after the event of the command that caused it.
+    error: Option<Error>,
+    /// Whether the program halted or failed.
+    done: bool,
+}
+
+impl Executions {
+    /// Load the bytes of `src` onto the tape, and start running them.
+    pub fn new(src: &str, config: &Config) -> Self {
+        let mask = config.cell_width.mask();
+        let mut memory: Vec<u32> = src.bytes().map(|byte| byte as u32 & mask).collect();
+        let code_len = memory.len();
+        memory.push(0);
+        Self {
+            memory,
+            code_len,
+            ip: 0,
+            ptr: code_len,
+            tape_size: config.tape_size,
+            mask,
+            eof: config.eof,
+            empty_loop: config.empty_loop,
+            halt_command: config.halt_command,
+            random_command: config.random_command,
+            rng: Rng::new(config.seed),
+            max_steps: config.max_steps,
+            steps: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Give input to the program, to read after any input given before.
+    pub fn feed(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// End the input, after any input given before.
+    pub fn feed_eof(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Number of commands executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Count an executed command, enforcing the step limit.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        match self.max_steps {
+            Some(max) if self.steps > max => Err(Error::StepLimit(self.steps - 1)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Position of the bracket matching the one at `ip`, searching in the direction of `dir`.
+    fn matching(&self, ip: usize, dir: isize) -> Result<usize, Error> {
+        let (open, close) = if dir > 0 { (b'[', b']') } else { (b']', b'[') };
+        let mut depth = 0usize;
+        let mut i = ip;
+        loop {
+            match self.memory[i] {
+                c if c == open as u32 => depth += 1,
+                c if c == close as u32 && depth == 1 => return Ok(i),
+                c if c == close as u32 => depth -= 1,
+                _ => {},
+            }
+            i = i.checked_add_signed(dir)
+                .filter(|&i| i < self.code_len)
+                .ok_or(Error::UnmatchedBracket(ip))?;
+        }
+    }
+
+    /// Execute the next command, returning the event it caused, if any.
+    fn advance(&mut self) -> Result<Option<Event>, Error> {
+        if self.ip >= self.code_len {
+            return Ok(Some(Event::Halted));
+        }
+
+        let mut event = None;
+        match u8::try_from(self.memory[self.ip]).unwrap_or(0) {
+            b'>' => {
+                let ptr = self.ptr + 1;
+                if ptr >= self.code_len + self.tape_size {
+                    return Err(Error::PointerOutOfBounds);
+                }
+                if ptr == self.memory.len() {
+                    self.memory.push(0);
+                }
+                self.ptr = ptr;
+            },
+            b'<' => self.ptr = self.ptr.checked_sub(1).ok_or(Error::PointerOutOfBounds)?,
+            b'+' => self.memory[self.ptr] = self.memory[self.ptr].wrapping_add(1) & self.mask,
+            b'-' => self.memory[self.ptr] = self.memory[self.ptr].wrapping_sub(1) & self.mask,
+            // Only the lowest byte of wider cells is written
+            b'.' => event = Some(Event::Output(self.memory[self.ptr] as u8)),
+            b',' => match self.input.pop_front() {
+                Some(byte) => self.memory[self.ptr] = byte as u32 & self.mask,
+                None if !self.input_closed => return Ok(Some(Event::NeedsInput)),
+                None => match self.eof {
+                    EofMode::Unchanged => {},
+                    EofMode::Zero => self.memory[self.ptr] = 0,
+                    EofMode::Max => self.memory[self.ptr] = self.mask,
+                    EofMode::Error => return Err(Error::UnexpectedEof),
+                },
+            },
+            b'[' if self.memory[self.ptr] == 0 => self.ip = self.matching(self.ip, 1)?,
+            b'[' => event = Some(Event::LoopEntered),
+            b']' if self.memory[self.ptr] != 0 => {
+                let open = self.matching(self.ip, -1)?;
+                if open + 1 == self.ip && self.empty_loop == EmptyLoopMode::Error {
+                    return Err(Error::InfiniteLoop);
+                }
+                self.ip = open;
+            },
+            b']' => {},
+            b'?' if self.random_command => self.memory[self.ptr] = self.rng.byte() as u32 & self.mask,
+            b'@' if self.halt_command => {
+                self.step()?;
+                self.ip = self.code_len;
+                return Ok(Some(Event::Halted));
+            },
+            // Comments are not counted as steps
+            _ => {
+                self.ip += 1;
+                return Ok(None);
+            },
+        }
+
+        self.ip += 1;
+        match self.step() {
+            // Output written just before reaching the step limit is not lost
+            Err(e) if event.is_some() => self.error = Some(e),
+            result => result?,
+        }
+        Ok(event)
+    }
+}
+
+impl Iterator for Executions {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let result = match self.error.take() {
+                Some(e) => Err(e),
+                None => self.advance(),
+            };
+            match result {
+                Ok(None) => continue,
+                Ok(Some(event)) => {
+                    self.done = matches!(event, Event::Halted);
+                    return Some(event);
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Event::Error(e));
+                },
+            }
+        }
+    }
+}
+
+/// Run a self-modifying program to completion on the given input, returning its output.
+///
+/// The end of `input` is the end of input to the program.
+pub fn run(src: &str, config: &Config, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut executions = Executions::new(src, config);
+    executions.feed(input);
+    executions.feed_eof();
+    let mut output = Vec::new();
+    for event in executions {
+        match event {
+            Event::Output(byte) => output.push(byte),
+            Event::Error(e) => return Err(e),
+            _ => {},
+        }
+    }
+    Ok(output)
+}
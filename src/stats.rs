@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use bf::Instruction;
+
+/// Counts of the instructions in a program, including those in loop bodies.
+#[derive(Default)]
+struct Counts {
+    instructions: usize,
+    loops: usize,
+    max_depth: usize,
+    /// Pointer movements and tape switches.
+    moves: usize,
+    /// Changes of the current cell, by a constant or to a constant or random byte.
+    changes: usize,
+    /// Loops replaced by their closed form.
+    mul_loops: usize,
+    reads: usize,
+    writes: usize,
+    /// Pushes and pops of the stack dialect.
+    stack_ops: usize,
+}
+
+impl Counts {
+    fn of(prog: &[Instruction]) -> Self {
+        let mut counts = Counts::default();
+        counts.add(prog, 0);
+        counts
+    }
+
+    fn add(&mut self, prog: &[Instruction], depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+        for instr in prog {
+            use Instruction::*;
+            self.instructions += 1;
+            match instr {
+                IncPtr(_) | DecPtr(_) | SwitchTape(_) => self.moves += 1,
+                IncVal(_) | DecVal(_) | ClearVal | SetVal(_) | Random => self.changes += 1,
+                MulLoop(..) => self.mul_loops += 1,
+                Read => self.reads += 1,
+                Write | WriteConst(_) => self.writes += 1,
+                Push | Pop => self.stack_ops += 1,
+                Halt => {},
+                Loop(body, _) => {
+                    self.loops += 1;
+                    self.add(body, depth + 1);
+                },
+            }
+        }
+    }
+}
+
+/// Write a table comparing the instructions of the parsed and the optimized program.
+///
+/// ```text
+///                 parsed  optimized
+/// instructions       406        120
+/// loops               37         12
+/// ```
+pub fn write(parsed: &[Instruction], optimized: &[Instruction], w: &mut impl Write) -> io::Result<()> {
+    let (before, after) = (Counts::of(parsed), Counts::of(optimized));
+    let rows = [
+        ("instructions", before.instructions, after.instructions),
+        ("loops", before.loops, after.loops),
+        ("max loop depth", before.max_depth, after.max_depth),
+        ("moves", before.moves, after.moves),
+        ("cell changes", before.changes, after.changes),
+        ("multiply loops", before.mul_loops, after.mul_loops),
+        ("reads", before.reads, after.reads),
+        ("writes", before.writes, after.writes),
+        ("stack ops", before.stack_ops, after.stack_ops),
+    ];
+
+    writeln!(w, "{:<14}  {:>9}  {:>9}", "", "parsed", "optimized")?;
+    for (name, before, after) in rows {
+        writeln!(w, "{:<14}  {:>9}  {:>9}", name, before, after)?;
+    }
+    Ok(())
+}
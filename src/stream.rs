@@ -0,0 +1,211 @@
+//! Lexing and parsing programs as they are read, for generated programs too large to
+//! hold in memory as source.
+//!
+//! ```text
+//! use bf::{stream, config::Config};
+//!
+//! let file = std::fs::File::open("huge.bf")?;
+//! for instr in stream::Parser::new(file, &Config::default()) {
+//!     let instr = instr?;
+//!     // ...
+//! }
+//! ```
+//!
+//! The [`Parser`] yields the program one top-level instruction at a time, so only the
+//! loop that is being parsed is held in memory, along with a small read buffer.
+
+use std::{fmt, io::{self, BufRead, BufReader, Read}};
+
+use crate::{Instruction, config::Config, lexer::{Commands, Span, Token}, parser::{self, Warning}};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source could not be read.
+    Io(io::Error),
+    /// The source is not a valid program.
+    Parse(parser::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "could not read the program: {}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(e: parser::Error) -> Self {
+        Error::Parse(e)
+    }
+}
+
+/// Splits a source that is read as it goes into commands, along with their positions,
+/// as [`Lexer`](crate::lexer::Lexer) does without trivia.
+///
+/// Columns count characters as in the lexer when the source is valid UTF-8.
+pub struct Lexer<R: Read> {
+    rdr: BufReader<R>,
+    commands: Commands,
+    /// Position of the next byte.
+    pos: Span,
+}
+
+impl<R: Read> Lexer<R> {
+    /// Create a lexer that recognizes the commands of the configured dialect and extensions.
+    pub fn new(rdr: R, config: &Config) -> Self {
+        Self { rdr: BufReader::new(rdr), commands: Commands::from_config(config), pos: Span { line: 1, col: 1, offset: 0 } }
+    }
+
+    /// The next command, or `None` at the end of the source.
+    fn next_command(&mut self) -> io::Result<Option<(Token<'static>, Span)>> {
+        loop {
+            let buf = match self.rdr.fill_buf() {
+                Ok([]) => return Ok(None),
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let mut found = None;
+            let mut consumed = 0;
+            for &byte in buf {
+                let span = self.pos;
+                consumed += 1;
+                self.pos.offset += 1;
+                match byte {
+                    b'\n' => {
+                        self.pos.line += 1;
+                        self.pos.col = 1;
+                    },
+                    // Continuation bytes are part of the character before them
+                    0x80..=0xbf => {},
+                    _ => self.pos.col += 1,
+                }
+                // Every command is ASCII, so the other bytes do not need decoding
+                if let Some(token) = self.commands.token(byte as char) {
+                    found = Some((token, span));
+                    break;
+                }
+            }
+
+            self.rdr.consume(consumed);
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Lexer<R> {
+    type Item = io::Result<(Token<'static>, Span)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_command().transpose()
+    }
+}
+
+/// Parses a source as it is read, yielding every top-level instruction once it is
+/// complete, as [`Parser`](crate::parser::Parser) would return them.
+///
+/// After an error, the parser yields nothing more.
+pub struct Parser<R: Read> {
+    lexer: Lexer<R>,
+    warnings: Vec<Warning>,
+    /// The instructions of the unclosed loops, outermost first.
+    stack: Vec<Instruction>,
+    /// Where the body of every unclosed `[` starts on the stack, along with its position.
+    open: Vec<(usize, Span)>,
+    done: bool,
+}
+
+impl<R: Read> Parser<R> {
+    pub fn new(rdr: R, config: &Config) -> Self {
+        Self::with_lexer(Lexer::new(rdr, config))
+    }
+
+    pub fn with_lexer(lexer: Lexer<R>) -> Self {
+        Self { lexer, warnings: Vec::new(), stack: Vec::new(), open: Vec::new(), done: false }
+    }
+
+    /// Warnings found while parsing so far.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// The next top-level instruction, or `None` at the end of the source.
+    fn next_instruction(&mut self) -> Result<Option<Instruction>, Error> {
+        while let Some((token, span)) = self.lexer.next_command()? {
+            use Token::*;
+            use Instruction::*;
+            let instr = match token {
+                Gt    => IncPtr(1),
+                Lt    => DecPtr(1),
+                Plus  => IncVal(1),
+                Minus => DecVal(1),
+                Dot | Semicolon => Write,
+                Comma => Read,
+                LCurly => SwitchTape(-1),
+                RCurly => SwitchTape(1),
+                Caret => Push,
+                Underscore => Pop,
+                At => Halt,
+                Question => Random,
+                Comment(_) | Whitespace(_) => continue,
+                LSquare => {
+                    self.open.push((self.stack.len(), span));
+                    continue;
+                },
+                RSquare => {
+                    let Some((start, Span { line, col, .. })) = self.open.pop() else {
+                        return Err(parser::Error::MissingLoopOpen { line: span.line, col: span.col }.into());
+                    };
+                    if start == self.stack.len() {
+                        self.warnings.push(Warning::EmptyLoop { line, col });
+                    }
+                    Instruction::new_loop(self.stack.split_off(start))
+                },
+            };
+
+            if self.open.is_empty() {
+                return Ok(Some(instr));
+            }
+            self.stack.push(instr);
+        }
+
+        // Report the innermost unclosed loop, as every loop around it is unclosed as well
+        match self.open.last() {
+            Some(&(_, Span { line, col, .. })) => Err(parser::Error::MissingLoopEnd { line, col, count: self.open.len() }.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Parser<R> {
+    type Item = Result<Instruction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.next_instruction().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}};
+
+use bf::{Instruction, lexer::Lexer, literate, macros, parser::Parser, opt, cell::Cell, config::Config, eval::Context};
+
+/// Step limit for every test, unless configured otherwise.
+const DEFAULT_MAX_STEPS: u64 = 1_000_000_000;
+
+/// A program along with its input and expected output.
+struct Test {
+    name: String,
+    program: PathBuf,
+    input: Option<PathBuf>,
+    expected: PathBuf,
+}
+
+/// Find every `*.b` or `*.bf` program, or literate `*.md` program, in `dir` that has
+/// a sibling `.out` file, and optionally a sibling `.in` file.
+fn discover(dir: &Path) -> io::Result<Vec<Test>> {
+    let mut tests = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let program = entry?.path();
+        let is_program = program.extension()
+            .is_some_and(|ext| ext == "b" || ext == "bf" || ext == "md");
+        let expected = program.with_extension("out");
+        if !is_program || !expected.is_file() {
+            continue;
+        }
+
+        let input = Some(program.with_extension("in")).filter(|p| p.is_file());
+        let name = program.file_name().unwrap().to_string_lossy().into_owned();
+        tests.push(Test { name, program, input, expected });
+    }
+
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tests)
+}
+
+/// Run a single test, returning a description of the failure if it fails.
+fn run_test(test: &Test, config: &Config) -> Result<(), String> {
+    let mut src = fs::read_to_string(&test.program).map_err(|e| e.to_string())?;
+    if test.program.extension().is_some_and(|ext| ext == "md") {
+        src = literate::extract(&src);
+    }
+    let input = match &test.input {
+        Some(path) => fs::read(path).map_err(|e| e.to_string())?,
+        None => Vec::new(),
+    };
+    let expected = fs::read(&test.expected).map_err(|e| e.to_string())?;
+
+    let expansion = config.macros.then(|| macros::expand(&src)).transpose()
+        .map_err(|e| format!("macro error: {}", e))?;
+    let expanded = expansion.as_ref().map_or(&src[..], macros::Expansion::src);
+    let mut prog = Parser::new(Lexer::with_config(expanded, config)).parse()
+        .map_err(|e| e.relocate(|pos| expansion.as_ref().map_or(pos, |expansion| expansion.origin(pos))))
+        .map_err(|e| format!("parse error: {}", e))?;
+    opt::PassManager::from_config(config)?.run(&mut prog);
+
+    let mut rdr = &input[..];
+    let mut output = Vec::new();
+    with_cell!(config.cell_width, eval(&prog, config, &mut rdr, &mut output)).map_err(|e| format!("runtime error: {}", e))?;
+
+    if output == expected {
+        Ok(())
+    } else {
+        Err(describe_difference(&expected, &output))
+    }
+}
+
+/// Run the program with cells of type `C`.
+fn eval<C: Cell>(prog: &[Instruction], config: &Config, rdr: &mut impl Read, wtr: &mut impl Write) -> Result<(), bf::eval::Error> {
+    Context::<C>::new(rdr, wtr, config).eval(prog)?;
+    Ok(())
+}
+
+/// Describe the first difference between the expected and actual output.
+fn describe_difference(expected: &[u8], actual: &[u8]) -> String {
+    let pos = expected.iter().zip(actual)
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(actual.len()));
+    let line = expected[..pos].iter().filter(|&&b| b == b'\n').count() + 1;
+
+    // Show the differing line of both outputs
+    let line_of = |bytes: &[u8]| {
+        let start = bytes[..pos.min(bytes.len())].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let end = bytes[start..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| start + i);
+        String::from_utf8_lossy(&bytes[start..end]).into_owned()
+    };
+
+    format!("output differs at byte {} (line {}):\n  expected: {:?}\n  actual:   {:?}\n  ({} bytes expected, {} bytes written)",
+        pos, line, line_of(expected), line_of(actual), expected.len(), actual.len())
+}
+
+/// Run all tests in `dir`, printing the results. Returns whether all tests passed.
+pub fn run(dir: &Path, config: &Config) -> io::Result<bool> {
+    let mut config = config.clone();
+    config.max_steps.get_or_insert(DEFAULT_MAX_STEPS);
+
+    let tests = discover(dir)?;
+    println!("running {} tests", tests.len());
+
+    let mut failures = Vec::new();
+    for test in &tests {
+        match run_test(test, &config) {
+            Ok(()) => println!("test {} ... ok", test.name),
+            Err(e) => {
+                println!("test {} ... FAILED", test.name);
+                failures.push((&test.name, e));
+            },
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, e) in &failures {
+            println!("\n---- {} ----\n{}", name, e);
+        }
+    }
+
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!("\ntest result: {}. {} passed; {} failed", result, tests.len() - failures.len(), failures.len());
+    Ok(failures.is_empty())
+}
@@ -0,0 +1,516 @@
+//! Symbolic execution, which runs a program on unknown input to find input that makes it
+//! write a given byte or reach a given instruction, or to show that no such input exists.
+//!
+//! Every byte read is a variable, and every cell holds a linear combination of these
+//! variables plus a constant, modulo the cell size, which is all that the arithmetic of
+//! Brainfuck and multiply loops can compute. Whenever a loop tests a cell that depends on
+//! the input, the run forks into a path on which the cell is zero and one on which it is
+//! not, each with that as a constraint on the input. The paths are explored depth first,
+//! and a search over the input bytes finds input that meets the constraints of a path.
+//!
+//! The input is a fixed number of bytes, followed by the end of input. Only the cell
+//! widths of the intermediate representation are supported, not big cells, and only
+//! byte I/O.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use crate::{Instruction, opt, config::{Config, Dialect, EofMode}};
+
+/// A constant plus a linear combination of input bytes, modulo 2^32.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expr {
+    pub constant: u32,
+    /// Nonzero coefficients of the input bytes, by the index of the byte in the input,
+    /// in order of the index.
+    pub terms: Vec<(usize, u32)>,
+}
+
+impl Expr {
+    pub fn constant(x: u32) -> Self {
+        Self { constant: x, terms: Vec::new() }
+    }
+
+    /// The input byte at `index`.
+    pub fn input(index: usize) -> Self {
+        Self { constant: 0, terms: vec![(index, 1)] }
+    }
+
+    /// The value, if it does not depend on the input.
+    pub fn as_constant(&self) -> Option<u32> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+
+    /// The value for the given input.
+    pub fn eval(&self, input: &[u8]) -> u32 {
+        self.terms.iter().fold(self.constant, |sum, &(i, coefficient)| sum.wrapping_add(coefficient.wrapping_mul(input[i] as u32)))
+    }
+
+    fn add(&mut self, x: u32) {
+        self.constant = self.constant.wrapping_add(x);
+    }
+
+    /// Add `other` times `factor`, keeping only the bits in `mask`.
+    fn add_product(&mut self, other: &Expr, factor: u32, mask: u32) {
+        self.constant = self.constant.wrapping_add(other.constant.wrapping_mul(factor));
+        let mut terms = BTreeMap::from_iter(self.terms.iter().copied());
+        for &(i, coefficient) in &other.terms {
+            let term = terms.entry(i).or_insert(0);
+            *term = term.wrapping_add(coefficient.wrapping_mul(factor));
+        }
+        self.terms = terms.into_iter().filter(|&(_, coefficient)| coefficient & mask != 0).collect();
+        self.mask(mask);
+    }
+
+    /// Keep only the bits in `mask`, which is one less than a power of two.
+    fn mask(&mut self, mask: u32) {
+        self.constant &= mask;
+        for (_, coefficient) in &mut self.terms {
+            *coefficient &= mask;
+        }
+    }
+
+    /// Index of the last input byte this depends on.
+    fn last_input(&self) -> Option<usize> {
+        self.terms.last().map(|&(i, _)| i)
+    }
+}
+
+/// A condition on the input: whether the bits of an expression selected by a mask
+/// are equal to a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub expr: Expr,
+    pub mask: u32,
+    pub value: u32,
+    pub equal: bool,
+}
+
+impl Constraint {
+    pub fn holds(&self, input: &[u8]) -> bool {
+        (self.expr.eval(input) & self.mask == self.value) == self.equal
+    }
+}
+
+/// Result of searching for input that meets some constraints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    Sat(Vec<u8>),
+    Unsat,
+    /// The search ran out of budget.
+    Unknown,
+}
+
+/// Find input of `len` bytes that meets all `constraints`, trying at most `budget`
+/// values for bytes. Bytes that no constraint depends on are zero.
+///
+/// Bytes that no constraint relates are chosen independently, so that the search only
+/// backtracks over bytes that depend on each other.
+pub fn solve(constraints: &[Constraint], len: usize, budget: &mut u64) -> Solution {
+    // Group the bytes related by constraints, naming every group by one of its bytes
+    let mut group: Vec<usize> = (0..len).collect();
+    fn find(group: &mut [usize], i: usize) -> usize {
+        if group[i] != i {
+            group[i] = find(group, group[i]);
+        }
+        group[i]
+    }
+    for constraint in constraints {
+        let Some(&(first, _)) = constraint.expr.terms.first() else {
+            if !constraint.holds(&[]) {
+                return Solution::Unsat;
+            }
+            continue;
+        };
+        for &(i, _) in &constraint.expr.terms[1..] {
+            let (a, b) = (find(&mut group, first), find(&mut group, i));
+            group[a] = b;
+        }
+    }
+    let mut groups: BTreeMap<usize, Vec<&Constraint>> = BTreeMap::new();
+    for constraint in constraints {
+        if let Some(&(first, _)) = constraint.expr.terms.first() {
+            groups.entry(find(&mut group, first)).or_default().push(constraint);
+        }
+    }
+
+    let mut input = vec![0; len];
+    let mut unknown = false;
+    for constraints in groups.values() {
+        let mut bytes: Vec<usize> = constraints.iter().flat_map(|constraint| constraint.expr.terms.iter().map(|&(i, _)| i)).collect();
+        bytes.sort_unstable();
+        bytes.dedup();
+        // Check every constraint as soon as the last byte it depends on is chosen
+        let mut checks = vec![Vec::new(); bytes.len()];
+        for constraint in constraints {
+            let last = constraint.expr.last_input().unwrap_or_default();
+            checks[bytes.binary_search(&last).unwrap_or_default()].push(*constraint);
+        }
+        match assign(0, &bytes, &mut input, &checks, budget) {
+            Some(true) => {},
+            Some(false) => return Solution::Unsat,
+            None => unknown = true,
+        }
+    }
+    if unknown { Solution::Unknown } else { Solution::Sat(input) }
+}
+
+/// Choose the input bytes at the indices `bytes[i..]`, returning whether that is possible,
+/// or `None` when out of budget.
+fn assign(i: usize, bytes: &[usize], input: &mut [u8], checks: &[Vec<&Constraint>], budget: &mut u64) -> Option<bool> {
+    let Some(&byte) = bytes.get(i) else {
+        return Some(true);
+    };
+    for value in 0..=u8::MAX {
+        *budget = budget.checked_sub(1)?;
+        input[byte] = value;
+        if checks[i].iter().all(|constraint| constraint.holds(input)) && assign(i + 1, bytes, input, checks, budget)? {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+/// What to find input for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// The program writes this byte.
+    Write(u8),
+    /// The program reaches the instruction with this index, counting every loop before
+    /// the instructions in its body, as [`opt::fold`] visits them.
+    Reach(usize),
+}
+
+/// Bounds on the exploration of a program.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Number of input bytes before the end of input.
+    pub input_len: usize,
+    /// Number of steps on a single path, see [`Context::steps`](crate::eval::Context::steps).
+    pub max_steps: u64,
+    /// Number of paths to explore.
+    pub max_paths: usize,
+    /// Number of values to try for input bytes in every search for input.
+    pub solver_budget: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { input_len: 16, max_steps: 1_000_000, max_paths: 100_000, solver_budget: 1 << 20 }
+    }
+}
+
+/// The answer to the question asked by a [`Goal`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// This input reaches the goal.
+    Found(Vec<u8>),
+    /// No input of the given length reaches the goal.
+    Impossible,
+    /// The limits were reached before finding out.
+    Unknown,
+}
+
+/// Result of [`explore`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exploration {
+    pub outcome: Outcome,
+    /// Number of paths explored.
+    pub paths: usize,
+}
+
+/// A single path through the program.
+#[derive(Clone)]
+struct Path<'p> {
+    /// The bodies being run, innermost last, with the position of the next instruction in each.
+    frames: Vec<(&'p [Instruction], usize)>,
+    /// All tapes with their data pointers; only one without the multi-tape dialect.
+    tapes: Vec<(Vec<Expr>, usize)>,
+    /// Index of the current tape.
+    current: usize,
+    /// The stack of the stack dialect, with its top last.
+    stack: Vec<Expr>,
+    constraints: Vec<Constraint>,
+    /// Number of input bytes read.
+    read: usize,
+    steps: u64,
+}
+
+impl Path<'_> {
+    fn cell(&mut self) -> &mut Expr {
+        let (tape, ptr) = &mut self.tapes[self.current];
+        &mut tape[*ptr]
+    }
+
+    /// Make sure the current tape includes `cell`, or return `false` if it is too far.
+    fn reach(&mut self, cell: usize, tape_size: usize) -> bool {
+        let tape = &mut self.tapes[self.current].0;
+        if cell >= tape_size {
+            return false;
+        }
+        if cell >= tape.len() {
+            tape.resize(cell + 1, Expr::default());
+        }
+        true
+    }
+}
+
+/// How a path continues after a step.
+enum Next {
+    Continue,
+    Found(Vec<u8>),
+    /// The path ends, without reaching the goal.
+    End,
+    /// The path was cut short by the limits.
+    Cut,
+}
+
+struct Explorer<'p> {
+    goal: Goal,
+    limits: Limits,
+    mask: u32,
+    tape_size: usize,
+    eof: EofMode,
+    /// Index of every instruction, by its address.
+    pcs: BTreeMap<*const Instruction, usize>,
+    /// Paths still to explore.
+    pending: Vec<Path<'p>>,
+}
+
+/// Look for input of `limits.input_len` bytes with which `prog` reaches `goal`.
+pub fn explore(prog: &[Instruction], config: &Config, goal: Goal, limits: Limits) -> Exploration {
+    let tapes = match config.dialect {
+        Dialect::MultiTape => config.tapes,
+        Dialect::Standard | Dialect::SelfModifying | Dialect::Boolfuck | Dialect::Stack => 1,
+    };
+    let start = Path {
+        frames: vec![(prog, 0)],
+        tapes: vec![(vec![Expr::default()], 0); tapes.max(1)],
+        current: 0,
+        stack: Vec::new(),
+        constraints: Vec::new(),
+        read: 0,
+        steps: 0,
+    };
+    let pcs = opt::fold(prog, BTreeMap::new(), &mut |mut pcs, instr| {
+        let pc = pcs.len();
+        pcs.insert(instr as *const _, pc);
+        pcs
+    });
+    let mut explorer = Explorer {
+        goal,
+        limits,
+        mask: config.cell_width.mask(),
+        tape_size: config.tape_size,
+        eof: config.eof,
+        pcs,
+        pending: vec![start],
+    };
+
+    let mut complete = true;
+    let mut paths = 0;
+    while let Some(mut path) = explorer.pending.pop() {
+        if paths == limits.max_paths {
+            complete = false;
+            break;
+        }
+        paths += 1;
+        loop {
+            match explorer.advance(&mut path) {
+                Next::Continue => {},
+                Next::Found(input) => return Exploration { outcome: Outcome::Found(input), paths },
+                Next::End => break,
+                Next::Cut => {
+                    complete = false;
+                    break;
+                },
+            }
+        }
+    }
+    let outcome = if complete { Outcome::Impossible } else { Outcome::Unknown };
+    Exploration { outcome, paths }
+}
+
+impl<'p> Explorer<'p> {
+    /// Search for input meeting the constraints of `path` and `extra`.
+    fn solve(&self, path: &Path, extra: Option<Constraint>) -> Solution {
+        let mut constraints = path.constraints.clone();
+        constraints.extend(extra);
+        let mut budget = self.limits.solver_budget;
+        solve(&constraints, self.limits.input_len, &mut budget)
+    }
+
+    /// The input reaching the goal at this point of `path`, given the extra constraint.
+    fn found(&self, path: &Path, extra: Option<Constraint>) -> Next {
+        match self.solve(path, extra) {
+            // Only the input read so far matters
+            Solution::Sat(input) => Next::Found(input[..path.read].to_vec()),
+            Solution::Unsat => Next::Continue,
+            Solution::Unknown => Next::Cut,
+        }
+    }
+
+    /// Decide whether the current cell of `path` is nonzero, adding a path for the
+    /// other case when the input decides. Returns `None` if neither case is possible.
+    fn branch(&mut self, path: &mut Path<'p>) -> Option<bool> {
+        let cell = path.cell().clone();
+        if let Some(value) = cell.as_constant() {
+            return Some(value & self.mask != 0);
+        }
+        let zero = Constraint { expr: cell, mask: self.mask, value: 0, equal: true };
+        let nonzero = Constraint { equal: false, ..zero.clone() };
+        // Only skip a case when it is certainly impossible
+        let possible = |constraint: &Constraint| self.solve(path, Some(constraint.clone())) != Solution::Unsat;
+        match (possible(&zero), possible(&nonzero)) {
+            (true, true) => {
+                let mut fork = path.clone();
+                fork.constraints.push(nonzero);
+                self.pending.push(fork);
+                path.constraints.push(zero);
+                Some(false)
+            },
+            (true, false) => {
+                path.constraints.push(zero);
+                Some(false)
+            },
+            (false, true) => {
+                path.constraints.push(nonzero);
+                Some(true)
+            },
+            (false, false) => None,
+        }
+    }
+
+    /// Count a step, cutting the path at the step limit.
+    fn step(&self, path: &mut Path) -> Next {
+        path.steps += 1;
+        if path.steps > self.limits.max_steps { Next::Cut } else { Next::Continue }
+    }
+
+    /// Execute the next instruction of `path`.
+    fn advance(&mut self, path: &mut Path<'p>) -> Next {
+        let depth = path.frames.len();
+        let (body, i) = path.frames[depth - 1];
+        let Some(instr) = body.get(i) else {
+            if depth == 1 {
+                return Next::End;
+            }
+            // Test the condition of the loop again, and leave it if the cell is zero
+            if let Next::Cut = self.step(path) {
+                return Next::Cut;
+            }
+            match self.branch(path) {
+                Some(true) => path.frames[depth - 1].1 = 0,
+                Some(false) => {
+                    path.frames.pop();
+                    path.frames[depth - 2].1 += 1;
+                    return self.step(path);
+                },
+                None => return Next::End,
+            }
+            return Next::Continue;
+        };
+
+        if let Goal::Reach(pc) = self.goal && self.pcs[&(instr as *const _)] == pc {
+            return match self.found(path, None) {
+                Next::Continue => Next::End,
+                next => next,
+            };
+        }
+
+        use Instruction::*;
+        match instr {
+            IncPtr(x) => {
+                let ptr = path.tapes[path.current].1 + x;
+                if !path.reach(ptr, self.tape_size) {
+                    return Next::End;
+                }
+                path.tapes[path.current].1 = ptr;
+            },
+            DecPtr(x) => match path.tapes[path.current].1.checked_sub(*x) {
+                Some(ptr) => path.tapes[path.current].1 = ptr,
+                None => return Next::End,
+            },
+            IncVal(x) => {
+                path.cell().add(*x as u32);
+                path.cell().mask(self.mask);
+            },
+            DecVal(x) => {
+                path.cell().add((*x as u32).wrapping_neg());
+                path.cell().mask(self.mask);
+            },
+            ClearVal => *path.cell() = Expr::default(),
+            SetVal(x) => *path.cell() = Expr::constant(x & self.mask),
+            Write => if let Goal::Write(byte) = self.goal {
+                let output = Constraint { expr: path.cell().clone(), mask: 0xff, value: byte as u32, equal: true };
+                if let next @ (Next::Found(_) | Next::Cut) = self.found(path, Some(output)) {
+                    return next;
+                }
+            },
+            WriteConst(x) => if self.goal == Goal::Write(*x) {
+                return match self.found(path, None) {
+                    Next::Continue => Next::End,
+                    next => next,
+                };
+            },
+            Read if path.read < self.limits.input_len => {
+                *path.cell() = Expr::input(path.read);
+                path.read += 1;
+            },
+            Read => match self.eof {
+                EofMode::Unchanged => {},
+                EofMode::Zero => *path.cell() = Expr::default(),
+                EofMode::Max => *path.cell() = Expr::constant(self.mask),
+                EofMode::Error => return Next::End,
+            },
+            SwitchTape(x) => path.current = (path.current as isize + x).rem_euclid(path.tapes.len() as isize) as usize,
+            Halt => return Next::End,
+            // What follows depends on chance rather than on the input
+            Random => return Next::Cut,
+            Push if path.stack.len() >= self.tape_size => return Next::End,
+            Push => {
+                let value = path.cell().clone();
+                path.stack.push(value);
+            },
+            Pop => match path.stack.pop() {
+                Some(value) => *path.cell() = value,
+                None => return Next::End,
+            },
+            MulLoop(targets, step) => {
+                let ptr = path.tapes[path.current].1;
+                let cells: Option<Vec<usize>> = targets.iter()
+                    .map(|(offset, _)| ptr.checked_add_signed(*offset).filter(|&cell| path.reach(cell, self.tape_size)))
+                    .collect();
+                let Some(cells) = cells else {
+                    // The loop fails if it runs at all
+                    return match self.branch(path) {
+                        Some(false) => {
+                            path.frames[depth - 1].1 += 1;
+                            self.step(path)
+                        },
+                        _ => Next::End,
+                    };
+                };
+                // A zero counter means no iterations, so the closed form holds either way
+                let mut iterations = Expr::default();
+                iterations.add_product(path.cell(), opt::inverse(*step), self.mask);
+                for (cell, (_, factor)) in cells.into_iter().zip(targets) {
+                    path.tapes[path.current].0[cell].add_product(&iterations, *factor, self.mask);
+                }
+                *path.cell() = Expr::default();
+            },
+            Loop(inner, _) => match self.branch(path) {
+                // An empty loop never ends, or fails with `empty_loop = "error"`
+                Some(true) if inner.is_empty() => return Next::End,
+                Some(true) => {
+                    path.frames.push((inner, 0));
+                    return Next::Continue;
+                },
+                Some(false) => {},
+                None => return Next::End,
+            },
+        }
+
+        path.frames[depth - 1].1 += 1;
+        self.step(path)
+    }
+}
@@ -0,0 +1,196 @@
+//! Tracking which input bytes the output of a run depends on.
+//!
+//! Every cell carries the set of input bytes its value was computed from, its taint.
+//! Reading a byte taints the cell with just that byte. Changing a cell otherwise adds the
+//! taint of the loops that decided whether the change happens: the taint of the cell each
+//! running loop tested to start its current iteration. Only the loops that started their
+//! iteration after the cell was last read or set count, as the value the cell got then
+//! does not depend on how often the loops running since before went around. A multiply
+//! loop adds the taint of its counter to its targets, as the loop it replaces would.
+//! Every byte written carries the taint of its cell and of all running loops.
+//!
+//! So `,[->+<]>.` writes a byte tainted by the first input byte, and so does `,[>+.<-]`
+//! every time, as whether and how often it writes depends on that byte. What follows
+//! from the input through the control flow only is not tracked: which input byte is read
+//! next, or which cell is found after a loop such as `[>]`.
+
+use alloc::{collections::BTreeSet, format, string::String, vec::Vec};
+use core::fmt;
+
+/// Indices of the input bytes a value depends on.
+pub type Sources = BTreeSet<usize>;
+
+/// The taint of every cell and output byte of a run, kept up to date by
+/// [`Executions`](crate::events::Executions), see [`track_taint`](crate::events::Executions::track_taint).
+#[derive(Clone, Debug, Default)]
+pub struct Taint {
+    /// Taint of the cells used so far, by tape, with the time the cell was last overwritten.
+    tapes: Vec<Vec<(Sources, u64)>>,
+    /// Taint of the test that started the current iteration of every running loop,
+    /// with the time it started, innermost last.
+    control: Vec<(Sources, u64)>,
+    /// Number of changes so far, to order them in time.
+    time: u64,
+    /// Every byte written, with its taint.
+    outputs: Vec<(u8, Sources)>,
+    /// Number of input bytes read.
+    inputs: usize,
+    /// Taint of the values on the stack of the stack dialect, top last.
+    stack: Vec<Sources>,
+}
+
+impl Taint {
+    pub fn new(tapes: usize) -> Self {
+        Self { tapes: alloc::vec![Vec::new(); tapes], ..Self::default() }
+    }
+
+    /// Every byte written, with the input bytes it depends on.
+    pub fn outputs(&self) -> &[(u8, Sources)] {
+        &self.outputs
+    }
+
+    /// Number of input bytes read.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    fn cell(&mut self, tape: usize, cell: usize) -> &mut (Sources, u64) {
+        let cells = &mut self.tapes[tape];
+        if cell >= cells.len() {
+            cells.resize(cell + 1, (Sources::new(), 0));
+        }
+        &mut cells[cell]
+    }
+
+    /// Taint of the running loops that started their current iteration after `since`.
+    fn control(&self, since: u64) -> Sources {
+        self.control.iter()
+            .filter(|(_, start)| *start > since)
+            .flat_map(|(sources, _)| sources.iter().copied())
+            .collect()
+    }
+
+    /// A cell was changed depending on its own value.
+    pub(crate) fn update(&mut self, tape: usize, cell: usize) {
+        let since = self.cell(tape, cell).1;
+        let control = self.control(since);
+        self.cell(tape, cell).0.extend(control);
+    }
+
+    /// A cell was set to a constant.
+    pub(crate) fn set(&mut self, tape: usize, cell: usize) {
+        let since = self.cell(tape, cell).1;
+        let control = self.control(since);
+        self.time += 1;
+        *self.cell(tape, cell) = (control, self.time);
+    }
+
+    /// A cell was set to the next input byte.
+    pub(crate) fn read(&mut self, tape: usize, cell: usize) {
+        self.time += 1;
+        *self.cell(tape, cell) = (Sources::from([self.inputs]), self.time);
+        self.inputs += 1;
+    }
+
+    /// The value of `cell` was pushed onto the stack.
+    pub(crate) fn push(&mut self, tape: usize, cell: usize) {
+        let sources = self.cell(tape, cell).0.clone();
+        self.stack.push(sources);
+    }
+
+    /// The value on top of the stack was popped into `cell`.
+    pub(crate) fn pop(&mut self, tape: usize, cell: usize) {
+        let since = self.cell(tape, cell).1;
+        let mut sources = self.control(since);
+        sources.extend(self.stack.pop().unwrap_or_default());
+        self.time += 1;
+        *self.cell(tape, cell) = (sources, self.time);
+    }
+
+    /// The value of `cell` was written, or a constant without a cell.
+    pub(crate) fn write(&mut self, byte: u8, cell: Option<(usize, usize)>) {
+        let mut sources = self.control(0);
+        if let Some((tape, cell)) = cell {
+            sources.extend(self.cell(tape, cell).0.iter().copied());
+        }
+        self.outputs.push((byte, sources));
+    }
+
+    /// A multiply loop with a nonzero counter added it to the `targets` and cleared it.
+    pub(crate) fn multiply(&mut self, tape: usize, counter: usize, targets: impl IntoIterator<Item = usize>) {
+        let sources = self.cell(tape, counter).0.clone();
+        for target in targets {
+            let since = self.cell(tape, target).1;
+            let control = self.control(since);
+            self.cell(tape, target).0.extend(sources.iter().copied().chain(control));
+        }
+        self.set(tape, counter);
+    }
+
+    /// A loop started running its body, as its `cell` is not zero.
+    pub(crate) fn enter(&mut self, tape: usize, cell: usize) {
+        let sources = self.cell(tape, cell).0.clone();
+        self.time += 1;
+        self.control.push((sources, self.time));
+    }
+
+    /// The innermost loop runs its body again, as its `cell` is still not zero.
+    pub(crate) fn repeat(&mut self, tape: usize, cell: usize) {
+        let sources = self.cell(tape, cell).0.clone();
+        self.time += 1;
+        if let Some(control) = self.control.last_mut() {
+            *control = (sources, self.time);
+        }
+    }
+
+    /// The innermost loop ended.
+    pub(crate) fn leave(&mut self) {
+        self.control.pop();
+    }
+}
+
+/// Indices as a list of ranges, such as `0-3, 7`, or `none` if there are none.
+fn ranges(indices: impl IntoIterator<Item = usize>) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for i in indices {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == i => *end = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+    if ranges.is_empty() {
+        return "none".into();
+    }
+    ranges.iter()
+        .map(|&(start, end)| if start == end { format!("{}", start) } else { format!("{}-{}", start, end) })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A table of the input bytes that every output byte depends on,
+/// with consecutive output bytes that depend on the same input as one row.
+///
+/// ```text
+/// 6 bytes written, 3 bytes read
+/// output     input
+/// 0-1        none
+/// 2-4        0-2
+/// 5          1
+/// ```
+impl fmt::Display for Taint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} bytes written, {} bytes read", self.outputs.len(), self.inputs)?;
+        if self.outputs.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "{:<10} input", "output")?;
+        let mut start = 0;
+        while start < self.outputs.len() {
+            let sources = &self.outputs[start].1;
+            let len = self.outputs[start..].iter().take_while(|(_, other)| other == sources).count();
+            writeln!(f, "{:<10} {}", ranges(start..start + len), ranges(sources.iter().copied()))?;
+            start += len;
+        }
+        Ok(())
+    }
+}
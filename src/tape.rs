@@ -0,0 +1,228 @@
+//! Storage of the tape cells, chosen by [`Config::tape_growth`].
+
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+
+use crate::{cell::Cell, config::{Config, TapeGrowth}, eval::Error};
+
+/// Cells addressed by position, all zero initially.
+///
+/// Only cells below [`Tape::len`] may be accessed; the tape decides in [`Tape::reach`]
+/// which positions further along it makes available.
+pub trait Tape<C: Cell>: Send + Sync {
+    /// Value of the cell at `cell`.
+    fn get(&self, cell: usize) -> C;
+
+    fn set(&mut self, cell: usize, value: C);
+
+    /// Number of cells that may be accessed.
+    fn len(&self) -> usize;
+
+    /// Make sure the tape includes `cell`, growing it if allowed.
+    fn reach(&mut self, cell: usize) -> Result<(), Error>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Largest number of cells held in memory so far.
+    fn peak_size(&self) -> usize {
+        self.len()
+    }
+
+    /// Position `offset` cells from `ptr`, which the tape then includes.
+    fn shift(&mut self, ptr: usize, offset: isize) -> Result<usize, Error> {
+        let cell = ptr.checked_add_signed(offset).ok_or(Error::PointerOutOfBounds)?;
+        self.reach(cell)?;
+        Ok(cell)
+    }
+
+    /// The cells up to and including the last nonzero one.
+    fn to_vec(&self) -> Vec<C> {
+        let len = (0..self.len()).rposition(|cell| !self.get(cell).is_zero()).map_or(0, |i| i + 1);
+        (0..len).map(|cell| self.get(cell)).collect()
+    }
+}
+
+/// Create an empty tape as configured.
+pub fn new<C: Cell>(config: &Config) -> Box<dyn Tape<C>> {
+    let growable = |growth| Box::new(Growable::new(config.initial_tape_size, config.tape_size, growth));
+    match config.tape_growth {
+        TapeGrowth::Fixed => Box::new(Fixed::new(config.tape_size)),
+        TapeGrowth::Sparse => Box::new(Sparse::new(config.tape_size)),
+        // Out of address space, grow the tape on the heap as far as possible instead
+        TapeGrowth::Mapped => C::mapped(config.tape_size).unwrap_or_else(|| growable(TapeGrowth::Double)),
+        growth => growable(growth),
+    }
+}
+
+/// All cells allocated up front.
+pub struct Fixed<C> {
+    cells: Box<[C]>,
+}
+
+impl<C: Cell> Fixed<C> {
+    pub fn new(size: usize) -> Self {
+        Self { cells: vec![C::default(); size].into_boxed_slice() }
+    }
+}
+
+impl<C: Cell> Tape<C> for Fixed<C> {
+    fn get(&self, cell: usize) -> C {
+        self.cells[cell].clone()
+    }
+
+    fn set(&mut self, cell: usize, value: C) {
+        self.cells[cell] = value;
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn reach(&mut self, cell: usize) -> Result<(), Error> {
+        if cell < self.cells.len() { Ok(()) } else { Err(Error::PointerOutOfBounds) }
+    }
+}
+
+/// Cells allocated as they are used, up to a limit.
+pub struct Growable<C> {
+    cells: Vec<C>,
+    limit: usize,
+    growth: TapeGrowth,
+}
+
+impl<C: Cell> Growable<C> {
+    /// A tape with `initial` cells, growing by `growth` up to `limit` cells.
+    pub fn new(initial: usize, limit: usize, growth: TapeGrowth) -> Self {
+        Self { cells: vec![C::default(); initial.min(limit)], limit, growth }
+    }
+}
+
+impl<C: Cell> Tape<C> for Growable<C> {
+    fn get(&self, cell: usize) -> C {
+        self.cells[cell].clone()
+    }
+
+    fn set(&mut self, cell: usize, value: C) {
+        self.cells[cell] = value;
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn reach(&mut self, cell: usize) -> Result<(), Error> {
+        if cell < self.cells.len() {
+            return Ok(());
+        }
+        if cell >= self.limit {
+            return Err(Error::PointerOutOfBounds);
+        }
+
+        let len = match self.growth {
+            TapeGrowth::Chunks(n) => (cell / n + 1).saturating_mul(n),
+            TapeGrowth::Exact => cell + 1,
+            _ => (self.cells.len() * 2).max(cell + 1),
+        };
+        let len = len.min(self.limit);
+        self.cells.reserve_exact(len - self.cells.len());
+        self.cells.resize(len, C::default());
+        Ok(())
+    }
+}
+
+/// Only the nonzero cells, for programs that use a few cells far apart.
+pub struct Sparse<C> {
+    cells: BTreeMap<usize, C>,
+    limit: usize,
+    /// Largest number of nonzero cells so far.
+    peak: usize,
+}
+
+impl<C: Cell> Sparse<C> {
+    pub fn new(limit: usize) -> Self {
+        Self { cells: BTreeMap::new(), limit, peak: 0 }
+    }
+}
+
+impl<C: Cell> Tape<C> for Sparse<C> {
+    fn get(&self, cell: usize) -> C {
+        self.cells.get(&cell).cloned().unwrap_or_default()
+    }
+
+    fn set(&mut self, cell: usize, value: C) {
+        if value.is_zero() {
+            self.cells.remove(&cell);
+        } else {
+            self.cells.insert(cell, value);
+            self.peak = self.peak.max(self.cells.len());
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.limit
+    }
+
+    fn peak_size(&self) -> usize {
+        self.peak
+    }
+
+    fn reach(&mut self, cell: usize) -> Result<(), Error> {
+        if cell < self.limit { Ok(()) } else { Err(Error::PointerOutOfBounds) }
+    }
+
+    fn to_vec(&self) -> Vec<C> {
+        let len = self.cells.last_key_value().map_or(0, |(&cell, _)| cell + 1);
+        (0..len).map(|cell| self.get(cell)).collect()
+    }
+}
+
+/// All cells in a memory mapping, which only commits the pages that are used.
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos")))]
+pub struct Mapped<C> {
+    cells: crate::mmap::Mapping<C>,
+    /// Number of cells up to and including the last one reached.
+    reached: usize,
+}
+
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos")))]
+impl<C: Cell> Mapped<C> {
+    pub fn new(cells: crate::mmap::Mapping<C>) -> Self {
+        Self { cells, reached: 0 }
+    }
+}
+
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos")))]
+impl<C: Cell> Tape<C> for Mapped<C> {
+    fn get(&self, cell: usize) -> C {
+        self.cells[cell].clone()
+    }
+
+    fn set(&mut self, cell: usize, value: C) {
+        self.cells[cell] = value;
+        self.reached = self.reached.max(cell + 1);
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn peak_size(&self) -> usize {
+        self.reached
+    }
+
+    fn reach(&mut self, cell: usize) -> Result<(), Error> {
+        if cell >= self.cells.len() {
+            return Err(Error::PointerOutOfBounds);
+        }
+        self.reached = self.reached.max(cell + 1);
+        Ok(())
+    }
+
+    /// The cells up to and including the last nonzero one, without touching the pages
+    /// past the cells reached.
+    fn to_vec(&self) -> Vec<C> {
+        let len = self.cells[..self.reached].iter().rposition(|cell| !cell.is_zero()).map_or(0, |i| i + 1);
+        self.cells[..len].to_vec()
+    }
+}
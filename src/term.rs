@@ -0,0 +1,174 @@
+use std::io;
+
+/// Puts the terminal attached to stdin into raw mode for as long as it is alive.
+///
+/// In raw mode `,` sees every key press immediately, instead of only after Enter.
+/// The original terminal settings are restored when the guard is dropped.
+/// Uses termios on Unix and the console API on Windows.
+pub struct RawMode {
+    original: sys::Mode,
+}
+
+impl RawMode {
+    /// Switch stdin to raw (unbuffered, non-echoing) input.
+    pub fn enable() -> io::Result<Self> {
+        let original = sys::get()?;
+        let mut raw = original;
+        sys::make_raw(&mut raw);
+        sys::set(&raw)?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = sys::set(&self.original);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::io;
+
+    const STDIN_FILENO: i32 = 0;
+    const TCSANOW: i32 = 0;
+
+    #[cfg(target_os = "linux")]
+    mod consts {
+        pub type Flag = u32;
+        pub const NCCS: usize = 32;
+        pub const ICANON: Flag = 0o2;
+        pub const ECHO: Flag = 0o10;
+        pub const VTIME: usize = 5;
+        pub const VMIN: usize = 6;
+    }
+
+    #[cfg(target_os = "macos")]
+    mod consts {
+        pub type Flag = u64;
+        pub const NCCS: usize = 20;
+        pub const ICANON: Flag = 0x100;
+        pub const ECHO: Flag = 0x8;
+        pub const VMIN: usize = 16;
+        pub const VTIME: usize = 17;
+    }
+
+    use consts::*;
+
+    pub type Mode = Termios;
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    pub struct Termios {
+        c_iflag: Flag,
+        c_oflag: Flag,
+        c_cflag: Flag,
+        c_lflag: Flag,
+        #[cfg(target_os = "linux")]
+        c_line: u8,
+        c_cc: [u8; NCCS],
+        c_ispeed: Flag,
+        c_ospeed: Flag,
+    }
+
+    unsafe extern "C" {
+        fn tcgetattr(fd: i32, termios: *mut Termios) -> i32;
+        fn tcsetattr(fd: i32, action: i32, termios: *const Termios) -> i32;
+    }
+
+    pub fn get() -> io::Result<Termios> {
+        let mut termios = std::mem::MaybeUninit::<Termios>::uninit();
+        // SAFETY: `tcgetattr` fully initializes the struct when it returns zero.
+        if unsafe { tcgetattr(STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { termios.assume_init() })
+    }
+
+    pub fn set(termios: &Termios) -> io::Result<()> {
+        // SAFETY: `termios` points to a valid, initialized struct.
+        if unsafe { tcsetattr(STDIN_FILENO, TCSANOW, termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Disable line buffering and echo, and let reads return after a single byte.
+    /// Signals are left enabled, so Ctrl-C still interrupts the program.
+    pub fn make_raw(termios: &mut Termios) {
+        termios.c_lflag &= !(ICANON | ECHO);
+        termios.c_cc[VMIN] = 1;
+        termios.c_cc[VTIME] = 0;
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{ffi::c_void, io};
+
+    type Handle = *mut c_void;
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const INVALID_HANDLE_VALUE: Handle = -1isize as Handle;
+    const ENABLE_LINE_INPUT: u32 = 0x0002;
+    const ENABLE_ECHO_INPUT: u32 = 0x0004;
+
+    /// The console input mode flags.
+    pub type Mode = u32;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetStdHandle(std_handle: u32) -> Handle;
+        fn GetConsoleMode(console: Handle, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: Handle, mode: u32) -> i32;
+    }
+
+    fn stdin_handle() -> io::Result<Handle> {
+        // SAFETY: `GetStdHandle` has no preconditions.
+        let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(handle)
+    }
+
+    pub fn get() -> io::Result<Mode> {
+        let mut mode = 0;
+        // SAFETY: the handle is valid and `mode` is a valid out pointer.
+        if unsafe { GetConsoleMode(stdin_handle()?, &mut mode) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(mode)
+    }
+
+    pub fn set(mode: &Mode) -> io::Result<()> {
+        // SAFETY: the handle is valid.
+        if unsafe { SetConsoleMode(stdin_handle()?, *mode) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Disable line buffering and echo, so reads return as soon as a key is pressed.
+    /// Processed input is left enabled, so Ctrl-C still interrupts the program.
+    pub fn make_raw(mode: &mut Mode) {
+        *mode &= !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod sys {
+    use std::io;
+
+    pub type Mode = ();
+
+    pub fn get() -> io::Result<Mode> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "raw mode is not supported on this platform"))
+    }
+
+    pub fn set(_mode: &Mode) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn make_raw(_mode: &mut Mode) {}
+}
@@ -0,0 +1,166 @@
+//! Lightweight structured logging of the interpreter phases.
+//!
+//! Spans and events are only compiled in with the `trace` feature. By default they are
+//! written to stderr when enabled through the `BF_LOG` environment variable, e.g.
+//! `BF_LOG=debug`, but embedders can pass them on to their own logger instead, and set
+//! the level themselves:
+//!
+//! ```text
+//! bf::trace::set_logger(&|level, target, msg| my_log(level, target, &msg.to_string()));
+//! bf::trace::set_max_level(Some(bf::trace::Level::Debug));
+//! ```
+//!
+//! Without the feature all of this compiles to nothing.
+
+use core::fmt;
+#[cfg(feature = "trace")]
+use std::{env, sync::{RwLock, atomic::{AtomicU8, Ordering}}};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Receives every event that is logged, with its level and target.
+pub type Logger = dyn Fn(Level, &str, fmt::Arguments) + Sync;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[cfg(feature = "trace")]
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// The maximum level as stored in [`MAX_LEVEL`], where `0` logs nothing.
+    fn encode(level: Option<Level>) -> u8 {
+        level.map_or(0, |level| level as u8 + 1)
+    }
+
+    fn decode(raw: u8) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace].get(usize::from(raw).checked_sub(1)?).copied()
+    }
+}
+
+/// Maximum level that is logged, as [`Level::encode`] stores it,
+/// or [`UNSET`] until it is set or read from `BF_LOG`.
+#[cfg(feature = "trace")]
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(UNSET);
+#[cfg(feature = "trace")]
+const UNSET: u8 = u8::MAX;
+
+#[cfg(feature = "trace")]
+static LOGGER: RwLock<&'static Logger> = RwLock::new(&(stderr as fn(Level, &str, fmt::Arguments)));
+
+/// Maximum level that is logged, as set by [`set_max_level`], or as configured by `BF_LOG`.
+#[cfg(feature = "trace")]
+fn max_level() -> Option<Level> {
+    let raw = MAX_LEVEL.load(Ordering::Relaxed);
+    if raw != UNSET {
+        return Level::decode(raw);
+    }
+    let level = match env::var("BF_LOG").ok().map(|level| level.to_ascii_lowercase()).as_deref() {
+        Some("error") => Some(Level::Error),
+        Some("warn") => Some(Level::Warn),
+        Some("info") => Some(Level::Info),
+        Some("debug") => Some(Level::Debug),
+        Some("trace") => Some(Level::Trace),
+        _ => None,
+    };
+    // A level set meanwhile takes precedence over the environment
+    match MAX_LEVEL.compare_exchange(UNSET, Level::encode(level), Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => level,
+        Err(raw) => Level::decode(raw),
+    }
+}
+
+/// Log the events up to and including `level`, or none at all, instead of the level
+/// configured by `BF_LOG`.
+pub fn set_max_level(level: Option<Level>) {
+    #[cfg(feature = "trace")]
+    MAX_LEVEL.store(Level::encode(level), Ordering::Relaxed);
+    #[cfg(not(feature = "trace"))]
+    let _ = level;
+}
+
+/// Pass every event to `logger` instead of writing it to stderr.
+pub fn set_logger(logger: &'static Logger) {
+    #[cfg(feature = "trace")]
+    {
+        *LOGGER.write().unwrap_or_else(|e| e.into_inner()) = logger;
+    }
+    #[cfg(not(feature = "trace"))]
+    let _ = logger;
+}
+
+/// The default logger, which writes every event to stderr.
+#[cfg(feature = "trace")]
+fn stderr(level: Level, target: &str, msg: fmt::Arguments) {
+    eprintln!("{:>5} {}: {}", level.name(), target, msg);
+}
+
+/// Whether events at the given level are logged.
+pub fn enabled(level: Level) -> bool {
+    #[cfg(feature = "trace")]
+    return max_level().is_some_and(|max| level <= max);
+    #[cfg(not(feature = "trace"))]
+    {
+        let _ = level;
+        false
+    }
+}
+
+/// Pass a single event to the logger.
+pub fn emit(level: Level, target: &str, msg: fmt::Arguments) {
+    #[cfg(feature = "trace")]
+    {
+        let logger = *LOGGER.read().unwrap_or_else(|e| e.into_inner());
+        logger(level, target, msg);
+    }
+    #[cfg(not(feature = "trace"))]
+    let _ = (level, target, msg);
+}
+
+/// Log an event at the given level, e.g. `event!(Debug, "opt", "removed {} instructions", n)`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __event {
+    ($level:ident, $target:expr, $($arg:tt)+) => {
+        if $crate::trace::enabled($crate::trace::Level::$level) {
+            $crate::trace::emit($crate::trace::Level::$level, $target, format_args!($($arg)+));
+        }
+    };
+}
+
+pub use __event as event;
+
+/// A timed phase of execution, logged at the `info` level when it ends.
+#[cfg(feature = "std")]
+pub struct Span {
+    name: &'static str,
+    start: Instant,
+}
+
+/// Enter a span, which lasts until the returned guard is dropped.
+#[cfg(feature = "std")]
+pub fn span(name: &'static str) -> Span {
+    event!(Trace, name, "enter");
+    Span { name, start: Instant::now() }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        event!(Info, self.name, "finished in {:.3?}", self.start.elapsed());
+    }
+}
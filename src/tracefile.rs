@@ -0,0 +1,218 @@
+//! A record of every executed instruction, compact enough for long runs.
+//!
+//! Every step is written as a [`Record`], either as CSV with a header line, or in a
+//! binary format: the magic bytes `BFTRACE1`, followed by one entry per record with the
+//! [`Op`] as a single byte and then the increase of the step count since the previous
+//! record, the program counter, the data pointer and the cell, each as an unsigned LEB128
+//! number. Most records take five to eight bytes.
+
+use std::{collections::HashMap, io::{self, BufRead, Write}};
+
+use crate::Instruction;
+
+/// Magic bytes at the start of a binary trace.
+const MAGIC: &[u8] = b"BFTRACE1";
+
+/// Header of a CSV trace, naming the fields of a [`Record`].
+pub const CSV_HEADER: &str = "step,pc,pointer,cell,op";
+
+/// The kind of instruction executed in a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    IncPtr,
+    DecPtr,
+    IncVal,
+    DecVal,
+    ClearVal,
+    SetVal,
+    Write,
+    WriteConst,
+    Read,
+    SwitchTape,
+    MulLoop,
+    /// Leaving a loop, or skipping it as the cell is zero.
+    Loop,
+    /// A test of the condition of a loop after running its body.
+    LoopTest,
+    Halt,
+    Random,
+    Push,
+    Pop,
+}
+
+impl Op {
+    const ALL: [Op; 17] = [
+        Op::IncPtr, Op::DecPtr, Op::IncVal, Op::DecVal, Op::ClearVal, Op::SetVal, Op::Write,
+        Op::WriteConst, Op::Read, Op::SwitchTape, Op::MulLoop, Op::Loop, Op::LoopTest, Op::Halt,
+        Op::Random, Op::Push, Op::Pop,
+    ];
+
+    /// The kind of `instr`, when it finishes.
+    pub fn of(instr: &Instruction) -> Self {
+        use Instruction::*;
+        match instr {
+            IncPtr(_) => Op::IncPtr,
+            DecPtr(_) => Op::DecPtr,
+            IncVal(_) => Op::IncVal,
+            DecVal(_) => Op::DecVal,
+            ClearVal => Op::ClearVal,
+            SetVal(_) => Op::SetVal,
+            Write => Op::Write,
+            WriteConst(_) => Op::WriteConst,
+            Read => Op::Read,
+            SwitchTape(_) => Op::SwitchTape,
+            Halt => Op::Halt,
+            Random => Op::Random,
+            Push => Op::Push,
+            Pop => Op::Pop,
+            MulLoop(..) => Op::MulLoop,
+            Loop(..) => Op::Loop,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        Op::ALL.get(byte as usize).copied()
+    }
+}
+
+/// The state after a single step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Number of steps executed, including this one.
+    pub step: u64,
+    /// Index of the instruction in the program, counting every loop before the
+    /// instructions in its body, as [`opt::fold`](crate::opt::fold) visits them.
+    pub pc: u32,
+    pub ptr: usize,
+    /// The lowest 32 bits of the cell at the data pointer.
+    pub cell: u32,
+    pub op: Op,
+}
+
+impl Record {
+    /// Write the record as a line of CSV.
+    pub fn write_csv(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "{},{},{},{},{:?}", self.step, self.pc, self.ptr, self.cell, self.op)
+    }
+}
+
+/// Writes the records of a run as they happen.
+pub struct TraceWriter<'a> {
+    out: Box<dyn Write + Send + 'a>,
+    csv: bool,
+    /// Step of the previous record.
+    step: u64,
+    /// Index of every instruction of the program being traced, by its address.
+    pcs: HashMap<usize, u32>,
+}
+
+impl<'a> TraceWriter<'a> {
+    /// Write a binary trace to `out`, or a CSV trace if `csv` is set.
+    pub fn new(mut out: impl Write + Send + 'a, csv: bool) -> io::Result<Self> {
+        if csv {
+            writeln!(out, "{}", CSV_HEADER)?;
+        } else {
+            out.write_all(MAGIC)?;
+        }
+        Ok(Self { out: Box::new(out), csv, step: 0, pcs: HashMap::new() })
+    }
+
+    /// Number the instructions of `prog`, unless this was done before.
+    pub fn index(&mut self, prog: &[Instruction]) {
+        if self.pcs.is_empty() {
+            self.pcs = crate::opt::fold(prog, HashMap::new(), &mut |mut pcs, instr| {
+                let pc = pcs.len() as u32;
+                pcs.insert(instr as *const Instruction as usize, pc);
+                pcs
+            });
+        }
+    }
+
+    /// Record a step that executed `instr`, an instruction of the indexed program.
+    pub fn record(&mut self, step: u64, instr: &Instruction, op: Op, ptr: usize, cell: u32) -> io::Result<()> {
+        let pc = self.pcs.get(&(instr as *const Instruction as usize)).copied().unwrap_or(u32::MAX);
+        let record = Record { step, pc, ptr, cell, op };
+        if self.csv {
+            return record.write_csv(&mut self.out);
+        }
+        let mut buf = Vec::with_capacity(32);
+        buf.push(op as u8);
+        for n in [step - self.step, pc as u64, ptr as u64, cell as u64] {
+            write_leb128(&mut buf, n);
+        }
+        self.step = step;
+        self.out.write_all(&buf)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Reads the records of a binary trace.
+pub struct TraceReader<R> {
+    rdr: R,
+    step: u64,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    /// Start reading a binary trace, checking its magic bytes.
+    pub fn new(mut rdr: R) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        rdr.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary trace"));
+        }
+        Ok(Self { rdr, step: 0 })
+    }
+
+    fn read_leb128(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.rdr.read_exact(&mut byte)?;
+            n |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "number too large"))
+    }
+
+    fn read_record(&mut self, op: u8) -> io::Result<Record> {
+        let op = Op::from_byte(op)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid operation {}", op)))?;
+        self.step += self.read_leb128()?;
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "number out of range");
+        let pc = self.read_leb128()?.try_into().map_err(invalid)?;
+        let ptr = self.read_leb128()?.try_into().map_err(invalid)?;
+        let cell = self.read_leb128()?.try_into().map_err(invalid)?;
+        Ok(Record { step: self.step, pc, ptr, cell, op })
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let op = match self.rdr.fill_buf() {
+            Ok([]) => return None,
+            Ok(buf) => buf[0],
+            Err(e) => return Some(Err(e)),
+        };
+        self.rdr.consume(1);
+        Some(self.read_record(op))
+    }
+}
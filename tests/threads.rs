@@ -0,0 +1,43 @@
+//! Sharing one parsed and optimized program between threads.
+
+use std::{io::Cursor, thread};
+
+use bf::{Program, config::{Config, EofMode}, eval::Context, events, opt::Level};
+
+/// Uppercases its input of lowercase letters until the end of input, so that every thread
+/// can give it different input.
+const UPPERCASE: &str = ",[>++++[<-------->-]<.,]";
+
+#[test]
+fn threads_share_one_program() {
+    let config = Config { eof: EofMode::Zero, ..Config::default() };
+    let prog = Program::parse_with(UPPERCASE, config.clone()).unwrap().optimize(Level::Aggressive);
+    let inputs: Vec<String> = ('a'..='h').map(|c| format!("thread{}", c)).collect();
+
+    let outputs: Vec<Vec<u8>> = thread::scope(|s| {
+        let handles: Vec<_> = inputs.iter()
+            .map(|input| s.spawn(|| events::run(prog.ir(), &config, input.as_bytes()).unwrap()))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    for (input, output) in inputs.iter().zip(outputs) {
+        assert_eq!(output, input.to_ascii_uppercase().into_bytes());
+        assert_eq!(output, events::run(prog.ir(), &config, input.as_bytes()).unwrap());
+    }
+}
+
+#[test]
+fn contexts_with_typed_io_move_between_threads() {
+    let config = Config { eof: EofMode::Zero, ..Config::default() };
+    let prog = Program::parse_with(UPPERCASE, config.clone()).unwrap().optimize(Level::Aggressive);
+
+    let mut rdr = Cursor::new(b"moved".to_vec());
+    let mut output = Vec::new();
+    let mut ctx = Context::<u8, _, _>::with_typed_io(&mut rdr, &mut output, &config);
+    let ir = prog.ir();
+    thread::scope(|s| {
+        s.spawn(move || ctx.eval(ir).unwrap());
+    });
+    assert_eq!(output, b"MOVED");
+}